@@ -0,0 +1,576 @@
+use crate::error::MutinyError;
+use crate::event::{HTLCStatus, PaymentInfo};
+use crate::labels::LabelStorage;
+use crate::ldkstorage::payment_key;
+use crate::nodemanager::{ActivityItem, ChannelClosure, TransactionDetails};
+use crate::rebalance::{Rebalance, RebalanceStatus};
+use crate::storage::MutinyStorage;
+use crate::swaps::{Swap, SwapStatus};
+use bdk::chain::ConfirmationTime;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256, Hash};
+use lightning::ln::PaymentHash;
+use lightning_invoice::{Invoice, InvoiceDescription};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const ACTIVITY_INDEX_PREFIX_KEY: &str = "activity_index/";
+/// Set once the activity index has been built from the existing payments
+pub(crate) const ACTIVITY_INDEX_BUILT_KEY: &str = "activity_index_built";
+
+/// The direction of a payment or transaction, relative to the wallet
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ActivityDirection {
+    Inbound,
+    Outbound,
+}
+
+/// A simplified status of an activity item, used for filtering
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ActivityStatus {
+    /// Lightning payments that have not completed or unconfirmed on-chain transactions
+    Pending,
+    /// Completed lightning payments, confirmed on-chain transactions and channel closures
+    Succeeded,
+    /// Failed lightning payments
+    Failed,
+}
+
+impl From<&HTLCStatus> for ActivityStatus {
+    fn from(status: &HTLCStatus) -> Self {
+        match status {
+            HTLCStatus::Pending | HTLCStatus::Held | HTLCStatus::InFlight => Self::Pending,
+            HTLCStatus::Succeeded => Self::Succeeded,
+            HTLCStatus::Failed => Self::Failed,
+        }
+    }
+}
+
+/// Filter for [`crate::nodemanager::NodeManager::query_activity`].
+/// Every field that is set must match for an item to be included.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ActivityFilter {
+    /// Only include activity last updated at or after this time, in seconds since the epoch
+    pub start_time: Option<u64>,
+    /// Only include activity last updated at or before this time, in seconds since the epoch
+    pub end_time: Option<u64>,
+    pub direction: Option<ActivityDirection>,
+    pub status: Option<ActivityStatus>,
+    /// Only include activity that has this label
+    pub label: Option<String>,
+    /// Only include activity tagged with the contact with this id
+    pub contact: Option<String>,
+    pub min_amount_sats: Option<u64>,
+    pub max_amount_sats: Option<u64>,
+    /// Case-insensitive search over invoice descriptions
+    pub text: Option<String>,
+}
+
+/// A page of results from [`crate::nodemanager::NodeManager::query_activity`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityPage {
+    /// The activity items in this page, newest first
+    pub items: Vec<ActivityItem>,
+    /// The total number of items that matched the filter
+    pub total: usize,
+}
+
+/// The fields of an activity item that can be filtered on
+pub(crate) struct ActivitySummary<'a> {
+    /// None for pending on-chain transactions
+    pub last_updated: Option<u64>,
+    pub direction: Option<ActivityDirection>,
+    pub status: ActivityStatus,
+    pub amount_sats: Option<u64>,
+    pub labels: &'a [String],
+    pub description: Option<&'a str>,
+}
+
+impl<'a> From<&'a ActivityIndexItem> for ActivitySummary<'a> {
+    fn from(item: &'a ActivityIndexItem) -> Self {
+        let direction = if item.inbound {
+            ActivityDirection::Inbound
+        } else {
+            ActivityDirection::Outbound
+        };
+
+        ActivitySummary {
+            last_updated: Some(item.last_updated),
+            direction: Some(direction),
+            status: (&item.status).into(),
+            amount_sats: item.amount_sats,
+            labels: &item.labels,
+            description: item.description.as_deref(),
+        }
+    }
+}
+
+impl<'a> From<&'a TransactionDetails> for ActivitySummary<'a> {
+    fn from(tx: &'a TransactionDetails) -> Self {
+        let (last_updated, status) = match tx.confirmation_time {
+            ConfirmationTime::Confirmed { time, .. } => (Some(time), ActivityStatus::Succeeded),
+            ConfirmationTime::Unconfirmed { .. } => (None, ActivityStatus::Pending),
+        };
+        let (direction, amount_sats) = if tx.received > tx.sent {
+            (ActivityDirection::Inbound, tx.received - tx.sent)
+        } else {
+            (ActivityDirection::Outbound, tx.sent - tx.received)
+        };
+
+        ActivitySummary {
+            last_updated,
+            direction: Some(direction),
+            status,
+            amount_sats: Some(amount_sats),
+            labels: &tx.labels,
+            description: None,
+        }
+    }
+}
+
+impl<'a> From<&'a ChannelClosure> for ActivitySummary<'a> {
+    fn from(closure: &'a ChannelClosure) -> Self {
+        ActivitySummary {
+            last_updated: Some(closure.timestamp),
+            direction: None,
+            status: ActivityStatus::Succeeded,
+            amount_sats: None,
+            labels: &[],
+            description: None,
+        }
+    }
+}
+
+impl<'a> From<&'a Rebalance> for ActivitySummary<'a> {
+    fn from(rebalance: &'a Rebalance) -> Self {
+        let status = match rebalance.status {
+            RebalanceStatus::InFlight => ActivityStatus::Pending,
+            RebalanceStatus::Succeeded => ActivityStatus::Succeeded,
+            RebalanceStatus::Failed(_) => ActivityStatus::Failed,
+        };
+
+        ActivitySummary {
+            last_updated: Some(rebalance.last_updated),
+            direction: None,
+            status,
+            amount_sats: Some(rebalance.amount_sats),
+            labels: &[],
+            description: None,
+        }
+    }
+}
+
+impl<'a> From<&'a Swap> for ActivitySummary<'a> {
+    fn from(swap: &'a Swap) -> Self {
+        let status = match swap.status {
+            SwapStatus::Created | SwapStatus::Funded | SwapStatus::Claimed => {
+                ActivityStatus::Pending
+            }
+            SwapStatus::Completed => ActivityStatus::Succeeded,
+            SwapStatus::Refunded | SwapStatus::Failed(_) => ActivityStatus::Failed,
+        };
+
+        ActivitySummary {
+            last_updated: Some(swap.last_updated),
+            direction: None,
+            status,
+            amount_sats: Some(swap.amount_received_sats()),
+            labels: &[],
+            description: None,
+        }
+    }
+}
+
+impl ActivityFilter {
+    pub(crate) fn matches(&self, item: &ActivitySummary) -> bool {
+        // pending items without a timestamp are treated as happening now
+        let time = item
+            .last_updated
+            .unwrap_or_else(|| crate::utils::now().as_secs());
+        if self.start_time.is_some_and(|start| time < start)
+            || self.end_time.is_some_and(|end| time > end)
+        {
+            return false;
+        }
+
+        if self.direction.is_some() && self.direction != item.direction {
+            return false;
+        }
+
+        if self.status.is_some_and(|status| status != item.status) {
+            return false;
+        }
+
+        if let Some(label) = self.label.as_ref() {
+            if !item.labels.contains(label) {
+                return false;
+            }
+        }
+
+        // contacts are stored as labels using the contact's id
+        if let Some(contact) = self.contact.as_ref() {
+            if !item.labels.contains(contact) {
+                return false;
+            }
+        }
+
+        if let Some(min) = self.min_amount_sats {
+            if item.amount_sats.map_or(true, |amt| amt < min) {
+                return false;
+            }
+        }
+
+        if let Some(max) = self.max_amount_sats {
+            if item.amount_sats.map_or(true, |amt| amt > max) {
+                return false;
+            }
+        }
+
+        if let Some(text) = self.text.as_ref() {
+            let text = text.to_lowercase();
+            if !item
+                .description
+                .is_some_and(|d| d.to_lowercase().contains(&text))
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// A lightweight entry in the activity index, kept in sync with the
+/// [`PaymentInfo`] it was created from so activity can be filtered
+/// without reading and decoding every payment.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct ActivityIndexItem {
+    pub payment_hash: sha256::Hash,
+    /// The id of the node that the payment belongs to
+    pub node_id: String,
+    pub inbound: bool,
+    pub status: HTLCStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount_sats: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Expiry time of the invoice, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire: Option<u64>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    pub last_updated: u64,
+}
+
+impl ActivityIndexItem {
+    pub(crate) fn new(
+        payment_hash: &PaymentHash,
+        node_id: &str,
+        inbound: bool,
+        payment_info: &PaymentInfo,
+        labels: Vec<String>,
+    ) -> Self {
+        let (amount_sats, description, expire) = match payment_info.bolt11.as_ref() {
+            Some(invoice) => {
+                let amount_msat = invoice
+                    .amount_milli_satoshis()
+                    .filter(|amt| *amt != 0)
+                    .or(payment_info.amt_msat.0);
+                let description = match invoice.description() {
+                    InvoiceDescription::Direct(d) if !d.is_empty() => Some(d.to_string()),
+                    _ => None,
+                };
+                let expire =
+                    invoice.duration_since_epoch().as_secs() + invoice.expiry_time().as_secs();
+                (amount_msat.map(|a| a / 1_000), description, Some(expire))
+            }
+            None => (payment_info.amt_msat.0.map(|a| a / 1_000), None, None),
+        };
+
+        ActivityIndexItem {
+            payment_hash: sha256::Hash::from_inner(payment_hash.0),
+            node_id: node_id.to_string(),
+            inbound,
+            status: payment_info.status.clone(),
+            amount_sats,
+            description,
+            expire,
+            labels,
+            last_updated: payment_info.last_update,
+        }
+    }
+
+    /// Unpaid invoices that have expired are not shown in the activity
+    pub(crate) fn is_expired(&self, now: u64) -> bool {
+        self.expire.is_some_and(|expire| expire < now)
+            && !matches!(
+                self.status,
+                HTLCStatus::Succeeded | HTLCStatus::InFlight | HTLCStatus::Held
+            )
+    }
+}
+
+fn get_activity_index_key(payment_hash: &sha256::Hash, inbound: bool, node_id: &str) -> String {
+    let direction = if inbound { "inbound" } else { "outbound" };
+    format!(
+        "{ACTIVITY_INDEX_PREFIX_KEY}{}_{direction}_{node_id}",
+        payment_hash.to_hex()
+    )
+}
+
+pub(crate) trait ActivityStorage {
+    /// Adds or updates the index entry for a payment
+    fn index_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        node_id: &str,
+        inbound: bool,
+        payment_info: &PaymentInfo,
+    ) -> Result<(), MutinyError>;
+    /// Updates the labels of the index entries for the given invoice
+    fn set_activity_index_labels(
+        &self,
+        invoice: &Invoice,
+        labels: &[String],
+    ) -> Result<(), MutinyError>;
+    /// Indexes all the given payments for a node, used to build the index for existing payments
+    fn index_payments(
+        &self,
+        node_id: &str,
+        inbound: bool,
+        payments: Vec<(PaymentHash, PaymentInfo)>,
+    ) -> Result<(), MutinyError>;
+    /// Lists every entry in the activity index
+    fn list_activity_index(&self) -> Result<Vec<ActivityIndexItem>, MutinyError>;
+    /// Reads the full payment info that an index entry was created from
+    fn get_indexed_payment_info(
+        &self,
+        item: &ActivityIndexItem,
+    ) -> Result<Option<PaymentInfo>, MutinyError>;
+}
+
+impl<S: MutinyStorage> ActivityStorage for S {
+    fn index_payment(
+        &self,
+        payment_hash: &PaymentHash,
+        node_id: &str,
+        inbound: bool,
+        payment_info: &PaymentInfo,
+    ) -> Result<(), MutinyError> {
+        let hash = sha256::Hash::from_inner(payment_hash.0);
+        let key = get_activity_index_key(&hash, inbound, node_id);
+
+        // keep the labels we already have indexed, otherwise look them up
+        let labels = match self.get_data::<ActivityIndexItem>(&key)? {
+            Some(existing) => existing.labels,
+            None => match payment_info.bolt11.as_ref() {
+                Some(invoice) => self
+                    .get_invoice_labels()?
+                    .remove(invoice)
+                    .unwrap_or_default(),
+                None => vec![],
+            },
+        };
+
+        let item = ActivityIndexItem::new(payment_hash, node_id, inbound, payment_info, labels);
+        self.set_data(key, item, None)
+    }
+
+    fn set_activity_index_labels(
+        &self,
+        invoice: &Invoice,
+        labels: &[String],
+    ) -> Result<(), MutinyError> {
+        let prefix = format!(
+            "{ACTIVITY_INDEX_PREFIX_KEY}{}_",
+            invoice.payment_hash().to_hex()
+        );
+        let items: HashMap<String, ActivityIndexItem> = self.scan(&prefix, None)?;
+        for (key, mut item) in items {
+            item.labels = labels.to_vec();
+            self.set_data(key, item, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn index_payments(
+        &self,
+        node_id: &str,
+        inbound: bool,
+        payments: Vec<(PaymentHash, PaymentInfo)>,
+    ) -> Result<(), MutinyError> {
+        let labels_map = self.get_invoice_labels()?;
+        for (payment_hash, payment_info) in payments {
+            let labels = payment_info
+                .bolt11
+                .as_ref()
+                .and_then(|invoice| labels_map.get(invoice).cloned())
+                .unwrap_or_default();
+            let item =
+                ActivityIndexItem::new(&payment_hash, node_id, inbound, &payment_info, labels);
+            let key = get_activity_index_key(&item.payment_hash, inbound, node_id);
+            self.set_data(key, item, None)?;
+        }
+
+        Ok(())
+    }
+
+    fn list_activity_index(&self) -> Result<Vec<ActivityIndexItem>, MutinyError> {
+        let map: HashMap<String, ActivityIndexItem> = self.scan(ACTIVITY_INDEX_PREFIX_KEY, None)?;
+        Ok(map.into_values().collect())
+    }
+
+    fn get_indexed_payment_info(
+        &self,
+        item: &ActivityIndexItem,
+    ) -> Result<Option<PaymentInfo>, MutinyError> {
+        let payment_hash = PaymentHash(item.payment_hash.into_inner());
+        let key = format!(
+            "{}_{}",
+            payment_key(item.inbound, &payment_hash),
+            item.node_id
+        );
+        self.get_data(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::MillisatAmount;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use std::str::FromStr;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const INVOICE: &str = "lnbc923720n1pj9nrefpp5pczykgk37af5388n8dzynljpkzs7sje4melqgazlwv9y3apay8jqhp5rd8saxz3juve3eejq7z5fjttxmpaq88d7l92xv34n4h3mq6kwq2qcqzzsxqzfvsp5z0jwpehkuz9f2kv96h62p8x30nku76aj8yddpcust7g8ad0tr52q9qyyssqfy622q25helv8cj8hyxqltws4rdwz0xx2hw0uh575mn7a76cp3q4jcptmtjkjs4a34dqqxn8uy70d0qlxqleezv4zp84uk30pp5q3nqq4c9gkz";
+
+    #[test]
+    fn test_index_payment() {
+        let test_name = "index_payment";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let invoice = Invoice::from_str(INVOICE).unwrap();
+        let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+        let labels = vec!["test".to_string()];
+        storage
+            .set_invoice_labels(invoice.clone(), labels.clone())
+            .unwrap();
+
+        let mut payment_info = PaymentInfo {
+            preimage: None,
+            secret: None,
+            status: HTLCStatus::Pending,
+            amt_msat: MillisatAmount(None),
+            fee_paid_msat: None,
+            bolt11: Some(invoice.clone()),
+            payee_pubkey: None,
+            lsp_pubkey: None,
+            claim_deadline: None,
+            last_update: 1690000000,
+        };
+        storage
+            .index_payment(&payment_hash, "node", true, &payment_info)
+            .unwrap();
+
+        let items = storage.list_activity_index().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].payment_hash, *invoice.payment_hash());
+        assert_eq!(items[0].amount_sats, Some(92_372));
+        assert_eq!(items[0].status, HTLCStatus::Pending);
+        assert_eq!(items[0].labels, labels);
+
+        // updating the payment keeps the labels
+        payment_info.status = HTLCStatus::Succeeded;
+        payment_info.last_update = 1690000100;
+        storage
+            .index_payment(&payment_hash, "node", true, &payment_info)
+            .unwrap();
+
+        let items = storage.list_activity_index().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].status, HTLCStatus::Succeeded);
+        assert_eq!(items[0].last_updated, 1690000100);
+        assert_eq!(items[0].labels, labels);
+
+        // updating the labels updates the index
+        let new_labels = vec!["new".to_string()];
+        storage
+            .set_invoice_labels(invoice, new_labels.clone())
+            .unwrap();
+
+        let items = storage.list_activity_index().unwrap();
+        assert_eq!(items[0].labels, new_labels);
+    }
+
+    #[test]
+    fn test_activity_filter() {
+        let test_name = "activity_filter";
+        log!("{}", test_name);
+
+        let labels = vec!["coffee".to_string()];
+        let item = ActivitySummary {
+            last_updated: Some(1690000000),
+            direction: Some(ActivityDirection::Inbound),
+            status: ActivityStatus::Succeeded,
+            amount_sats: Some(5_000),
+            labels: &labels,
+            description: Some("Morning Coffee"),
+        };
+
+        assert!(ActivityFilter::default().matches(&item));
+
+        let filter = ActivityFilter {
+            start_time: Some(1680000000),
+            end_time: Some(1700000000),
+            direction: Some(ActivityDirection::Inbound),
+            status: Some(ActivityStatus::Succeeded),
+            label: Some("coffee".to_string()),
+            contact: None,
+            min_amount_sats: Some(1_000),
+            max_amount_sats: Some(10_000),
+            text: Some("coffee".to_string()),
+        };
+        assert!(filter.matches(&item));
+
+        let filter = ActivityFilter {
+            start_time: Some(1695000000),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&item));
+
+        let filter = ActivityFilter {
+            direction: Some(ActivityDirection::Outbound),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&item));
+
+        let filter = ActivityFilter {
+            status: Some(ActivityStatus::Pending),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&item));
+
+        let filter = ActivityFilter {
+            contact: Some("contact-id".to_string()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&item));
+
+        let filter = ActivityFilter {
+            max_amount_sats: Some(1_000),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&item));
+
+        let filter = ActivityFilter {
+            text: Some("tea".to_string()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&item));
+    }
+}
@@ -1,4 +1,6 @@
 #![allow(dead_code)]
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::socks5::{http_client, Socks5Proxy};
 use crate::{
     error::MutinyError,
     lnurlauth::{make_lnurl_auth_connection, AuthManager},
@@ -28,6 +30,9 @@ pub struct MutinyAuthClient {
     http_client: Client,
     jwt: RwLock<Option<String>>,
     logger: Arc<MutinyLogger>,
+    /// The proxy all of our requests go through, if any
+    #[cfg(not(target_arch = "wasm32"))]
+    socks5_proxy: Option<Socks5Proxy>,
 }
 
 impl MutinyAuthClient {
@@ -45,9 +50,32 @@ impl MutinyAuthClient {
             http_client,
             jwt: RwLock::new(None),
             logger,
+            #[cfg(not(target_arch = "wasm32"))]
+            socks5_proxy: None,
         }
     }
 
+    /// Sends all of our requests, including the LNURL-auth ones, through the proxy.
+    /// In strict mode this fails unless the auth server is an onion service.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_socks5_proxy(mut self, proxy: Socks5Proxy) -> Result<Self, MutinyError> {
+        self.http_client = http_client(Some(&proxy), &self.url)?;
+        self.lnurl_client = Arc::new(
+            lnurl::Builder::default()
+                .proxy(&proxy.url())
+                .build_async()
+                .map_err(|_| MutinyError::LnUrlFailure)?,
+        );
+        self.socks5_proxy = Some(proxy);
+        Ok(self)
+    }
+
+    /// The proxy our requests go through, if any
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn socks5_proxy(&self) -> Option<&Socks5Proxy> {
+        self.socks5_proxy.as_ref()
+    }
+
     pub async fn authenticate(&self) -> Result<(), MutinyError> {
         self.retrieve_new_jwt().await?;
         Ok(())
@@ -118,7 +146,14 @@ impl MutinyAuthClient {
             .map_err(|_| MutinyError::LnUrlFailure)?;
         url.set_path("/v1/lnurlAuth");
 
-        let mut ws = WebSocketImpl::new(url.to_string()).await.map_err(|e| {
+        #[cfg(target_arch = "wasm32")]
+        let ws = WebSocketImpl::new(url.to_string()).await;
+        #[cfg(not(target_arch = "wasm32"))]
+        let ws = match self.socks5_proxy.as_ref() {
+            Some(proxy) => WebSocketImpl::new_with_proxy(url.to_string(), proxy).await,
+            None => WebSocketImpl::new(url.to_string()).await,
+        };
+        let mut ws = ws.map_err(|e| {
             log_error!(self.logger, "Error starting up auth ws: {e}");
             MutinyError::LnUrlFailure
         })?;
@@ -138,6 +173,12 @@ impl MutinyAuthClient {
             }
         };
 
+        // the server could give us a clearnet url to authenticate with
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(proxy) = self.socks5_proxy.as_ref() {
+            proxy.check_url(&lnurl.url)?;
+        }
+
         make_lnurl_auth_connection(
             self.auth.clone(),
             self.lnurl_client.clone(),
@@ -171,7 +212,11 @@ impl MutinyAuthClient {
 #[cfg(test)]
 mod tests {
     use super::MutinyAuthClient;
+    #[cfg(not(target_arch = "wasm32"))]
+    use crate::error::MutinyError;
     use crate::logging::MutinyLogger;
+    #[cfg(not(target_arch = "wasm32"))]
+    use crate::networking::socks5::Socks5Proxy;
     use crate::test_utils::*;
     use reqwest::{Method, Url};
     use std::sync::Arc;
@@ -215,4 +260,37 @@ mod tests {
             Err(e) => panic!("Request failed with error: {:?}", e),
         };
     }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_strict_proxy_refuses_clearnet_auth() {
+        let test_name = "test_strict_proxy_refuses_clearnet_auth";
+        log!("{}", test_name);
+
+        let lnurl_client = Arc::new(
+            lnurl::Builder::default()
+                .build_async()
+                .expect("failed to make lnurl client"),
+        );
+        let logger = Arc::new(MutinyLogger::default());
+        let proxy = Socks5Proxy::new("127.0.0.1:9050".to_string(), true);
+
+        let auth_client = MutinyAuthClient::new(
+            create_manager(),
+            lnurl_client.clone(),
+            logger.clone(),
+            "https://auth-staging.mutinywallet.com".to_string(),
+        );
+        assert!(matches!(
+            auth_client.with_socks5_proxy(proxy.clone()),
+            Err(MutinyError::ClearnetConnectionRefused)
+        ));
+
+        let onion = "http://mutinyv3xqy2yemmt6ajmq3v2kbm6ykbg7w6yhvqjbxuuhoxyvvqad.onion";
+        let auth_client =
+            MutinyAuthClient::new(create_manager(), lnurl_client, logger, onion.to_string())
+                .with_socks5_proxy(proxy.clone())
+                .unwrap();
+        assert_eq!(auth_client.socks5_proxy(), Some(&proxy));
+    }
 }
@@ -350,6 +350,12 @@ macro_rules! define_run_body {
 			if should_prune {
 				// The network graph must not be pruned while rapid sync completion is pending
 				if let Some(network_graph) = $gossip_sync.prunable_network_graph() {
+					// RGS snapshots already leave out stale channels, only a graph
+					// synced over P2P gossip needs them removed
+					if matches!($gossip_sync, GossipSync::P2P(_)) {
+						log_trace!($logger, "Pruning network graph.");
+						network_graph.remove_stale_channels_and_tracking_with_time(crate::utils::now().as_secs());
+					}
 					#[cfg(not(feature = "std"))] {
 						log_trace!($logger, "Persisting network graph.");
 					}
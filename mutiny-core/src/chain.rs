@@ -4,8 +4,8 @@ use crate::esplora::EsploraSyncClient;
 use bitcoin::{Script, Transaction, Txid};
 use lightning::chain::chaininterface::BroadcasterInterface;
 use lightning::chain::{Filter, WatchedOutput};
-use lightning::log_warn;
 use lightning::util::logger::Logger;
+use lightning::{log_debug, log_warn};
 
 use crate::logging::MutinyLogger;
 use crate::onchain::OnChainWallet;
@@ -46,6 +46,13 @@ impl<S: MutinyStorage> BroadcasterInterface for MutinyChain<S> {
     fn broadcast_transactions(&self, txs: &[&Transaction]) {
         let txs_clone = txs
             .iter()
+            .filter(|tx| {
+                let held = self.wallet.is_broadcast_held(&tx.txid());
+                if held {
+                    log_debug!(self.logger, "Holding back broadcast of {}", tx.txid());
+                }
+                !held
+            })
             .map(|tx| (*tx).clone())
             .collect::<Vec<Transaction>>();
         let wallet = self.wallet.clone();
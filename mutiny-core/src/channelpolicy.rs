@@ -0,0 +1,504 @@
+use crate::error::MutinyError;
+use crate::utils;
+use async_trait::async_trait;
+use bitcoin::secp256k1::PublicKey;
+use futures::future::{Either, LocalBoxFuture};
+use futures::pin_mut;
+use lightning::ln::channelmanager::{ChannelDetails, BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA};
+use lightning::util::config::{
+    ChannelConfig, ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig,
+};
+use serde::{Deserialize, Serialize};
+
+/// The most confirmations we will ask for before using a channel
+const MAX_MINIMUM_DEPTH: u32 = 144;
+/// The max number of rejected inbound channels we keep for each node
+pub(crate) const MAX_CHANNEL_REJECTIONS: usize = 50;
+/// The on-chain funds we keep for fee bumping each anchor channel at close time
+pub const ANCHOR_CHANNEL_RESERVE_SATS: u64 = 25_000;
+/// How long we wait on an [`InboundChannelApprover`] before rejecting the channel, in milliseconds.
+/// The peer gives up on the channel before long anyway.
+pub(crate) const INBOUND_CHANNEL_APPROVAL_TIMEOUT_MS: i32 = 60_000;
+
+/// How our nodes open, accept and forward over channels.
+///
+/// A policy can be set for all nodes in the `MutinyWalletConfig` and
+/// overridden per node, the node's policy is saved with its `NodeIndex`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelPolicy {
+    /// Confirmations needed before a channel can be used
+    pub minimum_depth: u32,
+    /// If our channels are announced to the network, otherwise they are private
+    pub announced_channels: bool,
+    /// The most we can lose to dust HTLCs on a channel, in millisatoshis
+    pub max_dust_htlc_exposure_msat: u64,
+    /// The most of the channel's value that can be in flight to us at once, in percent
+    pub max_inbound_htlc_value_in_flight_percent: u8,
+    /// The CLTV delta we need when forwarding a payment
+    pub cltv_expiry_delta: u16,
+    /// The base fee we charge to forward a payment, in millisatoshis
+    pub forwarding_fee_base_msat: u32,
+    /// The fee we charge to forward a payment, in millionths of the amount
+    pub forwarding_fee_proportional_millionths: u32,
+    /// The to_self_delay our peers have to wait for their funds if they force close
+    pub our_to_self_delay: u16,
+    /// The longest to_self_delay we will accept from a peer
+    pub max_their_to_self_delay: u16,
+    /// Negotiate anchor output channels, so we can bump their fees at close time
+    #[serde(default = "default_anchor_channels")]
+    pub anchor_channels: bool,
+    /// The on-chain funds we keep for fee bumping each anchor channel, in sats
+    #[serde(default = "default_anchor_reserve_sats")]
+    pub anchor_reserve_sats: u64,
+}
+
+fn default_anchor_channels() -> bool {
+    true
+}
+
+fn default_anchor_reserve_sats() -> u64 {
+    ANCHOR_CHANNEL_RESERVE_SATS
+}
+
+impl Default for ChannelPolicy {
+    fn default() -> Self {
+        Self {
+            minimum_depth: 1,
+            announced_channels: false,
+            // 20k sats, 4x more than normal due to high fee rates
+            // Any lightning payment above this, but below current
+            // HTLC fees will have issues paying on channels without
+            // anchor outputs, which we still have when there are no
+            // on-chain funds to reserve for fee bumping
+            max_dust_htlc_exposure_msat: 20_000_000,
+            max_inbound_htlc_value_in_flight_percent: 100,
+            cltv_expiry_delta: ChannelConfig::default().cltv_expiry_delta,
+            forwarding_fee_base_msat: ChannelConfig::default().forwarding_fee_base_msat,
+            forwarding_fee_proportional_millionths: ChannelConfig::default()
+                .forwarding_fee_proportional_millionths,
+            our_to_self_delay: BREAKDOWN_TIMEOUT,
+            // lnd's max to_self_delay is 2016, so we want to be compatible.
+            max_their_to_self_delay: 2016,
+            anchor_channels: default_anchor_channels(),
+            anchor_reserve_sats: default_anchor_reserve_sats(),
+        }
+    }
+}
+
+impl ChannelPolicy {
+    /// Checks the policy is one LDK and our peers will work with
+    pub fn validate(&self) -> Result<(), MutinyError> {
+        let invalid = |reason: &str| Err(MutinyError::InvalidChannelPolicy(reason.to_string()));
+
+        if self.minimum_depth == 0 || self.minimum_depth > MAX_MINIMUM_DEPTH {
+            return invalid("minimum_depth must be between 1 and 144");
+        }
+        if self.max_inbound_htlc_value_in_flight_percent == 0
+            || self.max_inbound_htlc_value_in_flight_percent > 100
+        {
+            return invalid("max_inbound_htlc_value_in_flight_percent must be between 1 and 100");
+        }
+        if self.cltv_expiry_delta < MIN_CLTV_EXPIRY_DELTA {
+            return invalid("cltv_expiry_delta is below the minimum");
+        }
+        if self.our_to_self_delay < BREAKDOWN_TIMEOUT {
+            return invalid("our_to_self_delay must be at least 144");
+        }
+        if self.max_their_to_self_delay < BREAKDOWN_TIMEOUT {
+            return invalid("max_their_to_self_delay must be at least 144");
+        }
+
+        Ok(())
+    }
+
+    /// The on-chain funds we need to keep to bump the fees of all of the channels
+    pub(crate) fn anchor_reserve_for(&self, channels: &[ChannelDetails]) -> u64 {
+        let anchor_channels = channels.iter().filter(|c| is_anchor_channel(c)).count() as u64;
+        anchor_channels * self.anchor_reserve_sats
+    }
+
+    /// The channel config for forwarding over our channels
+    pub(crate) fn channel_config(&self) -> ChannelConfig {
+        ChannelConfig {
+            max_dust_htlc_exposure_msat: self.max_dust_htlc_exposure_msat,
+            cltv_expiry_delta: self.cltv_expiry_delta,
+            forwarding_fee_base_msat: self.forwarding_fee_base_msat,
+            forwarding_fee_proportional_millionths: self.forwarding_fee_proportional_millionths,
+            // LSPS2 LSPs take their fee out of the payment that opens the JIT channel.
+            // We check the skimmed fee against the one we agreed to when the payment is claimable.
+            accept_underpaying_htlcs: true,
+            ..Default::default()
+        }
+    }
+
+    /// The config for the channel manager
+    pub(crate) fn user_config(&self) -> UserConfig {
+        UserConfig {
+            channel_handshake_limits: ChannelHandshakeLimits {
+                their_to_self_delay: self.max_their_to_self_delay,
+                ..Default::default()
+            },
+            channel_handshake_config: ChannelHandshakeConfig {
+                minimum_depth: self.minimum_depth,
+                our_to_self_delay: self.our_to_self_delay,
+                announced_channel: self.announced_channels,
+                // scid privacy is only for private channels
+                negotiate_scid_privacy: !self.announced_channels,
+                commit_upfront_shutdown_pubkey: false,
+                max_inbound_htlc_value_in_flight_percent_of_channel: self
+                    .max_inbound_htlc_value_in_flight_percent,
+                negotiate_anchors_zero_fee_htlc_tx: self.anchor_channels,
+                ..Default::default()
+            },
+            manually_accept_inbound_channels: true,
+            channel_config: self.channel_config(),
+            ..Default::default()
+        }
+    }
+}
+
+/// If the channel has anchor outputs we need to bump the fees of at close time
+pub(crate) fn is_anchor_channel(channel: &ChannelDetails) -> bool {
+    channel
+        .channel_type
+        .as_ref()
+        .is_some_and(|t| t.requires_anchors_zero_fee_htlc_tx())
+}
+
+/// Which inbound channels our nodes accept.
+///
+/// Channels from our LSPs are always accepted with zero confirmations,
+/// everything else has to pass this policy, then the
+/// [`InboundChannelApprover`] if there is one.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundChannelPolicy {
+    /// Only accept channels from these peers, if set
+    #[serde(default)]
+    pub allowed_peers: Option<Vec<PublicKey>>,
+    /// The smallest channel we accept, in sats
+    #[serde(default)]
+    pub min_channel_size_sats: Option<u64>,
+    /// The largest channel we accept, in sats
+    #[serde(default)]
+    pub max_channel_size_sats: Option<u64>,
+    /// Peers we trust enough to use their channels before they confirm.
+    /// These are accepted even if they are not in `allowed_peers`.
+    #[serde(default)]
+    pub zero_conf_peers: Vec<PublicKey>,
+}
+
+impl InboundChannelPolicy {
+    /// Checks the channel size limits make sense
+    pub fn validate(&self) -> Result<(), MutinyError> {
+        if let (Some(min), Some(max)) = (self.min_channel_size_sats, self.max_channel_size_sats) {
+            if min > max {
+                return Err(MutinyError::InvalidChannelPolicy(
+                    "min_channel_size_sats is larger than max_channel_size_sats".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check(&self, request: &InboundChannelRequest) -> InboundChannelDecision {
+        let zero_conf = self.zero_conf_peers.contains(&request.counterparty_node_id);
+
+        if let Some(allowed_peers) = self.allowed_peers.as_ref() {
+            if !zero_conf && !allowed_peers.contains(&request.counterparty_node_id) {
+                return InboundChannelDecision::Reject("peer is not allowed".to_string());
+            }
+        }
+        if let Some(min) = self.min_channel_size_sats {
+            if request.funding_satoshis < min {
+                return InboundChannelDecision::Reject(format!(
+                    "channel is smaller than the minimum of {min} sats"
+                ));
+            }
+        }
+        if let Some(max) = self.max_channel_size_sats {
+            if request.funding_satoshis > max {
+                return InboundChannelDecision::Reject(format!(
+                    "channel is larger than the maximum of {max} sats"
+                ));
+            }
+        }
+
+        if zero_conf {
+            InboundChannelDecision::AcceptZeroConf
+        } else {
+            InboundChannelDecision::Accept
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum InboundChannelDecision {
+    Accept,
+    AcceptZeroConf,
+    Reject(String),
+}
+
+/// A channel a peer wants to open with us
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundChannelRequest {
+    pub counterparty_node_id: PublicKey,
+    pub funding_satoshis: u64,
+    pub push_msat: u64,
+}
+
+/// Decides if we accept an inbound channel, after it passed the [`InboundChannelPolicy`].
+/// This can be used to ask the user.
+#[async_trait(?Send)]
+pub trait InboundChannelApprover {
+    /// Returns if the channel should be accepted
+    async fn approve(&self, request: &InboundChannelRequest) -> bool;
+}
+
+/// Asks the approver about the channel. Returns `None` if it did not answer within the timeout.
+pub(crate) async fn approve_with_timeout(
+    approver: &dyn InboundChannelApprover,
+    request: &InboundChannelRequest,
+    timeout_ms: i32,
+) -> Option<bool> {
+    let approve = approver.approve(request);
+    let timeout = utils::sleep(timeout_ms);
+    pin_mut!(approve);
+    pin_mut!(timeout);
+    match futures::future::select(approve, timeout).await {
+        Either::Left((approved, _)) => Some(approved),
+        Either::Right(_) => None,
+    }
+}
+
+/// An [`InboundChannelApprover`] that calls a function
+pub struct CallbackChannelApprover {
+    callback: Box<dyn Fn(InboundChannelRequest) -> LocalBoxFuture<'static, bool>>,
+}
+
+impl CallbackChannelApprover {
+    pub fn new(
+        callback: impl Fn(InboundChannelRequest) -> LocalBoxFuture<'static, bool> + 'static,
+    ) -> Self {
+        Self {
+            callback: Box::new(callback),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl InboundChannelApprover for CallbackChannelApprover {
+    async fn approve(&self, request: &InboundChannelRequest) -> bool {
+        (self.callback)(request.clone()).await
+    }
+}
+
+/// An inbound channel we rejected
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelRejection {
+    pub counterparty_node_id: PublicKey,
+    pub funding_satoshis: u64,
+    pub reason: String,
+    pub timestamp: u64,
+}
+
+impl ChannelRejection {
+    pub(crate) fn new(request: &InboundChannelRequest, reason: String) -> Self {
+        Self {
+            counterparty_node_id: request.counterparty_node_id,
+            funding_satoshis: request.funding_satoshis,
+            reason,
+            timestamp: utils::now().as_secs(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use std::str::FromStr;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_default_channel_policy() {
+        let test_name = "test_default_channel_policy";
+        log!("{}", test_name);
+
+        let policy = ChannelPolicy::default();
+        assert!(policy.validate().is_ok());
+
+        let config = policy.user_config();
+        assert_eq!(config.channel_handshake_config.minimum_depth, 1);
+        assert!(!config.channel_handshake_config.announced_channel);
+        assert!(config.channel_handshake_config.negotiate_scid_privacy);
+        assert_eq!(config.channel_handshake_limits.their_to_self_delay, 2016);
+        assert!(
+            config
+                .channel_handshake_config
+                .negotiate_anchors_zero_fee_htlc_tx
+        );
+        assert_eq!(
+            config.channel_config.max_dust_htlc_exposure_msat,
+            20_000_000
+        );
+        assert!(config.manually_accept_inbound_channels);
+
+        // policies saved before anchors default to using them
+        let mut json = serde_json::to_value(&policy).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.remove("anchor_channels");
+        fields.remove("anchor_reserve_sats");
+        let old: ChannelPolicy = serde_json::from_value(json).unwrap();
+        assert_eq!(old, policy);
+    }
+
+    #[test]
+    fn test_validate_channel_policy() {
+        let test_name = "test_validate_channel_policy";
+        log!("{}", test_name);
+
+        let invalid = [
+            ChannelPolicy {
+                minimum_depth: 0,
+                ..Default::default()
+            },
+            ChannelPolicy {
+                max_inbound_htlc_value_in_flight_percent: 101,
+                ..Default::default()
+            },
+            ChannelPolicy {
+                cltv_expiry_delta: 1,
+                ..Default::default()
+            },
+            ChannelPolicy {
+                our_to_self_delay: 10,
+                ..Default::default()
+            },
+        ];
+        for policy in invalid {
+            assert!(matches!(
+                policy.validate(),
+                Err(MutinyError::InvalidChannelPolicy(_))
+            ));
+        }
+
+        let announced = ChannelPolicy {
+            announced_channels: true,
+            minimum_depth: 6,
+            forwarding_fee_proportional_millionths: 100,
+            ..Default::default()
+        };
+        assert!(announced.validate().is_ok());
+        let config = announced.user_config();
+        assert!(config.channel_handshake_config.announced_channel);
+        assert!(!config.channel_handshake_config.negotiate_scid_privacy);
+        assert_eq!(
+            config.channel_config.forwarding_fee_proportional_millionths,
+            100
+        );
+    }
+
+    #[test]
+    fn test_inbound_channel_policy() {
+        let test_name = "test_inbound_channel_policy";
+        log!("{}", test_name);
+
+        let allowed = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let trusted = PublicKey::from_str(
+            "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+        )
+        .unwrap();
+        let stranger = PublicKey::from_str(
+            "02465ed5be53d04fde66c9418ff14a5f2267723810176c9212b722e542dc1afb1b",
+        )
+        .unwrap();
+        let request = |counterparty_node_id, funding_satoshis| InboundChannelRequest {
+            counterparty_node_id,
+            funding_satoshis,
+            push_msat: 0,
+        };
+
+        // accept everything by default
+        let policy = InboundChannelPolicy::default();
+        assert!(policy.validate().is_ok());
+        assert_eq!(
+            policy.check(&request(stranger, 1)),
+            InboundChannelDecision::Accept
+        );
+
+        let policy = InboundChannelPolicy {
+            allowed_peers: Some(vec![allowed]),
+            min_channel_size_sats: Some(100_000),
+            max_channel_size_sats: Some(1_000_000),
+            zero_conf_peers: vec![trusted],
+        };
+        assert!(policy.validate().is_ok());
+        assert_eq!(
+            policy.check(&request(allowed, 100_000)),
+            InboundChannelDecision::Accept
+        );
+        assert_eq!(
+            policy.check(&request(trusted, 100_000)),
+            InboundChannelDecision::AcceptZeroConf
+        );
+        assert!(matches!(
+            policy.check(&request(stranger, 100_000)),
+            InboundChannelDecision::Reject(_)
+        ));
+        assert!(matches!(
+            policy.check(&request(allowed, 99_999)),
+            InboundChannelDecision::Reject(_)
+        ));
+        assert!(matches!(
+            policy.check(&request(trusted, 1_000_001)),
+            InboundChannelDecision::Reject(_)
+        ));
+
+        let policy = InboundChannelPolicy {
+            min_channel_size_sats: Some(2),
+            max_channel_size_sats: Some(1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            policy.validate(),
+            Err(MutinyError::InvalidChannelPolicy(_))
+        ));
+    }
+
+    #[test]
+    async fn test_approve_with_timeout() {
+        let test_name = "test_approve_with_timeout";
+        log!("{}", test_name);
+
+        let request = InboundChannelRequest {
+            counterparty_node_id: PublicKey::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            funding_satoshis: 100_000,
+            push_msat: 0,
+        };
+
+        let approver = CallbackChannelApprover::new(|_| Box::pin(async { true }));
+        assert_eq!(
+            approve_with_timeout(&approver, &request, 1_000).await,
+            Some(true)
+        );
+
+        let approver = CallbackChannelApprover::new(|_| Box::pin(async { false }));
+        assert_eq!(
+            approve_with_timeout(&approver, &request, 1_000).await,
+            Some(false)
+        );
+
+        // an approver that never answers is given up on
+        let approver = CallbackChannelApprover::new(|_| Box::pin(futures::future::pending()));
+        assert_eq!(approve_with_timeout(&approver, &request, 10).await, None);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::channelpolicy::ChannelRejection;
+use crate::error::MutinyError;
+use crate::event::{HTLCStatus, PaymentInfo};
+use crate::logging::{LogRecord, REDACTED};
+use crate::node::Node;
+use crate::nodemanager::{ChannelClosure, NodeIndex};
+use crate::storage::MutinyStorage;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Network, OutPoint};
+use lightning::ln::channelmanager::ChannelDetails;
+use lightning::ln::PaymentHash;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The max number of failed payments included for each node
+const RECENT_FAILED_PAYMENTS_LIMIT: usize = 25;
+/// The max number of log records included in the bundle
+pub(crate) const RECENT_LOGS_LIMIT: usize = 500;
+
+/// Bolt11 invoices are longer than this, shorter `ln` words are left alone
+const MIN_INVOICE_LEN: usize = 100;
+
+/// A snapshot of the wallet's state to help debug issues like stuck payments.
+///
+/// This does not contain any secrets, preimages, payment secrets and invoices
+/// are left out, logs are redacted before they are saved and invoices in them
+/// are redacted when they are bundled, so it is safe to attach to a support ticket.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DiagnosticsBundle {
+    /// When the bundle was created, in seconds since the epoch
+    pub created_at: u64,
+    pub network: Network,
+    pub nodes: Vec<NodeDiagnostics>,
+    pub channel_closures: Vec<ChannelClosure>,
+    /// The last time the network graph was synced with RGS, in seconds since the epoch
+    pub last_gossip_sync: Option<u32>,
+    pub fee_estimates: FeeEstimates,
+    /// The number of keys in storage, grouped by key prefix
+    pub storage_key_counts: BTreeMap<String, usize>,
+    /// The most recent log records, oldest first
+    pub logs: Vec<LogRecord>,
+}
+
+/// Fee estimates in sats per 1000 weight units
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeeEstimates {
+    pub background: u32,
+    pub normal: u32,
+    pub high_priority: u32,
+}
+
+/// The state of a single node, nodes that are not running only have their index
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NodeDiagnostics {
+    pub uuid: String,
+    pub child_index: u32,
+    pub lsp: Option<String>,
+    pub archived: bool,
+    pub running: bool,
+    pub pubkey: Option<PublicKey>,
+    pub best_block_height: Option<u32>,
+    pub channels: Vec<ChannelDiagnostics>,
+    /// Funds that are waiting to be claimed on-chain from closed channels
+    pub force_close_balance_sats: u64,
+    /// Outbound payments that are in flight and inbound payments that are held
+    pub pending_htlcs: Vec<PaymentDiagnostics>,
+    /// The most recent failed payments, newest first
+    pub recent_failed_payments: Vec<PaymentDiagnostics>,
+    /// The most recent inbound channels we rejected, oldest first
+    pub channel_rejections: Vec<ChannelRejection>,
+}
+
+impl NodeDiagnostics {
+    pub(crate) fn new(uuid: String, index: &NodeIndex) -> Self {
+        Self {
+            uuid,
+            child_index: index.child_index,
+            lsp: index.lsp.clone(),
+            archived: index.is_archived(),
+            running: false,
+            pubkey: None,
+            best_block_height: None,
+            channels: vec![],
+            force_close_balance_sats: 0,
+            pending_htlcs: vec![],
+            recent_failed_payments: vec![],
+            channel_rejections: vec![],
+        }
+    }
+
+    /// Adds the channels and payments of a running node
+    pub(crate) fn add_node_state<S: MutinyStorage>(
+        &mut self,
+        node: &Node<S>,
+    ) -> Result<(), MutinyError> {
+        self.running = true;
+        self.pubkey = Some(node.pubkey);
+        self.best_block_height = Some(node.channel_manager.current_best_block().height());
+
+        let channels = node.channel_manager.list_channels();
+        self.channels = channels.iter().map(ChannelDiagnostics::from).collect();
+
+        let ignored_channels: Vec<&ChannelDetails> = channels.iter().collect();
+        self.force_close_balance_sats = node
+            .chain_monitor
+            .get_claimable_balances(&ignored_channels)
+            .iter()
+            .map(|b| b.claimable_amount_satoshis())
+            .sum();
+
+        let mut payments: Vec<PaymentDiagnostics> = vec![];
+        for inbound in [true, false] {
+            payments.extend(
+                node.persister
+                    .list_payment_info(inbound)?
+                    .into_iter()
+                    .map(|(hash, info)| PaymentDiagnostics::new(&hash, inbound, &info)),
+            );
+        }
+        payments.sort_by(|a, b| b.last_update.cmp(&a.last_update));
+
+        self.pending_htlcs = payments
+            .iter()
+            .filter(|p| matches!(p.status, HTLCStatus::InFlight | HTLCStatus::Held))
+            .cloned()
+            .collect();
+        self.recent_failed_payments = payments
+            .into_iter()
+            .filter(|p| p.status == HTLCStatus::Failed)
+            .take(RECENT_FAILED_PAYMENTS_LIMIT)
+            .collect();
+
+        self.channel_rejections = node.persister.list_channel_rejections()?;
+
+        Ok(())
+    }
+}
+
+/// The state of a channel, taken from LDK's [`ChannelDetails`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelDiagnostics {
+    pub channel_id: String,
+    pub user_channel_id: String,
+    pub peer: PublicKey,
+    pub funding_txo: Option<OutPoint>,
+    pub short_channel_id: Option<u64>,
+    pub size_sats: u64,
+    pub balance_msat: u64,
+    pub outbound_capacity_msat: u64,
+    pub inbound_capacity_msat: u64,
+    pub next_outbound_htlc_limit_msat: u64,
+    pub reserve_sats: Option<u64>,
+    pub feerate_sat_per_1000_weight: Option<u32>,
+    pub confirmations: Option<u32>,
+    pub confirmations_required: Option<u32>,
+    pub is_outbound: bool,
+    pub is_channel_ready: bool,
+    pub is_usable: bool,
+    pub is_public: bool,
+}
+
+impl From<&ChannelDetails> for ChannelDiagnostics {
+    fn from(c: &ChannelDetails) -> Self {
+        ChannelDiagnostics {
+            channel_id: c.channel_id.to_hex(),
+            user_channel_id: c.user_channel_id.to_hex(),
+            peer: c.counterparty.node_id,
+            funding_txo: c.funding_txo.map(|f| f.into_bitcoin_outpoint()),
+            short_channel_id: c.short_channel_id,
+            size_sats: c.channel_value_satoshis,
+            balance_msat: c.balance_msat,
+            outbound_capacity_msat: c.outbound_capacity_msat,
+            inbound_capacity_msat: c.inbound_capacity_msat,
+            next_outbound_htlc_limit_msat: c.next_outbound_htlc_limit_msat,
+            reserve_sats: c.unspendable_punishment_reserve,
+            feerate_sat_per_1000_weight: c.feerate_sat_per_1000_weight,
+            confirmations: c.confirmations,
+            confirmations_required: c.confirmations_required,
+            is_outbound: c.is_outbound,
+            is_channel_ready: c.is_channel_ready,
+            is_usable: c.is_usable,
+            is_public: c.is_public,
+        }
+    }
+}
+
+/// A payment without any of its secrets
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PaymentDiagnostics {
+    pub payment_hash: String,
+    pub inbound: bool,
+    pub status: HTLCStatus,
+    pub amount_msat: Option<u64>,
+    pub fee_paid_msat: Option<u64>,
+    pub payee_pubkey: Option<PublicKey>,
+    pub claim_deadline: Option<u32>,
+    pub last_update: u64,
+}
+
+impl PaymentDiagnostics {
+    pub(crate) fn new(payment_hash: &PaymentHash, inbound: bool, info: &PaymentInfo) -> Self {
+        Self {
+            payment_hash: payment_hash.0.to_hex(),
+            inbound,
+            status: info.status.clone(),
+            amount_msat: info.amt_msat.0,
+            fee_paid_msat: info.fee_paid_msat,
+            payee_pubkey: info.payee_pubkey,
+            claim_deadline: info.claim_deadline,
+            last_update: info.last_update,
+        }
+    }
+}
+
+/// Counts the keys in storage, grouped by their prefix. Keys that belong to a
+/// node have the node's uuid removed so they are grouped together.
+pub(crate) fn storage_key_counts<S: MutinyStorage>(
+    storage: &S,
+    node_uuids: &[String],
+) -> Result<BTreeMap<String, usize>, MutinyError> {
+    let mut counts = BTreeMap::new();
+    for key in storage.scan_keys("", None)? {
+        *counts
+            .entry(storage_key_category(&key, node_uuids))
+            .or_insert(0) += 1;
+    }
+    Ok(counts)
+}
+
+fn storage_key_category(key: &str, node_uuids: &[String]) -> String {
+    if let Some(index) = key.find('/') {
+        return key[..=index].to_string();
+    }
+
+    node_uuids
+        .iter()
+        .find_map(|uuid| key.strip_suffix(&format!("_{uuid}")))
+        .unwrap_or(key)
+        .to_string()
+}
+
+/// Redacts anything that looks like a bolt11 invoice from a log message,
+/// a long bech32 word starting with `ln`, like `lnbc` or `lntb`.
+pub(crate) fn redact_invoices(message: &str) -> String {
+    // ascii lowercase keeps the byte indexes the same as the original
+    let lowercase = message.to_ascii_lowercase();
+    let bytes = lowercase.as_bytes();
+    let mut result = String::with_capacity(message.len());
+    let mut position = 0;
+    let mut search = 0;
+    while let Some(found) = lowercase[search..].find("ln") {
+        let start = search + found;
+        let end = lowercase[start..]
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .map_or(lowercase.len(), |i| start + i);
+        let word_start = start == 0 || !bytes[start - 1].is_ascii_alphanumeric();
+        if word_start && end - start > MIN_INVOICE_LEN && lowercase[start..end].contains('1') {
+            result.push_str(&message[position..start]);
+            result.push_str(REDACTED);
+            position = end;
+        }
+        search = end;
+    }
+    result.push_str(&message[position..]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::MillisatAmount;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_storage_key_counts() {
+        let test_name = "storage_key_counts";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let uuid = "a1b2c3d4".to_string();
+        let keys = [
+            format!("manager_{uuid}"),
+            format!("payment_inbound/0000_{uuid}"),
+            format!("payment_inbound/1111_{uuid}"),
+            format!("monitors/0000:0_{uuid}"),
+            "nodes".to_string(),
+            "last_sync_timestamp".to_string(),
+        ];
+        for key in keys.iter() {
+            storage.set_data(key, 1, None).unwrap();
+        }
+
+        let counts = storage_key_counts(&storage, &[uuid]).unwrap();
+        assert_eq!(counts.get("manager"), Some(&1));
+        assert_eq!(counts.get("payment_inbound/"), Some(&2));
+        assert_eq!(counts.get("monitors/"), Some(&1));
+        assert_eq!(counts.get("nodes"), Some(&1));
+        assert_eq!(counts.get("last_sync_timestamp"), Some(&1));
+    }
+
+    #[test]
+    fn test_redact_invoices() {
+        let test_name = "redact_invoices";
+        log!("{}", test_name);
+
+        let invoice = format!("lnbc1{}", "q".repeat(200));
+        let redacted = redact_invoices(&format!("paying invoice: {invoice}, attempt 1"));
+        assert_eq!(redacted, "paying invoice: [REDACTED], attempt 1");
+
+        let redacted = redact_invoices(&format!("uri lightning:{}", invoice.to_uppercase()));
+        assert_eq!(redacted, "uri lightning:[REDACTED]");
+
+        // words that only start with ln are unchanged
+        let message = format!("lnurl, unlnbc1{} and {}", "q".repeat(200), "ln1q");
+        assert_eq!(redact_invoices(&message), message);
+    }
+
+    #[test]
+    fn test_payment_diagnostics_has_no_secrets() {
+        let test_name = "payment_diagnostics_has_no_secrets";
+        log!("{}", test_name);
+
+        let preimage = [1; 32];
+        let secret = [2; 32];
+        let info = PaymentInfo {
+            preimage: Some(preimage),
+            secret: Some(secret),
+            status: HTLCStatus::Failed,
+            amt_msat: MillisatAmount(Some(1_000)),
+            fee_paid_msat: None,
+            bolt11: None,
+            payee_pubkey: None,
+            lsp_pubkey: None,
+            claim_deadline: None,
+            last_update: 1690000000,
+        };
+        let payment_hash = PaymentHash([0u8; 32]);
+
+        let payment = PaymentDiagnostics::new(&payment_hash, true, &info);
+        assert_eq!(payment.payment_hash, [0u8; 32].to_hex());
+        assert_eq!(payment.status, HTLCStatus::Failed);
+        assert_eq!(payment.amount_msat, Some(1_000));
+
+        let json = serde_json::to_string(&payment).unwrap();
+        assert!(!json.contains(&preimage.to_hex()));
+        assert!(!json.contains(&secret.to_hex()));
+    }
+}
@@ -28,6 +28,9 @@ pub enum MutinyError {
     /// A network connection has been closed.
     #[error("Network connection closed.")]
     ConnectionFailed,
+    /// The proxy is in strict mode and the destination is not an onion service.
+    #[error("Refused to make a clearnet connection in strict proxy mode.")]
+    ClearnetConnectionRefused,
     /// The invoice or address is on a different network
     #[error("The invoice or address is on a different network.")]
     IncorrectNetwork(Network),
@@ -65,12 +68,42 @@ pub enum MutinyError {
     /// LSP indicated it was not connected to the client node.
     #[error("Failed to have a connection to the LSP node.")]
     LspConnectionError,
+    /// The LSP's fee is higher than the max fee configured for it.
+    #[error("Failed to request channel from LSP due to fee being above the configured max.")]
+    LspFeeTooHighError,
+    /// The LSP's fee went up too much since the invoice was quoted.
+    #[error("Failed to create invoice, the LSP's fee changed since the quote.")]
+    LspQuoteChangedError,
+    /// No swap provider is configured.
+    #[error("Swap provider not configured.")]
+    SwapProviderNotConfigured,
+    /// The swap provider could not be reached or gave an invalid response.
+    #[error("Failed to make a request to the swap provider.")]
+    SwapProviderError,
+    /// The HTLC or invoice the swap provider gave does not match the swap.
+    #[error("The swap provider's HTLC does not match the swap.")]
+    SwapHtlcMismatch,
+    /// The swap provider's fee is higher than the max fee given.
+    #[error("The swap provider's fee is above the max fee.")]
+    SwapFeeTooHighError,
+    /// The fee of paying on-chain from lightning went up since the quote.
+    #[error("Failed to pay on-chain, the fee went up since the quote.")]
+    PayOnchainQuoteChangedError,
     /// Subscription Client Not Configured
     #[error("Subscription Client Not Configured")]
     SubscriptionClientNotConfigured,
     /// Invalid Arguments were given
     #[error("Invalid Arguments were given")]
     InvalidArgumentsError,
+    /// The channel policy is not one we can use
+    #[error("Invalid channel policy: {0}")]
+    InvalidChannelPolicy(String),
+    /// Not enough on-chain funds would be left to fee bump our anchor channels
+    #[error("Not enough on-chain funds would be left to fee bump our anchor channels.")]
+    AnchorReserveRequired,
+    /// The channel backup is too big to fit in a peer storage message
+    #[error("The channel backup is too large for peer storage.")]
+    PeerStorageTooLarge,
     /// No route for the given target could be found.
     #[error("Failed to find route.")]
     RoutingFailed,
@@ -301,6 +334,12 @@ impl<G> From<std::sync::PoisonError<G>> for MutinyStorageError {
     }
 }
 
+impl<G> From<std::sync::PoisonError<G>> for MutinyError {
+    fn from(_e: std::sync::PoisonError<G>) -> Self {
+        MutinyStorageError::LockError.into()
+    }
+}
+
 impl<G> From<std::sync::TryLockError<G>> for MutinyError {
     fn from(_e: std::sync::TryLockError<G>) -> Self {
         MutinyStorageError::LockError.into()
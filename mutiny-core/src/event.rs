@@ -1,22 +1,34 @@
+use crate::chain::MutinyChain;
+use crate::channelpolicy::{
+    approve_with_timeout, ChannelPolicy, ChannelRejection, InboundChannelApprover,
+    InboundChannelDecision, InboundChannelPolicy, InboundChannelRequest,
+    INBOUND_CHANNEL_APPROVAL_TIMEOUT_MS,
+};
 use crate::fees::MutinyFeeEstimator;
 use crate::keymanager::PhantomKeysManager;
-use crate::ldkstorage::{MutinyNodePersister, PhantomChannelManager};
+use crate::ldkstorage::{
+    ChannelBatch, ChannelBatchMemberState, ChannelMetadata, MutinyNodePersister,
+    PhantomChannelManager,
+};
 use crate::logging::MutinyLogger;
 use crate::nodemanager::ChannelClosure;
 use crate::onchain::OnChainWallet;
 use crate::redshift::RedshiftStorage;
+use crate::scb::backup::request_scb_backup;
 use crate::storage::MutinyStorage;
-use crate::utils::sleep;
+use crate::utils::{self, sleep};
 use anyhow::anyhow;
 use bitcoin::hashes::hex::ToHex;
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::secp256k1::Secp256k1;
-use lightning::events::{Event, PaymentPurpose};
+use bitcoin::{Transaction, TxOut};
+use lightning::events::bump_transaction::{BumpTransactionEventHandler, Wallet};
+use lightning::events::{ClosureReason, Event, PaymentPurpose};
+use lightning::ln::{PaymentHash, PaymentSecret};
 use lightning::sign::SpendableOutputDescriptor;
 use lightning::{
     chain::chaininterface::{ConfirmationTarget, FeeEstimator},
     log_debug, log_error, log_info, log_warn,
-    util::errors::APIError,
     util::logger::Logger,
 };
 use lightning_invoice::Invoice;
@@ -38,6 +50,13 @@ pub(crate) struct PaymentInfo {
     pub bolt11: Option<Invoice>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub payee_pubkey: Option<PublicKey>,
+    /// The LSP used to create the invoice, for inbound payments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lsp_pubkey: Option<PublicKey>,
+    /// The block height at which a held payment will be failed back by LDK.
+    /// Only set for hold invoices that have received a payment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claim_deadline: Option<u32>,
     pub last_update: u64,
 }
 
@@ -51,13 +70,22 @@ impl MillisatAmount {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(crate) enum HTLCStatus {
+pub enum HTLCStatus {
     Pending,
+    /// A payment for a hold invoice has arrived and is waiting to be settled or cancelled
+    Held,
     InFlight,
     Succeeded,
     Failed,
 }
 
+pub(crate) type BumpTxEventHandler<S: MutinyStorage> = BumpTransactionEventHandler<
+    Arc<MutinyChain<S>>,
+    Arc<Wallet<Arc<OnChainWallet<S>>, Arc<MutinyLogger>>>,
+    Arc<PhantomKeysManager<S>>,
+    Arc<MutinyLogger>,
+>;
+
 #[derive(Clone)]
 pub struct EventHandler<S: MutinyStorage> {
     channel_manager: Arc<PhantomChannelManager<S>>,
@@ -65,18 +93,30 @@ pub struct EventHandler<S: MutinyStorage> {
     wallet: Arc<OnChainWallet<S>>,
     keys_manager: Arc<PhantomKeysManager<S>>,
     persister: Arc<MutinyNodePersister<S>>,
-    lsp_client_pubkey: Option<PublicKey>,
+    /// The LSPs we accept zero-conf channels from
+    lsp_client_pubkeys: Vec<PublicKey>,
+    inbound_channel_policy: InboundChannelPolicy,
+    inbound_channel_approver: Option<Arc<dyn InboundChannelApprover>>,
+    /// Used for the on-chain reserve of anchor channels
+    channel_policy: ChannelPolicy,
+    /// Fee bumps the transactions of our anchor channels with our on-chain funds
+    bump_tx_event_handler: Arc<BumpTxEventHandler<S>>,
     logger: Arc<MutinyLogger>,
 }
 
 impl<S: MutinyStorage> EventHandler<S> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         channel_manager: Arc<PhantomChannelManager<S>>,
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
         wallet: Arc<OnChainWallet<S>>,
         keys_manager: Arc<PhantomKeysManager<S>>,
         persister: Arc<MutinyNodePersister<S>>,
-        lsp_client_pubkey: Option<PublicKey>,
+        lsp_client_pubkeys: Vec<PublicKey>,
+        inbound_channel_policy: InboundChannelPolicy,
+        inbound_channel_approver: Option<Arc<dyn InboundChannelApprover>>,
+        channel_policy: ChannelPolicy,
+        bump_tx_event_handler: Arc<BumpTxEventHandler<S>>,
         logger: Arc<MutinyLogger>,
     ) -> Self {
         Self {
@@ -84,12 +124,229 @@ impl<S: MutinyStorage> EventHandler<S> {
             fee_estimator,
             wallet,
             keys_manager,
-            lsp_client_pubkey,
+            lsp_client_pubkeys,
+            inbound_channel_policy,
+            inbound_channel_approver,
+            channel_policy,
+            bump_tx_event_handler,
             persister,
             logger,
         }
     }
 
+    fn accept_inbound_channel(
+        &self,
+        temporary_channel_id: &[u8; 32],
+        request: &InboundChannelRequest,
+        zero_conf: bool,
+    ) {
+        let mut internal_channel_id_bytes = [0u8; 16];
+        if getrandom::getrandom(&mut internal_channel_id_bytes).is_err() {
+            log_debug!(
+                self.logger,
+                "EVENT: OpenChannelRequest failed random number generation"
+            );
+        };
+        let internal_channel_id = u128::from_be_bytes(internal_channel_id_bytes);
+
+        let result = if zero_conf {
+            self.channel_manager
+                .accept_inbound_channel_from_trusted_peer_0conf(
+                    temporary_channel_id,
+                    &request.counterparty_node_id,
+                    internal_channel_id,
+                )
+        } else {
+            self.channel_manager.accept_inbound_channel(
+                temporary_channel_id,
+                &request.counterparty_node_id,
+                internal_channel_id,
+            )
+        };
+
+        match result {
+            Ok(_) => log_debug!(self.logger, "EVENT: OpenChannelRequest accepted"),
+            Err(e) => log_debug!(self.logger, "EVENT: OpenChannelRequest error: {e:?}"),
+        }
+    }
+
+    /// Funds every channel in the batch with a single transaction once LDK has
+    /// given us the funding output for all of them.
+    fn fund_channel_batch(&self, batch: &[u128], sats_per_vbyte: f32) {
+        let mut members = Vec::with_capacity(batch.len());
+        for id in batch {
+            match self.persister.get_channel_open_params(*id) {
+                Ok(Some(params)) => match params.funding_output {
+                    Some(output) => members.push((*id, output)),
+                    None => {
+                        log_debug!(self.logger, "Waiting for the rest of the channel batch");
+                        return;
+                    }
+                },
+                _ => {
+                    log_error!(self.logger, "ERROR: Channel {id} of batch failed to open");
+                    abort_channel_batch(
+                        &self.channel_manager,
+                        &self.persister,
+                        &self.wallet,
+                        batch,
+                        &self.logger,
+                    );
+                    return;
+                }
+            }
+        }
+
+        let channels = self.channel_manager.list_channels();
+        let mut funding = Vec::with_capacity(members.len());
+        for (id, _) in members.iter() {
+            match channels.iter().find(|c| c.user_channel_id == *id) {
+                Some(c) => funding.push((c.channel_id, c.counterparty.node_id)),
+                None => {
+                    log_error!(self.logger, "ERROR: Could not find channel {id} of batch");
+                    abort_channel_batch(
+                        &self.channel_manager,
+                        &self.persister,
+                        &self.wallet,
+                        batch,
+                        &self.logger,
+                    );
+                    return;
+                }
+            }
+        }
+
+        let outputs = members
+            .iter()
+            .map(|(_, output)| (output.script_pubkey.clone(), output.value))
+            .collect();
+        let psbt = match self
+            .wallet
+            .create_signed_psbt_to_outputs(outputs, Some(sats_per_vbyte))
+        {
+            Ok(psbt) => psbt,
+            Err(e) => {
+                log_error!(
+                    self.logger,
+                    "ERROR: Could not create a signed transaction to open channel batch with: {e}"
+                );
+                abort_channel_batch(
+                    &self.channel_manager,
+                    &self.persister,
+                    &self.wallet,
+                    batch,
+                    &self.logger,
+                );
+                return;
+            }
+        };
+
+        let labels = funding
+            .iter()
+            .map(|(_, node_id)| format!("LN Channel: {}", node_id.to_hex()))
+            .collect();
+        if let Err(e) = self.wallet.label_psbt(&psbt, labels) {
+            log_warn!(
+                self.logger,
+                "ERROR: Could not label PSBT, but continuing: {e}"
+            );
+        }
+
+        let tx = psbt.extract_tx();
+
+        // LDK broadcasts the transaction as soon as one peer signs for their channel,
+        // so we hold it back until every channel in the batch is signed
+        let mut state = channel_batch_state(&self.persister, batch);
+        if state.is_aborted() {
+            log_error!(
+                self.logger,
+                "ERROR: Channel batch was aborted before it was funded"
+            );
+            abort_channel_batch(
+                &self.channel_manager,
+                &self.persister,
+                &self.wallet,
+                batch,
+                &self.logger,
+            );
+            return;
+        }
+        state.funding_tx = Some(tx.clone());
+        if let Err(e) = self.persister.persist_channel_batch(&state) {
+            log_error!(
+                self.logger,
+                "ERROR: Could not save channel batch funding transaction: {e}"
+            );
+            abort_channel_batch(
+                &self.channel_manager,
+                &self.persister,
+                &self.wallet,
+                batch,
+                &self.logger,
+            );
+            return;
+        }
+        self.wallet.hold_broadcast(tx.txid());
+
+        for (temporary_channel_id, counterparty_node_id) in funding {
+            if let Err(e) = self.channel_manager.funding_transaction_generated(
+                &temporary_channel_id,
+                &counterparty_node_id,
+                tx.clone(),
+            ) {
+                log_error!(
+                    self.logger,
+                    "ERROR: Could not send funding transaction of channel batch to channel manager: {e:?}"
+                );
+                abort_channel_batch(
+                    &self.channel_manager,
+                    &self.persister,
+                    &self.wallet,
+                    batch,
+                    &self.logger,
+                );
+                return;
+            }
+        }
+
+        log_info!(self.logger, "EVENT: FundingGenerationReady batch success");
+    }
+
+    /// If we have the on-chain funds to fee bump another anchor channel
+    fn can_reserve_anchor_channel(&self) -> bool {
+        self.wallet
+            .can_reserve_anchors(0, self.channel_policy.anchor_reserve_sats)
+            .unwrap_or(false)
+    }
+
+    fn reject_inbound_channel(
+        &self,
+        temporary_channel_id: &[u8; 32],
+        request: &InboundChannelRequest,
+        reason: String,
+    ) {
+        log_info!(
+            self.logger,
+            "EVENT: OpenChannelRequest from {} rejected: {reason}",
+            request.counterparty_node_id
+        );
+
+        if let Err(e) = self.channel_manager.force_close_without_broadcasting_txn(
+            temporary_channel_id,
+            &request.counterparty_node_id,
+        ) {
+            log_error!(self.logger, "EVENT: OpenChannelRequest reject error: {e:?}");
+        }
+
+        let rejection = ChannelRejection::new(request, reason);
+        if let Err(e) = self.persister.persist_channel_rejection(rejection) {
+            log_error!(
+                self.logger,
+                "ERROR: could not persist channel rejection: {e}"
+            );
+        }
+    }
+
     pub async fn handle_event(&self, event: Event) {
         match event {
             Event::FundingGenerationReady {
@@ -110,6 +367,35 @@ impl<S: MutinyStorage> EventHandler<S> {
                     }
                 };
 
+                // channels opened together wait for each other and share one funding transaction
+                if let Some(mut params) = params_opt.clone().filter(|p| p.batch.is_some()) {
+                    params.funding_output = Some(TxOut {
+                        value: channel_value_satoshis,
+                        script_pubkey: output_script,
+                    });
+                    let batch = params.batch.clone().unwrap_or_default();
+                    if let Err(e) = self
+                        .persister
+                        .persist_channel_open_params(user_channel_id, params.clone())
+                    {
+                        log_error!(
+                            self.logger,
+                            "ERROR: Could not save channel funding output: {e}"
+                        );
+                        abort_channel_batch(
+                            &self.channel_manager,
+                            &self.persister,
+                            &self.wallet,
+                            &batch,
+                            &self.logger,
+                        );
+                        return;
+                    }
+
+                    self.fund_channel_batch(&batch, params.sats_per_vbyte);
+                    return;
+                }
+
                 let psbt_result = match &params_opt {
                     None => {
                         log_warn!(
@@ -201,19 +487,49 @@ impl<S: MutinyStorage> EventHandler<S> {
                 payment_hash,
                 purpose,
                 amount_msat,
+                counterparty_skimmed_fee_msat,
+                claim_deadline,
                 ..
             } => {
                 log_debug!(self.logger, "EVENT: PaymentReceived received payment from payment hash {} of {amount_msat} millisatoshis to {receiver_node_id:?}", payment_hash.0.to_hex());
 
-                if let Some(payment_preimage) = match purpose {
+                // An LSPS2 LSP takes its channel opening fee out of the payment,
+                // make sure it didn't take more than the fee we agreed to.
+                if counterparty_skimmed_fee_msat > 0 {
+                    let agreed_fee_msat = self
+                        .persister
+                        .read_payment_info(&payment_hash, true, &self.logger)
+                        .and_then(|info| info.fee_paid_msat)
+                        .unwrap_or(0);
+                    if counterparty_skimmed_fee_msat > agreed_fee_msat {
+                        log_error!(self.logger, "ERROR: LSP skimmed {counterparty_skimmed_fee_msat} msats from payment, expected at most {agreed_fee_msat} msats, failing payment");
+                        self.channel_manager.fail_htlc_backwards(&payment_hash);
+                        return;
+                    }
+                }
+
+                match purpose {
                     PaymentPurpose::InvoicePayment {
-                        payment_preimage, ..
-                    } => payment_preimage,
-                    PaymentPurpose::SpontaneousPayment(preimage) => Some(preimage),
-                } {
-                    self.channel_manager.claim_funds(payment_preimage);
-                } else {
-                    log_error!(self.logger, "ERROR: No payment preimage found");
+                        payment_preimage: Some(payment_preimage),
+                        ..
+                    }
+                    | PaymentPurpose::SpontaneousPayment(payment_preimage) => {
+                        self.channel_manager.claim_funds(payment_preimage);
+                    }
+                    PaymentPurpose::InvoicePayment {
+                        payment_preimage: None,
+                        payment_secret,
+                    } => {
+                        // we don't know the preimage, this is a hold invoice
+                        // so we hold the payment until it is settled or cancelled
+                        self.hold_payment(
+                            payment_hash,
+                            payment_secret,
+                            amount_msat,
+                            receiver_node_id,
+                            claim_deadline,
+                        );
+                    }
                 };
             }
             Event::PaymentClaimed {
@@ -240,7 +556,10 @@ impl<S: MutinyStorage> EventHandler<S> {
                         let payment_preimage = payment_preimage.map(|p| p.0);
                         let payment_secret = payment_secret.map(|p| p.0);
                         saved_payment_info.status = HTLCStatus::Succeeded;
-                        saved_payment_info.preimage = payment_preimage;
+                        // a hold invoice was settled with a preimage we already saved
+                        if payment_preimage.is_some() {
+                            saved_payment_info.preimage = payment_preimage;
+                        }
                         saved_payment_info.secret = payment_secret;
                         saved_payment_info.amt_msat = MillisatAmount(Some(amount_msat));
                         saved_payment_info.last_update = crate::utils::now().as_secs();
@@ -268,7 +587,9 @@ impl<S: MutinyStorage> EventHandler<S> {
                             amt_msat: MillisatAmount(Some(amount_msat)),
                             fee_paid_msat: None,
                             payee_pubkey: receiver_node_id,
+                            lsp_pubkey: None,
                             bolt11: None,
+                            claim_deadline: None,
                             last_update,
                         };
                         match self.persister.persist_payment_info(
@@ -330,6 +651,9 @@ impl<S: MutinyStorage> EventHandler<S> {
             Event::OpenChannelRequest {
                 temporary_channel_id,
                 counterparty_node_id,
+                funding_satoshis,
+                push_msat,
+                channel_type,
                 ..
             } => {
                 log_debug!(
@@ -337,38 +661,73 @@ impl<S: MutinyStorage> EventHandler<S> {
                     "EVENT: OpenChannelRequest incoming: {counterparty_node_id}"
                 );
 
-                let mut internal_channel_id_bytes = [0u8; 16];
-                if getrandom::getrandom(&mut internal_channel_id_bytes).is_err() {
-                    log_debug!(
-                        self.logger,
-                        "EVENT: OpenChannelRequest failed random number generation"
-                    );
+                let request = InboundChannelRequest {
+                    counterparty_node_id,
+                    funding_satoshis,
+                    push_msat,
                 };
-                let internal_channel_id = u128::from_be_bytes(internal_channel_id_bytes);
 
-                let log_result = |result: Result<(), APIError>| match result {
-                    Ok(_) => log_debug!(self.logger, "EVENT: OpenChannelRequest accepted"),
-                    Err(e) => log_debug!(self.logger, "EVENT: OpenChannelRequest error: {e:?}"),
-                };
+                // our LSP opens the first channel of a new wallet that has no on-chain
+                // funds yet, so we trust it with anchor channels without a reserve
+                if self.lsp_client_pubkeys.contains(&counterparty_node_id) {
+                    // matched lsp pubkey, accept 0 conf
+                    self.accept_inbound_channel(&temporary_channel_id, &request, true);
+                    return;
+                }
 
-                if self.lsp_client_pubkey.as_ref() != Some(&counterparty_node_id) {
-                    // did not match the lsp pubkey, normal open
-                    let result = self.channel_manager.accept_inbound_channel(
+                // anyone else's anchor channel could need us to bump its fees
+                if channel_type.requires_anchors_zero_fee_htlc_tx()
+                    && !self.can_reserve_anchor_channel()
+                {
+                    self.reject_inbound_channel(
                         &temporary_channel_id,
-                        &counterparty_node_id,
-                        internal_channel_id,
+                        &request,
+                        "not enough on-chain funds to reserve for an anchor channel".to_string(),
                     );
-                    log_result(result);
-                } else {
-                    // matched lsp pubkey, accept 0 conf
-                    let result = self
-                        .channel_manager
-                        .accept_inbound_channel_from_trusted_peer_0conf(
-                            &temporary_channel_id,
-                            &counterparty_node_id,
-                            internal_channel_id,
-                        );
-                    log_result(result);
+                    return;
+                }
+
+                let zero_conf = match self.inbound_channel_policy.check(&request) {
+                    InboundChannelDecision::Accept => false,
+                    InboundChannelDecision::AcceptZeroConf => true,
+                    InboundChannelDecision::Reject(reason) => {
+                        self.reject_inbound_channel(&temporary_channel_id, &request, reason);
+                        return;
+                    }
+                };
+
+                match self.inbound_channel_approver.clone() {
+                    None => self.accept_inbound_channel(&temporary_channel_id, &request, zero_conf),
+                    Some(approver) => {
+                        // ask in the background so we don't block other events,
+                        // this could be waiting on the user
+                        let handler = self.clone();
+                        utils::spawn(async move {
+                            let approved = approve_with_timeout(
+                                approver.as_ref(),
+                                &request,
+                                INBOUND_CHANNEL_APPROVAL_TIMEOUT_MS,
+                            )
+                            .await;
+                            match approved {
+                                Some(true) => handler.accept_inbound_channel(
+                                    &temporary_channel_id,
+                                    &request,
+                                    zero_conf,
+                                ),
+                                Some(false) => handler.reject_inbound_channel(
+                                    &temporary_channel_id,
+                                    &request,
+                                    "not approved".to_string(),
+                                ),
+                                None => handler.reject_inbound_channel(
+                                    &temporary_channel_id,
+                                    &request,
+                                    "timed out waiting for approval".to_string(),
+                                ),
+                            }
+                        });
+                    }
                 }
             }
             Event::PaymentPathSuccessful { .. } => {
@@ -454,7 +813,18 @@ impl<S: MutinyStorage> EventHandler<S> {
             } => {
                 // if we still have channel open params, then it was just a failed channel open
                 // we should not persist this as a closed channel and just delete the channel open params
-                if let Ok(Some(_)) = self.persister.get_channel_open_params(user_channel_id) {
+                if let Ok(Some(params)) = self.persister.get_channel_open_params(user_channel_id) {
+                    // a batch is funded by one transaction, so if one channel fails they all do
+                    if let Some(batch) = params.batch {
+                        abort_channel_batch(
+                            &self.channel_manager,
+                            &self.persister,
+                            &self.wallet,
+                            &batch,
+                            &self.logger,
+                        );
+                    }
+
                     let _ = self.persister.delete_channel_open_params(user_channel_id);
                     return;
                 };
@@ -476,13 +846,39 @@ impl<S: MutinyStorage> EventHandler<S> {
                     }
                 });
 
-                let closure = ChannelClosure::new(user_channel_id, channel_id, node_id, reason);
+                // the destination is only used when the channel closes cooperatively,
+                // if it was force closed after all our funds went to our wallet
+                let cooperative = matches!(reason, ClosureReason::CooperativeClosure);
+                let mut closure = ChannelClosure::new(user_channel_id, channel_id, node_id, reason);
+                match self.persister.get_channel_close_params(user_channel_id) {
+                    Ok(Some(params)) => {
+                        if cooperative {
+                            closure.destination = params.destination;
+                        }
+                        let _ = self.persister.delete_channel_close_params(user_channel_id);
+                    }
+                    Ok(None) => {}
+                    Err(e) => log_error!(self.logger, "Failed to get channel close params: {e}"),
+                }
+                match self.persister.get_channel_metadata(user_channel_id) {
+                    Ok(Some(metadata)) => {
+                        closure.label = metadata.label;
+                        closure.note = metadata.note;
+                        let _ = self.persister.delete_channel_metadata(user_channel_id);
+                    }
+                    Ok(None) => {}
+                    Err(e) => log_error!(self.logger, "Failed to get channel metadata: {e}"),
+                }
                 if let Err(e) = self
                     .persister
                     .persist_channel_closure(user_channel_id, closure)
                 {
                     log_error!(self.logger, "Failed to persist channel closure: {e}");
                 }
+
+                if let Err(e) = request_scb_backup(&self.persister.storage) {
+                    log_error!(self.logger, "Failed to request channel backup: {e}");
+                }
             }
             Event::DiscardFunding { .. } => {
                 // A "real" node should probably "lock" the UTXOs spent in funding transactions until
@@ -525,6 +921,10 @@ impl<S: MutinyStorage> EventHandler<S> {
                         }
                     }
                 }
+
+                if let Err(e) = request_scb_backup(&self.persister.storage) {
+                    log_error!(self.logger, "Failed to request channel backup: {e}");
+                }
             }
             Event::ChannelPending {
                 channel_id,
@@ -539,15 +939,127 @@ impl<S: MutinyStorage> EventHandler<S> {
                     user_channel_id,
                     counterparty_node_id.to_hex());
 
+                let params = match self.persister.get_channel_open_params(user_channel_id) {
+                    Ok(params) => params,
+                    Err(e) => {
+                        log_error!(self.logger, "ERROR: Could not get channel open params: {e}");
+                        None
+                    }
+                };
+
+                // keep the label and note for the lifetime of the channel
+                if let Some(params) = params.as_ref() {
+                    let metadata = ChannelMetadata::from(params);
+                    if !metadata.is_empty() {
+                        if let Err(e) = self
+                            .persister
+                            .persist_channel_metadata(user_channel_id, metadata)
+                        {
+                            log_error!(self.logger, "ERROR: Could not save channel metadata: {e}");
+                        }
+                    }
+                }
+
                 if let Err(e) = self.persister.delete_channel_open_params(user_channel_id) {
                     log_warn!(
                         self.logger,
                         "ERROR: Could not delete channel open params, but continuing: {e}"
                     );
                 }
+
+                // the funding transaction of a batch goes out once every channel is signed
+                if let Some(batch) = params.and_then(|p| p.batch) {
+                    let mut state = channel_batch_state(&self.persister, &batch);
+                    state.set_state(user_channel_id, ChannelBatchMemberState::Signed);
+                    if let Err(e) = self.persister.persist_channel_batch(&state) {
+                        log_error!(self.logger, "ERROR: Could not save channel batch: {e}");
+                    }
+
+                    if state.is_aborted() {
+                        log_error!(
+                            self.logger,
+                            "ERROR: Channel of an aborted batch is pending, not broadcasting its funding transaction"
+                        );
+                    } else if let Some(tx) = state.funding_tx.filter(|_| state.is_signed()) {
+                        log_info!(self.logger, "EVENT: ChannelPending batch fully signed");
+                        if let Err(e) = self.wallet.release_broadcast(tx).await {
+                            log_error!(
+                                self.logger,
+                                "ERROR: Could not broadcast channel batch funding transaction: {e}"
+                            );
+                        }
+                    }
+                }
+
+                // back up the new channel before it is ready, in case we lose our data
+                if let Err(e) = request_scb_backup(&self.persister.storage) {
+                    log_error!(self.logger, "Failed to request channel backup: {e}");
+                }
             }
             Event::HTLCIntercepted { .. } => {}
-            Event::BumpTransaction(_) => {} // we do not support anchors
+            Event::BumpTransaction(event) => {
+                log_debug!(self.logger, "EVENT: BumpTransaction");
+                self.bump_tx_event_handler.handle_event(&event);
+            }
+        }
+    }
+
+    /// Holds a payment for a hold invoice we don't know the preimage of,
+    /// until it is settled or cancelled. Fails it back if it was already cancelled.
+    fn hold_payment(
+        &self,
+        payment_hash: PaymentHash,
+        payment_secret: PaymentSecret,
+        amount_msat: u64,
+        receiver_node_id: Option<PublicKey>,
+        claim_deadline: Option<u32>,
+    ) {
+        let last_update = crate::utils::now().as_secs();
+        let payment_info = match self
+            .persister
+            .read_payment_info(&payment_hash, true, &self.logger)
+        {
+            Some(saved_payment_info) if saved_payment_info.status == HTLCStatus::Failed => {
+                // the hold invoice was cancelled before the payment arrived
+                log_info!(
+                    self.logger,
+                    "Failing back payment for cancelled hold invoice: {}",
+                    payment_hash.0.to_hex()
+                );
+                self.channel_manager.fail_htlc_backwards(&payment_hash);
+                return;
+            }
+            Some(mut saved_payment_info) => {
+                saved_payment_info.status = HTLCStatus::Held;
+                saved_payment_info.amt_msat = MillisatAmount(Some(amount_msat));
+                saved_payment_info.claim_deadline = claim_deadline;
+                saved_payment_info.last_update = last_update;
+                saved_payment_info
+            }
+            None => PaymentInfo {
+                preimage: None,
+                secret: Some(payment_secret.0),
+                status: HTLCStatus::Held,
+                amt_msat: MillisatAmount(Some(amount_msat)),
+                fee_paid_msat: None,
+                payee_pubkey: receiver_node_id,
+                lsp_pubkey: None,
+                bolt11: None,
+                claim_deadline,
+                last_update,
+            },
+        };
+
+        match self
+            .persister
+            .persist_payment_info(&payment_hash, &payment_info, true)
+        {
+            Ok(_) => log_info!(
+                self.logger,
+                "Holding payment for hold invoice: {}, claim deadline: {claim_deadline:?}",
+                payment_hash.0.to_hex()
+            ),
+            Err(e) => log_error!(self.logger, "ERROR: could not persist payment info: {e}"),
         }
     }
 
@@ -600,12 +1112,79 @@ impl<S: MutinyStorage> EventHandler<S> {
     }
 }
 
+/// Aborts a batch of channels that is still waiting for one of its peers to sign,
+/// by force closing every channel in it without broadcasting anything. The channels
+/// that were not signed yet are marked aborted, so the funding transaction is never
+/// released and no funds are locked up. Their open params are cleaned up by the
+/// `ChannelClosed` events.
+pub(crate) fn abort_channel_batch<S: MutinyStorage>(
+    channel_manager: &PhantomChannelManager<S>,
+    persister: &MutinyNodePersister<S>,
+    wallet: &OnChainWallet<S>,
+    batch: &[u128],
+    logger: &MutinyLogger,
+) {
+    // once every channel is signed the funding transaction is out, too late to abort
+    let mut state = channel_batch_state(persister, batch);
+    if state.is_signed() {
+        return;
+    }
+
+    for (_, member) in state.members.iter_mut() {
+        if *member != ChannelBatchMemberState::Signed {
+            *member = ChannelBatchMemberState::Aborted;
+        }
+    }
+    if let Err(e) = persister.persist_channel_batch(&state) {
+        log_error!(logger, "ERROR: Could not save aborted channel batch: {e}");
+    }
+
+    let channels = channel_manager.list_channels();
+    for channel in channels
+        .iter()
+        .filter(|c| batch.contains(&c.user_channel_id))
+    {
+        if let Err(e) = channel_manager.force_close_without_broadcasting_txn(
+            &channel.channel_id,
+            &channel.counterparty.node_id,
+        ) {
+            log_error!(
+                logger,
+                "ERROR: Could not abort channel {} of batch: {e:?}",
+                channel.user_channel_id
+            );
+        }
+    }
+
+    if let Some(tx) = state.funding_tx {
+        wallet.cancel_broadcast(&tx.txid());
+    }
+}
+
+/// The saved state of a batch of channels, or a new one if it was never saved
+pub(crate) fn channel_batch_state<S: MutinyStorage>(
+    persister: &MutinyNodePersister<S>,
+    batch: &[u128],
+) -> ChannelBatch {
+    persister
+        .get_channel_batch(batch)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| ChannelBatch::new(batch.to_vec()))
+}
+
 #[cfg(test)]
 mod test {
-    use crate::event::{HTLCStatus, MillisatAmount, PaymentInfo};
+    use crate::event::{channel_batch_state, HTLCStatus, MillisatAmount, PaymentInfo};
+    use crate::ldkstorage::{ChannelBatch, ChannelBatchMemberState, MutinyNodePersister};
+    use crate::logging::MutinyLogger;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
     use crate::utils;
     use bitcoin::secp256k1::PublicKey;
+    use bitcoin::{PackedLockTime, Transaction};
     use std::str::FromStr;
+    use std::sync::Arc;
 
     use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
     wasm_bindgen_test_configure!(run_in_browser);
@@ -625,7 +1204,9 @@ mod test {
             fee_paid_msat: None,
             bolt11: None,
             payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
             secret: None,
+            claim_deadline: None,
             last_update: utils::now().as_secs(),
         };
 
@@ -637,4 +1218,60 @@ mod test {
         let deserialized: PaymentInfo = serde_json::from_value(serialized).unwrap();
         assert_eq!(payment_info, deserialized);
     }
+
+    #[test]
+    fn test_channel_batch_state() {
+        let test_name = "channel_batch_state";
+        log!("{}", test_name);
+
+        let persister = MutinyNodePersister::new(
+            "test".to_string(),
+            MemoryStorage::default(),
+            Arc::new(MutinyLogger::default()),
+        );
+        let batch: Vec<u128> = vec![1, 2, 3];
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+
+        // never saved, every channel is waiting on its peer
+        let mut state = channel_batch_state(&persister, &batch);
+        assert_eq!(state, ChannelBatch::new(batch.clone()));
+        assert!(!state.is_signed());
+        assert!(!state.is_aborted());
+
+        // funded, waiting on the peers to sign
+        state.funding_tx = Some(tx.clone());
+        persister.persist_channel_batch(&state).unwrap();
+        assert_eq!(channel_batch_state(&persister, &batch), state);
+
+        // some of the channels are signed, the funding transaction must still be held
+        state.set_state(batch[0], ChannelBatchMemberState::Signed);
+        state.set_state(batch[1], ChannelBatchMemberState::Signed);
+        persister.persist_channel_batch(&state).unwrap();
+        let state = channel_batch_state(&persister, &batch);
+        assert!(!state.is_signed());
+        assert_eq!(state.funding_tx, Some(tx.clone()));
+
+        // the last one signed, the batch can be broadcast
+        let mut signed = state.clone();
+        signed.set_state(batch[2], ChannelBatchMemberState::Signed);
+        assert!(signed.is_signed());
+        assert!(!signed.is_aborted());
+
+        // one channel signed and another one failed, a late signature must not release it
+        let mut state = ChannelBatch::new(batch.clone());
+        state.funding_tx = Some(tx);
+        state.set_state(batch[0], ChannelBatchMemberState::Signed);
+        state.set_state(batch[1], ChannelBatchMemberState::Aborted);
+        persister.persist_channel_batch(&state).unwrap();
+
+        let mut state = channel_batch_state(&persister, &batch);
+        state.set_state(batch[2], ChannelBatchMemberState::Signed);
+        assert!(state.is_aborted());
+        assert!(!state.is_signed());
+    }
 }
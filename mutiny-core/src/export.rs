@@ -0,0 +1,384 @@
+use crate::nodemanager::ActivityItem;
+use crate::swaps::SwapType;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::Address;
+use chrono::{TimeZone, Utc};
+use lightning_invoice::Invoice;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Header of the activity CSV export
+pub(crate) const ACTIVITY_CSV_HEADER: &str =
+    "date,type,direction,amount_sats,fee_sats,fiat_value_usd,labels,id";
+
+/// The type of a [BIP-329](https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki) label record
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Bip329Type {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+    /// Not part of BIP-329, used for lightning invoice labels.
+    /// Other wallets will ignore these records.
+    Invoice,
+    /// Any type we do not know about, these are ignored on import
+    #[serde(other)]
+    Unknown,
+}
+
+/// A single BIP-329 label record, exported as one line of JSONL
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Bip329Label {
+    #[serde(rename = "type")]
+    pub label_type: Bip329Type,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Bip329Label {
+    pub(crate) fn new(label_type: Bip329Type, reference: String, labels: &[String]) -> Self {
+        Bip329Label {
+            label_type,
+            reference,
+            label: Some(join_labels(labels)),
+            origin: None,
+            spendable: None,
+        }
+    }
+
+    /// BIP-329 only has a single label per record, we store multiple
+    /// labels as a comma separated list. See [`join_labels`].
+    pub(crate) fn labels(&self) -> Vec<String> {
+        split_labels(self.label.as_deref().unwrap_or_default())
+    }
+}
+
+/// What the labels of an imported BIP-329 record are set on
+pub(crate) enum Bip329Target {
+    /// Our addresses from an address record, or the outputs of a transaction record
+    Addresses(Vec<Address>),
+    Invoice(Invoice),
+}
+
+/// Joins labels into a single comma separated label. Commas and backslashes
+/// inside a label are escaped with a backslash so [`split_labels`] gets back
+/// exactly the same labels.
+fn join_labels(labels: &[String]) -> String {
+    labels
+        .iter()
+        .map(|l| l.replace('\\', "\\\\").replace(',', "\\,"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Splits a label made by [`join_labels`] on its unescaped commas
+fn split_labels(label: &str) -> Vec<String> {
+    let mut labels = vec![];
+    let mut current = String::new();
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            ',' => labels.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    labels.push(current);
+
+    labels
+        .into_iter()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// The bitcoin price in USD at the given unix time, from a list of
+/// `(unix time, price)` sorted by time. Only a price from within a day
+/// of the time is used, otherwise there is none.
+pub(crate) fn price_at(prices: &[(u64, f32)], time: u64) -> Option<f32> {
+    let index = prices.partition_point(|(t, _)| *t <= time);
+    let before = index.checked_sub(1).and_then(|i| prices.get(i));
+    let after = prices.get(index);
+
+    [before, after]
+        .into_iter()
+        .flatten()
+        .min_by_key(|(t, _)| t.abs_diff(time))
+        .filter(|(t, _)| t.abs_diff(time) <= 24 * 60 * 60)
+        .map(|(_, price)| *price)
+}
+
+/// Escapes a field for a CSV file, quoting it if needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Creates a row for the activity CSV export. Channel closures do not move
+/// funds by themselves so they are not included.
+///
+/// Contact ids in the labels are replaced with the contact's name. The fiat value
+/// uses the bitcoin price at the time of the item, from `historical_prices`, see [`price_at`].
+/// Items that are not confirmed yet use the current price.
+pub(crate) fn activity_csv_row(
+    item: &ActivityItem,
+    contact_names: &HashMap<String, String>,
+    historical_prices: &[(u64, f32)],
+    current_price: Option<f32>,
+) -> Option<String> {
+    let (kind, inbound, amount_sats, fee_sats, id) = match item {
+        ActivityItem::OnChain(tx) => {
+            let inbound = tx.received > tx.sent;
+            let amount_sats = if inbound {
+                tx.received - tx.sent
+            } else {
+                tx.sent - tx.received
+            };
+            // we only pay the fee for transactions we send
+            let fee = if inbound { None } else { tx.fee };
+            ("onchain", inbound, Some(amount_sats), fee, tx.txid.to_hex())
+        }
+        ActivityItem::Lightning(ln) => {
+            let fee = if ln.inbound { None } else { ln.fees_paid };
+            (
+                "lightning",
+                ln.inbound,
+                ln.amount_sats,
+                fee,
+                ln.payment_hash.to_hex(),
+            )
+        }
+        ActivityItem::ChannelClosed(_) => return None,
+        ActivityItem::Rebalance(r) => (
+            "rebalance",
+            false,
+            Some(r.amount_sats),
+            r.fees_paid,
+            r.payment_hash.to_hex(),
+        ),
+        // a loop in pays us on lightning, a loop out pays us on-chain
+        ActivityItem::Swap(s) => (
+            "swap",
+            s.swap_type == SwapType::LoopIn,
+            Some(s.amount_received_sats()),
+            s.fees_paid,
+            s.id.clone(),
+        ),
+    };
+
+    // unconfirmed transactions don't have a time yet
+    let time = item.last_updated();
+    let date = time
+        .and_then(|time| Utc.timestamp_opt(time as i64, 0).single())
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_default();
+
+    let direction = if inbound { "inbound" } else { "outbound" };
+
+    let bitcoin_price = match time {
+        Some(time) => price_at(historical_prices, time),
+        None => current_price,
+    };
+    let fiat_value = match (amount_sats, bitcoin_price) {
+        (Some(amount), Some(price)) => {
+            format!("{:.2}", amount as f64 / 100_000_000.0 * price as f64)
+        }
+        _ => String::new(),
+    };
+
+    let labels = item
+        .labels()
+        .into_iter()
+        .map(|l| contact_names.get(&l).cloned().unwrap_or(l))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let fields = [
+        date,
+        kind.to_string(),
+        direction.to_string(),
+        amount_sats.map(|a| a.to_string()).unwrap_or_default(),
+        fee_sats.map(|f| f.to_string()).unwrap_or_default(),
+        fiat_value,
+        labels,
+        id,
+    ];
+
+    Some(
+        fields
+            .iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nodemanager::{MutinyInvoice, TransactionDetails};
+    use crate::test_utils::*;
+    use bdk::chain::ConfirmationTime;
+    use bitcoin::hashes::sha256;
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+    use std::str::FromStr;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_bip329_serialization() {
+        let test_name = "bip329_serialization";
+        log!("{}", test_name);
+
+        let label = Bip329Label::new(
+            Bip329Type::Addr,
+            "bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c".to_string(),
+            &["Address".to_string(), "Savings".to_string()],
+        );
+        let json = serde_json::to_string(&label).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"Address, Savings"}"#
+        );
+
+        let parsed: Bip329Label = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, label);
+        assert_eq!(
+            parsed.labels(),
+            vec!["Address".to_string(), "Savings".to_string()]
+        );
+
+        // labels with commas in them come back the same
+        let labels = vec![
+            "rent, june".to_string(),
+            "back\\slash".to_string(),
+            "Savings".to_string(),
+        ];
+        let label = Bip329Label::new(Bip329Type::Tx, "abc".to_string(), &labels);
+        assert_eq!(
+            label.label.as_deref(),
+            Some(r"rent\, june, back\\slash, Savings")
+        );
+        let json = serde_json::to_string(&label).unwrap();
+        let parsed: Bip329Label = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.labels(), labels);
+
+        // unknown types are still parsed so they can be skipped
+        let parsed: Bip329Label = serde_json::from_str(
+            r#"{"type":"something","ref":"abc","label":"test","origin":"wpkh([d34db33f/84'/0'/0'])"}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.label_type, Bip329Type::Unknown);
+    }
+
+    #[test]
+    fn test_price_at() {
+        let test_name = "price_at";
+        log!("{}", test_name);
+
+        let prices = vec![(1_000, 10.0), (90_000, 20.0), (200_000, 30.0)];
+
+        assert_eq!(price_at(&prices, 1_000), Some(10.0));
+        assert_eq!(price_at(&prices, 40_000), Some(10.0));
+        assert_eq!(price_at(&prices, 60_000), Some(20.0));
+        assert_eq!(price_at(&prices, 0), Some(10.0));
+        // too far from any price
+        assert_eq!(price_at(&prices, 400_000), None);
+        assert_eq!(price_at(&[], 1_000), None);
+    }
+
+    #[test]
+    fn test_activity_csv_row() {
+        let test_name = "activity_csv_row";
+        log!("{}", test_name);
+
+        let txid =
+            Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16")
+                .unwrap();
+        let tx = TransactionDetails {
+            transaction: None,
+            txid,
+            received: 0,
+            sent: 100_000,
+            fee: Some(500),
+            confirmation_time: ConfirmationTime::Confirmed {
+                height: 800_000,
+                time: 1690000000,
+            },
+            labels: vec!["contact-id".to_string(), "rent, june".to_string()],
+        };
+
+        let contact_names =
+            HashMap::from([("contact-id".to_string(), "Alice \"Landlord\"".to_string())]);
+
+        // the price at the time of the transaction is used, not the current one
+        let prices = vec![(1689990000, 30_000.0), (1700000000, 40_000.0)];
+        let row = activity_csv_row(
+            &ActivityItem::OnChain(tx.clone()),
+            &contact_names,
+            &prices,
+            Some(50_000.0),
+        )
+        .unwrap();
+        assert_eq!(
+            row,
+            format!("2023-07-22T04:26:40+00:00,onchain,outbound,100000,500,30.00,\"Alice \"\"Landlord\"\", rent, june\",{txid}")
+        );
+
+        // without a historical price the fiat value is left empty
+        let row = activity_csv_row(
+            &ActivityItem::OnChain(tx),
+            &contact_names,
+            &[],
+            Some(50_000.0),
+        )
+        .unwrap();
+        assert_eq!(
+            row,
+            format!("2023-07-22T04:26:40+00:00,onchain,outbound,100000,500,,\"Alice \"\"Landlord\"\", rent, june\",{txid}")
+        );
+
+        let payment_hash = sha256::Hash::hash(&[0; 32]);
+        let invoice = MutinyInvoice {
+            bolt11: None,
+            description: None,
+            payment_hash,
+            preimage: None,
+            payee_pubkey: None,
+            lsp_pubkey: None,
+            amount_sats: Some(1_000),
+            expire: 1690000000,
+            paid: true,
+            held: false,
+            fees_paid: Some(1),
+            inbound: true,
+            labels: vec![],
+            last_updated: 1690000000,
+        };
+        let row = activity_csv_row(
+            &ActivityItem::Lightning(Box::new(invoice)),
+            &HashMap::new(),
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            row,
+            format!("2023-07-22T04:26:40+00:00,lightning,inbound,1000,,,,{payment_hash}")
+        );
+    }
+}
@@ -1,25 +1,34 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use bdk_esplora::esplora_client::AsyncClient;
 use bitcoin::hashes::hex::{FromHex, ToHex};
-use bitcoin::Network;
+use bitcoin::{BlockHash, Network, TxOut};
 use lightning::routing::gossip::NodeId;
+use lightning::routing::utxo::{UtxoFuture, UtxoLookup, UtxoLookupError, UtxoResult};
 use lightning::util::logger::Logger;
 use lightning::util::ser::{ReadableArgs, Writeable};
 use lightning::{
-    ln::msgs::NodeAnnouncement, routing::scoring::ProbabilisticScoringDecayParameters,
+    ln::msgs::{NetAddress, NodeAnnouncement},
+    routing::scoring::ProbabilisticScoringDecayParameters,
 };
-use lightning::{log_debug, log_error, log_info, log_warn};
+use lightning::{log_debug, log_error, log_info, log_trace, log_warn};
+#[cfg(target_arch = "wasm32")]
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 use crate::error::MutinyError;
 use crate::logging::MutinyLogger;
-use crate::node::{NetworkGraph, ProbScorer, RapidGossipSync};
+use crate::node::{NetworkGraph, P2PGossipSync, ProbScorer, RapidGossipSync};
 use crate::storage::MutinyStorage;
 use crate::utils;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::socks5::{http_client, Socks5Proxy};
+
 pub(crate) const LN_PEER_METADATA_KEY_PREFIX: &str = "ln_peer/";
 pub const GOSSIP_SYNC_TIME_KEY: &str = "last_sync_timestamp";
 pub const NETWORK_GRAPH_KEY: &str = "network_graph";
@@ -45,11 +54,8 @@ async fn get_gossip_data(
     storage: &impl MutinyStorage,
     logger: Arc<MutinyLogger>,
 ) -> Result<Option<Gossip>, MutinyError> {
-    // Get the `last_sync_timestamp`
-    let last_sync_timestamp: u32 = match storage.get_data(GOSSIP_SYNC_TIME_KEY)? {
-        Some(last_sync_timestamp) => last_sync_timestamp,
-        None => return Ok(None),
-    };
+    // Get the `last_sync_timestamp`, a graph synced over P2P gossip won't have one
+    let last_sync_timestamp: u32 = storage.get_data(GOSSIP_SYNC_TIME_KEY)?.unwrap_or(0);
 
     // Get the `network_graph`
     let network_graph: Arc<NetworkGraph> = match storage.get_data::<String>(NETWORK_GRAPH_KEY)? {
@@ -112,10 +118,16 @@ fn write_gossip_data(
     Ok(())
 }
 
+/// Loads our network graph and scorer, updating the graph from the RGS server.
+///
+/// When the graph is synced over P2P gossip instead the RGS server is not used,
+/// so we don't have to trust it.
 pub async fn get_gossip_sync(
     storage: &impl MutinyStorage,
     user_rgs_url: Option<String>,
     network: Network,
+    #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<&Socks5Proxy>,
+    #[cfg(not(target_arch = "wasm32"))] p2p_gossip: bool,
     logger: Arc<MutinyLogger>,
 ) -> Result<(RapidGossipSync, ProbScorer), MutinyError> {
     // if we error out, we just use the default gossip data
@@ -148,8 +160,15 @@ pub async fn get_gossip_sync(
         }
     };
 
-    if let Some(rgs_url) = get_rgs_url(network, user_rgs_url, Some(gossip_data.last_sync_timestamp))
-    {
+    #[cfg(target_arch = "wasm32")]
+    let p2p_gossip = false;
+    let rgs_url = if p2p_gossip {
+        None
+    } else {
+        get_rgs_url(network, user_rgs_url, Some(gossip_data.last_sync_timestamp))
+    };
+
+    if let Some(rgs_url) = rgs_url {
         log_info!(&logger, "RGS URL: {}", rgs_url);
 
         let now = utils::now().as_secs();
@@ -159,6 +178,8 @@ pub async fn get_gossip_sync(
             gossip_data.last_sync_timestamp,
             &gossip_sync,
             storage,
+            #[cfg(not(target_arch = "wasm32"))]
+            socks5_proxy,
             &logger,
         )
         .await;
@@ -180,11 +201,15 @@ async fn fetch_updated_gossip(
     last_sync_timestamp: u32,
     gossip_sync: &RapidGossipSync,
     storage: &impl MutinyStorage,
+    #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<&Socks5Proxy>,
     logger: &MutinyLogger,
 ) -> Result<(), MutinyError> {
+    #[cfg(target_arch = "wasm32")]
     let http_client = Client::builder()
         .build()
         .map_err(|_| MutinyError::RapidGossipSyncError)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    let http_client = http_client(socks5_proxy, &rgs_url)?;
     let rgs_response = http_client
         .get(rgs_url)
         .send()
@@ -233,9 +258,74 @@ pub struct LnPeerMetadata {
     /// Our nodes' uuids that are connected to this node
     #[serde(default)]
     pub nodes: Vec<String>,
+    /// The addresses given from the node announcement, tried
+    /// when we can't connect with the connection string
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    /// How our connections to this node have gone, by the uuid of our node that connected.
+    /// Each of our nodes connects on its own, so one node's failures don't hold back the others.
+    #[serde(default)]
+    pub connection_histories: HashMap<String, PeerConnectionHistory>,
+}
+
+/// How our connections to a peer have gone, times are in seconds since the epoch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct PeerConnectionHistory {
+    /// The last time we connected to the peer
+    pub last_connected: Option<u64>,
+    /// The last time we failed to connect to the peer
+    pub last_failure: Option<u64>,
+    /// How many times in a row we have failed to connect
+    pub consecutive_failures: u32,
+    /// How long our last connection took to open, in milliseconds
+    pub latency_ms: Option<u64>,
+}
+
+impl PeerConnectionHistory {
+    pub(crate) fn record_connected(&mut self, now: u64, latency: Duration) {
+        self.last_connected = Some(now);
+        self.consecutive_failures = 0;
+        self.latency_ms = Some(latency.as_millis() as u64);
+    }
+
+    pub(crate) fn record_failure(&mut self, now: u64) {
+        self.last_failure = Some(now);
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    }
+
+    /// The last time we tried to connect, successfully or not
+    fn last_attempt(&self) -> Option<u64> {
+        self.last_connected.max(self.last_failure)
+    }
+
+    /// Keeps whichever history has the latest connection attempt
+    fn merge(&self, other: &PeerConnectionHistory) -> PeerConnectionHistory {
+        if self.last_attempt() >= other.last_attempt() {
+            self.clone()
+        } else {
+            other.clone()
+        }
+    }
 }
 
 impl LnPeerMetadata {
+    /// How the given node's connections to this peer have gone
+    pub(crate) fn connection_history(&self, node: &str) -> PeerConnectionHistory {
+        self.connection_histories
+            .get(node)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The history of whichever of our nodes last tried to connect to this peer
+    pub(crate) fn latest_connection_history(&self) -> PeerConnectionHistory {
+        self.connection_histories
+            .values()
+            .fold(PeerConnectionHistory::default(), |latest, h| {
+                latest.merge(h)
+            })
+    }
+
     pub(crate) fn with_connection_string(self, connection_string: String) -> Self {
         Self {
             connection_string: Some(connection_string),
@@ -289,6 +379,21 @@ impl LnPeerMetadata {
         nodes.sort();
         nodes.dedup();
 
+        let addresses = if primary.addresses.is_empty() {
+            secondary.addresses
+        } else {
+            primary.addresses
+        };
+
+        let mut connection_histories = secondary.connection_histories;
+        for (node, history) in primary.connection_histories {
+            let merged = match connection_histories.get(&node) {
+                Some(other) => history.merge(other),
+                None => history,
+            };
+            connection_histories.insert(node, merged);
+        }
+
         Self {
             connection_string: primary.connection_string.or(secondary.connection_string),
             alias: primary.alias.or(secondary.alias),
@@ -296,6 +401,8 @@ impl LnPeerMetadata {
             label: primary.label.or(secondary.label),
             timestamp: primary.timestamp.or(secondary.timestamp),
             nodes,
+            addresses,
+            connection_histories,
         }
     }
 }
@@ -303,16 +410,50 @@ impl LnPeerMetadata {
 impl From<NodeAnnouncement> for LnPeerMetadata {
     fn from(value: NodeAnnouncement) -> Self {
         Self {
-            connection_string: None, // todo get from addresses
+            connection_string: None,
             alias: Some(value.contents.alias.to_string()),
             color: Some(value.contents.rgb.to_hex()),
             label: None,
             timestamp: Some(value.contents.timestamp),
             nodes: vec![],
+            addresses: value
+                .contents
+                .addresses
+                .iter()
+                .filter_map(net_address_to_string)
+                .collect(),
+            connection_histories: HashMap::new(),
         }
     }
 }
 
+/// The `host:port` for an address from a node announcement,
+/// None for the ones we can't connect to.
+fn net_address_to_string(address: &NetAddress) -> Option<String> {
+    match address {
+        NetAddress::IPv4 { addr, port } => {
+            Some(SocketAddr::from((Ipv4Addr::from(*addr), *port)).to_string())
+        }
+        NetAddress::IPv6 { addr, port } => {
+            Some(SocketAddr::from((Ipv6Addr::from(*addr), *port)).to_string())
+        }
+        NetAddress::OnionV3 {
+            ed25519_pubkey,
+            checksum,
+            version,
+            port,
+        } => {
+            let mut bytes = ed25519_pubkey.to_vec();
+            bytes.extend_from_slice(&checksum.to_be_bytes());
+            bytes.push(*version);
+            Some(format!("{}.onion:{port}", utils::base32_encode(&bytes)))
+        }
+        NetAddress::Hostname { hostname, port } => Some(format!("{}:{port}", hostname.as_str())),
+        // tor v2 is no longer supported
+        NetAddress::OnionV2(_) => None,
+    }
+}
+
 pub(crate) fn read_peer_info(
     storage: &impl MutinyStorage,
     node_id: &NodeId,
@@ -432,6 +573,240 @@ pub(crate) fn save_ln_peer_info(
     Ok(())
 }
 
+/// Updates our node's connection history with a peer we have saved, returns the updated info
+fn update_connection_history(
+    storage: &impl MutinyStorage,
+    node: &str,
+    node_id: &NodeId,
+    update: impl FnOnce(&mut PeerConnectionHistory),
+) -> Result<Option<LnPeerMetadata>, MutinyError> {
+    let key = format!("{LN_PEER_METADATA_KEY_PREFIX}{node_id}");
+
+    let current: Option<LnPeerMetadata> = storage.get_data(&key)?;
+
+    // we only track peers we have saved
+    let Some(mut current) = current else {
+        return Ok(None);
+    };
+    update(
+        current
+            .connection_histories
+            .entry(node.to_string())
+            .or_default(),
+    );
+    storage.set_data(key, current.clone(), None)?;
+
+    Ok(Some(current))
+}
+
+pub(crate) fn record_peer_connected(
+    storage: &impl MutinyStorage,
+    node: &str,
+    node_id: &NodeId,
+    latency: Duration,
+) -> Result<(), MutinyError> {
+    let now = utils::now().as_secs();
+    update_connection_history(storage, node, node_id, |h| h.record_connected(now, latency))?;
+    Ok(())
+}
+
+pub(crate) fn record_peer_connection_failed(
+    storage: &impl MutinyStorage,
+    node: &str,
+    node_id: &NodeId,
+) -> Result<(), MutinyError> {
+    let now = utils::now().as_secs();
+    update_connection_history(storage, node, node_id, |h| h.record_failure(now))?;
+    Ok(())
+}
+
+/// How many funding outputs we look up with Esplora at the same time
+const MAX_CONCURRENT_UTXO_LOOKUPS: usize = 4;
+
+/// How many announced channels can wait on a lookup before we ignore new ones
+const MAX_QUEUED_UTXO_LOOKUPS: usize = 1_000;
+
+/// How long we remember a funding output lookup, in seconds
+const UTXO_LOOKUP_CACHE_SECS: u64 = 60 * 60;
+
+/// How many funding output lookups we remember
+const MAX_CACHED_UTXO_LOOKUPS: usize = 10_000;
+
+/// Creates the gossip sync for getting our network graph from our peers. Channels we
+/// hear about are checked against the chain, so peers can't fill our graph with fake ones.
+pub(crate) fn p2p_gossip_sync(
+    network_graph: Arc<NetworkGraph>,
+    utxo_lookup: Arc<EsploraUtxoLookup>,
+    logger: Arc<MutinyLogger>,
+) -> P2PGossipSync {
+    P2PGossipSync::new(network_graph, Some(utxo_lookup), logger)
+}
+
+/// Looks up the funding outputs of announced channels with Esplora.
+///
+/// One is shared by the gossip syncs of all our nodes. Lookups are remembered by
+/// short channel id, and only a few are made at once so a flood of announcements
+/// doesn't flood Esplora.
+pub(crate) struct EsploraUtxoLookup {
+    esplora: Arc<AsyncClient>,
+    network_graph: Arc<NetworkGraph>,
+    state: Arc<Mutex<UtxoLookupState>>,
+    logger: Arc<MutinyLogger>,
+}
+
+#[derive(Default)]
+struct UtxoLookupState {
+    /// The lookups we made, with when we made them
+    cache: HashMap<u64, (u64, Result<TxOut, UtxoLookupError>)>,
+    /// The lookups waiting on a worker
+    queue: VecDeque<(u64, UtxoFuture)>,
+    /// How many workers are looking up funding outputs
+    workers: usize,
+}
+
+impl UtxoLookupState {
+    fn cached(&self, short_channel_id: u64, now: u64) -> Option<Result<TxOut, UtxoLookupError>> {
+        self.cache
+            .get(&short_channel_id)
+            .filter(|(time, _)| now < time + UTXO_LOOKUP_CACHE_SECS)
+            .map(|(_, utxo)| utxo.clone())
+    }
+
+    fn insert(&mut self, short_channel_id: u64, now: u64, utxo: Result<TxOut, UtxoLookupError>) {
+        if self.cache.len() >= MAX_CACHED_UTXO_LOOKUPS {
+            self.cache
+                .retain(|_, (time, _)| now < *time + UTXO_LOOKUP_CACHE_SECS);
+        }
+        if self.cache.len() >= MAX_CACHED_UTXO_LOOKUPS {
+            self.cache.clear();
+        }
+        self.cache.insert(short_channel_id, (now, utxo));
+    }
+}
+
+impl EsploraUtxoLookup {
+    pub(crate) fn new(
+        esplora: Arc<AsyncClient>,
+        network_graph: Arc<NetworkGraph>,
+        logger: Arc<MutinyLogger>,
+    ) -> Self {
+        Self {
+            esplora,
+            network_graph,
+            state: Arc::new(Mutex::new(UtxoLookupState::default())),
+            logger,
+        }
+    }
+}
+
+impl UtxoLookup for EsploraUtxoLookup {
+    fn get_utxo(&self, _genesis_hash: &BlockHash, short_channel_id: u64) -> UtxoResult {
+        let Ok(mut state) = self.state.lock() else {
+            return UtxoResult::Sync(Err(UtxoLookupError::UnknownTx));
+        };
+        if let Some(utxo) = state.cached(short_channel_id, utils::now().as_secs()) {
+            return UtxoResult::Sync(utxo);
+        }
+        if state.queue.len() >= MAX_QUEUED_UTXO_LOOKUPS {
+            log_debug!(
+                self.logger,
+                "Too many channels to look up, ignoring channel {short_channel_id}"
+            );
+            return UtxoResult::Sync(Err(UtxoLookupError::UnknownTx));
+        }
+
+        let future = UtxoFuture::new();
+        state.queue.push_back((short_channel_id, future.clone()));
+        if state.workers >= MAX_CONCURRENT_UTXO_LOOKUPS {
+            return UtxoResult::Async(future);
+        }
+        state.workers += 1;
+
+        let esplora = self.esplora.clone();
+        let network_graph = self.network_graph.clone();
+        let state = self.state.clone();
+        let logger = self.logger.clone();
+        // LDK calls this while our peers' messages are handled, which on native
+        // is always within our LocalSet, so the lookup can be spawned locally
+        utils::spawn(async move {
+            loop {
+                let next = match state.lock() {
+                    Ok(mut state) => {
+                        let next = state.queue.pop_front();
+                        if next.is_none() {
+                            state.workers -= 1;
+                        }
+                        next
+                    }
+                    Err(_) => None,
+                };
+                let Some((short_channel_id, future)) = next else {
+                    break;
+                };
+
+                let utxo = lookup_funding_output(&esplora, short_channel_id).await;
+                if utxo.is_err() {
+                    log_trace!(logger, "No funding output for channel {short_channel_id}");
+                }
+                if let Ok(mut state) = state.lock() {
+                    state.insert(short_channel_id, utils::now().as_secs(), utxo.clone());
+                }
+                future.resolve_without_forwarding(&network_graph, utxo);
+            }
+        });
+
+        UtxoResult::Async(future)
+    }
+}
+
+/// Splits a short channel id into the block height, the transaction's index
+/// in the block and the output's index in the transaction
+fn split_short_channel_id(short_channel_id: u64) -> (u32, usize, u16) {
+    let height = (short_channel_id >> 40) as u32;
+    let tx_index = ((short_channel_id >> 16) & 0xff_ffff) as usize;
+    let vout = (short_channel_id & 0xffff) as u16;
+    (height, tx_index, vout)
+}
+
+/// Finds the funding output of a channel, it must still be unspent
+async fn lookup_funding_output(
+    esplora: &AsyncClient,
+    short_channel_id: u64,
+) -> Result<TxOut, UtxoLookupError> {
+    let (height, tx_index, vout) = split_short_channel_id(short_channel_id);
+
+    let block_hash = esplora
+        .get_block_hash(height)
+        .await
+        .map_err(|_| UtxoLookupError::UnknownChain)?;
+    let txid = esplora
+        .get_txid_at_block_index(&block_hash, tx_index)
+        .await
+        .map_err(|_| UtxoLookupError::UnknownTx)?
+        .ok_or(UtxoLookupError::UnknownTx)?;
+    let tx = esplora
+        .get_tx(&txid)
+        .await
+        .map_err(|_| UtxoLookupError::UnknownTx)?
+        .ok_or(UtxoLookupError::UnknownTx)?;
+    let output = tx
+        .output
+        .get(vout as usize)
+        .cloned()
+        .ok_or(UtxoLookupError::UnknownTx)?;
+
+    // a spent funding output means the channel was closed
+    let status = esplora
+        .get_output_status(&txid, vout as u64)
+        .await
+        .map_err(|_| UtxoLookupError::UnknownTx)?;
+    if status.is_some_and(|s| s.spent) {
+        return Err(UtxoLookupError::UnknownTx);
+    }
+
+    Ok(output)
+}
+
 pub(crate) fn get_rgs_url(
     network: Network,
     user_provided_url: Option<String>,
@@ -487,6 +862,8 @@ mod test {
             label: Some("test label".to_string()),
             timestamp: Some(utils::now().as_secs() as u32),
             nodes: vec![uuid],
+            addresses: vec!["127.0.0.1:9735".to_string()],
+            connection_histories: HashMap::new(),
         };
 
         (node_id, data)
@@ -523,9 +900,18 @@ mod test {
         let storage = MemoryStorage::default();
 
         let logger = Arc::new(MutinyLogger::default());
-        let _gossip_sync = get_gossip_sync(&storage, None, Network::Regtest, logger.clone())
-            .await
-            .unwrap();
+        let _gossip_sync = get_gossip_sync(
+            &storage,
+            None,
+            Network::Regtest,
+            #[cfg(not(target_arch = "wasm32"))]
+            None,
+            #[cfg(not(target_arch = "wasm32"))]
+            false,
+            logger.clone(),
+        )
+        .await
+        .unwrap();
 
         let data = get_gossip_data(&storage, logger).await.unwrap();
 
@@ -533,6 +919,263 @@ mod test {
         assert!(data.unwrap().last_sync_timestamp > 0);
     }
 
+    #[test]
+    fn test_split_short_channel_id() {
+        let (height, tx_index, vout) = split_short_channel_id(0x0c35_0000_0001_0002);
+        assert_eq!(height, 800_000);
+        assert_eq!(tx_index, 1);
+        assert_eq!(vout, 2);
+
+        assert_eq!(split_short_channel_id(0), (0, 0, 0));
+    }
+
+    /// Serves the Esplora endpoints a funding output lookup uses, for a single
+    /// transaction at index 1 of block 100. Each path it serves is sent to the receiver.
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn mock_esplora(
+        tx: bitcoin::Transaction,
+        spent: bool,
+    ) -> (
+        Arc<AsyncClient>,
+        futures::channel::mpsc::UnboundedReceiver<String>,
+    ) {
+        use bitcoin::hashes::Hash;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+
+        tokio::task::spawn_local(async move {
+            let block_hash = BlockHash::all_zeros();
+            let txid = tx.txid();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+                let body = if path == "/block-height/100" {
+                    Some(block_hash.to_string().into_bytes())
+                } else if path == format!("/block/{block_hash}/txid/1") {
+                    Some(txid.to_string().into_bytes())
+                } else if path == format!("/tx/{txid}/raw") {
+                    Some(bitcoin::consensus::serialize(&tx))
+                } else if path == format!("/tx/{txid}/outspend/0") {
+                    Some(format!(r#"{{"spent":{spent}}}"#).into_bytes())
+                } else {
+                    None
+                };
+                let mut response = match body.as_ref() {
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    ),
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    }
+                }
+                .into_bytes();
+                response.extend(body.unwrap_or_default());
+                stream.write_all(&response).await.unwrap();
+
+                let _ = sender.unbounded_send(path.to_string());
+            }
+        });
+
+        let esplora = esplora_client::Builder::new(&url).build_async().unwrap();
+        (Arc::new(esplora), receiver)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn funding_tx(script_pubkey: bitcoin::Script) -> bitcoin::Transaction {
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::PackedLockTime(0),
+            input: vec![bitcoin::TxIn::default()],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey,
+            }],
+        }
+    }
+
+    /// A channel announcement signed by both nodes and their funding keys,
+    /// with the script of its 2-of-2 funding output
+    #[cfg(not(target_arch = "wasm32"))]
+    fn signed_channel_announcement(
+        short_channel_id: u64,
+    ) -> (lightning::ln::msgs::ChannelAnnouncement, bitcoin::Script) {
+        use bitcoin::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_2};
+        use bitcoin::blockdata::script::Builder;
+        use bitcoin::hashes::{sha256d, Hash};
+        use bitcoin::secp256k1::{Message, PublicKey};
+        use lightning::ln::features::ChannelFeatures;
+        use lightning::ln::msgs::{ChannelAnnouncement, UnsignedChannelAnnouncement};
+
+        let secp = Secp256k1::new();
+        // the lower key comes first, for both the nodes and the funding keys
+        let sorted_keys = |a: [u8; 32], b: [u8; 32]| {
+            let a = SecretKey::from_slice(&a).unwrap();
+            let b = SecretKey::from_slice(&b).unwrap();
+            let mut keys = [
+                (PublicKey::from_secret_key(&secp, &a), a),
+                (PublicKey::from_secret_key(&secp, &b), b),
+            ];
+            keys.sort_by_key(|(pubkey, _)| pubkey.serialize());
+            keys
+        };
+        let [(node_1, node_key_1), (node_2, node_key_2)] = sorted_keys([1; 32], [2; 32]);
+        let [(bitcoin_1, bitcoin_key_1), (bitcoin_2, bitcoin_key_2)] =
+            sorted_keys([3; 32], [4; 32]);
+
+        let contents = UnsignedChannelAnnouncement {
+            features: ChannelFeatures::empty(),
+            chain_hash: bitcoin::blockdata::constants::genesis_block(Network::Regtest).block_hash(),
+            short_channel_id,
+            node_id_1: NodeId::from_pubkey(&node_1),
+            node_id_2: NodeId::from_pubkey(&node_2),
+            bitcoin_key_1: NodeId::from_pubkey(&bitcoin_1),
+            bitcoin_key_2: NodeId::from_pubkey(&bitcoin_2),
+            excess_data: vec![],
+        };
+        let hash = sha256d::Hash::hash(&contents.encode());
+        let message = Message::from_slice(&hash[..]).unwrap();
+        let announcement = ChannelAnnouncement {
+            node_signature_1: secp.sign_ecdsa(&message, &node_key_1),
+            node_signature_2: secp.sign_ecdsa(&message, &node_key_2),
+            bitcoin_signature_1: secp.sign_ecdsa(&message, &bitcoin_key_1),
+            bitcoin_signature_2: secp.sign_ecdsa(&message, &bitcoin_key_2),
+            contents,
+        };
+
+        let funding_script = Builder::new()
+            .push_opcode(OP_PUSHNUM_2)
+            .push_slice(&bitcoin_1.serialize())
+            .push_slice(&bitcoin_2.serialize())
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+            .to_v0_p2wsh();
+
+        (announcement, funding_script)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_lookup_funding_output() {
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let (_, funding_script) = signed_channel_announcement(0);
+                let tx = funding_tx(funding_script);
+                let short_channel_id = (100 << 40) | (1 << 16);
+
+                let (esplora, _) = mock_esplora(tx.clone(), false).await;
+                let output = lookup_funding_output(&esplora, short_channel_id)
+                    .await
+                    .unwrap();
+                assert_eq!(output, tx.output[0]);
+
+                // not in the block, no such output, no such block
+                for short_channel_id in [(100 << 40) | (2 << 16), (100 << 40) | (1 << 16) | 1] {
+                    assert!(matches!(
+                        lookup_funding_output(&esplora, short_channel_id).await,
+                        Err(UtxoLookupError::UnknownTx)
+                    ));
+                }
+                assert!(matches!(
+                    lookup_funding_output(&esplora, (101 << 40) | (1 << 16)).await,
+                    Err(UtxoLookupError::UnknownChain)
+                ));
+
+                // the channel was closed
+                let (esplora, _) = mock_esplora(tx, true).await;
+                assert!(matches!(
+                    lookup_funding_output(&esplora, short_channel_id).await,
+                    Err(UtxoLookupError::UnknownTx)
+                ));
+            })
+            .await;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_get_utxo() {
+        use bitcoin::hashes::Hash;
+        use futures::StreamExt;
+        use lightning::ln::msgs::RoutingMessageHandler;
+
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                let short_channel_id = (100 << 40) | (1 << 16);
+                let (announcement, funding_script) = signed_channel_announcement(short_channel_id);
+                let tx = funding_tx(funding_script);
+                let (esplora, mut requests) = mock_esplora(tx.clone(), false).await;
+
+                let logger = Arc::new(MutinyLogger::default());
+                let network_graph = Arc::new(NetworkGraph::new(Network::Regtest, logger.clone()));
+                let lookup = Arc::new(EsploraUtxoLookup::new(
+                    esplora,
+                    network_graph.clone(),
+                    logger.clone(),
+                ));
+                let gossip_sync = p2p_gossip_sync(network_graph.clone(), lookup.clone(), logger);
+
+                // LDK is not held up on Esplora, the lookup finishes in the background
+                assert!(gossip_sync
+                    .handle_channel_announcement(&announcement)
+                    .is_err());
+                assert!(network_graph
+                    .read_only()
+                    .channel(short_channel_id)
+                    .is_none());
+
+                let txid = tx.txid();
+                let mut served = vec![];
+                for _ in 0..4 {
+                    served.push(requests.next().await.unwrap());
+                }
+                assert_eq!(
+                    served,
+                    vec![
+                        "/block-height/100".to_string(),
+                        format!("/block/{}/txid/1", BlockHash::all_zeros()),
+                        format!("/tx/{txid}/raw"),
+                        format!("/tx/{txid}/outspend/0"),
+                    ]
+                );
+
+                // once the lookup resolves the channel is in our graph
+                for _ in 0..10_000 {
+                    if network_graph
+                        .read_only()
+                        .channel(short_channel_id)
+                        .is_some()
+                    {
+                        break;
+                    }
+                    tokio::task::yield_now().await;
+                }
+                let graph = network_graph.read_only();
+                let channel = graph.channel(short_channel_id).unwrap();
+                assert_eq!(channel.capacity_sats, Some(100_000));
+                assert_eq!(channel.node_one, announcement.contents.node_id_1);
+                assert_eq!(channel.node_two, announcement.contents.node_id_2);
+
+                // the lookup is remembered, any of our nodes asking again doesn't hit Esplora
+                let genesis_hash =
+                    bitcoin::blockdata::constants::genesis_block(Network::Regtest).block_hash();
+                assert!(matches!(
+                    lookup.get_utxo(&genesis_hash, short_channel_id),
+                    UtxoResult::Sync(Ok(output)) if output == tx.output[0]
+                ));
+                assert!(requests.try_next().is_err());
+            })
+            .await;
+    }
+
     #[test]
     fn test_peer_info() {
         let storage = MemoryStorage::default();
@@ -576,4 +1219,90 @@ mod test {
         assert!(read.is_some());
         assert_eq!(read.unwrap(), expected);
     }
+
+    #[test]
+    fn test_peer_connection_history() {
+        let storage = MemoryStorage::default();
+        let (node_id, data) = dummy_peer_info();
+        let node = data.nodes[0].clone();
+        let other_node = "other node";
+
+        // unknown peers are not tracked
+        record_peer_connection_failed(&storage, &node, &node_id).unwrap();
+        assert!(read_peer_info(&storage, &node_id).unwrap().is_none());
+
+        save_ln_peer_info(&storage, &node_id, &data).unwrap();
+        record_peer_connection_failed(&storage, &node, &node_id).unwrap();
+        record_peer_connection_failed(&storage, &node, &node_id).unwrap();
+
+        let read = read_peer_info(&storage, &node_id).unwrap().unwrap();
+        let history = read.connection_history(&node);
+        assert_eq!(history.consecutive_failures, 2);
+        assert!(history.last_failure.is_some());
+        assert!(history.last_connected.is_none());
+        // each of our nodes has its own history with the peer
+        assert_eq!(
+            read.connection_history(other_node),
+            PeerConnectionHistory::default()
+        );
+
+        record_peer_connected(&storage, other_node, &node_id, Duration::from_millis(150)).unwrap();
+        let read = read_peer_info(&storage, &node_id).unwrap().unwrap();
+        assert_eq!(read.connection_history(&node).consecutive_failures, 2);
+        let history = read.connection_history(other_node);
+        assert_eq!(history.consecutive_failures, 0);
+        assert_eq!(history.latency_ms, Some(150));
+        assert!(history.last_connected.is_some());
+
+        record_peer_connected(&storage, &node, &node_id, Duration::from_millis(100)).unwrap();
+        let read = read_peer_info(&storage, &node_id).unwrap().unwrap();
+        assert_eq!(read.connection_history(&node).consecutive_failures, 0);
+        let latest = read.latest_connection_history();
+        assert_eq!(latest.consecutive_failures, 0);
+        assert!(latest.last_connected.is_some());
+
+        // a new node announcement keeps the history
+        let announcement = LnPeerMetadata {
+            alias: Some("new alias".to_string()),
+            timestamp: Some(u32::MAX),
+            ..Default::default()
+        };
+        save_ln_peer_info(&storage, &node_id, &announcement).unwrap();
+        let merged = read_peer_info(&storage, &node_id).unwrap().unwrap();
+        assert_eq!(merged.alias, announcement.alias);
+        assert_eq!(merged.addresses, data.addresses);
+        assert_eq!(merged.connection_histories, read.connection_histories);
+    }
+
+    #[test]
+    fn test_net_address_to_string() {
+        let ipv4 = NetAddress::IPv4 {
+            addr: [127, 0, 0, 1],
+            port: 9735,
+        };
+        assert_eq!(
+            net_address_to_string(&ipv4),
+            Some("127.0.0.1:9735".to_string())
+        );
+
+        let ipv6 = NetAddress::IPv6 {
+            addr: Ipv6Addr::LOCALHOST.octets(),
+            port: 9735,
+        };
+        assert_eq!(net_address_to_string(&ipv6), Some("[::1]:9735".to_string()));
+
+        let onion = NetAddress::OnionV3 {
+            ed25519_pubkey: FromHex::from_hex(
+                "d1b38b83a83b3ed918c5bb69dd444ad56bc8d5835a914de73447474e5f02591b",
+            )
+            .unwrap(),
+            checksum: 56793,
+            version: 3,
+            port: 9735,
+        };
+        assert_eq!(
+            net_address_to_string(&onion),
+            Some("2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion:9735".to_string())
+        );
+    }
 }
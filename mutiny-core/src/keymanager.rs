@@ -12,6 +12,7 @@ use bitcoin::secp256k1::ecdsa::Signature;
 use bitcoin::secp256k1::{PublicKey, Scalar, Secp256k1, Signing};
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
 use bitcoin::{Script, Transaction, TxOut};
+use lightning::ln::chan_utils::ChannelTransactionParameters;
 use lightning::ln::msgs::{DecodeError, UnsignedGossipMessage};
 use lightning::ln::script::ShutdownScript;
 use lightning::log_warn;
@@ -21,6 +22,8 @@ use lightning::sign::{
     SpendableOutputDescriptor,
 };
 use lightning::util::logger::Logger;
+use lightning::util::ser::Readable;
+use std::cell::Cell;
 use std::sync::Arc;
 
 pub struct PhantomKeysManager<S: MutinyStorage> {
@@ -175,6 +178,76 @@ impl<S: MutinyStorage> SignerProvider for PhantomKeysManager<S> {
     }
 }
 
+/// A [`SignerProvider`] for reading a channel monitor that also keeps the
+/// channel value from its signer, the monitor itself doesn't expose it.
+pub(crate) struct ChannelValueReader<'a, S: MutinyStorage> {
+    keys_manager: &'a PhantomKeysManager<S>,
+    channel_value_sats: Cell<Option<u64>>,
+}
+
+impl<'a, S: MutinyStorage> ChannelValueReader<'a, S> {
+    pub(crate) fn new(keys_manager: &'a PhantomKeysManager<S>) -> Self {
+        Self {
+            keys_manager,
+            channel_value_sats: Cell::new(None),
+        }
+    }
+
+    /// The channel value of the last signer read
+    pub(crate) fn channel_value_sats(&self) -> Option<u64> {
+        self.channel_value_sats.get()
+    }
+}
+
+impl<'a, S: MutinyStorage> SignerProvider for ChannelValueReader<'a, S> {
+    type Signer = InMemorySigner;
+
+    fn generate_channel_keys_id(
+        &self,
+        inbound: bool,
+        channel_value_satoshis: u64,
+        user_channel_id: u128,
+    ) -> [u8; 32] {
+        self.keys_manager
+            .generate_channel_keys_id(inbound, channel_value_satoshis, user_channel_id)
+    }
+
+    fn derive_channel_signer(
+        &self,
+        channel_value_satoshis: u64,
+        channel_keys_id: [u8; 32],
+    ) -> Self::Signer {
+        self.keys_manager
+            .derive_channel_signer(channel_value_satoshis, channel_keys_id)
+    }
+
+    fn read_chan_signer(&self, reader: &[u8]) -> Result<Self::Signer, DecodeError> {
+        let signer = self.keys_manager.read_chan_signer(reader)?;
+        self.channel_value_sats
+            .set(Some(read_signer_channel_value(reader)?));
+        Ok(signer)
+    }
+
+    fn get_destination_script(&self) -> Result<Script, ()> {
+        self.keys_manager.get_destination_script()
+    }
+
+    fn get_shutdown_scriptpubkey(&self) -> Result<ShutdownScript, ()> {
+        self.keys_manager.get_shutdown_scriptpubkey()
+    }
+}
+
+/// Reads the channel value of a serialized [`InMemorySigner`], it comes after
+/// the version prefix, the six channel secrets and the channel parameters.
+fn read_signer_channel_value(mut reader: &[u8]) -> Result<u64, DecodeError> {
+    let _version: u16 = Readable::read(&mut reader)?;
+    for _ in 0..6 {
+        let _secret: [u8; 32] = Readable::read(&mut reader)?;
+    }
+    let _params: Option<ChannelTransactionParameters> = Readable::read(&mut reader)?;
+    Readable::read(&mut reader)
+}
+
 pub fn generate_seed(num_words: u8) -> Result<Mnemonic, MutinyError> {
     // the bip39 library supports 12. 15, 18, 21, and 24 word mnemonics
     // we only support 12 & 24 for backwards compatibility with other wallets
@@ -242,7 +315,7 @@ mod tests {
         encrypt::encryption_key_from_pass, keymanager::pubkey_from_keys_manager, test_utils::*,
     };
 
-    use super::create_keys_manager;
+    use super::{create_keys_manager, read_signer_channel_value};
     use crate::fees::MutinyFeeEstimator;
     use crate::logging::MutinyLogger;
     use crate::onchain::OnChainWallet;
@@ -251,6 +324,8 @@ mod tests {
     use bitcoin::util::bip32::ExtendedPrivKey;
     use bitcoin::Network;
     use esplora_client::Builder;
+    use lightning::sign::SignerProvider;
+    use lightning::util::ser::Writeable;
     use std::str::FromStr;
     use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
@@ -310,4 +385,49 @@ mod tests {
 
         assert_eq!(second_pubkey, second_pubkey_again);
     }
+
+    #[test]
+    async fn read_channel_value_from_signer() {
+        let test_name = "read_channel_value_from_signer";
+        log!("{}", test_name);
+
+        let mnemonic = Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").expect("could not generate");
+        let esplora = Arc::new(
+            Builder::new("https://blockstream.info/testnet/api/")
+                .build_async()
+                .unwrap(),
+        );
+        let pass = uuid::Uuid::new_v4().to_string();
+        let cipher = encryption_key_from_pass(&pass).unwrap();
+        let db = MemoryStorage::new(Some(pass), Some(cipher));
+        let logger = Arc::new(MutinyLogger::default());
+        let fees = Arc::new(MutinyFeeEstimator::new(
+            db.clone(),
+            esplora.clone(),
+            logger.clone(),
+        ));
+        let stop = Arc::new(AtomicBool::new(false));
+        let xpriv = ExtendedPrivKey::new_master(Network::Testnet, &mnemonic.to_seed("")).unwrap();
+        let wallet = Arc::new(
+            OnChainWallet::new(
+                xpriv,
+                db,
+                Network::Testnet,
+                esplora,
+                fees,
+                stop,
+                logger.clone(),
+            )
+            .unwrap(),
+        );
+        let km = create_keys_manager(wallet, xpriv, 1, logger).unwrap();
+
+        let keys_id = km.generate_channel_keys_id(false, 123_456, 0);
+        let signer = km.derive_channel_signer(123_456, keys_id);
+        assert_eq!(
+            read_signer_channel_value(&signer.encode()).unwrap(),
+            123_456
+        );
+        assert!(read_signer_channel_value(&[1, 1]).is_err());
+    }
 }
@@ -1,3 +1,4 @@
+use crate::activity::ActivityStorage;
 use crate::error::MutinyError;
 use crate::nodemanager::NodeManager;
 use crate::storage::MutinyStorage;
@@ -161,6 +162,9 @@ impl<S: MutinyStorage> LabelStorage for S {
         invoice_labels.insert(invoice.clone(), labels.clone());
         self.set_data(INVOICE_LABELS_MAP_KEY, invoice_labels, None)?;
 
+        // update the activity index
+        self.set_activity_index_labels(&invoice, &labels)?;
+
         // update the label items
         let now = crate::utils::now().as_secs();
         for label in labels {
@@ -255,6 +259,7 @@ impl<S: MutinyStorage> LabelStorage for S {
                             .map(|l| if l == label_str { id.clone() } else { l })
                             .collect();
 
+                        self.set_activity_index_labels(&inv, &new_labels)?;
                         updated.insert(inv, new_labels);
                     }
                 }
@@ -1,20 +1,23 @@
+use crate::activity::ActivityStorage;
 use crate::chain::MutinyChain;
+use crate::channelpolicy::{ChannelRejection, MAX_CHANNEL_REJECTIONS};
 use crate::error::{MutinyError, MutinyStorageError};
 use crate::event::PaymentInfo;
 use crate::fees::MutinyFeeEstimator;
 use crate::gossip::{NETWORK_GRAPH_KEY, PROB_SCORER_KEY};
 use crate::keymanager::PhantomKeysManager;
 use crate::logging::MutinyLogger;
-use crate::node::{default_user_config, ChainMonitor, ProbScorer};
+use crate::node::{ChainMonitor, ProbScorer};
 use crate::node::{NetworkGraph, Router};
 use crate::nodemanager::ChannelClosure;
+use crate::scb::backup::request_scb_backup;
 use crate::storage::{MutinyStorage, VersionedValue};
 use crate::utils;
 use anyhow::anyhow;
 use bdk_esplora::esplora_client::AsyncClient;
 use bitcoin::hashes::hex::{FromHex, ToHex};
 use bitcoin::Network;
-use bitcoin::{BlockHash, Transaction};
+use bitcoin::{Address, BlockHash, Transaction, TxOut};
 use futures::{try_join, TryFutureExt};
 use lightning::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
 use lightning::chain::transaction::OutPoint;
@@ -25,6 +28,7 @@ use lightning::ln::channelmanager::{
 };
 use lightning::ln::PaymentHash;
 use lightning::sign::{InMemorySigner, SpendableOutputDescriptor, WriteableEcdsaChannelSigner};
+use lightning::util::config::UserConfig;
 use lightning::util::logger::Logger;
 use lightning::util::persist::Persister;
 use lightning::util::ser::{Readable, ReadableArgs, Writeable};
@@ -44,8 +48,12 @@ pub const MONITORS_PREFIX_KEY: &str = "monitors/";
 const PAYMENT_INBOUND_PREFIX_KEY: &str = "payment_inbound/";
 const PAYMENT_OUTBOUND_PREFIX_KEY: &str = "payment_outbound/";
 const CHANNEL_OPENING_PARAMS_PREFIX: &str = "chan_open_params/";
+const CHANNEL_BATCH_PREFIX: &str = "chan_batch/";
 const CHANNEL_CLOSURE_PREFIX: &str = "channel_closure/";
+const CHANNEL_CLOSE_PARAMS_PREFIX: &str = "chan_close_params/";
+const CHANNEL_METADATA_PREFIX: &str = "chan_metadata/";
 const FAILED_SPENDABLE_OUTPUT_DESCRIPTOR_KEY: &str = "failed_spendable_outputs";
+const CHANNEL_REJECTIONS_KEY: &str = "channel_rejections";
 
 pub(crate) type PhantomChannelManager<S: MutinyStorage> = LdkChannelManager<
     Arc<ChainMonitor<S>>,
@@ -174,6 +182,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         router: Arc<Router>,
         channel_monitors: Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>,
         esplora: Arc<AsyncClient>,
+        user_config: UserConfig,
     ) -> Result<ReadChannelManager<S>, MutinyError> {
         let key = self.get_key(CHANNEL_MANAGER_KEY);
         match self.storage.get_data::<VersionedValue>(&key) {
@@ -190,6 +199,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
                     keys_manager,
                     router,
                     channel_monitors,
+                    user_config,
                 )?;
 
                 self.manager_version
@@ -210,6 +220,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
                     router,
                     channel_monitors,
                     esplora,
+                    user_config,
                 )
                 .await
             }
@@ -225,6 +236,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
                     keys_manager,
                     router,
                     channel_monitors,
+                    user_config,
                 )
             }
         }
@@ -240,6 +252,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         keys_manager: Arc<PhantomKeysManager<S>>,
         router: Arc<Router>,
         mut channel_monitors: Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>,
+        user_config: UserConfig,
     ) -> Result<ReadChannelManager<S>, MutinyError> {
         let mut channel_monitor_mut_references = Vec::new();
         for (_, channel_monitor) in channel_monitors.iter_mut() {
@@ -254,7 +267,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             mutiny_chain,
             router,
             mutiny_logger,
-            default_user_config(),
+            user_config,
             channel_monitor_mut_references,
         );
         let mut readable_kv_value = Cursor::new(bytes);
@@ -279,6 +292,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         router: Arc<Router>,
         channel_monitors: Vec<(BlockHash, ChannelMonitor<InMemorySigner>)>,
         esplora: Arc<AsyncClient>,
+        user_config: UserConfig,
     ) -> Result<ReadChannelManager<S>, MutinyError> {
         // if regtest, we don't need to get the tip hash and can
         // just use genesis, this also lets us use regtest in tests
@@ -308,7 +322,7 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             keys_manager.clone(),
             keys_manager.clone(),
             keys_manager,
-            default_user_config(),
+            user_config,
             chain_params,
             utils::now().as_secs() as u32,
         );
@@ -329,6 +343,11 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         let key = self.get_key(payment_key(inbound, payment_hash).as_str());
         self.storage
             .set_data(key, payment_info, None)
+            .map_err(io::Error::other)?;
+
+        // keep the activity index up to date
+        self.storage
+            .index_payment(payment_hash, &self.node_id, inbound, payment_info)
             .map_err(io::Error::other)
     }
 
@@ -413,6 +432,28 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
             .collect())
     }
 
+    /// Saves an inbound channel we rejected, dropping the oldest if we have too many
+    pub(crate) fn persist_channel_rejection(
+        &self,
+        rejection: ChannelRejection,
+    ) -> Result<(), MutinyError> {
+        let key = self.get_key(CHANNEL_REJECTIONS_KEY);
+        let mut rejections: Vec<ChannelRejection> =
+            self.storage.get_data(&key)?.unwrap_or_default();
+        rejections.push(rejection);
+        if rejections.len() > MAX_CHANNEL_REJECTIONS {
+            rejections.drain(..rejections.len() - MAX_CHANNEL_REJECTIONS);
+        }
+        self.storage.set_data(key, rejections, None)?;
+        Ok(())
+    }
+
+    /// The inbound channels we rejected, oldest first
+    pub(crate) fn list_channel_rejections(&self) -> Result<Vec<ChannelRejection>, MutinyError> {
+        let key = self.get_key(CHANNEL_REJECTIONS_KEY);
+        Ok(self.storage.get_data(key)?.unwrap_or_default())
+    }
+
     /// Persists the failed spendable outputs to storage.
     /// Previously failed spendable outputs are not overwritten.
     ///
@@ -492,12 +533,98 @@ impl<S: MutinyStorage> MutinyNodePersister<S> {
         let key = self.get_key(&channel_open_params_key(id));
         self.storage.delete(&[key])
     }
+
+    /// Saves the state of a batch of channels, keyed by its first channel
+    pub(crate) fn persist_channel_batch(&self, batch: &ChannelBatch) -> Result<(), MutinyError> {
+        let key = self.get_key(&channel_batch_key(&batch.ids()));
+        self.storage.set_data(key, batch, None)
+    }
+
+    /// Gets the state of a batch of channels from the user channel ids in it
+    pub(crate) fn get_channel_batch(
+        &self,
+        batch: &[u128],
+    ) -> Result<Option<ChannelBatch>, MutinyError> {
+        let key = self.get_key(&channel_batch_key(batch));
+        self.storage.get_data(key)
+    }
+
+    pub(crate) fn persist_channel_close_params(
+        &self,
+        id: u128,
+        params: ChannelCloseParams,
+    ) -> Result<(), MutinyError> {
+        let key = self.get_key(&channel_close_params_key(id));
+        self.storage.set_data(key, params, None)
+    }
+
+    pub(crate) fn get_channel_close_params(
+        &self,
+        id: u128,
+    ) -> Result<Option<ChannelCloseParams>, MutinyError> {
+        let key = self.get_key(&channel_close_params_key(id));
+        self.storage.get_data(key)
+    }
+
+    pub(crate) fn delete_channel_close_params(&self, id: u128) -> Result<(), MutinyError> {
+        let key = self.get_key(&channel_close_params_key(id));
+        self.storage.delete(&[key])
+    }
+
+    pub(crate) fn persist_channel_metadata(
+        &self,
+        id: u128,
+        metadata: ChannelMetadata,
+    ) -> Result<(), MutinyError> {
+        let key = self.get_key(&format!("{CHANNEL_METADATA_PREFIX}{id}"));
+        self.storage.set_data(key, metadata, None)
+    }
+
+    /// Gets the label and note a channel was opened with, from its open params
+    /// if it is not pending yet.
+    pub(crate) fn get_channel_metadata(
+        &self,
+        id: u128,
+    ) -> Result<Option<ChannelMetadata>, MutinyError> {
+        let key = self.get_key(&format!("{CHANNEL_METADATA_PREFIX}{id}"));
+        if let Some(metadata) = self.storage.get_data(key)? {
+            return Ok(Some(metadata));
+        }
+
+        Ok(self
+            .get_channel_open_params(id)?
+            .map(|p| ChannelMetadata::from(&p))
+            .filter(|m| !m.is_empty()))
+    }
+
+    pub(crate) fn delete_channel_metadata(&self, id: u128) -> Result<(), MutinyError> {
+        let key = self.get_key(&format!("{CHANNEL_METADATA_PREFIX}{id}"));
+        self.storage.delete(&[key])
+    }
 }
 
 fn channel_open_params_key(id: u128) -> String {
     format!("{CHANNEL_OPENING_PARAMS_PREFIX}{id}")
 }
 
+fn channel_batch_key(batch: &[u128]) -> String {
+    let id = batch.first().copied().unwrap_or_default();
+    format!("{CHANNEL_BATCH_PREFIX}{id}")
+}
+
+fn channel_close_params_key(id: u128) -> String {
+    format!("{CHANNEL_CLOSE_PARAMS_PREFIX}{id}")
+}
+
+/// Parameters for a cooperative close we initiated, kept until the channel is closed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub(crate) struct ChannelCloseParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) destination: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) max_fee_sats: Option<u64>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub(crate) struct ChannelOpenParams {
     pub(crate) sats_per_vbyte: f32,
@@ -509,6 +636,18 @@ pub(crate) struct ChannelOpenParams {
     pub(crate) labels: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) opening_tx: Option<Transaction>,
+    /// The user channel ids of every channel funded by the same transaction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) batch: Option<Vec<u128>>,
+    /// The funding output LDK asked for, kept until every channel in the batch is ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) funding_output: Option<TxOut>,
+    /// A label for the channel itself, kept for its whole lifetime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    /// A note for the channel itself, kept for its whole lifetime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) note: Option<String>,
 }
 
 impl ChannelOpenParams {
@@ -519,6 +658,24 @@ impl ChannelOpenParams {
             utxos: None,
             labels: None,
             opening_tx: None,
+            batch: None,
+            funding_output: None,
+            label: None,
+            note: None,
+        }
+    }
+
+    pub fn new_batch(sats_per_vbyte: f32, batch: Vec<u128>) -> Self {
+        Self {
+            sats_per_vbyte,
+            absolute_fee: None,
+            utxos: None,
+            labels: None,
+            opening_tx: None,
+            batch: Some(batch),
+            funding_output: None,
+            label: None,
+            note: None,
         }
     }
 
@@ -533,11 +690,98 @@ impl ChannelOpenParams {
             utxos: Some(utxos),
             labels: None,
             opening_tx: None,
+            batch: None,
+            funding_output: None,
+            label: None,
+            note: None,
+        }
+    }
+}
+
+/// Where a channel of a batch is in getting signed by its peer
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChannelBatchMemberState {
+    /// Waiting on the peer to sign the funding transaction
+    Pending,
+    /// The peer signed, the channel is pending confirmation
+    Signed,
+    /// The channel failed to open, so the funding transaction must never go out
+    Aborted,
+}
+
+/// The channels funded by a single transaction and the transaction itself.
+///
+/// The funding transaction may only be broadcast once every channel is signed,
+/// and never after any of them was aborted.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub(crate) struct ChannelBatch {
+    pub(crate) members: Vec<(u128, ChannelBatchMemberState)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) funding_tx: Option<Transaction>,
+}
+
+impl ChannelBatch {
+    pub fn new(batch: Vec<u128>) -> Self {
+        Self {
+            members: batch
+                .into_iter()
+                .map(|id| (id, ChannelBatchMemberState::Pending))
+                .collect(),
+            funding_tx: None,
+        }
+    }
+
+    /// The user channel ids of the channels in the batch
+    pub(crate) fn ids(&self) -> Vec<u128> {
+        self.members.iter().map(|(id, _)| *id).collect()
+    }
+
+    pub(crate) fn set_state(&mut self, id: u128, state: ChannelBatchMemberState) {
+        if let Some(member) = self.members.iter_mut().find(|(i, _)| *i == id) {
+            member.1 = state;
+        }
+    }
+
+    /// If every channel in the batch has been signed by its peer
+    pub(crate) fn is_signed(&self) -> bool {
+        self.members
+            .iter()
+            .all(|(_, state)| *state == ChannelBatchMemberState::Signed)
+    }
+
+    /// If any channel in the batch failed to open
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.members
+            .iter()
+            .any(|(_, state)| *state == ChannelBatchMemberState::Aborted)
+    }
+}
+
+/// The label and note a channel was opened with
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ChannelMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) note: Option<String>,
+}
+
+impl ChannelMetadata {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.label.is_none() && self.note.is_none()
+    }
+}
+
+impl From<&ChannelOpenParams> for ChannelMetadata {
+    fn from(params: &ChannelOpenParams) -> Self {
+        Self {
+            label: params.label.clone(),
+            note: params.note.clone(),
         }
     }
 }
 
-fn payment_key(inbound: bool, payment_hash: &PaymentHash) -> String {
+pub(crate) fn payment_key(inbound: bool, payment_hash: &PaymentHash) -> String {
     if inbound {
         format!(
             "{}{}",
@@ -625,7 +869,13 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, S: MutinyStorage> Persist<Chann
         };
 
         match self.persist_local_storage(&key, monitor, Some(version)) {
-            Ok(()) => chain::ChannelMonitorUpdateStatus::Completed,
+            Ok(()) => {
+                // back up our channels now that there is a new one
+                if let Err(e) = request_scb_backup(&self.storage) {
+                    log_error!(self.logger, "Failed to request channel backup: {e}");
+                }
+                chain::ChannelMonitorUpdateStatus::Completed
+            }
             Err(_) => chain::ChannelMonitorUpdateStatus::PermanentFailure,
         }
     }
@@ -659,6 +909,7 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, S: MutinyStorage> Persist<Chann
 
 #[cfg(test)]
 mod test {
+    use crate::channelpolicy::ChannelPolicy;
     use crate::event::{HTLCStatus, MillisatAmount};
     use crate::keymanager::create_keys_manager;
     use crate::onchain::OnChainWallet;
@@ -710,7 +961,9 @@ mod test {
             fee_paid_msat: None,
             bolt11: None,
             payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
             secret: None,
+            claim_deadline: None,
             last_update: utils::now().as_secs(),
         };
         let result = persister.persist_payment_info(&payment_hash, &payment_info, true);
@@ -753,6 +1006,9 @@ mod test {
             node_id: None,
             reason: "This is a test.".to_string(),
             timestamp: utils::now().as_secs(),
+            destination: None,
+            label: None,
+            note: None,
         };
         let result = persister.persist_channel_closure(user_channel_id, closure.clone());
         assert!(result.is_ok());
@@ -764,6 +1020,158 @@ mod test {
         assert_eq!(result, Some(closure));
     }
 
+    #[test]
+    fn test_persist_batch_channel_open_params() {
+        let test_name = "test_persist_batch_channel_open_params";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+
+        let batch: Vec<u128> = vec![1, 2, 3];
+        let mut params = ChannelOpenParams::new_batch(5.0, batch.clone());
+        persister
+            .persist_channel_open_params(batch[0], params.clone())
+            .unwrap();
+
+        let read = persister
+            .get_channel_open_params(batch[0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.batch, Some(batch.clone()));
+        assert_eq!(read.funding_output, None);
+
+        let output = TxOut {
+            value: 100_000,
+            script_pubkey: Address::from_str("bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c")
+                .unwrap()
+                .script_pubkey(),
+        };
+        params.funding_output = Some(output.clone());
+        persister
+            .persist_channel_open_params(batch[0], params)
+            .unwrap();
+
+        let read = persister
+            .get_channel_open_params(batch[0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.batch, Some(batch));
+        assert_eq!(read.funding_output, Some(output));
+    }
+
+    #[test]
+    fn test_persist_channel_metadata() {
+        let test_name = "test_persist_channel_metadata";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+
+        let user_channel_id: u128 = 123456789;
+        assert_eq!(
+            persister.get_channel_metadata(user_channel_id).unwrap(),
+            None
+        );
+
+        // before the channel is pending it comes from the open params
+        let mut params = ChannelOpenParams::new(1.0);
+        params.label = Some("LSP".to_string());
+        params.note = Some("Paid for inbound".to_string());
+        persister
+            .persist_channel_open_params(user_channel_id, params.clone())
+            .unwrap();
+        let expected = ChannelMetadata::from(&params);
+        assert_eq!(
+            persister.get_channel_metadata(user_channel_id).unwrap(),
+            Some(expected.clone())
+        );
+
+        // and afterwards from its own record
+        persister
+            .persist_channel_metadata(user_channel_id, expected.clone())
+            .unwrap();
+        persister
+            .delete_channel_open_params(user_channel_id)
+            .unwrap();
+        assert_eq!(
+            persister.get_channel_metadata(user_channel_id).unwrap(),
+            Some(expected)
+        );
+
+        persister.delete_channel_metadata(user_channel_id).unwrap();
+        assert_eq!(
+            persister.get_channel_metadata(user_channel_id).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_persist_channel_close_params() {
+        let test_name = "test_persist_channel_close_params";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+
+        let user_channel_id: u128 = 123456789;
+        assert_eq!(
+            persister.get_channel_close_params(user_channel_id).unwrap(),
+            None
+        );
+
+        let params = ChannelCloseParams {
+            destination: Some(
+                Address::from_str("bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c").unwrap(),
+            ),
+            max_fee_sats: Some(1_000),
+        };
+        persister
+            .persist_channel_close_params(user_channel_id, params.clone())
+            .unwrap();
+        assert_eq!(
+            persister.get_channel_close_params(user_channel_id).unwrap(),
+            Some(params)
+        );
+
+        persister
+            .delete_channel_close_params(user_channel_id)
+            .unwrap();
+        assert_eq!(
+            persister.get_channel_close_params(user_channel_id).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_persist_channel_rejection() {
+        let test_name = "test_persist_channel_rejection";
+        log!("{}", test_name);
+
+        let persister = get_test_persister();
+        assert!(persister.list_channel_rejections().unwrap().is_empty());
+
+        let node_id = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        for i in 0..MAX_CHANNEL_REJECTIONS + 5 {
+            let rejection = ChannelRejection {
+                counterparty_node_id: node_id,
+                funding_satoshis: i as u64,
+                reason: "This is a test.".to_string(),
+                timestamp: utils::now().as_secs(),
+            };
+            persister.persist_channel_rejection(rejection).unwrap();
+        }
+
+        // only keeps the newest ones
+        let rejections = persister.list_channel_rejections().unwrap();
+        assert_eq!(rejections.len(), MAX_CHANNEL_REJECTIONS);
+        assert_eq!(rejections[0].funding_satoshis, 5);
+        assert_eq!(
+            rejections.last().unwrap().funding_satoshis,
+            (MAX_CHANNEL_REJECTIONS + 4) as u64
+        );
+    }
+
     #[test]
     fn test_persist_spendable_output_descriptor() {
         let test_name = "test_persist_spendable_output_descriptor";
@@ -911,6 +1319,7 @@ mod test {
                 router.clone(),
                 vec![],
                 esplora.clone(),
+                ChannelPolicy::default().user_config(),
             )
             .await
             .unwrap();
@@ -934,6 +1343,7 @@ mod test {
                 router,
                 vec![],
                 esplora,
+                ChannelPolicy::default().user_config(),
             )
             .await
             .unwrap();
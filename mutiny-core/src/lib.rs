@@ -7,15 +7,19 @@
 )]
 #![feature(io_error_other)]
 #![feature(async_fn_in_trait)]
+pub mod activity;
 // background file is mostly an LDK copy paste
 mod background;
 
 pub mod auth;
 mod chain;
+mod channelpolicy;
+pub mod diagnostics;
 pub mod encrypt;
 pub mod error;
 pub mod esplora;
 mod event;
+mod export;
 mod fees;
 mod gossip;
 mod keymanager;
@@ -24,28 +28,45 @@ mod ldkstorage;
 pub mod lnurlauth;
 pub mod logging;
 mod lspclient;
+pub mod lsps;
+mod messagehandler;
 mod networking;
 mod node;
 pub mod nodemanager;
 pub mod nostr;
 mod onchain;
+pub mod pay_onchain;
 mod peermanager;
+pub mod rebalance;
 pub mod redshift;
 pub mod scb;
 pub mod storage;
 mod subscription;
+pub mod swaps;
 pub mod vss;
 
 #[cfg(any(test, feature = "test-utils"))]
 pub mod test_utils;
 mod utils;
 
+pub use crate::channelpolicy::{
+    CallbackChannelApprover, ChannelPolicy, ChannelRejection, InboundChannelApprover,
+    InboundChannelPolicy, InboundChannelRequest,
+};
 pub use crate::gossip::{GOSSIP_SYNC_TIME_KEY, NETWORK_GRAPH_KEY, PROB_SCORER_KEY};
 pub use crate::keymanager::generate_seed;
 pub use crate::ldkstorage::{CHANNEL_MANAGER_KEY, MONITORS_PREFIX_KEY};
+pub use crate::lspclient::{LspConfig, LspSelectionPolicy};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::networking::socks5::Socks5Proxy;
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::networking::tcp_listener::PeerListenerConfig;
 
 use crate::auth::MutinyAuthClient;
+use crate::logging::LogLevel;
+use crate::scb::backup::ScbBackupDestination;
 use crate::storage::MutinyStorage;
+use crate::swaps::SwapProvider;
 use crate::{error::MutinyError, nostr::ReservedProfile};
 use crate::{nodemanager::NodeManager, nostr::ProfileType};
 use crate::{nostr::NostrManager, utils::sleep};
@@ -67,13 +88,28 @@ pub struct MutinyWalletConfig {
     xprivkey: ExtendedPrivKey,
     #[cfg(target_arch = "wasm32")]
     websocket_proxy_addr: Option<String>,
+    #[cfg(not(target_arch = "wasm32"))]
+    socks5_proxy: Option<Socks5Proxy>,
+    #[cfg(not(target_arch = "wasm32"))]
+    peer_listener: Option<PeerListenerConfig>,
+    #[cfg(not(target_arch = "wasm32"))]
+    p2p_gossip: bool,
     network: Network,
     user_esplora_url: Option<String>,
     user_rgs_url: Option<String>,
     lsp_url: Option<String>,
+    lsps: Vec<LspConfig>,
+    lsp_selection_policy: LspSelectionPolicy,
+    channel_policy: ChannelPolicy,
+    inbound_channel_policy: InboundChannelPolicy,
+    inbound_channel_approver: Option<Arc<dyn InboundChannelApprover>>,
+    scb_backup_destinations: Vec<Arc<dyn ScbBackupDestination>>,
+    swap_provider_url: Option<String>,
+    swap_provider: Option<Arc<dyn SwapProvider>>,
     auth_client: Option<Arc<MutinyAuthClient>>,
     subscription_url: Option<String>,
     do_not_connect_peers: bool,
+    log_level: LogLevel,
 }
 
 impl MutinyWalletConfig {
@@ -92,13 +128,28 @@ impl MutinyWalletConfig {
             xprivkey,
             #[cfg(target_arch = "wasm32")]
             websocket_proxy_addr,
+            #[cfg(not(target_arch = "wasm32"))]
+            socks5_proxy: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            peer_listener: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            p2p_gossip: false,
             network,
             user_esplora_url,
             user_rgs_url,
             lsp_url,
+            lsps: vec![],
+            lsp_selection_policy: LspSelectionPolicy::default(),
+            channel_policy: ChannelPolicy::default(),
+            inbound_channel_policy: InboundChannelPolicy::default(),
+            inbound_channel_approver: None,
+            scb_backup_destinations: vec![],
+            swap_provider_url: None,
+            swap_provider: None,
             auth_client,
             subscription_url,
             do_not_connect_peers: false,
+            log_level: LogLevel::Trace,
         }
     }
 
@@ -106,6 +157,115 @@ impl MutinyWalletConfig {
         self.do_not_connect_peers = true;
         self
     }
+
+    /// Sends our peer connections, Esplora, RGS and LSP requests through
+    /// a SOCKS5 proxy like Tor. This is needed to connect to onion peers.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_socks5_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.socks5_proxy = Some(proxy);
+        self
+    }
+
+    /// Accepts connections from other lightning nodes and announces our addresses
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_peer_listener(mut self, peer_listener: PeerListenerConfig) -> Self {
+        self.peer_listener = Some(peer_listener);
+        self
+    }
+
+    /// Syncs the network graph from our peers over P2P gossip instead of from an RGS server,
+    /// checking the channels we hear about against Esplora
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_p2p_gossip(mut self) -> Self {
+        self.p2p_gossip = true;
+        self
+    }
+
+    /// Sets the minimum level of logs that are saved to storage
+    pub fn with_log_level(mut self, log_level: LogLevel) -> Self {
+        self.log_level = log_level;
+        self
+    }
+
+    /// Adds LSPs with their max fees, these are used after any from `lsp_url`.
+    /// If an LSP is also in `lsp_url` its max fee is taken from here.
+    pub fn with_lsps(mut self, lsps: Vec<LspConfig>) -> Self {
+        self.lsps.extend(lsps);
+        self
+    }
+
+    /// Sets how we choose which LSP to create an invoice with
+    pub fn with_lsp_selection_policy(mut self, policy: LspSelectionPolicy) -> Self {
+        self.lsp_selection_policy = policy;
+        self
+    }
+
+    /// Sets the channel policy for all of our nodes, this can be
+    /// overridden for a node with `NodeManager::set_node_channel_policy`.
+    pub fn with_channel_policy(mut self, policy: ChannelPolicy) -> Self {
+        self.channel_policy = policy;
+        self
+    }
+
+    /// Sets which inbound channels our nodes accept
+    pub fn with_inbound_channel_policy(mut self, policy: InboundChannelPolicy) -> Self {
+        self.inbound_channel_policy = policy;
+        self
+    }
+
+    /// Asks the approver about every inbound channel that passes the
+    /// inbound channel policy, so the user can be prompted
+    pub fn with_inbound_channel_approver(
+        mut self,
+        approver: Arc<dyn InboundChannelApprover>,
+    ) -> Self {
+        self.inbound_channel_approver = Some(approver);
+        self
+    }
+
+    /// Adds a destination to push our static channel backups to
+    /// whenever our channels change.
+    pub fn with_scb_backup_destination(
+        mut self,
+        destination: Arc<dyn ScbBackupDestination>,
+    ) -> Self {
+        self.scb_backup_destinations.push(destination);
+        self
+    }
+
+    /// Makes swaps between our on-chain and lightning balances with the swap provider at the url
+    pub fn with_swap_provider_url(mut self, url: String) -> Self {
+        self.swap_provider_url = Some(url);
+        self
+    }
+
+    /// Makes swaps between our on-chain and lightning balances with the given swap provider,
+    /// this is used over one from `with_swap_provider_url`.
+    pub fn with_swap_provider(mut self, provider: Arc<dyn SwapProvider>) -> Self {
+        self.swap_provider = Some(provider);
+        self
+    }
+
+    /// All the configured LSPs, in order of preference
+    pub(crate) fn lsp_configs(&self) -> Vec<LspConfig> {
+        let mut configs: Vec<LspConfig> = self
+            .lsp_url
+            .iter()
+            .flat_map(|urls| urls.split(','))
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| LspConfig::new(url.to_string(), None))
+            .collect();
+
+        for lsp in self.lsps.iter() {
+            match configs.iter_mut().find(|c| c.url == lsp.url) {
+                Some(config) => config.max_fee_msat = lsp.max_fee_msat,
+                None => configs.push(lsp.clone()),
+            }
+        }
+
+        configs
+    }
 }
 
 #[derive(Clone)]
@@ -120,6 +280,10 @@ pub struct MutinyWallet<S: MutinyStorage> {
 }
 
 impl<S: MutinyStorage> MutinyWallet<S> {
+    /// Starts the wallet and its nodes.
+    ///
+    /// On native this has to be run within a tokio `LocalSet` that is kept running,
+    /// the wallet's background tasks like syncing and accepting peer connections run on it.
     pub async fn new(
         storage: S,
         config: MutinyWalletConfig,
@@ -135,6 +299,8 @@ impl<S: MutinyStorage> MutinyWallet<S> {
         };
 
         NodeManager::start_sync(node_manager.clone());
+        NodeManager::start_swaps(node_manager.clone());
+        NodeManager::start_pay_onchain_jobs(node_manager.clone());
 
         // create nostr manager
         let nostr = Arc::new(NostrManager::from_mnemonic(
@@ -163,6 +329,8 @@ impl<S: MutinyStorage> MutinyWallet<S> {
             Arc::new(NodeManager::new(self.config.clone(), self.storage.clone()).await?);
         NodeManager::start_sync(self.node_manager.clone());
         NodeManager::start_redshifts(self.node_manager.clone());
+        NodeManager::start_swaps(self.node_manager.clone());
+        NodeManager::start_pay_onchain_jobs(self.node_manager.clone());
         Ok(())
     }
 
@@ -364,8 +532,8 @@ impl<S: MutinyStorage> MutinyWallet<S> {
 #[cfg(test)]
 mod tests {
     use crate::{
-        encrypt::encryption_key_from_pass, generate_seed, nodemanager::NodeManager, MutinyWallet,
-        MutinyWalletConfig,
+        encrypt::encryption_key_from_pass, generate_seed, nodemanager::NodeManager, LspConfig,
+        MutinyWallet, MutinyWalletConfig,
     };
     use bitcoin::util::bip32::ExtendedPrivKey;
     use bitcoin::Network;
@@ -377,6 +545,39 @@ mod tests {
 
     wasm_bindgen_test_configure!(run_in_browser);
 
+    #[test]
+    fn test_lsp_configs() {
+        let test_name = "test_lsp_configs";
+        log!("{}", test_name);
+
+        let mnemonic = generate_seed(12).unwrap();
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &mnemonic.to_seed("")).unwrap();
+        let config = MutinyWalletConfig::new(
+            xpriv,
+            #[cfg(target_arch = "wasm32")]
+            None,
+            Network::Regtest,
+            None,
+            None,
+            Some("https://lsp1.example.com, https://lsp2.example.com".to_string()),
+            None,
+            None,
+        )
+        .with_lsps(vec![
+            LspConfig::new("https://lsp2.example.com".to_string(), Some(10_000)),
+            LspConfig::new("https://lsp3.example.com".to_string(), Some(5_000)),
+        ]);
+
+        assert_eq!(
+            config.lsp_configs(),
+            vec![
+                LspConfig::new("https://lsp1.example.com".to_string(), None),
+                LspConfig::new("https://lsp2.example.com".to_string(), Some(10_000)),
+                LspConfig::new("https://lsp3.example.com".to_string(), Some(5_000)),
+            ]
+        );
+    }
+
     #[test]
     async fn create_mutiny_wallet() {
         let test_name = "create_mutiny_wallet";
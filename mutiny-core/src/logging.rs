@@ -6,24 +6,169 @@ use std::sync::{
 use crate::storage::MutinyStorage;
 use crate::utils::Mutex;
 use crate::{error::MutinyError, utils, utils::sleep};
-use chrono::Utc;
+use bitcoin::secp256k1::PublicKey;
+use chrono::{TimeZone, Utc};
+use core::fmt;
 use lightning::util::logger::{Level, Logger, Record};
 use log::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 pub(crate) const LOGGING_KEY: &str = "logs";
+/// Once the current log gets too big it is moved here and a new one is started
+pub(crate) const ROTATED_LOGGING_KEY: &str = "logs_rotated";
+
+/// The approximate max size in bytes of the persisted log before it is rotated
+const MAX_LOG_SIZE_BYTES: usize = 1_000_000;
+/// Approximate size of a log record, not including its module and message
+const LOG_RECORD_OVERHEAD_BYTES: usize = 128;
+
+const NWC_URI_PREFIX: &str = "nostr+walletconnect:";
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+/// The level of a log record, mirrors LDK's [`Level`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Gossip,
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<Level> for LogLevel {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Gossip => LogLevel::Gossip,
+            Level::Trace => LogLevel::Trace,
+            Level::Debug => LogLevel::Debug,
+            Level::Info => LogLevel::Info,
+            Level::Warn => LogLevel::Warn,
+            Level::Error => LogLevel::Error,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            LogLevel::Gossip => "GOSSIP",
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        };
+        f.pad(str)
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = MutinyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gossip" => Ok(LogLevel::Gossip),
+            "trace" => Ok(LogLevel::Trace),
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(MutinyError::InvalidArgumentsError),
+        }
+    }
+}
 
-const MAX_LOG_ITEMS: usize = 10_000;
+/// A single structured log entry
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LogRecord {
+    /// Milliseconds since the epoch
+    pub timestamp: u64,
+    pub level: LogLevel,
+    pub module: String,
+    pub line: u32,
+    /// The node that created the log, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_pubkey: Option<PublicKey>,
+    pub message: String,
+}
+
+impl LogRecord {
+    fn approximate_size(&self) -> usize {
+        self.module.len() + self.message.len() + LOG_RECORD_OVERHEAD_BYTES
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Note that a "real" lightning node almost certainly does *not* want subsecond
+        // precision for message-receipt information as it makes log entries a target for
+        // deanonymization attacks. For testing, however, its quite useful.
+        let time = Utc
+            .timestamp_millis_opt(self.timestamp as i64)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+            .unwrap_or_default();
+        write!(
+            f,
+            "{time} {:<5} [{}:{}] ",
+            self.level, self.module, self.line
+        )?;
+        if let Some(pubkey) = self.node_pubkey {
+            write!(f, "[{pubkey}] ")?;
+        }
+        writeln!(f, "{}", self.message)
+    }
+}
+
+/// Filter for [`MutinyLogger::query_logs`], every field that is set must match
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LogQuery {
+    /// Only include records at or above this level
+    pub min_level: Option<LogLevel>,
+    /// Only include records at or after this time, in milliseconds since the epoch
+    pub start_time: Option<u64>,
+    /// Only include records at or before this time, in milliseconds since the epoch
+    pub end_time: Option<u64>,
+    /// Only include records from modules that start with this path
+    pub module: Option<String>,
+    /// Only include records from this node
+    pub node_pubkey: Option<PublicKey>,
+    /// Only return up to this many of the most recent records
+    pub limit: Option<usize>,
+}
+
+impl LogQuery {
+    fn matches(&self, record: &LogRecord) -> bool {
+        self.min_level.map_or(true, |l| record.level >= l)
+            && self.start_time.map_or(true, |t| record.timestamp >= t)
+            && self.end_time.map_or(true, |t| record.timestamp <= t)
+            && self
+                .module
+                .as_ref()
+                .map_or(true, |m| record.module.starts_with(m.as_str()))
+            && self
+                .node_pubkey
+                .map_or(true, |pk| record.node_pubkey == Some(pk))
+    }
+}
 
 #[derive(Clone)]
 pub struct MutinyLogger {
     should_write_to_storage: bool,
-    memory_logs: Arc<Mutex<Vec<String>>>,
+    /// Records below this level are not written to storage
+    min_level: LogLevel,
+    node_pubkey: Option<PublicKey>,
+    memory_logs: Arc<Mutex<Vec<LogRecord>>>,
 }
 
 impl MutinyLogger {
     pub fn with_writer<S: MutinyStorage>(stop: Arc<AtomicBool>, logging_db: S) -> Self {
         let l = MutinyLogger {
             should_write_to_storage: true,
+            min_level: LogLevel::Trace,
+            node_pubkey: None,
             memory_logs: Arc::new(Mutex::new(vec![])),
         };
 
@@ -54,7 +199,7 @@ impl MutinyLogger {
                 if let Some(logs) = memory_logs_clone {
                     if !logs.is_empty() {
                         // append them to storage
-                        match write_logging_data(&logging_db, logs).await {
+                        match write_logging_data(&logging_db, logs, MAX_LOG_SIZE_BYTES).await {
                             Ok(_) => {}
                             Err(_) => {
                                 error!("could not write logging data to storage, trying again next time, log entries may be lost");
@@ -68,6 +213,22 @@ impl MutinyLogger {
         l
     }
 
+    /// Sets the minimum level of the records that are written to storage.
+    /// Console output is still controlled by the `log` crate's max level.
+    pub fn with_min_level(mut self, min_level: LogLevel) -> Self {
+        self.min_level = min_level;
+        self
+    }
+
+    /// Creates a logger that tags every record with the given node's pubkey.
+    /// The new logger writes to the same storage as this one.
+    pub(crate) fn with_node_pubkey(&self, node_pubkey: PublicKey) -> Self {
+        Self {
+            node_pubkey: Some(node_pubkey),
+            ..self.clone()
+        }
+    }
+
     pub(crate) fn get_logs<S: MutinyStorage>(
         &self,
         storage: &S,
@@ -75,7 +236,31 @@ impl MutinyLogger {
         if !self.should_write_to_storage {
             return Ok(None);
         }
-        get_logging_data(storage)
+        let logs = get_logging_data(storage)?
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        Ok(Some(logs))
+    }
+
+    /// Returns the persisted log records that match the query, oldest first.
+    pub(crate) fn query_logs<S: MutinyStorage>(
+        &self,
+        storage: &S,
+        query: &LogQuery,
+    ) -> Result<Option<Vec<LogRecord>>, MutinyError> {
+        if !self.should_write_to_storage {
+            return Ok(None);
+        }
+        let mut records: Vec<LogRecord> = get_logging_data(storage)?
+            .into_iter()
+            .filter(|r| query.matches(r))
+            .collect();
+        if let Some(limit) = query.limit {
+            let start_index = records.len().saturating_sub(limit);
+            records.drain(..start_index);
+        }
+        Ok(Some(records))
     }
 }
 
@@ -83,6 +268,8 @@ impl Default for MutinyLogger {
     fn default() -> Self {
         Self {
             should_write_to_storage: Default::default(),
+            min_level: LogLevel::Trace,
+            node_pubkey: None,
             memory_logs: Arc::new(Mutex::new(vec![])),
         }
     }
@@ -90,22 +277,19 @@ impl Default for MutinyLogger {
 
 impl Logger for MutinyLogger {
     fn log(&self, record: &Record) {
-        let raw_log = record.args.to_string();
-        let log = format!(
-            "{} {:<5} [{}:{}] {}\n",
-            // Note that a "real" lightning node almost certainly does *not* want subsecond
-            // precision for message-receipt information as it makes log entries a target for
-            // deanonymization attacks. For testing, however, its quite useful.
-            Utc::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-            record.level,
-            record.module_path,
-            record.line,
-            raw_log
-        );
+        let log_record = LogRecord {
+            timestamp: utils::now().as_millis() as u64,
+            level: record.level.into(),
+            module: record.module_path.to_string(),
+            line: record.line,
+            node_pubkey: self.node_pubkey,
+            message: redact_secrets(&record.args.to_string()),
+        };
+        let log = log_record.to_string();
 
-        if self.should_write_to_storage && record.level >= Level::Trace {
+        if self.should_write_to_storage && log_record.level >= self.min_level {
             if let Ok(mut memory_logs) = self.memory_logs.lock() {
-                memory_logs.push(log.clone());
+                memory_logs.push(log_record);
             } else {
                 warn!("Failed to lock memory_logs, log entry may be lost.");
             }
@@ -122,22 +306,106 @@ impl Logger for MutinyLogger {
     }
 }
 
-fn get_logging_data<S: MutinyStorage>(storage: &S) -> Result<Option<Vec<String>>, MutinyError> {
-    storage.get_data(LOGGING_KEY)
+/// Removes secrets from a log message so they are never written to storage.
+/// This redacts nostr wallet connect URIs and anything that looks like a preimage.
+pub(crate) fn redact_secrets(message: &str) -> String {
+    let message = redact_nwc_uris(message);
+    redact_preimages(&message)
+}
+
+fn redact_nwc_uris(message: &str) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(index) = rest.find(NWC_URI_PREFIX) {
+        let after = &rest[index + NWC_URI_PREFIX.len()..];
+        let end = after
+            .find(|c: char| c.is_whitespace() || c == '"' || c == '\'')
+            .unwrap_or(after.len());
+        result.push_str(&rest[..index]);
+        result.push_str(NWC_URI_PREFIX);
+        result.push_str(REDACTED);
+        rest = &after[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Redacts the value following the word "preimage", either as a hex string
+/// or as the byte array from a `PaymentPreimage`'s debug output.
+fn redact_preimages(message: &str) -> String {
+    const KEYWORD: &str = "preimage";
+    // ascii lowercase keeps the byte indexes the same as the original
+    let lowercase = message.to_ascii_lowercase();
+    let mut result = String::with_capacity(message.len());
+    let mut position = 0;
+    while let Some(found) = lowercase[position..].find(KEYWORD) {
+        let keyword_end = position + found + KEYWORD.len();
+        result.push_str(&message[position..keyword_end]);
+        position = keyword_end;
+
+        // skip separators between the keyword and the value
+        let rest = &message[position..];
+        let value_start = rest
+            .find(|c: char| !matches!(c, ' ' | ':' | '=' | '"' | '\'' | '('))
+            .unwrap_or(rest.len());
+        let value = &rest[value_start..];
+
+        let value_len = if value.starts_with('[') {
+            value.find(']').map(|i| i + 1).unwrap_or(value.len())
+        } else {
+            let hex_len = value
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(value.len());
+            if hex_len == 64 {
+                hex_len
+            } else {
+                0
+            }
+        };
+
+        if value_len > 0 {
+            result.push_str(&rest[..value_start]);
+            result.push_str(REDACTED);
+            position += value_start + value_len;
+        }
+    }
+    result.push_str(&message[position..]);
+    result
+}
+
+/// Reads the persisted log records, oldest first
+fn get_logging_data<S: MutinyStorage>(storage: &S) -> Result<Vec<LogRecord>, MutinyError> {
+    let mut records = read_log_records(storage, ROTATED_LOGGING_KEY);
+    records.append(&mut read_log_records(storage, LOGGING_KEY));
+    Ok(records)
+}
+
+fn read_log_records<S: MutinyStorage>(storage: &S, key: &str) -> Vec<LogRecord> {
+    // logs from before structured logging can't be read, these are just dropped
+    storage
+        .get_data::<Vec<LogRecord>>(key)
+        .ok()
+        .flatten()
+        .unwrap_or_default()
 }
 
 async fn write_logging_data<S: MutinyStorage>(
     storage: &S,
-    mut recent_logs: Vec<String>,
+    mut recent_logs: Vec<LogRecord>,
+    max_size: usize,
 ) -> Result<(), MutinyError> {
-    // get the existing data so we can append to it, trimming if needed
+    // get the existing data so we can append to it, rotating if needed
     // Note there is a potential race condition here if the logs are being written to
     // concurrently, but we don't care about that for now.
-    let mut existing_logs: Vec<String> = get_logging_data(storage)?.unwrap_or_default();
+    let mut existing_logs = read_log_records(storage, LOGGING_KEY);
     existing_logs.append(&mut recent_logs);
-    if existing_logs.len() > MAX_LOG_ITEMS {
-        let start_index = existing_logs.len() - MAX_LOG_ITEMS;
-        existing_logs.drain(..start_index);
+
+    let size: usize = existing_logs.iter().map(|r| r.approximate_size()).sum();
+    if size > max_size {
+        // move the current logs to the rotated key, replacing the previously
+        // rotated logs, and start a new log
+        storage.set_data(ROTATED_LOGGING_KEY, &existing_logs, None)?;
+        existing_logs.clear();
     }
 
     // Save the logs
@@ -187,7 +455,7 @@ mod tests {
 
     use crate::{test_utils::*, utils::sleep};
 
-    use crate::logging::MutinyLogger;
+    use super::*;
     use crate::storage::MemoryStorage;
 
     #[test]
@@ -232,4 +500,151 @@ mod tests {
 
         stop.swap(true, Ordering::Relaxed);
     }
+
+    #[test]
+    fn test_redact_secrets() {
+        let test_name = "redact_secrets";
+        log!("{}", test_name);
+
+        let uri = "nostr+walletconnect://f6d8d0a1fbd14a9ef9d3e0a5ba3ad1f6e2f7d9e1b1a0ae6e0e66a1c6b4c3d2e1?relay=wss%3A%2F%2Frelay.damus.io&secret=0dbe8ba48e0a9c3ae1d59f2a2f1e1c2a";
+        let redacted = redact_secrets(&format!("created nwc profile: {uri} done"));
+        assert_eq!(
+            redacted,
+            "created nwc profile: nostr+walletconnect:[REDACTED] done"
+        );
+
+        let preimage = "8f8a3a1b07ab3a0d3cda4bb2c23bee7bd1aa1b1c8e25b1e8f0c2b5e0a5c8f1d2";
+        let redacted = redact_secrets(&format!("Claimed payment with Preimage: {preimage}"));
+        assert_eq!(redacted, "Claimed payment with Preimage: [REDACTED]");
+
+        let redacted = redact_secrets("got payment_preimage: Some(PaymentPreimage([1, 2, 3]))");
+        assert_eq!(
+            redacted,
+            "got payment_preimage: Some(PaymentPreimage([REDACTED]))"
+        );
+
+        // messages without secrets are unchanged
+        let message = "no preimage known for payment hash";
+        assert_eq!(redact_secrets(message), message);
+    }
+
+    #[test]
+    async fn test_query_logs() {
+        let test_name = "query_logs";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let logger = MutinyLogger::with_writer(Arc::new(AtomicBool::new(true)), storage.clone());
+
+        let pubkey = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let record = |timestamp: u64, level: LogLevel, module: &str| LogRecord {
+            timestamp,
+            level,
+            module: module.to_string(),
+            line: 1,
+            node_pubkey: None,
+            message: "test".to_string(),
+        };
+        let records = vec![
+            record(1_000, LogLevel::Debug, "mutiny_core::node"),
+            record(2_000, LogLevel::Info, "mutiny_core::nodemanager"),
+            LogRecord {
+                node_pubkey: Some(pubkey),
+                ..record(3_000, LogLevel::Error, "mutiny_core::node")
+            },
+            record(4_000, LogLevel::Warn, "lightning::ln::channelmanager"),
+        ];
+        write_logging_data(&storage, records.clone(), MAX_LOG_SIZE_BYTES)
+            .await
+            .unwrap();
+
+        let all = logger
+            .query_logs(&storage, &LogQuery::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(all, records);
+
+        let query = LogQuery {
+            min_level: Some(LogLevel::Info),
+            module: Some("mutiny_core".to_string()),
+            ..Default::default()
+        };
+        let result = logger.query_logs(&storage, &query).unwrap().unwrap();
+        assert_eq!(result, vec![records[1].clone(), records[2].clone()]);
+
+        let query = LogQuery {
+            start_time: Some(2_000),
+            end_time: Some(3_000),
+            node_pubkey: Some(pubkey),
+            ..Default::default()
+        };
+        let result = logger.query_logs(&storage, &query).unwrap().unwrap();
+        assert_eq!(result, vec![records[2].clone()]);
+
+        let query = LogQuery {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let result = logger.query_logs(&storage, &query).unwrap().unwrap();
+        assert_eq!(result, records[2..].to_vec());
+
+        assert_eq!(
+            MutinyLogger::default()
+                .query_logs(&storage, &LogQuery::default())
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    async fn test_log_rotation() {
+        let test_name = "log_rotation";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let logger = MutinyLogger::with_writer(Arc::new(AtomicBool::new(true)), storage.clone());
+
+        let records: Vec<LogRecord> = (0..10)
+            .map(|i| LogRecord {
+                timestamp: i,
+                level: LogLevel::Info,
+                module: "mutiny_core::node".to_string(),
+                line: 1,
+                node_pubkey: None,
+                message: format!("message {i}"),
+            })
+            .collect();
+        let max_size = records[0].approximate_size() * 5;
+
+        write_logging_data(&storage, records[..4].to_vec(), max_size)
+            .await
+            .unwrap();
+        assert_eq!(read_log_records(&storage, ROTATED_LOGGING_KEY), vec![]);
+
+        // going over the max size moves the logs to the rotated key
+        write_logging_data(&storage, records[4..8].to_vec(), max_size)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_log_records(&storage, ROTATED_LOGGING_KEY),
+            records[..8].to_vec()
+        );
+        assert_eq!(read_log_records(&storage, LOGGING_KEY), vec![]);
+
+        write_logging_data(&storage, records[8..].to_vec(), max_size)
+            .await
+            .unwrap();
+        let logs = logger.get_logs(&storage).unwrap().unwrap();
+        assert_eq!(logs.len(), 10);
+        assert!(logs.last().unwrap().contains("message 9"));
+
+        // old unstructured logs are ignored
+        storage
+            .set_data(LOGGING_KEY, vec!["old log".to_string()], None)
+            .unwrap();
+        assert_eq!(read_log_records(&storage, LOGGING_KEY), vec![]);
+    }
 }
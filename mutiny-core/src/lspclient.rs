@@ -1,15 +1,90 @@
 use bitcoin::secp256k1::PublicKey;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 use crate::error::MutinyError;
+use crate::node::{is_websocket_url, split_peer_connection_string};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::socks5::{http_client, Socks5Proxy};
+
+/// An LSP to get channels from and the most we are willing to pay it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LspConfig {
+    /// The url of a Voltage style LSP or the connection string of an LSPS one
+    pub url: String,
+    /// The max fee we will pay the LSP to open a channel, in millisatoshis
+    pub max_fee_msat: Option<u64>,
+}
+
+impl LspConfig {
+    pub fn new(url: String, max_fee_msat: Option<u64>) -> Self {
+        Self { url, max_fee_msat }
+    }
+}
+
+/// How we choose which LSP to create an invoice with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LspSelectionPolicy {
+    /// Use the node's LSP, falling back to the others in the order they were configured
+    #[default]
+    Preferred,
+    /// Get a quote from every LSP and use the cheapest one,
+    /// falling back to the next cheapest if it fails
+    Cheapest,
+}
+
+impl FromStr for LspSelectionPolicy {
+    type Err = MutinyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "preferred" => Ok(LspSelectionPolicy::Preferred),
+            "cheapest" => Ok(LspSelectionPolicy::Cheapest),
+            _ => Err(MutinyError::InvalidArgumentsError),
+        }
+    }
+}
+
+impl LspSelectionPolicy {
+    /// Picks the LSP for a node that doesn't have one saved yet, from the
+    /// given LSPs in order of preference.
+    ///
+    /// We don't have an amount to get quotes for yet, so with the cheapest
+    /// policy this is the LSP with the lowest max fee we will pay it.
+    pub(crate) fn select_node_lsp<'a>(
+        &self,
+        lsp_clients: &'a [LspClient],
+    ) -> Option<&'a LspClient> {
+        match self {
+            LspSelectionPolicy::Preferred => lsp_clients.first(),
+            LspSelectionPolicy::Cheapest => lsp_clients
+                .iter()
+                .min_by_key(|lsp| lsp.max_fee_msat.unwrap_or(u64::MAX)),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct LspClient {
     pub pubkey: PublicKey,
     pub connection_string: String,
+    /// The url or connection string the client was created from
     pub url: String,
-    pub http_client: Client,
+    pub protocol: LspProtocol,
+    /// The max fee we will pay this LSP, in millisatoshis
+    pub max_fee_msat: Option<u64>,
+}
+
+/// How we talk to the LSP
+#[derive(Clone, Debug)]
+pub(crate) enum LspProtocol {
+    /// Voltage's HTTP API
+    Voltage(Client),
+    /// LSPS0 messages sent over our connection to the LSP,
+    /// with the optional token the LSP gave us
+    Lsps { token: Option<String> },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,13 +144,52 @@ struct ErrorResponse {
     message: String,
 }
 
+/// Which of an LSP's connection methods we prefer, lowest first.
+/// Returns None for the ones we can't use.
+fn connection_method_priority(item_type: GetInfoAddressType) -> Option<u8> {
+    match item_type {
+        // In the browser a websocket lets us skip the websocket proxy
+        #[cfg(target_arch = "wasm32")]
+        GetInfoAddressType::Websocket => Some(0),
+        GetInfoAddressType::IPV4 => Some(1),
+        GetInfoAddressType::IPV6 => Some(2),
+        GetInfoAddressType::TORV3 => Some(3),
+        _ => None,
+    }
+}
+
+/// The address part of a connection string for one of an LSP's connection methods
+fn connection_method_address(address: &GetInfoAddress) -> String {
+    match address.item_type {
+        GetInfoAddressType::Websocket if is_websocket_url(&address.address) => {
+            address.address.clone()
+        }
+        GetInfoAddressType::Websocket => format!("wss://{}:{}", address.address, address.port),
+        _ => format!("{}:{}", address.address, address.port),
+    }
+}
+
 const GET_INFO_PATH: &str = "/api/v1/info";
 const PROPOSAL_PATH: &str = "/api/v1/proposal";
 const FEE_PATH: &str = "/api/v1/fee";
 
 impl LspClient {
-    pub async fn new(url: &str) -> Result<Self, MutinyError> {
+    /// Creates a client from either an http(s) url for a Voltage style LSP or
+    /// a `pubkey@host:port` connection string for an LSPS one. An LSPS
+    /// connection string can have a `?token=` at the end to pass to the LSP.
+    pub async fn new(
+        config: &LspConfig,
+        #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<&Socks5Proxy>,
+    ) -> Result<Self, MutinyError> {
+        let url = config.url.as_str();
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Self::new_lsps(url, config.max_fee_msat);
+        }
+
+        #[cfg(target_arch = "wasm32")]
         let http_client = Client::new();
+        #[cfg(not(target_arch = "wasm32"))]
+        let http_client = http_client(socks5_proxy, url)?;
         let get_info_response: GetInfoResponse = http_client
             .get(format!("{}{}", url, GET_INFO_PATH))
             .send()
@@ -88,26 +202,15 @@ impl LspClient {
         let connection_string = get_info_response
             .connection_methods
             .iter()
-            .filter(|address| {
-                matches!(
-                    address.item_type,
-                    GetInfoAddressType::IPV4 | GetInfoAddressType::IPV6 | GetInfoAddressType::TORV3
-                )
+            .filter_map(|address| {
+                connection_method_priority(address.item_type).map(|p| (p, address))
             })
-            .min_by_key(|address| match address.item_type {
-                // Prioritize IPV4, then 6, then tor
-                // TODO support websocket one day
-                GetInfoAddressType::IPV4 => 0,
-                GetInfoAddressType::IPV6 => 1,
-                GetInfoAddressType::TORV3 => 2,
-                _ => unreachable!(),
-            })
-            .map(|address| {
+            .min_by_key(|(priority, _)| *priority)
+            .map(|(_, address)| {
                 format!(
-                    "{}@{}:{}",
-                    get_info_response.pubkey.to_string(),
-                    address.address,
-                    address.port
+                    "{}@{}",
+                    get_info_response.pubkey,
+                    connection_method_address(address)
                 )
             })
             .ok_or_else(|| anyhow::anyhow!("No suitable connection method found"))?;
@@ -116,10 +219,43 @@ impl LspClient {
             pubkey: get_info_response.pubkey,
             url: String::from(url),
             connection_string,
-            http_client,
+            protocol: LspProtocol::Voltage(http_client),
+            max_fee_msat: config.max_fee_msat,
+        })
+    }
+
+    fn new_lsps(url: &str, max_fee_msat: Option<u64>) -> Result<Self, MutinyError> {
+        let (connection_string, token) = match url.split_once("?token=") {
+            Some((connection_string, token)) => (connection_string, Some(token.to_string())),
+            None => (url, None),
+        };
+        let (pubkey, _) = split_peer_connection_string(connection_string)?;
+
+        Ok(LspClient {
+            pubkey,
+            connection_string: connection_string.to_string(),
+            url: String::from(url),
+            protocol: LspProtocol::Lsps { token },
+            max_fee_msat,
         })
     }
 
+    /// Checks the fee against the max fee configured for this LSP
+    pub(crate) fn check_fee(&self, fee_msat: u64) -> Result<(), MutinyError> {
+        match self.max_fee_msat {
+            Some(max_fee_msat) if fee_msat > max_fee_msat => Err(MutinyError::LspFeeTooHighError),
+            _ => Ok(()),
+        }
+    }
+
+    /// The http client for a Voltage style LSP, LSPS ones do not have an http API
+    fn http_client(&self) -> Result<&Client, MutinyError> {
+        match &self.protocol {
+            LspProtocol::Voltage(client) => Ok(client),
+            LspProtocol::Lsps { .. } => Err(MutinyError::LspGenericError),
+        }
+    }
+
     pub(crate) async fn get_lsp_invoice(&self, bolt11: String) -> Result<String, MutinyError> {
         let payload = ProposalRequest {
             bolt11,
@@ -128,7 +264,7 @@ impl LspClient {
         };
 
         let response: reqwest::Response = self
-            .http_client
+            .http_client()?
             .post(format!("{}{}", &self.url, PROPOSAL_PATH))
             .json(&payload)
             .send()
@@ -171,7 +307,7 @@ impl LspClient {
         fee_request: FeeRequest,
     ) -> Result<u64, MutinyError> {
         let fee_response: FeeResponse = self
-            .http_client
+            .http_client()?
             .post(format!("{}{}", &self.url, FEE_PATH))
             .json(&fee_request)
             .send()
@@ -184,3 +320,153 @@ impl LspClient {
         Ok(fee_response.fee_amount_msat)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const PUBKEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    async fn test_lsps_client() {
+        let test_name = "test_lsps_client";
+        log!("{}", test_name);
+
+        let url = format!("{PUBKEY}@127.0.0.1:9735?token=abc");
+        let client = LspClient::new(
+            &LspConfig::new(url.clone(), Some(1_000)),
+            #[cfg(not(target_arch = "wasm32"))]
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(client.pubkey, PublicKey::from_str(PUBKEY).unwrap());
+        assert_eq!(client.connection_string, format!("{PUBKEY}@127.0.0.1:9735"));
+        assert_eq!(client.url, url);
+        assert!(matches!(client.protocol, LspProtocol::Lsps { token: Some(ref t) } if t == "abc"));
+
+        // LSPS LSPs don't have an http API
+        let fee = client
+            .get_lsp_fee_msat(FeeRequest {
+                pubkey: PUBKEY.to_string(),
+                amount_msat: 1_000,
+            })
+            .await;
+        assert!(matches!(fee, Err(MutinyError::LspGenericError)));
+
+        assert!(LspClient::new(
+            &LspConfig::new("not a pubkey".to_string(), None),
+            #[cfg(not(target_arch = "wasm32"))]
+            None,
+        )
+        .await
+        .is_err());
+    }
+
+    #[test]
+    async fn test_check_fee() {
+        let test_name = "test_check_fee";
+        log!("{}", test_name);
+
+        let url = format!("{PUBKEY}@127.0.0.1:9735");
+        let capped = LspClient::new(
+            &LspConfig::new(url.clone(), Some(1_000)),
+            #[cfg(not(target_arch = "wasm32"))]
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(capped.check_fee(1_000).is_ok());
+        assert!(matches!(
+            capped.check_fee(1_001),
+            Err(MutinyError::LspFeeTooHighError)
+        ));
+
+        let uncapped = LspClient::new(
+            &LspConfig::new(url, None),
+            #[cfg(not(target_arch = "wasm32"))]
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(uncapped.check_fee(u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_lsp_selection_policy_from_str() {
+        let test_name = "test_lsp_selection_policy_from_str";
+        log!("{}", test_name);
+
+        assert_eq!(
+            LspSelectionPolicy::from_str("cheapest").unwrap(),
+            LspSelectionPolicy::Cheapest
+        );
+        assert_eq!(
+            LspSelectionPolicy::from_str("Preferred").unwrap(),
+            LspSelectionPolicy::Preferred
+        );
+        assert!(LspSelectionPolicy::from_str("random").is_err());
+    }
+
+    #[test]
+    async fn test_select_node_lsp() {
+        let test_name = "test_select_node_lsp";
+        log!("{}", test_name);
+
+        let mut lsp_clients = vec![];
+        for (port, max_fee_msat) in [(9735, None), (9736, Some(2_000)), (9737, Some(1_000))] {
+            let client = LspClient::new(
+                &LspConfig::new(format!("{PUBKEY}@127.0.0.1:{port}"), max_fee_msat),
+                #[cfg(not(target_arch = "wasm32"))]
+                None,
+            )
+            .await
+            .unwrap();
+            lsp_clients.push(client);
+        }
+
+        let preferred = LspSelectionPolicy::Preferred.select_node_lsp(&lsp_clients);
+        assert_eq!(preferred.unwrap().url, lsp_clients[0].url);
+        let cheapest = LspSelectionPolicy::Cheapest.select_node_lsp(&lsp_clients);
+        assert_eq!(cheapest.unwrap().url, lsp_clients[2].url);
+
+        assert!(LspSelectionPolicy::Cheapest.select_node_lsp(&[]).is_none());
+    }
+
+    #[test]
+    fn test_connection_method_address() {
+        let test_name = "test_connection_method_address";
+        log!("{}", test_name);
+
+        let address = |item_type, address: &str| GetInfoAddress {
+            item_type,
+            port: 443,
+            address: address.to_string(),
+        };
+
+        assert_eq!(
+            connection_method_address(&address(GetInfoAddressType::IPV4, "127.0.0.1")),
+            "127.0.0.1:443"
+        );
+        assert_eq!(
+            connection_method_address(&address(GetInfoAddressType::Websocket, "lsp.com")),
+            "wss://lsp.com:443"
+        );
+        assert_eq!(
+            connection_method_address(&address(GetInfoAddressType::Websocket, "wss://lsp.com/ln")),
+            "wss://lsp.com/ln"
+        );
+
+        // we can connect to websockets directly from the browser
+        #[cfg(target_arch = "wasm32")]
+        assert_eq!(
+            connection_method_priority(GetInfoAddressType::Websocket),
+            Some(0)
+        );
+        assert_eq!(connection_method_priority(GetInfoAddressType::TORV2), None);
+    }
+}
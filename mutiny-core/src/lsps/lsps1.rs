@@ -0,0 +1,321 @@
+use crate::error::MutinyError;
+use crate::lsps::{string_amount, LspsMessageHandler};
+use bitcoin::secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+
+pub(crate) const GET_INFO_METHOD: &str = "lsps1.get_info";
+pub(crate) const CREATE_ORDER_METHOD: &str = "lsps1.create_order";
+pub(crate) const GET_ORDER_METHOD: &str = "lsps1.get_order";
+
+pub(crate) const ORDER_NOT_FOUND_ERROR: i32 = 101;
+pub(crate) const OPTION_MISMATCH_ERROR: i32 = 1000;
+
+/// The version of LSPS1 we support
+const LSPS1_VERSION: u16 = 1;
+
+/// How many blocks we want the channel to stay open for if none is given, about 30 days
+const DEFAULT_CHANNEL_EXPIRY_BLOCKS: u32 = 4_320;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetInfoResponse {
+    pub supported_versions: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    pub options: LiquidityOptions,
+}
+
+/// The limits of the channels an LSP will sell
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LiquidityOptions {
+    pub minimum_channel_confirmations: u16,
+    pub max_channel_expiry_blocks: u32,
+    #[serde(with = "string_amount")]
+    pub min_initial_lsp_balance_sat: u64,
+    #[serde(with = "string_amount")]
+    pub max_initial_lsp_balance_sat: u64,
+    #[serde(with = "string_amount")]
+    pub min_channel_balance_sat: u64,
+    #[serde(with = "string_amount")]
+    pub max_channel_balance_sat: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CreateOrderRequest {
+    pub api_version: u16,
+    #[serde(with = "string_amount")]
+    pub lsp_balance_sat: u64,
+    #[serde(with = "string_amount")]
+    pub client_balance_sat: u64,
+    pub confirms_within_blocks: u32,
+    pub channel_expiry_blocks: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refund_onchain_address: Option<String>,
+    pub announce_channel: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetOrderRequest {
+    pub order_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderState {
+    Created,
+    Completed,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderPaymentState {
+    ExpectPayment,
+    Hold,
+    Paid,
+    Refunded,
+}
+
+/// How to pay for an order, either with the lightning invoice or on-chain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderPayment {
+    pub state: OrderPaymentState,
+    #[serde(with = "string_amount")]
+    pub fee_total_sat: u64,
+    #[serde(with = "string_amount")]
+    pub order_total_sat: u64,
+    pub bolt11_invoice: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub onchain_address: Option<String>,
+}
+
+/// The channel opened for an order, once it has been paid
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct OrderChannel {
+    pub funded_at: String,
+    pub funding_outpoint: String,
+    pub expires_at: String,
+}
+
+/// An order for a channel from the LSP with inbound liquidity
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LiquidityOrder {
+    pub order_id: String,
+    #[serde(with = "string_amount")]
+    pub lsp_balance_sat: u64,
+    #[serde(with = "string_amount")]
+    pub client_balance_sat: u64,
+    pub channel_expiry_blocks: u32,
+    pub created_at: String,
+    pub expires_at: String,
+    pub order_state: OrderState,
+    pub payment: OrderPayment,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<OrderChannel>,
+}
+
+/// Gets the limits of the channels the LSP will sell
+pub(crate) async fn get_liquidity_options(
+    handler: &LspsMessageHandler,
+    lsp_pubkey: &PublicKey,
+) -> Result<LiquidityOptions, MutinyError> {
+    let info: GetInfoResponse = handler
+        .send_request(lsp_pubkey, GET_INFO_METHOD, serde_json::json!({}))
+        .await?;
+    if !info.supported_versions.contains(&LSPS1_VERSION) {
+        return Err(MutinyError::LspGenericError);
+    }
+    Ok(info.options)
+}
+
+/// Creates an order for a channel with the given amount of inbound liquidity.
+/// The order has to be paid before the LSP will open the channel.
+pub(crate) async fn create_liquidity_order(
+    handler: &LspsMessageHandler,
+    lsp_pubkey: &PublicKey,
+    lsp_balance_sat: u64,
+    channel_expiry_blocks: Option<u32>,
+    token: Option<String>,
+    refund_onchain_address: Option<String>,
+) -> Result<LiquidityOrder, MutinyError> {
+    let options = get_liquidity_options(handler, lsp_pubkey).await?;
+
+    if lsp_balance_sat < options.min_initial_lsp_balance_sat
+        || lsp_balance_sat < options.min_channel_balance_sat
+    {
+        return Err(MutinyError::BadAmountError);
+    }
+    if lsp_balance_sat > options.max_initial_lsp_balance_sat
+        || lsp_balance_sat > options.max_channel_balance_sat
+    {
+        return Err(MutinyError::LspAmountTooHighError);
+    }
+
+    let channel_expiry_blocks = channel_expiry_blocks
+        .unwrap_or(DEFAULT_CHANNEL_EXPIRY_BLOCKS)
+        .min(options.max_channel_expiry_blocks);
+
+    let request = CreateOrderRequest {
+        api_version: LSPS1_VERSION,
+        lsp_balance_sat,
+        client_balance_sat: 0,
+        confirms_within_blocks: 6,
+        channel_expiry_blocks,
+        token,
+        refund_onchain_address,
+        announce_channel: false,
+    };
+
+    handler
+        .send_request(lsp_pubkey, CREATE_ORDER_METHOD, request)
+        .await
+}
+
+/// Gets the current state of an order
+pub(crate) async fn get_liquidity_order(
+    handler: &LspsMessageHandler,
+    lsp_pubkey: &PublicKey,
+    order_id: String,
+) -> Result<LiquidityOrder, MutinyError> {
+    handler
+        .send_request(lsp_pubkey, GET_ORDER_METHOD, GetOrderRequest { order_id })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::MutinyLogger;
+    use crate::lsps::{LspsResponseError, MockLsp};
+    use crate::test_utils::*;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn order(order_state: &str, payment_state: &str, channel: Value) -> Value {
+        json!({
+            "order_id": "order-1",
+            "lsp_balance_sat": "1000000",
+            "client_balance_sat": "0",
+            "confirms_within_blocks": 6,
+            "channel_expiry_blocks": 2016,
+            "announce_channel": false,
+            "created_at": "2023-07-22T04:26:40.000Z",
+            "expires_at": "2023-07-23T04:26:40.000Z",
+            "order_state": order_state,
+            "payment": {
+                "state": payment_state,
+                "fee_total_sat": "5000",
+                "order_total_sat": "5000",
+                "bolt11_invoice": "lnbc50u1...",
+                "onchain_address": "bc1qxyz",
+            },
+            "channel": channel,
+        })
+    }
+
+    fn mock_lsp() -> MockLsp {
+        MockLsp::new(|method, params| match method {
+            GET_INFO_METHOD => Ok(json!({
+                "supported_versions": [1],
+                "website": "https://example.com",
+                "options": {
+                    "minimum_channel_confirmations": 0,
+                    "minimum_onchain_payment_confirmations": 1,
+                    "max_channel_expiry_blocks": 2016,
+                    "min_initial_client_balance_sat": "0",
+                    "max_initial_client_balance_sat": "0",
+                    "min_initial_lsp_balance_sat": "100000",
+                    "max_initial_lsp_balance_sat": "10000000",
+                    "min_channel_balance_sat": "100000",
+                    "max_channel_balance_sat": "10000000",
+                },
+            })),
+            CREATE_ORDER_METHOD => {
+                assert_eq!(params["lsp_balance_sat"], "1000000");
+                assert_eq!(params["client_balance_sat"], "0");
+                // capped to the LSP's max
+                assert_eq!(params["channel_expiry_blocks"], 2016);
+                Ok(order("CREATED", "EXPECT_PAYMENT", Value::Null))
+            }
+            GET_ORDER_METHOD => match params["order_id"].as_str() {
+                Some("order-1") => Ok(order(
+                    "COMPLETED",
+                    "PAID",
+                    json!({
+                        "funded_at": "2023-07-22T05:26:40.000Z",
+                        "funding_outpoint": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+                        "expires_at": "2023-08-05T05:26:40.000Z",
+                    }),
+                )),
+                _ => Err(LspsResponseError {
+                    code: ORDER_NOT_FOUND_ERROR,
+                    message: "Not found".to_string(),
+                    data: None,
+                }),
+            },
+            _ => panic!("unexpected method {method}"),
+        })
+    }
+
+    #[test]
+    async fn test_liquidity_order_flow() {
+        let test_name = "liquidity_order_flow";
+        log!("{}", test_name);
+
+        let handler = LspsMessageHandler::new(Arc::new(MutinyLogger::default()));
+        let lsp = mock_lsp();
+
+        let (order, methods) = futures::join!(
+            create_liquidity_order(&handler, &lsp.pubkey, 1_000_000, None, None, None),
+            lsp.run(&handler, 2)
+        );
+        assert_eq!(methods, vec![GET_INFO_METHOD, CREATE_ORDER_METHOD]);
+        let order = order.unwrap();
+        assert_eq!(order.order_id, "order-1");
+        assert_eq!(order.order_state, OrderState::Created);
+        assert_eq!(order.payment.state, OrderPaymentState::ExpectPayment);
+        assert_eq!(order.payment.order_total_sat, 5_000);
+        assert_eq!(order.channel, None);
+
+        let (order, _) = futures::join!(
+            get_liquidity_order(&handler, &lsp.pubkey, order.order_id),
+            lsp.run(&handler, 1)
+        );
+        let order = order.unwrap();
+        assert_eq!(order.order_state, OrderState::Completed);
+        assert_eq!(order.payment.state, OrderPaymentState::Paid);
+        assert!(order.channel.is_some());
+
+        let (order, _) = futures::join!(
+            get_liquidity_order(&handler, &lsp.pubkey, "unknown".to_string()),
+            lsp.run(&handler, 1)
+        );
+        assert!(matches!(order, Err(MutinyError::NotFound)));
+    }
+
+    #[test]
+    async fn test_liquidity_order_limits() {
+        let test_name = "liquidity_order_limits";
+        log!("{}", test_name);
+
+        let handler = LspsMessageHandler::new(Arc::new(MutinyLogger::default()));
+        let lsp = mock_lsp();
+
+        let (order, methods) = futures::join!(
+            create_liquidity_order(&handler, &lsp.pubkey, 1_000, None, None, None),
+            lsp.run(&handler, 1)
+        );
+        assert_eq!(methods, vec![GET_INFO_METHOD]);
+        assert!(matches!(order, Err(MutinyError::BadAmountError)));
+
+        let (order, _) = futures::join!(
+            create_liquidity_order(&handler, &lsp.pubkey, 100_000_000, None, None, None),
+            lsp.run(&handler, 1)
+        );
+        assert!(matches!(order, Err(MutinyError::LspAmountTooHighError)));
+    }
+}
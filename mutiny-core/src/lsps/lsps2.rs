@@ -0,0 +1,359 @@
+use crate::error::MutinyError;
+use crate::lsps::{parse_scid, string_amount, LspsMessageHandler};
+use bitcoin::secp256k1::PublicKey;
+use chrono::DateTime;
+use lightning::routing::gossip::RoutingFees;
+use lightning::routing::router::{RouteHint, RouteHintHop};
+use serde::{Deserialize, Serialize};
+
+pub(crate) const GET_VERSIONS_METHOD: &str = "lsps2.get_versions";
+pub(crate) const GET_INFO_METHOD: &str = "lsps2.get_info";
+pub(crate) const BUY_METHOD: &str = "lsps2.buy";
+
+pub(crate) const PAYMENT_SIZE_TOO_SMALL_ERROR: i32 = 202;
+pub(crate) const PAYMENT_SIZE_TOO_LARGE_ERROR: i32 = 203;
+
+/// The version of LSPS2 we support
+const LSPS2_VERSION: u16 = 1;
+
+/// Fee params that expire sooner than this are not used, so we have
+/// time to create the invoice and have it paid.
+const FEE_PARAMS_EXPIRY_BUFFER_SECS: u64 = 60;
+
+/// A fee offered by the LSP for opening a JIT channel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OpeningFeeParams {
+    #[serde(with = "string_amount")]
+    pub min_fee_msat: u64,
+    /// Parts per million of the payment size
+    pub proportional: u32,
+    /// ISO 8601 date after which these params can no longer be used
+    pub valid_until: String,
+    pub min_lifetime: u32,
+    pub max_client_to_self_delay: u32,
+    /// Signature from the LSP over these params, we just pass it back
+    pub promise: String,
+}
+
+impl OpeningFeeParams {
+    /// Calculates the fee for a payment of the given size, as defined by LSPS2
+    pub(crate) fn opening_fee_msat(&self, payment_size_msat: u64) -> Option<u64> {
+        let fee = payment_size_msat
+            .checked_mul(self.proportional as u64)?
+            .checked_add(999_999)?
+            / 1_000_000;
+        Some(fee.max(self.min_fee_msat))
+    }
+
+    pub(crate) fn is_valid(&self, now_secs: u64) -> bool {
+        DateTime::parse_from_rfc3339(&self.valid_until)
+            .map(|valid_until| {
+                valid_until.timestamp() > (now_secs + FEE_PARAMS_EXPIRY_BUFFER_SECS) as i64
+            })
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetVersionsResponse {
+    pub versions: Vec<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetInfoRequest {
+    pub version: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GetInfoResponse {
+    /// Sorted from cheapest to most expensive
+    pub opening_fee_params_menu: Vec<OpeningFeeParams>,
+    #[serde(with = "string_amount")]
+    pub min_payment_size_msat: u64,
+    #[serde(with = "string_amount")]
+    pub max_payment_size_msat: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BuyRequest {
+    pub version: u16,
+    pub opening_fee_params: OpeningFeeParams,
+    #[serde(with = "string_amount")]
+    pub payment_size_msat: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BuyResponse {
+    pub jit_channel_scid: String,
+    pub lsp_cltv_expiry_delta: u16,
+    #[serde(default)]
+    pub client_trusts_lsp: bool,
+}
+
+/// The cheapest fee offered by the LSP for a payment of a given size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JitChannelQuote {
+    pub lsp_pubkey: PublicKey,
+    pub payment_size_msat: u64,
+    pub opening_fee_msat: u64,
+    pub opening_fee_params: OpeningFeeParams,
+}
+
+/// A JIT channel bought from the LSP. The LSP will open a channel to us when
+/// a payment is made to the invoice with [`JitChannel::route_hint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct JitChannel {
+    pub lsp_pubkey: PublicKey,
+    pub scid: u64,
+    pub cltv_expiry_delta: u16,
+    pub payment_size_msat: u64,
+    /// The fee the LSP will take out of the payment
+    pub opening_fee_msat: u64,
+}
+
+impl JitChannel {
+    /// The route hint to put in the invoice, the LSP forwards payments
+    /// over the fake `scid` into the new channel.
+    pub(crate) fn route_hint(&self) -> RouteHint {
+        RouteHint(vec![RouteHintHop {
+            src_node_id: self.lsp_pubkey,
+            short_channel_id: self.scid,
+            fees: RoutingFees {
+                base_msat: 0,
+                proportional_millionths: 0,
+            },
+            cltv_expiry_delta: self.cltv_expiry_delta,
+            htlc_minimum_msat: None,
+            htlc_maximum_msat: None,
+        }])
+    }
+}
+
+/// Gets the cheapest fee from the LSP for a JIT channel that will receive
+/// a payment of the given size.
+pub(crate) async fn get_jit_channel_quote(
+    handler: &LspsMessageHandler,
+    lsp_pubkey: &PublicKey,
+    token: Option<String>,
+    payment_size_msat: u64,
+) -> Result<JitChannelQuote, MutinyError> {
+    let versions: GetVersionsResponse = handler
+        .send_request(lsp_pubkey, GET_VERSIONS_METHOD, serde_json::json!({}))
+        .await?;
+    if !versions.versions.contains(&LSPS2_VERSION) {
+        return Err(MutinyError::LspGenericError);
+    }
+
+    let info: GetInfoResponse = handler
+        .send_request(
+            lsp_pubkey,
+            GET_INFO_METHOD,
+            GetInfoRequest {
+                version: LSPS2_VERSION,
+                token,
+            },
+        )
+        .await?;
+
+    if payment_size_msat < info.min_payment_size_msat {
+        return Err(MutinyError::BadAmountError);
+    }
+    if payment_size_msat > info.max_payment_size_msat {
+        return Err(MutinyError::LspAmountTooHighError);
+    }
+
+    let now = crate::utils::now().as_secs();
+    let opening_fee_params = info
+        .opening_fee_params_menu
+        .into_iter()
+        .find(|params| params.is_valid(now))
+        .ok_or(MutinyError::LspGenericError)?;
+    let opening_fee_msat = opening_fee_params
+        .opening_fee_msat(payment_size_msat)
+        .ok_or(MutinyError::BadAmountError)?;
+
+    // the fee is taken out of the payment, so it has to leave something for us
+    if opening_fee_msat >= payment_size_msat {
+        return Err(MutinyError::BadAmountError);
+    }
+
+    Ok(JitChannelQuote {
+        lsp_pubkey: *lsp_pubkey,
+        payment_size_msat,
+        opening_fee_msat,
+        opening_fee_params,
+    })
+}
+
+/// Buys a JIT channel from the LSP with a quote from [`get_jit_channel_quote`]
+pub(crate) async fn buy_jit_channel(
+    handler: &LspsMessageHandler,
+    quote: JitChannelQuote,
+) -> Result<JitChannel, MutinyError> {
+    let response: BuyResponse = handler
+        .send_request(
+            &quote.lsp_pubkey,
+            BUY_METHOD,
+            BuyRequest {
+                version: LSPS2_VERSION,
+                opening_fee_params: quote.opening_fee_params,
+                payment_size_msat: quote.payment_size_msat,
+            },
+        )
+        .await?;
+
+    let scid = parse_scid(&response.jit_channel_scid).ok_or(MutinyError::LspGenericError)?;
+
+    Ok(JitChannel {
+        lsp_pubkey: quote.lsp_pubkey,
+        scid,
+        cltv_expiry_delta: response.lsp_cltv_expiry_delta,
+        payment_size_msat: quote.payment_size_msat,
+        opening_fee_msat: quote.opening_fee_msat,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::MutinyLogger;
+    use crate::lsps::{LspsResponseError, MockLsp};
+    use crate::test_utils::*;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn fee_params(min_fee_msat: u64, proportional: u32, valid_until: &str) -> Value {
+        json!({
+            "min_fee_msat": min_fee_msat.to_string(),
+            "proportional": proportional,
+            "valid_until": valid_until,
+            "min_lifetime": 1008,
+            "max_client_to_self_delay": 2016,
+            "promise": format!("promise-{min_fee_msat}"),
+        })
+    }
+
+    fn mock_lsp() -> MockLsp {
+        MockLsp::new(|method, params| match method {
+            GET_VERSIONS_METHOD => Ok(json!({ "versions": [1] })),
+            GET_INFO_METHOD => {
+                assert_eq!(params["version"], 1);
+                assert_eq!(params["token"], "token");
+                Ok(json!({
+                    "opening_fee_params_menu": [
+                        // expired, should be skipped
+                        fee_params(1_000, 1_000, "2020-01-01T00:00:00.000Z"),
+                        fee_params(2_000_000, 10_000, "2100-01-01T00:00:00.000Z"),
+                        fee_params(3_000_000, 10_000, "2100-01-01T00:00:00.000Z"),
+                    ],
+                    "min_payment_size_msat": "1000000",
+                    "max_payment_size_msat": "1000000000",
+                }))
+            }
+            BUY_METHOD => {
+                assert_eq!(params["opening_fee_params"]["promise"], "promise-2000000");
+                if params["payment_size_msat"] == "500000000" {
+                    return Err(LspsResponseError {
+                        code: PAYMENT_SIZE_TOO_LARGE_ERROR,
+                        message: "payment_size_too_large".to_string(),
+                        data: None,
+                    });
+                }
+                Ok(json!({
+                    "jit_channel_scid": "800000x1x0",
+                    "lsp_cltv_expiry_delta": 144,
+                    "client_trusts_lsp": false,
+                }))
+            }
+            _ => panic!("unexpected method {method}"),
+        })
+    }
+
+    #[test]
+    fn test_opening_fee() {
+        let test_name = "opening_fee";
+        log!("{}", test_name);
+
+        let params: OpeningFeeParams =
+            serde_json::from_value(fee_params(2_000_000, 10_000, "2100-01-01T00:00:00.000Z"))
+                .unwrap();
+        assert_eq!(params.min_fee_msat, 2_000_000);
+        assert!(params.is_valid(crate::utils::now().as_secs()));
+
+        // min fee applies to small payments
+        assert_eq!(params.opening_fee_msat(10_000_000), Some(2_000_000));
+        // 1% of the payment, rounded up
+        assert_eq!(params.opening_fee_msat(300_000_001), Some(3_000_001));
+        assert_eq!(params.opening_fee_msat(u64::MAX), None);
+    }
+
+    #[test]
+    async fn test_jit_channel_flow() {
+        let test_name = "jit_channel_flow";
+        log!("{}", test_name);
+
+        let handler = LspsMessageHandler::new(Arc::new(MutinyLogger::default()));
+        let lsp = mock_lsp();
+        let payment_size_msat = 100_000_000;
+
+        let (quote, methods) = futures::join!(
+            get_jit_channel_quote(
+                &handler,
+                &lsp.pubkey,
+                Some("token".to_string()),
+                payment_size_msat
+            ),
+            lsp.run(&handler, 2)
+        );
+        assert_eq!(methods, vec![GET_VERSIONS_METHOD, GET_INFO_METHOD]);
+        let quote = quote.unwrap();
+        assert_eq!(quote.opening_fee_msat, 2_000_000);
+
+        let (channel, methods) =
+            futures::join!(buy_jit_channel(&handler, quote), lsp.run(&handler, 1));
+        assert_eq!(methods, vec![BUY_METHOD]);
+        let channel = channel.unwrap();
+        assert_eq!(channel.scid, parse_scid("800000x1x0").unwrap());
+        assert_eq!(channel.cltv_expiry_delta, 144);
+        assert_eq!(channel.payment_size_msat, payment_size_msat);
+        assert_eq!(channel.opening_fee_msat, 2_000_000);
+
+        let hint = channel.route_hint();
+        assert_eq!(hint.0.len(), 1);
+        assert_eq!(hint.0[0].src_node_id, lsp.pubkey);
+        assert_eq!(hint.0[0].short_channel_id, channel.scid);
+    }
+
+    #[test]
+    async fn test_jit_channel_errors() {
+        let test_name = "jit_channel_errors";
+        log!("{}", test_name);
+
+        let handler = LspsMessageHandler::new(Arc::new(MutinyLogger::default()));
+        let lsp = mock_lsp();
+        let token = Some("token".to_string());
+
+        // below the LSP's min payment size
+        let (quote, _) = futures::join!(
+            get_jit_channel_quote(&handler, &lsp.pubkey, token.clone(), 1_000),
+            lsp.run(&handler, 2)
+        );
+        assert!(matches!(quote, Err(MutinyError::BadAmountError)));
+
+        // the LSP rejects the payment size when buying
+        let (quote, _) = futures::join!(
+            get_jit_channel_quote(&handler, &lsp.pubkey, token, 500_000_000),
+            lsp.run(&handler, 2)
+        );
+        let (channel, _) = futures::join!(
+            buy_jit_channel(&handler, quote.unwrap()),
+            lsp.run(&handler, 1)
+        );
+        assert!(matches!(channel, Err(MutinyError::LspAmountTooHighError)));
+    }
+}
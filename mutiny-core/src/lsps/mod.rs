@@ -0,0 +1,425 @@
+//! Client side of the Lightning Service Provider specifications.
+//!
+//! [LSPS0](https://github.com/BitcoinAndLightningLayerSpecs/lsp/tree/main/LSPS0) is the
+//! transport, JSON-RPC 2.0 messages sent as lightning custom messages, that
+//! [LSPS1](https://github.com/BitcoinAndLightningLayerSpecs/lsp/tree/main/LSPS1) (buying
+//! channels) and [LSPS2](https://github.com/BitcoinAndLightningLayerSpecs/lsp/tree/main/LSPS2)
+//! (JIT channels) are built on.
+
+pub mod lsps1;
+pub(crate) mod lsps2;
+
+use crate::error::MutinyError;
+use crate::logging::MutinyLogger;
+use crate::utils::{self, Mutex};
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::secp256k1::PublicKey;
+use futures::channel::oneshot;
+use futures::{pin_mut, select, FutureExt};
+use lightning::io;
+use lightning::ln::msgs::DecodeError;
+use lightning::ln::wire::Type;
+use lightning::util::logger::Logger;
+use lightning::util::ser::{Writeable, Writer};
+use lightning::{log_debug, log_warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// The custom message type used for all LSPS messages
+pub(crate) const LSPS_MESSAGE_TYPE: u16 = 37913;
+
+const JSONRPC_VERSION: &str = "2.0";
+
+/// How long we wait for the LSP to respond to a request
+const LSPS_REQUEST_TIMEOUT_MS: i32 = 30_000;
+
+/// An LSPS0 message, the payload is a JSON-RPC 2.0 object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawLspsMessage {
+    pub payload: String,
+}
+
+impl RawLspsMessage {
+    pub(crate) fn read<R: io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let payload = String::from_utf8(bytes).map_err(|_| DecodeError::InvalidValue)?;
+        Ok(Self { payload })
+    }
+}
+
+impl Type for RawLspsMessage {
+    fn type_id(&self) -> u16 {
+        LSPS_MESSAGE_TYPE
+    }
+}
+
+impl Writeable for RawLspsMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        writer.write_all(self.payload.as_bytes())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct JsonRpcRequest {
+    jsonrpc: String,
+    method: String,
+    params: Value,
+    id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct JsonRpcResponse {
+    jsonrpc: String,
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<LspsResponseError>,
+}
+
+/// An error returned by the LSP for a request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LspsResponseError {
+    pub code: i32,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+type ResponseSender = oneshot::Sender<Result<Value, LspsResponseError>>;
+
+/// Sends LSPS requests to the node's peers and matches up their responses.
+///
+/// Messages are queued and sent when the peer manager next processes events,
+/// see [`crate::messagehandler::MutinyMessageHandler`].
+pub(crate) struct LspsMessageHandler {
+    pending_messages: Mutex<VecDeque<(PublicKey, RawLspsMessage)>>,
+    /// Requests waiting for a response, keyed by their id
+    pending_requests: Mutex<HashMap<String, (PublicKey, ResponseSender)>>,
+    logger: Arc<MutinyLogger>,
+}
+
+impl LspsMessageHandler {
+    pub(crate) fn new(logger: Arc<MutinyLogger>) -> Self {
+        Self {
+            pending_messages: Mutex::new(VecDeque::new()),
+            pending_requests: Mutex::new(HashMap::new()),
+            logger,
+        }
+    }
+
+    /// Sends a request to the LSP and waits for its response
+    pub(crate) async fn send_request<P: Serialize, R: DeserializeOwned>(
+        &self,
+        lsp_pubkey: &PublicKey,
+        method: &str,
+        params: P,
+    ) -> Result<R, MutinyError> {
+        let mut id_bytes = [0u8; 16];
+        getrandom::getrandom(&mut id_bytes).map_err(|_| MutinyError::LspGenericError)?;
+        let id = id_bytes.to_hex();
+
+        let request = JsonRpcRequest {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params: serde_json::to_value(params)?,
+            id: id.clone(),
+        };
+        let payload = serde_json::to_string(&request)?;
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending_requests
+            .lock()
+            .map_err(|_| MutinyError::LspGenericError)?
+            .insert(id.clone(), (*lsp_pubkey, sender));
+        self.pending_messages
+            .lock()
+            .map_err(|_| MutinyError::LspGenericError)?
+            .push_back((*lsp_pubkey, RawLspsMessage { payload }));
+        log_debug!(self.logger, "sent {method} request to lsp {lsp_pubkey}");
+
+        let response_fut = receiver.fuse();
+        let timeout_fut = Box::pin(utils::sleep(LSPS_REQUEST_TIMEOUT_MS)).fuse();
+        pin_mut!(response_fut);
+        pin_mut!(timeout_fut);
+        let response = select! {
+            response = response_fut => response.ok(),
+            _ = timeout_fut => None,
+        };
+
+        let Some(response) = response else {
+            log_warn!(
+                self.logger,
+                "lsp {lsp_pubkey} did not respond to {method} request"
+            );
+            if let Ok(mut pending_requests) = self.pending_requests.lock() {
+                pending_requests.remove(&id);
+            }
+            return Err(MutinyError::LspConnectionError);
+        };
+
+        match response {
+            Ok(result) => serde_json::from_value(result).map_err(|e| {
+                log_warn!(self.logger, "invalid response to {method} from lsp: {e}");
+                MutinyError::LspGenericError
+            }),
+            Err(e) => {
+                log_warn!(
+                    self.logger,
+                    "lsp returned error for {method}: {} {}",
+                    e.code,
+                    e.message
+                );
+                Err(error_from_response(method, &e))
+            }
+        }
+    }
+
+    /// Handles a message received from a peer, completing the matching request
+    pub(crate) fn handle_message(&self, msg: RawLspsMessage, sender: &PublicKey) {
+        let response: JsonRpcResponse = match serde_json::from_str(&msg.payload) {
+            Ok(response) => response,
+            Err(_) => {
+                // we are only a client, so we ignore any requests or notifications
+                log_debug!(
+                    self.logger,
+                    "ignoring non-response lsps message from {sender}"
+                );
+                return;
+            }
+        };
+
+        let pending = self
+            .pending_requests
+            .lock()
+            .ok()
+            .and_then(|mut pending_requests| {
+                // only the peer we sent the request to can respond to it
+                match pending_requests.get(&response.id) {
+                    Some((lsp_pubkey, _)) if lsp_pubkey == sender => {
+                        pending_requests.remove(&response.id)
+                    }
+                    _ => None,
+                }
+            });
+        let Some((_, pending)) = pending else {
+            log_debug!(
+                self.logger,
+                "ignoring lsps response from {sender} for unknown request {}",
+                response.id
+            );
+            return;
+        };
+
+        let result = match (response.result, response.error) {
+            (_, Some(error)) => Err(error),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(LspsResponseError {
+                code: -32603,
+                message: "response had no result".to_string(),
+                data: None,
+            }),
+        };
+
+        // the receiver is gone if the request timed out
+        let _ = pending.send(result);
+    }
+
+    pub(crate) fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, RawLspsMessage)> {
+        self.pending_messages
+            .lock()
+            .map(|mut msgs| msgs.drain(..).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether the message handler has any message to be sent.
+    pub fn has_pending_messages(&self) -> bool {
+        self.pending_messages
+            .lock()
+            .map(|msgs| !msgs.is_empty())
+            .unwrap_or(false)
+    }
+}
+
+/// Maps the errors defined by the LSPS specs to a [`MutinyError`]
+fn error_from_response(method: &str, error: &LspsResponseError) -> MutinyError {
+    match (method, error.code) {
+        (lsps2::BUY_METHOD, lsps2::PAYMENT_SIZE_TOO_SMALL_ERROR) => MutinyError::BadAmountError,
+        (lsps2::BUY_METHOD, lsps2::PAYMENT_SIZE_TOO_LARGE_ERROR) => {
+            MutinyError::LspAmountTooHighError
+        }
+        (lsps1::CREATE_ORDER_METHOD, lsps1::OPTION_MISMATCH_ERROR) => MutinyError::LspFundingError,
+        (lsps1::GET_ORDER_METHOD, lsps1::ORDER_NOT_FOUND_ERROR) => MutinyError::NotFound,
+        _ => MutinyError::LspGenericError,
+    }
+}
+
+/// Lists the LSPS protocols supported by the LSP
+pub(crate) async fn list_protocols(
+    handler: &LspsMessageHandler,
+    lsp_pubkey: &PublicKey,
+) -> Result<Vec<u16>, MutinyError> {
+    #[derive(Deserialize)]
+    struct ListProtocolsResponse {
+        protocols: Vec<u16>,
+    }
+
+    let response: ListProtocolsResponse = handler
+        .send_request(lsp_pubkey, "lsps0.list_protocols", serde_json::json!({}))
+        .await?;
+    Ok(response.protocols)
+}
+
+/// LSPS0 encodes amounts as strings so they are not limited by JSON numbers
+pub(crate) mod string_amount {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&amount.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let str = String::deserialize(deserializer)?;
+        str.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parses a short channel id in the `BLOCKxTXxOUTPUT` format used by LSPS0
+pub(crate) fn parse_scid(scid: &str) -> Option<u64> {
+    let mut parts = scid.split('x');
+    let block: u64 = parts.next()?.parse().ok()?;
+    let tx_index: u64 = parts.next()?.parse().ok()?;
+    let output_index: u64 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || block >= 1 << 24 || tx_index >= 1 << 24 || output_index >= 1 << 16
+    {
+        return None;
+    }
+    Some(block << 40 | tx_index << 16 | output_index)
+}
+
+/// A fake LSP for tests. It answers the requests queued in a client's
+/// [`LspsMessageHandler`] with the given responder.
+#[cfg(test)]
+pub(crate) struct MockLsp {
+    pub pubkey: PublicKey,
+    #[allow(clippy::type_complexity)]
+    responder: Box<dyn Fn(&str, &Value) -> Result<Value, LspsResponseError>>,
+}
+
+#[cfg(test)]
+impl MockLsp {
+    pub(crate) fn new(
+        responder: impl Fn(&str, &Value) -> Result<Value, LspsResponseError> + 'static,
+    ) -> Self {
+        use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+        let secret_key = SecretKey::from_slice(&[42; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+        Self {
+            pubkey,
+            responder: Box::new(responder),
+        }
+    }
+
+    /// Answers requests from the client until the given number of requests
+    /// have been handled. Returns the methods that were requested.
+    pub(crate) async fn run(&self, client: &LspsMessageHandler, requests: usize) -> Vec<String> {
+        let mut methods = vec![];
+        while methods.len() < requests {
+            for (peer, msg) in client.get_and_clear_pending_msg() {
+                assert_eq!(peer, self.pubkey);
+                let request: JsonRpcRequest = serde_json::from_str(&msg.payload).unwrap();
+                assert_eq!(request.jsonrpc, JSONRPC_VERSION);
+
+                let (result, error) = match (self.responder)(&request.method, &request.params) {
+                    Ok(result) => (Some(result), None),
+                    Err(error) => (None, Some(error)),
+                };
+                let response = JsonRpcResponse {
+                    jsonrpc: JSONRPC_VERSION.to_string(),
+                    id: request.id,
+                    result,
+                    error,
+                };
+                let payload = serde_json::to_string(&response).unwrap();
+                client.handle_message(RawLspsMessage { payload }, &self.pubkey);
+                methods.push(request.method);
+            }
+            utils::sleep(10).await;
+        }
+        methods
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use serde_json::json;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_parse_scid() {
+        let test_name = "parse_scid";
+        log!("{}", test_name);
+
+        assert_eq!(parse_scid("0x0x0"), Some(0));
+        assert_eq!(parse_scid("529x1x0"), Some(529 << 40 | 1 << 16),);
+        assert_eq!(parse_scid("1x2"), None);
+        assert_eq!(parse_scid("1x2x3x4"), None);
+        assert_eq!(parse_scid("1x2x65536"), None);
+        assert_eq!(parse_scid("axbxc"), None);
+    }
+
+    #[test]
+    fn test_raw_message_round_trip() {
+        let test_name = "raw_message_round_trip";
+        log!("{}", test_name);
+
+        let msg = RawLspsMessage {
+            payload: r#"{"jsonrpc":"2.0","method":"lsps0.list_protocols","params":{},"id":"1"}"#
+                .to_string(),
+        };
+        assert_eq!(msg.type_id(), LSPS_MESSAGE_TYPE);
+
+        let bytes = msg.encode();
+        let read = RawLspsMessage::read(&mut io::Cursor::new(bytes)).unwrap();
+        assert_eq!(read, msg);
+    }
+
+    #[test]
+    async fn test_list_protocols() {
+        let test_name = "list_protocols";
+        log!("{}", test_name);
+
+        let handler = LspsMessageHandler::new(Arc::new(MutinyLogger::default()));
+        let lsp = MockLsp::new(|method, _| match method {
+            "lsps0.list_protocols" => Ok(json!({ "protocols": [1, 2] })),
+            _ => Err(LspsResponseError {
+                code: -32601,
+                message: "Method not found".to_string(),
+                data: None,
+            }),
+        });
+
+        let (protocols, methods) =
+            futures::join!(list_protocols(&handler, &lsp.pubkey), lsp.run(&handler, 1));
+        assert_eq!(protocols.unwrap(), vec![1, 2]);
+        assert_eq!(methods, vec!["lsps0.list_protocols".to_string()]);
+        assert!(!handler.has_pending_messages());
+
+        // responses for unknown requests are ignored
+        handler.handle_message(
+            RawLspsMessage {
+                payload: r#"{"jsonrpc":"2.0","id":"unknown","result":{}}"#.to_string(),
+            },
+            &lsp.pubkey,
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::lsps::{LspsMessageHandler, RawLspsMessage, LSPS_MESSAGE_TYPE};
+use crate::scb::message_handler::{
+    SCBMessage, SCBMessageHandler, PEER_STORAGE_TYPE, YOUR_PEER_STORAGE_TYPE,
+};
+use bitcoin::secp256k1::PublicKey;
+use lightning::io;
+use lightning::ln::features::{InitFeatures, NodeFeatures};
+use lightning::ln::msgs::{DecodeError, LightningError};
+use lightning::ln::peer_handler::CustomMessageHandler;
+use lightning::ln::wire::{CustomMessageReader, Type};
+use lightning::util::ser::{Writeable, Writer};
+use std::sync::Arc;
+
+/// The messages sent by the [`MutinyMessageHandler`]
+#[derive(Clone, Debug)]
+pub enum MutinyMessage {
+    /// Messages for the [`SCBMessageHandler`], to get a peer to close a channel
+    /// or to have our peers store our backup
+    Scb(SCBMessage),
+    /// LSPS0 messages sent to and received from an LSP
+    Lsps(RawLspsMessage),
+}
+
+impl Type for MutinyMessage {
+    fn type_id(&self) -> u16 {
+        match self {
+            MutinyMessage::Scb(msg) => msg.type_id(),
+            MutinyMessage::Lsps(msg) => msg.type_id(),
+        }
+    }
+}
+
+impl Writeable for MutinyMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        match self {
+            MutinyMessage::Scb(msg) => msg.write(writer),
+            MutinyMessage::Lsps(msg) => msg.write(writer),
+        }
+    }
+}
+
+/// Custom message handler for a node, LDK only takes one so this
+/// combines the [`SCBMessageHandler`] and the [`LspsMessageHandler`].
+pub struct MutinyMessageHandler {
+    pub(crate) scb: Arc<SCBMessageHandler>,
+    pub(crate) lsps: Arc<LspsMessageHandler>,
+}
+
+impl MutinyMessageHandler {
+    pub(crate) fn new(scb: Arc<SCBMessageHandler>, lsps: Arc<LspsMessageHandler>) -> Self {
+        Self { scb, lsps }
+    }
+}
+
+impl CustomMessageReader for MutinyMessageHandler {
+    type CustomMessage = MutinyMessage;
+    fn read<R: io::Read>(
+        &self,
+        msg_type: u16,
+        buffer: &mut R,
+    ) -> Result<Option<Self::CustomMessage>, DecodeError> {
+        match msg_type {
+            LSPS_MESSAGE_TYPE => Ok(Some(MutinyMessage::Lsps(RawLspsMessage::read(buffer)?))),
+            PEER_STORAGE_TYPE | YOUR_PEER_STORAGE_TYPE => {
+                Ok(self.scb.read(msg_type, buffer)?.map(MutinyMessage::Scb))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl CustomMessageHandler for MutinyMessageHandler {
+    fn handle_custom_message(
+        &self,
+        msg: MutinyMessage,
+        sender_node_id: &PublicKey,
+    ) -> Result<(), LightningError> {
+        match msg {
+            MutinyMessage::Lsps(msg) => self.lsps.handle_message(msg, sender_node_id),
+            MutinyMessage::Scb(msg) => self.scb.handle_custom_message(msg, sender_node_id)?,
+        }
+        Ok(())
+    }
+
+    fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, Self::CustomMessage)> {
+        let scb_msgs = self
+            .scb
+            .get_and_clear_pending_msg()
+            .into_iter()
+            .map(|(node_id, msg)| (node_id, MutinyMessage::Scb(msg)));
+        let lsps_msgs = self
+            .lsps
+            .get_and_clear_pending_msg()
+            .into_iter()
+            .map(|(node_id, msg)| (node_id, MutinyMessage::Lsps(msg)));
+        scb_msgs.chain(lsps_msgs).collect()
+    }
+
+    fn provided_node_features(&self) -> NodeFeatures {
+        NodeFeatures::empty()
+    }
+
+    fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures {
+        InitFeatures::empty()
+    }
+}
@@ -7,5 +7,11 @@ pub mod proxy;
 #[cfg(target_arch = "wasm32")]
 pub mod ws_socket;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod socks5;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tcp_listener;
+
 #[cfg(not(target_arch = "wasm32"))]
 pub mod tcp_socket;
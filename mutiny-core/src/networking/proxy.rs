@@ -36,10 +36,12 @@ impl WsProxy {
         peer_connection_info: PubkeyConnectionInfo,
         logger: Arc<MutinyLogger>,
     ) -> Result<Self, MutinyError> {
-        let ws = match peer_connection_info.connection_type {
-            ConnectionType::Tcp(s) => WebSocket::open(&tcp_proxy_to_url(proxy_url, &s)?)
-                .map_err(|_| MutinyError::ConnectionFailed)?,
+        let url = match peer_connection_info.connection_type {
+            ConnectionType::Tcp(s) => tcp_proxy_to_url(proxy_url, &s)?,
+            // the peer speaks websockets itself, no need for the proxy
+            ConnectionType::Websocket(url) => url,
         };
+        let ws = WebSocket::open(&url).map_err(|_| MutinyError::ConnectionFailed)?;
 
         // wait for connected status or time out at 10s
         let mut retries = 10;
@@ -66,7 +68,7 @@ impl WsProxy {
         // offline and shortly cut off from the WS but that happens
         // outside of the connect flow. This will falsely return success.
 
-        log_debug!(logger, "connected to ws: {proxy_url}");
+        log_debug!(logger, "connected to ws: {url}");
 
         let (write, read) = ws.split();
         Ok(Self {
@@ -81,7 +81,14 @@ pub fn schedule_descriptor_read(
                                     Ok(_read_bool) => {
                                         peer_manager.process_events();
                                     }
-                                    Err(e) => log_error!(logger, "got an error reading event: {}", e),
+                                    Err(e) => {
+                                        // the peer manager wants the peer gone, like when
+                                        // it is not allowed to connect to us
+                                        log_error!(logger, "got an error reading event: {}", e);
+                                        descriptor.disconnect_socket();
+                                        peer_manager.socket_disconnected(&mut descriptor);
+                                        break;
+                                    }
                                 }
                             }
                             Err(e) => {
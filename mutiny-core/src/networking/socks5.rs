@@ -0,0 +1,216 @@
+use crate::error::MutinyError;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+/// A SOCKS5 proxy to send all of our traffic through, usually a Tor daemon
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Socks5Proxy {
+    /// The `host:port` of the proxy, for example `127.0.0.1:9050`
+    pub addr: String,
+    /// Only connect to onion services, refusing any clearnet connection
+    pub strict: bool,
+}
+
+impl Socks5Proxy {
+    pub fn new(addr: String, strict: bool) -> Self {
+        Self { addr, strict }
+    }
+
+    /// Errors if we are not allowed to connect to the host through this proxy
+    pub(crate) fn check_host(&self, host: &str) -> Result<(), MutinyError> {
+        if self.strict && !is_onion_host(host) {
+            return Err(MutinyError::ClearnetConnectionRefused);
+        }
+        Ok(())
+    }
+
+    /// Errors if we are not allowed to make a request to the url through this proxy
+    pub(crate) fn check_url(&self, url: &str) -> Result<(), MutinyError> {
+        let url = url::Url::parse(url).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        self.check_host(url.host_str().unwrap_or_default())
+    }
+
+    /// The proxy url for http clients, `socks5h` so the proxy
+    /// resolves the hostnames, which is needed for onion services.
+    pub(crate) fn url(&self) -> String {
+        format!("socks5h://{}", self.addr)
+    }
+
+    /// Opens a connection to the host through the proxy
+    pub(crate) async fn connect(&self, host: &str, port: u16) -> Result<TcpStream, MutinyError> {
+        self.check_host(host)?;
+        let request = connect_request(host, port)?;
+
+        let mut stream = TcpStream::connect(&self.addr)
+            .await
+            .map_err(|_| MutinyError::ConnectionFailed)?;
+        handshake(&mut stream, &request)
+            .await
+            .map_err(|_| MutinyError::ConnectionFailed)?;
+
+        Ok(stream)
+    }
+}
+
+/// Asks the proxy to connect us with the request, we only support no authentication
+async fn handshake(stream: &mut TcpStream, request: &[u8]) -> io::Result<()> {
+    let failed = |msg: &str| io::Error::new(io::ErrorKind::Other, msg.to_string());
+
+    stream
+        .write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION])
+        .await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [SOCKS_VERSION, NO_AUTHENTICATION] {
+        return Err(failed("proxy requires authentication"));
+    }
+
+    stream.write_all(request).await?;
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION || reply[1] != REPLY_SUCCEEDED {
+        return Err(failed("proxy could not connect"));
+    }
+
+    // skip the address the proxy bound to, we don't need it
+    let bound_addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(failed("unknown address type")),
+    };
+    let mut bound_addr = vec![0u8; bound_addr_len + 2];
+    stream.read_exact(&mut bound_addr).await?;
+
+    Ok(())
+}
+
+/// If the host is a Tor onion service
+pub(crate) fn is_onion_host(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_lowercase()
+        .ends_with(".onion")
+}
+
+/// Splits a `host:port` address, the host of an IPv6 address can be in brackets
+pub(crate) fn split_host_port(addr: &str) -> Option<(String, u16)> {
+    let (host, port) = addr.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+/// Builds a http client for making requests to the url, going
+/// through the proxy if we have one.
+pub(crate) fn http_client(proxy: Option<&Socks5Proxy>, url: &str) -> Result<Client, MutinyError> {
+    let mut builder = Client::builder();
+    if let Some(proxy) = proxy {
+        proxy.check_url(url)?;
+        let proxy =
+            reqwest::Proxy::all(proxy.url()).map_err(|_| MutinyError::InvalidArgumentsError)?;
+        builder = builder.proxy(proxy);
+    }
+    builder.build().map_err(|_| MutinyError::ConnectionFailed)
+}
+
+/// The SOCKS5 request to connect to the host
+fn connect_request(host: &str, port: u16) -> Result<Vec<u8>, MutinyError> {
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len: u8 = host
+                .len()
+                .try_into()
+                .map_err(|_| MutinyError::InvalidArgumentsError)?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    const ONION: &str = "mutinyv3xqy2yemmt6ajmq3v2kbm6ykbg7w6yhvqjbxuuhoxyvvqad.onion";
+
+    #[test]
+    fn test_strict_proxy_only_allows_onion() {
+        let test_name = "test_strict_proxy_only_allows_onion";
+        log!("{}", test_name);
+
+        let proxy = Socks5Proxy::new("127.0.0.1:9050".to_string(), true);
+        assert!(proxy.check_host(ONION).is_ok());
+        assert!(proxy.check_url(&format!("http://{ONION}/api")).is_ok());
+        assert!(matches!(
+            proxy.check_host("127.0.0.1"),
+            Err(MutinyError::ClearnetConnectionRefused)
+        ));
+        assert!(matches!(
+            proxy.check_url("https://mempool.space/api"),
+            Err(MutinyError::ClearnetConnectionRefused)
+        ));
+
+        let proxy = Socks5Proxy::new("127.0.0.1:9050".to_string(), false);
+        assert!(proxy.check_url("https://mempool.space/api").is_ok());
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        let test_name = "test_split_host_port";
+        log!("{}", test_name);
+
+        assert_eq!(
+            split_host_port(&format!("{ONION}:9735")),
+            Some((ONION.to_string(), 9735))
+        );
+        assert_eq!(
+            split_host_port("[::1]:9735"),
+            Some(("::1".to_string(), 9735))
+        );
+        assert_eq!(split_host_port("127.0.0.1"), None);
+        assert_eq!(split_host_port(":9735"), None);
+    }
+
+    #[test]
+    fn test_connect_request() {
+        let test_name = "test_connect_request";
+        log!("{}", test_name);
+
+        assert_eq!(
+            connect_request("127.0.0.1", 9735).unwrap(),
+            vec![5, 1, 0, 1, 127, 0, 0, 1, 0x26, 0x07]
+        );
+
+        let request = connect_request("abc.onion", 80).unwrap();
+        assert_eq!(&request[..5], &[5, 1, 0, 3, 9]);
+        assert_eq!(&request[5..14], b"abc.onion");
+        assert_eq!(&request[14..], &[0, 80]);
+    }
+}
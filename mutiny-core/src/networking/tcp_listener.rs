@@ -0,0 +1,389 @@
+use crate::error::MutinyError;
+use crate::logging::MutinyLogger;
+use crate::networking::socket::{schedule_descriptor_read, MutinySocketDescriptor};
+use crate::networking::socks5::{is_onion_host, split_host_port};
+use crate::networking::tcp_socket::TcpSocketDescriptor;
+use crate::peermanager::PeerManager;
+use crate::utils::{self, base32_decode, Mutex};
+use bitcoin::secp256k1::PublicKey;
+use futures::{pin_mut, select, FutureExt};
+use lightning::ln::msgs::NetAddress;
+use lightning::util::logger::Logger;
+use lightning::util::ser::Hostname;
+use lightning::{log_debug, log_info, log_warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// The most connections we accept from one IP address by default
+pub const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 5;
+/// How often we broadcast our node announcement
+const NODE_ANNOUNCEMENT_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Settings for accepting connections from other lightning nodes
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerListenerConfig {
+    /// The address to listen on, like `0.0.0.0:9735`.
+    /// Each node listens on this port plus its child index.
+    pub listen_addr: SocketAddr,
+    /// The `host:port` addresses to announce to the network, onion
+    /// addresses are supported. The ports are offset like `listen_addr`.
+    pub announced_addrs: Vec<String>,
+    /// The alias to announce, at most 32 bytes
+    pub alias: String,
+    /// The most connections we accept from one IP address
+    pub max_connections_per_ip: usize,
+    /// If set, only these peers can connect to us
+    pub allowed_peers: Option<Vec<PublicKey>>,
+}
+
+impl PeerListenerConfig {
+    pub fn new(listen_addr: SocketAddr) -> Self {
+        Self {
+            listen_addr,
+            announced_addrs: vec![],
+            alias: String::new(),
+            max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+            allowed_peers: None,
+        }
+    }
+
+    pub fn with_announced_addrs(mut self, announced_addrs: Vec<String>) -> Self {
+        self.announced_addrs = announced_addrs;
+        self
+    }
+
+    pub fn with_alias(mut self, alias: String) -> Self {
+        self.alias = alias;
+        self
+    }
+
+    pub fn with_max_connections_per_ip(mut self, max_connections_per_ip: usize) -> Self {
+        self.max_connections_per_ip = max_connections_per_ip;
+        self
+    }
+
+    pub fn with_allowed_peers(mut self, allowed_peers: Vec<PublicKey>) -> Self {
+        self.allowed_peers = Some(allowed_peers);
+        self
+    }
+
+    /// The address the node with the given child index listens on
+    pub(crate) fn listen_addr_for(&self, child_index: u32) -> Result<SocketAddr, MutinyError> {
+        let mut addr = self.listen_addr;
+        addr.set_port(offset_port(addr.port(), child_index)?);
+        Ok(addr)
+    }
+
+    /// The addresses the node with the given child index announces
+    pub(crate) fn announced_addrs_for(
+        &self,
+        child_index: u32,
+    ) -> Result<Vec<NetAddress>, MutinyError> {
+        self.announced_addrs
+            .iter()
+            .map(|addr| {
+                let (host, port) =
+                    split_host_port(addr).ok_or(MutinyError::InvalidArgumentsError)?;
+                parse_net_address(&host, offset_port(port, child_index)?)
+            })
+            .collect()
+    }
+
+    /// The alias in the form of a node announcement
+    pub(crate) fn alias_bytes(&self) -> [u8; 32] {
+        let mut alias = [0u8; 32];
+        let bytes = self.alias.as_bytes();
+        let len = bytes.len().min(32);
+        alias[..len].copy_from_slice(&bytes[..len]);
+        alias
+    }
+}
+
+fn offset_port(port: u16, child_index: u32) -> Result<u16, MutinyError> {
+    u16::try_from(child_index)
+        .ok()
+        .and_then(|i| port.checked_add(i))
+        .ok_or(MutinyError::InvalidArgumentsError)
+}
+
+fn parse_net_address(host: &str, port: u16) -> Result<NetAddress, MutinyError> {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => Ok(NetAddress::IPv4 {
+            addr: ip.octets(),
+            port,
+        }),
+        Ok(IpAddr::V6(ip)) => Ok(NetAddress::IPv6 {
+            addr: ip.octets(),
+            port,
+        }),
+        Err(_) if is_onion_host(host) => parse_onion_v3(host, port),
+        Err(_) => {
+            let hostname = Hostname::try_from(host.to_string())
+                .map_err(|_| MutinyError::InvalidArgumentsError)?;
+            Ok(NetAddress::Hostname { hostname, port })
+        }
+    }
+}
+
+/// Decodes a v3 onion address, which is the base32 encoding of
+/// the service's public key, a checksum and the version.
+fn parse_onion_v3(host: &str, port: u16) -> Result<NetAddress, MutinyError> {
+    let encoded = host
+        .trim_end_matches('.')
+        .to_lowercase()
+        .trim_end_matches(".onion")
+        .to_string();
+    if encoded.len() != 56 {
+        return Err(MutinyError::InvalidArgumentsError);
+    }
+    let bytes = base32_decode(&encoded).ok_or(MutinyError::InvalidArgumentsError)?;
+
+    let mut ed25519_pubkey = [0u8; 32];
+    ed25519_pubkey.copy_from_slice(&bytes[..32]);
+    Ok(NetAddress::OnionV3 {
+        ed25519_pubkey,
+        checksum: u16::from_be_bytes([bytes[32], bytes[33]]),
+        version: bytes[34],
+        port,
+    })
+}
+
+/// Counts the open inbound connections from each IP address
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionCounter {
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl ConnectionCounter {
+    /// Counts a new connection from the IP, unless it already has the max.
+    /// The connection is counted until the returned guard is dropped.
+    pub(crate) fn try_add(&self, ip: IpAddr, max: usize) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionGuard {
+            counter: self.clone(),
+            ip,
+        })
+    }
+
+    fn count(&self, ip: &IpAddr) -> usize {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(ip)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// Keeps an inbound connection counted while it is open
+pub(crate) struct ConnectionGuard {
+    counter: ConnectionCounter,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.counter.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Binds the listener for the node with the given child index
+pub(crate) async fn bind_peer_listener(
+    config: &PeerListenerConfig,
+    child_index: u32,
+) -> Result<TcpListener, MutinyError> {
+    let addr = config.listen_addr_for(child_index)?;
+    TcpListener::bind(addr)
+        .await
+        .map_err(|_| MutinyError::ConnectionFailed)
+}
+
+/// Accepts peer connections and hands them to the peer manager until we are stopped
+pub(crate) async fn accept_peer_connections(
+    listener: TcpListener,
+    max_connections_per_ip: usize,
+    peer_manager: Arc<dyn PeerManager>,
+    logger: Arc<MutinyLogger>,
+    stop: Arc<AtomicBool>,
+) {
+    if let Ok(addr) = listener.local_addr() {
+        log_info!(logger, "listening for peer connections on {addr}");
+    }
+
+    let counter = ConnectionCounter::default();
+    loop {
+        let accept_fut = Box::pin(listener.accept()).fuse();
+        let delay_fut = Box::pin(utils::sleep(1_000)).fuse();
+        pin_mut!(accept_fut);
+        pin_mut!(delay_fut);
+        select! {
+            accept_res = accept_fut => {
+                let (stream, remote_addr) = match accept_res {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        log_warn!(logger, "failed to accept peer connection: {e}");
+                        continue;
+                    }
+                };
+
+                let Some(guard) = counter.try_add(remote_addr.ip(), max_connections_per_ip) else {
+                    log_debug!(logger, "too many connections from {}, dropping", remote_addr.ip());
+                    continue;
+                };
+
+                let stream = match stream.into_std() {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log_warn!(logger, "failed to accept peer connection: {e}");
+                        continue;
+                    }
+                };
+
+                let descriptor = MutinySocketDescriptor::Native(
+                    TcpSocketDescriptor::new(Arc::new(tokio::sync::Mutex::new(stream)))
+                        .with_connection_guard(guard),
+                );
+                let net_addr = match remote_addr {
+                    SocketAddr::V4(addr) => NetAddress::IPv4 {
+                        addr: addr.ip().octets(),
+                        port: addr.port(),
+                    },
+                    SocketAddr::V6(addr) => NetAddress::IPv6 {
+                        addr: addr.ip().octets(),
+                        port: addr.port(),
+                    },
+                };
+
+                if let Err(e) = peer_manager.new_inbound_connection(descriptor.clone(), Some(net_addr)) {
+                    log_warn!(logger, "failed to accept peer connection: {e:?}");
+                    continue;
+                }
+                log_debug!(logger, "accepted peer connection from {remote_addr}");
+
+                schedule_descriptor_read(
+                    descriptor,
+                    peer_manager.clone(),
+                    logger.clone(),
+                    stop.clone(),
+                );
+            }
+            _ = delay_fut => {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Broadcasts our node announcement every so often until we are stopped.
+/// Peers only relay it once we have a public channel.
+pub(crate) async fn announce_node(
+    alias: [u8; 32],
+    addresses: Vec<NetAddress>,
+    peer_manager: Arc<dyn PeerManager>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut last_announcement: Option<u64> = None;
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let now = utils::now().as_secs();
+        if last_announcement.map_or(true, |t| now - t >= NODE_ANNOUNCEMENT_INTERVAL_SECS) {
+            peer_manager.broadcast_node_announcement([0; 3], alias, addresses.clone());
+            last_announcement = Some(now);
+        }
+
+        utils::sleep(1_000).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use std::str::FromStr;
+
+    const ONION: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+    #[test]
+    fn test_connection_counter() {
+        let test_name = "test_connection_counter";
+        log!("{}", test_name);
+
+        let counter = ConnectionCounter::default();
+        let ip = IpAddr::from_str("127.0.0.1").unwrap();
+        let first = counter.try_add(ip, 2).unwrap();
+        let second = counter.try_add(ip, 2).unwrap();
+        assert!(counter.try_add(ip, 2).is_none());
+        assert_eq!(counter.count(&ip), 2);
+
+        // other IPs have their own limit
+        let other = IpAddr::from_str("127.0.0.2").unwrap();
+        assert!(counter.try_add(other, 2).is_some());
+
+        drop(first);
+        assert_eq!(counter.count(&ip), 1);
+        assert!(counter.try_add(ip, 2).is_some());
+
+        drop(second);
+        assert_eq!(counter.count(&ip), 0);
+    }
+
+    #[test]
+    fn test_peer_listener_addresses() {
+        let test_name = "test_peer_listener_addresses";
+        log!("{}", test_name);
+
+        let config = PeerListenerConfig::new(SocketAddr::from_str("0.0.0.0:9735").unwrap())
+            .with_announced_addrs(vec![
+                "1.2.3.4:9735".to_string(),
+                format!("{ONION}:9735"),
+                "example.com:9735".to_string(),
+            ])
+            .with_alias("mutiny".to_string());
+
+        assert_eq!(config.listen_addr_for(0).unwrap().port(), 9735);
+        assert_eq!(config.listen_addr_for(2).unwrap().port(), 9737);
+        assert!(config.listen_addr_for(u32::MAX).is_err());
+
+        let addrs = config.announced_addrs_for(1).unwrap();
+        assert_eq!(
+            addrs[0],
+            NetAddress::IPv4 {
+                addr: [1, 2, 3, 4],
+                port: 9736
+            }
+        );
+        assert!(matches!(
+            addrs[1],
+            NetAddress::OnionV3 {
+                version: 3,
+                port: 9736,
+                ..
+            }
+        ));
+        assert!(matches!(addrs[2], NetAddress::Hostname { port: 9736, .. }));
+
+        assert_eq!(&config.alias_bytes()[..6], b"mutiny");
+        assert_eq!(config.alias_bytes()[6..], [0; 26]);
+    }
+}
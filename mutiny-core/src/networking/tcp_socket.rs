@@ -1,11 +1,15 @@
 use crate::error::MutinyError;
 use crate::networking::socket::ReadDescriptor;
+use crate::networking::tcp_listener::ConnectionGuard;
 use crate::utils;
 use lightning::ln::peer_handler;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::{hash::Hash, io::Read};
-use std::{io::Write, net::TcpStream};
+use std::{
+    io::Write,
+    net::{Shutdown, TcpStream},
+};
 use tokio::sync::Mutex;
 
 static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
@@ -13,12 +17,23 @@ static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 pub struct TcpSocketDescriptor {
     conn: Arc<Mutex<TcpStream>>,
     id: u64,
+    /// Keeps an inbound connection counted until every copy is dropped
+    guard: Option<Arc<ConnectionGuard>>,
 }
 
 impl TcpSocketDescriptor {
     pub fn new(conn: Arc<Mutex<TcpStream>>) -> Self {
         let id = ID_COUNTER.fetch_add(1, Ordering::AcqRel);
-        Self { conn, id }
+        Self {
+            conn,
+            id,
+            guard: None,
+        }
+    }
+
+    pub(crate) fn with_connection_guard(mut self, guard: ConnectionGuard) -> Self {
+        self.guard = Some(Arc::new(guard));
+        self
     }
 }
 
@@ -54,7 +69,11 @@ impl peer_handler::SocketDescriptor for TcpSocketDescriptor {
     }
 
     fn disconnect_socket(&mut self) {
-        // socket will be closed when dropped
+        // shut down the socket so the reader stops, it will be closed when dropped
+        let conn = self.conn.clone();
+        utils::spawn(async move {
+            let _ = conn.lock().await.shutdown(Shutdown::Both);
+        });
     }
 }
 impl Clone for TcpSocketDescriptor {
@@ -62,6 +81,7 @@ impl Clone for TcpSocketDescriptor {
         Self {
             conn: Arc::clone(&self.conn),
             id: self.id,
+            guard: self.guard.clone(),
         }
     }
 }
@@ -62,6 +62,27 @@ pub struct WebSocketImpl {
     >,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl WebSocketImpl {
+    /// Opens the websocket through a SOCKS5 proxy, see [`SimpleWebSocket::new`]
+    pub(crate) async fn new_with_proxy(
+        url: String,
+        proxy: &crate::networking::socks5::Socks5Proxy,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let parsed = url::Url::parse(&url)?;
+        let host = parsed.host_str().unwrap_or_default();
+        let port = parsed
+            .port_or_known_default()
+            .ok_or(crate::error::MutinyError::InvalidArgumentsError)?;
+        let stream = proxy.connect(host, port).await?;
+
+        let (ws_stream, _response) = tokio_tungstenite::client_async_tls(url, stream)
+            .await
+            .map_err(Box::new)?;
+        Ok(Self { ws: ws_stream })
+    }
+}
+
 #[async_trait(?Send)]
 #[cfg(not(target_arch = "wasm32"))]
 impl SimpleWebSocket for WebSocketImpl {
@@ -1,47 +1,65 @@
 use crate::keymanager::PhantomKeysManager;
 use crate::labels::LabelStorage;
-use crate::ldkstorage::ChannelOpenParams;
+use crate::ldkstorage::{ChannelBatch, ChannelCloseParams, ChannelOpenParams};
 use crate::nodemanager::ChannelClosure;
-use crate::scb::StaticChannelBackup;
+use crate::rebalance::REBALANCE_LABEL;
+use crate::scb::message_handler::MAX_PEER_STORAGE_SIZE;
+use crate::scb::{EncryptedSCB, StaticChannelBackup, StaticChannelBackupStorage};
 use crate::{
     background::process_events_async,
     chain::MutinyChain,
+    channelpolicy::{ChannelPolicy, InboundChannelApprover, InboundChannelPolicy},
     error::{MutinyError, MutinyStorageError},
-    event::{EventHandler, HTLCStatus, MillisatAmount, PaymentInfo},
+    event::{
+        abort_channel_batch, channel_batch_state, EventHandler, HTLCStatus, MillisatAmount,
+        PaymentInfo,
+    },
     fees::MutinyFeeEstimator,
-    gossip::{get_all_peers, read_peer_info, save_peer_connection_info},
+    gossip::{
+        get_all_peers, read_peer_info, record_peer_connected, record_peer_connection_failed,
+        save_peer_connection_info, LnPeerMetadata, PeerConnectionHistory,
+    },
     keymanager::{create_keys_manager, pubkey_from_keys_manager},
     ldkstorage::{MutinyNodePersister, PhantomChannelManager},
     logging::MutinyLogger,
-    lspclient::LspClient,
-    nodemanager::{MutinyInvoice, NodeIndex},
+    lspclient::{LspClient, LspProtocol, LspSelectionPolicy},
+    lsps::lsps1::{self, LiquidityOptions, LiquidityOrder},
+    lsps::lsps2::{self, JitChannelQuote},
+    nodemanager::{InvoiceQuote, MutinyChannel, MutinyInvoice, NodeIndex},
     onchain::OnChainWallet,
     peermanager::{GossipMessageHandler, PeerManager, PeerManagerImpl},
     utils::{self, sleep},
 };
 
+use crate::lsps::LspsMessageHandler;
+use crate::messagehandler::MutinyMessageHandler;
 use crate::scb::message_handler::SCBMessageHandler;
 use crate::{fees::P2WSH_OUTPUT_SIZE, peermanager::connect_peer_if_necessary};
 use crate::{lspclient::FeeRequest, storage::MutinyStorage};
 use anyhow::{anyhow, Context};
 use bdk::FeeRate;
 use bdk_esplora::esplora_client::AsyncClient;
+use bitcoin::bech32::ToBase32;
 use bitcoin::hashes::{hex::ToHex, sha256::Hash as Sha256};
-use bitcoin::secp256k1::rand;
-use bitcoin::{hashes::Hash, secp256k1::PublicKey, BlockHash, Network, OutPoint};
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::{hashes::Hash, secp256k1::PublicKey, Address, BlockHash, Network, OutPoint};
 use core::time::Duration;
 use lightning::chain::channelmonitor::ChannelMonitor;
-use lightning::util::ser::{ReadableArgs, Writeable};
+use lightning::events::bump_transaction::{BumpTransactionEventHandler, Wallet as BumpTxWallet};
+use lightning::util::ser::{Readable, ReadableArgs, Writeable};
 use lightning::{
     chain::chaininterface::{ConfirmationTarget, FeeEstimator},
     ln::channelmanager::{RecipientOnionFields, RetryableSendFailure},
     routing::scoring::ProbabilisticScoringFeeParameters,
-    util::config::ChannelConfig,
 };
 
 use bitcoin::util::bip32::ExtendedPrivKey;
+use lightning::ln::channelmanager::ChannelDetails;
+use lightning::ln::features::{ChannelFeatures, NodeFeatures};
+use lightning::ln::script::ShutdownScript;
 use lightning::ln::PaymentSecret;
-use lightning::sign::{EntropySource, InMemorySigner};
+use lightning::sign::{EntropySource, InMemorySigner, NodeSigner, Recipient};
+use lightning::util::config::UserConfig;
 use lightning::{
     chain::{chainmonitor, Filter, Watch},
     ln::{
@@ -53,19 +71,22 @@ use lightning::{
     routing::{
         gossip,
         gossip::NodeId,
-        router::{DefaultRouter, PaymentParameters, RouteParameters},
+        router::{
+            DefaultRouter, Path, PaymentParameters, Route, RouteHop, RouteParameters, Router as _,
+        },
         scoring::ProbabilisticScorer,
     },
-    util::{
-        config::{ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig},
-        logger::Logger,
-    },
+    util::logger::Logger,
 };
 use lightning_invoice::payment::PaymentError;
 use lightning_invoice::{
     payment::{pay_invoice, pay_zero_value_invoice},
-    utils::{create_invoice_from_channelmanager_and_duration_since_epoch, create_phantom_invoice},
-    Invoice,
+    utils::{
+        create_invoice_from_channelmanager_and_duration_since_epoch,
+        create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_hash,
+        create_phantom_invoice,
+    },
+    Invoice, InvoiceBuilder,
 };
 use std::collections::HashMap;
 use std::{
@@ -75,21 +96,45 @@ use std::{
         Arc, RwLock,
     },
 };
+use uuid::Uuid;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::gossip::EsploraUtxoLookup;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::socks5::Socks5Proxy;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::tcp_listener::{
+    accept_peer_connections, announce_node, bind_peer_listener, PeerListenerConfig,
+};
 
 const DEFAULT_PAYMENT_TIMEOUT: u64 = 30;
 const INITIAL_RECONNECTION_DELAY: u64 = 5;
 const MAX_RECONNECTION_DELAY: u64 = 60;
+/// How many blocks before the claim deadline we cancel a held payment
+pub(crate) const HOLD_INVOICE_CANCEL_SAFETY_MARGIN: u32 = 6;
+/// How long a quote from [`Node::quote_invoice`] can be used for
+const INVOICE_QUOTE_EXPIRY_SECS: u64 = 600;
+/// How much an LSP's fee can go up by between a quote and the invoice, 1%
+const INVOICE_QUOTE_FEE_TOLERANCE_PPM: u64 = 10_000;
+/// The least an LSP's fee can go up by, so small fees can still change by a sat
+const INVOICE_QUOTE_MIN_FEE_TOLERANCE_MSAT: u64 = 1_000;
 
 pub(crate) type RapidGossipSync =
     lightning_rapid_gossip_sync::RapidGossipSync<Arc<NetworkGraph>, Arc<MutinyLogger>>;
 
+pub(crate) type P2PGossipSync = gossip::P2PGossipSync<
+    Arc<NetworkGraph>,
+    Arc<crate::gossip::EsploraUtxoLookup>,
+    Arc<MutinyLogger>,
+>;
+
 pub(crate) type NetworkGraph = gossip::NetworkGraph<Arc<MutinyLogger>>;
 
 pub(crate) type MessageHandler<S: MutinyStorage> = LdkMessageHandler<
     Arc<PhantomChannelManager<S>>,
     Arc<GossipMessageHandler<S>>,
     Arc<IgnoringMessageHandler>,
-    Arc<SCBMessageHandler>,
+    Arc<MutinyMessageHandler>,
 >;
 
 pub(crate) type ChainMonitor<S: MutinyStorage> = chainmonitor::ChainMonitor<
@@ -114,6 +159,8 @@ pub(crate) type ProbScorer = ProbabilisticScorer<Arc<NetworkGraph>, Arc<MutinyLo
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ConnectionType {
     Tcp(String),
+    /// A `ws://` or `wss://` url the peer accepts connections on directly
+    Websocket(String),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -129,6 +176,16 @@ impl PubkeyConnectionInfo {
             return Err(MutinyError::PeerInfoParseFailed)
                 .context("connect_peer requires peer connection info")?;
         };
+        let (pubkey, peer_addr_str) = split_peer_connection_string(connection)?;
+        if is_websocket_url(&peer_addr_str) {
+            // keep the url as is, the path can be case sensitive
+            return Ok(Self {
+                pubkey,
+                connection_type: ConnectionType::Websocket(peer_addr_str.clone()),
+                original_connection_string: format!("{pubkey}@{peer_addr_str}"),
+            });
+        }
+
         let connection = connection.to_lowercase();
         let (pubkey, peer_addr_str) = parse_peer_info(&connection)?;
         Ok(Self {
@@ -139,6 +196,22 @@ impl PubkeyConnectionInfo {
     }
 }
 
+/// What an LSP will charge us to receive a payment
+#[derive(Debug, Clone)]
+struct LspQuote {
+    fee_msat: u64,
+    /// The JIT channel to buy from an LSPS2 LSP, if we need a new channel
+    jit_channel: Option<JitChannelQuote>,
+}
+
+/// A quote we gave out with [`Node::quote_invoice`] that has not been used yet
+struct PendingInvoiceQuote {
+    quote: InvoiceQuote,
+    /// The amount that was requested
+    amount_sat: u64,
+    lsp: Option<(LspClient, LspQuote)>,
+}
+
 pub(crate) struct Node<S: MutinyStorage> {
     pub _uuid: String,
     pub child_index: u32,
@@ -150,14 +223,34 @@ pub(crate) struct Node<S: MutinyStorage> {
     pub chain_monitor: Arc<ChainMonitor<S>>,
     pub fee_estimator: Arc<MutinyFeeEstimator<S>>,
     pub scb_message_handler: Arc<SCBMessageHandler>,
+    pub(crate) lsps_message_handler: Arc<LspsMessageHandler>,
     network: Network,
     pub persister: Arc<MutinyNodePersister<S>>,
     wallet: Arc<OnChainWallet<S>>,
+    router: Arc<Router>,
     logger: Arc<MutinyLogger>,
+    /// The LSP saved for this node, it is preferred over the others
     pub(crate) lsp_client: Option<LspClient>,
+    /// All the LSPs we can use, in order of preference
+    lsp_clients: Vec<LspClient>,
+    lsp_selection_policy: LspSelectionPolicy,
+    /// The channel policy this node uses, its own if it has one, otherwise the default
+    channel_policy: ChannelPolicy,
+    /// The channel policy saved for this node, overriding the default
+    channel_policy_override: Option<ChannelPolicy>,
+    /// Quotes from [`Node::quote_invoice`], by id
+    invoice_quotes: utils::Mutex<HashMap<String, PendingInvoiceQuote>>,
+    /// If we already asked our peers for the backups they stored for us,
+    /// see [`Node::request_peer_storage`]
+    peer_storage_requested: AtomicBool,
+    /// Handles this node's events, kept so tests can give it events directly
+    #[cfg(test)]
+    pub(crate) event_handler: EventHandler<S>,
     stop: Arc<AtomicBool>,
     #[cfg(target_arch = "wasm32")]
     websocket_proxy_addr: String,
+    #[cfg(not(target_arch = "wasm32"))]
+    socks5_proxy: Option<Socks5Proxy>,
 }
 
 impl<S: MutinyStorage> Node<S> {
@@ -168,6 +261,7 @@ impl<S: MutinyStorage> Node<S> {
         xprivkey: ExtendedPrivKey,
         storage: S,
         gossip_sync: Arc<RapidGossipSync>,
+        #[cfg(not(target_arch = "wasm32"))] p2p_utxo_lookup: Option<Arc<EsploraUtxoLookup>>,
         scorer: Arc<utils::Mutex<ProbScorer>>,
         chain: Arc<MutinyChain<S>>,
         fee_estimator: Arc<MutinyFeeEstimator<S>>,
@@ -175,13 +269,27 @@ impl<S: MutinyStorage> Node<S> {
         network: Network,
         esplora: Arc<AsyncClient>,
         lsp_clients: &[LspClient],
+        lsp_selection_policy: LspSelectionPolicy,
+        default_channel_policy: &ChannelPolicy,
+        inbound_channel_policy: &InboundChannelPolicy,
+        inbound_channel_approver: Option<Arc<dyn InboundChannelApprover>>,
         logger: Arc<MutinyLogger>,
         do_not_connect_peers: bool,
         empty_state: bool,
         #[cfg(target_arch = "wasm32")] websocket_proxy_addr: String,
+        #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<Socks5Proxy>,
+        #[cfg(not(target_arch = "wasm32"))] peer_listener: Option<PeerListenerConfig>,
     ) -> Result<Self, MutinyError> {
         log_info!(logger, "initializing a new node: {uuid}");
 
+        let channel_policy_override = node_index.channel_policy.clone();
+        let channel_policy = node_channel_policy(
+            channel_policy_override.as_ref(),
+            default_channel_policy,
+            &logger,
+        );
+        channel_policy.validate()?;
+
         // a list of components that need to be stopped and whether or not they are stopped
         let stopped_components = Arc::new(RwLock::new(vec![]));
 
@@ -193,6 +301,9 @@ impl<S: MutinyStorage> Node<S> {
         )?);
         let pubkey = pubkey_from_keys_manager(&keys_manager);
 
+        // tag everything this node logs with its pubkey
+        let logger = Arc::new(logger.with_node_pubkey(pubkey));
+
         // init the persister
         let persister = Arc::new(MutinyNodePersister::new(
             uuid.clone(),
@@ -222,6 +333,19 @@ impl<S: MutinyStorage> Node<S> {
                 })?
         };
 
+        // every node needs its own, it queues its gossip replies for its own peer manager,
+        // but they share the funding output lookups
+        #[cfg(target_arch = "wasm32")]
+        let p2p_gossip_sync: Option<Arc<P2PGossipSync>> = None;
+        #[cfg(not(target_arch = "wasm32"))]
+        let p2p_gossip_sync = p2p_utxo_lookup.map(|utxo_lookup| {
+            Arc::new(crate::gossip::p2p_gossip_sync(
+                gossip_sync.network_graph().clone(),
+                utxo_lookup,
+                logger.clone(),
+            ))
+        });
+
         let network_graph = gossip_sync.network_graph().clone();
 
         let router: Arc<Router> = Arc::new(DefaultRouter::new(
@@ -244,6 +368,7 @@ impl<S: MutinyStorage> Node<S> {
                 router.clone(),
                 channel_monitors,
                 esplora,
+                channel_policy.user_config(),
             )
             .await?
         } else {
@@ -258,6 +383,7 @@ impl<S: MutinyStorage> Node<S> {
                     router.clone(),
                     channel_monitors,
                     esplora,
+                    channel_policy.user_config(),
                 )
                 .await?
         };
@@ -265,17 +391,26 @@ impl<S: MutinyStorage> Node<S> {
         let channel_manager: Arc<PhantomChannelManager<S>> =
             Arc::new(read_channel_manager.channel_manager);
 
-        // Check all existing channels against default configs.
-        // If we have default config changes, those should apply
-        // to all existing and new channels.
-        let default_config = default_user_config().channel_config;
+        // Check all existing channels against our channel policy.
+        // If the policy changes, it should apply to all
+        // existing and new channels.
+        let default_config = channel_policy.channel_config();
         for channel in channel_manager.list_channels() {
+            // keep the fee limit of any cooperative close we started
+            let mut expected_config = default_config;
+            if let Some(max_fee_sats) = persister
+                .get_channel_close_params(channel.user_channel_id)?
+                .and_then(|p| p.max_fee_sats)
+            {
+                expected_config.force_close_avoidance_max_fee_satoshis = max_fee_sats;
+            }
+
             // unwrap is safe after LDK.0.0.109
-            if channel.config.unwrap() != default_config {
+            if channel.config.unwrap() != expected_config {
                 match channel_manager.update_channel_config(
                     &channel.counterparty.node_id,
                     &[channel.channel_id],
-                    &default_config,
+                    &expected_config,
                 ) {
                     Ok(_) => {
                         log_debug!(
@@ -295,19 +430,30 @@ impl<S: MutinyStorage> Node<S> {
             }
         }
 
+        #[cfg(target_arch = "wasm32")]
+        let allowed_inbound_peers = None;
+        #[cfg(not(target_arch = "wasm32"))]
+        let allowed_inbound_peers = peer_listener.as_ref().and_then(|l| l.allowed_peers.clone());
+
         let route_handler = Arc::new(GossipMessageHandler {
             storage: persister.storage.clone(),
             network_graph: gossip_sync.network_graph().clone(),
+            p2p_gossip_sync: p2p_gossip_sync.clone(),
+            allowed_inbound_peers,
             logger: logger.clone(),
         });
 
         // init peer manager
         let scb_message_handler = Arc::new(SCBMessageHandler::new());
+        let lsps_message_handler = Arc::new(LspsMessageHandler::new(logger.clone()));
         let ln_msg_handler = MessageHandler {
             chan_handler: channel_manager.clone(),
             route_handler,
             onion_message_handler: Arc::new(IgnoringMessageHandler {}),
-            custom_message_handler: scb_message_handler.clone(),
+            custom_message_handler: Arc::new(MutinyMessageHandler::new(
+                scb_message_handler.clone(),
+                lsps_message_handler.clone(),
+            )),
         };
 
         log_info!(logger, "creating lsp client");
@@ -317,17 +463,37 @@ impl<S: MutinyStorage> Node<S> {
                     log_info!(logger, "no lsp saved and no lsp clients available");
                     None
                 } else {
-                    log_info!(logger, "no lsp saved, picking random one");
-                    // If we don't have an lsp saved we should pick a random
-                    // one from our client list and save it for next time
-                    let rand = rand::random::<usize>() % lsp_clients.len();
-                    Some(lsp_clients[rand].clone())
+                    // If we don't have an lsp saved we should pick one from
+                    // the client list with our policy and save it for next time
+                    log_info!(
+                        logger,
+                        "no lsp saved, picking one with {lsp_selection_policy:?}"
+                    );
+                    lsp_selection_policy.select_node_lsp(lsp_clients).cloned()
                 }
             }
             Some(ref lsp) => lsp_clients.iter().find(|c| &c.url == lsp).cloned(),
         };
 
-        let lsp_client_pubkey = lsp_client.clone().map(|lsp| lsp.pubkey);
+        // the node's lsp goes first, then the rest in order of preference
+        let lsp_clients: Vec<LspClient> = lsp_client
+            .iter()
+            .chain(
+                lsp_clients
+                    .iter()
+                    .filter(|c| lsp_client.as_ref().map(|l| &l.url) != Some(&c.url)),
+            )
+            .cloned()
+            .collect();
+
+        let lsp_client_pubkeys = lsp_clients.iter().map(|lsp| lsp.pubkey).collect();
+
+        let bump_tx_event_handler = Arc::new(BumpTransactionEventHandler::new(
+            chain.clone(),
+            Arc::new(BumpTxWallet::new(wallet.clone(), logger.clone())),
+            keys_manager.clone(),
+            logger.clone(),
+        ));
 
         // init event handler
         let event_handler = EventHandler::new(
@@ -336,7 +502,11 @@ impl<S: MutinyStorage> Node<S> {
             wallet.clone(),
             keys_manager.clone(),
             persister.clone(),
-            lsp_client_pubkey,
+            lsp_client_pubkeys,
+            inbound_channel_policy.clone(),
+            inbound_channel_approver,
+            channel_policy.clone(),
+            bump_tx_event_handler,
             logger.clone(),
         );
 
@@ -423,13 +593,18 @@ impl<S: MutinyStorage> Node<S> {
         let background_processor_channel_manager = channel_manager.clone();
         let background_chain_monitor = chain_monitor.clone();
         let background_gossip_sync = gossip_sync.clone();
+        let background_p2p_gossip_sync = p2p_gossip_sync.clone();
         let background_logger = logger.clone();
         let background_stop = stop.clone();
         stopped_components.try_write()?.push(false);
         let background_stopped_components = stopped_components.clone();
         utils::spawn(async move {
             loop {
-                let gs = crate::background::GossipSync::rapid(background_gossip_sync.clone());
+                // P2P gossip has the graph pruned on the background schedule too
+                let gs = match background_p2p_gossip_sync.clone() {
+                    Some(p2p) => crate::background::GossipSync::P2P(p2p),
+                    None => crate::background::GossipSync::Rapid(background_gossip_sync.clone()),
+                };
                 let ev = background_event_handler.clone();
                 if let Err(e) = process_events_async(
                     background_persister.clone(),
@@ -471,9 +646,24 @@ impl<S: MutinyStorage> Node<S> {
             }
         });
 
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(peer_listener) = peer_listener {
+            start_peer_listener(
+                &peer_listener,
+                node_index.child_index,
+                peer_man.clone(),
+                logger.clone(),
+                stop.clone(),
+                stopped_components.clone(),
+            )
+            .await?;
+        }
+
         if !do_not_connect_peers {
             #[cfg(target_arch = "wasm32")]
             let reconnection_proxy_addr = websocket_proxy_addr.clone();
+            #[cfg(not(target_arch = "wasm32"))]
+            let reconnection_socks5_proxy = socks5_proxy.clone();
 
             let reconnection_storage = persister.storage.clone();
             let reconnection_pubkey = pubkey;
@@ -491,6 +681,8 @@ impl<S: MutinyStorage> Node<S> {
                     reconnection_pubkey,
                     #[cfg(target_arch = "wasm32")]
                     reconnection_proxy_addr,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    reconnection_socks5_proxy,
                     reconnection_peer_man,
                     reconnection_fee,
                     &reconnection_logger,
@@ -504,6 +696,20 @@ impl<S: MutinyStorage> Node<S> {
             });
         }
 
+        // the on-chain wallet is shared by all of our nodes,
+        // so it keeps the anchor reserve for all of them
+        let reserve_channel_manager = Arc::downgrade(&channel_manager);
+        let reserve_policy = channel_policy.clone();
+        wallet.register_anchor_reserve(
+            pubkey,
+            Box::new(move || {
+                reserve_channel_manager
+                    .upgrade()
+                    .map(|cm| reserve_policy.anchor_reserve_for(&cm.list_channels()))
+                    .unwrap_or_default()
+            }),
+        );
+
         Ok(Node {
             _uuid: uuid,
             stopped_components,
@@ -515,14 +721,26 @@ impl<S: MutinyStorage> Node<S> {
             chain_monitor,
             fee_estimator,
             scb_message_handler,
+            lsps_message_handler,
             network,
             persister,
             wallet,
+            router,
             logger,
             lsp_client,
+            lsp_clients,
+            lsp_selection_policy,
+            channel_policy,
+            channel_policy_override,
+            invoice_quotes: utils::Mutex::new(HashMap::new()),
+            peer_storage_requested: AtomicBool::new(false),
+            #[cfg(test)]
+            event_handler,
             stop,
             #[cfg(target_arch = "wasm32")]
             websocket_proxy_addr,
+            #[cfg(not(target_arch = "wasm32"))]
+            socks5_proxy,
         })
     }
 
@@ -557,6 +775,7 @@ impl<S: MutinyStorage> Node<S> {
             child_index: self.child_index,
             lsp: self.lsp_client.clone().map(|l| l.url),
             archived: Some(false),
+            channel_policy: self.channel_policy_override.clone(),
         }
     }
 
@@ -568,6 +787,8 @@ impl<S: MutinyStorage> Node<S> {
         let connect_res = connect_peer_if_necessary(
             #[cfg(target_arch = "wasm32")]
             &self.websocket_proxy_addr,
+            #[cfg(not(target_arch = "wasm32"))]
+            self.socks5_proxy.as_ref(),
             &peer_connection_info,
             self.logger.clone(),
             self.peer_manager.clone(),
@@ -623,6 +844,7 @@ impl<S: MutinyStorage> Node<S> {
 
     pub fn disconnect_peer(&self, peer_id: PublicKey) {
         self.peer_manager.disconnect_by_node_id(peer_id);
+        self.scb_message_handler.peer_disconnected(&peer_id);
     }
 
     pub fn get_phantom_route_hint(&self) -> PhantomRouteHints {
@@ -635,87 +857,567 @@ impl<S: MutinyStorage> Node<S> {
         labels: Vec<String>,
         route_hints: Option<Vec<PhantomRouteHints>>,
     ) -> Result<Invoice, MutinyError> {
-        // the amount to create for the invoice whether or not there is an lsp
-        let (amount_sat, lsp_fee_msat) = if let Some(lsp) = self.lsp_client.clone() {
-            // LSP requires an amount:
-            let amount_sat = amount_sat.ok_or(MutinyError::BadAmountError)?;
-
-            // Needs any amount over 0 if channel exists
-            // Needs amount over 10k if no channel
-            let has_usable_channel = self
-                .channel_manager
-                .list_channels_with_counterparty(&lsp.pubkey)
-                .iter()
-                .any(|c| c.inbound_capacity_msat >= amount_sat * 1000);
-            let min_amount_sat = if has_usable_channel {
-                1
-            } else {
-                utils::min_lightning_amount(self.network)
+        self.create_invoice_for_hash(amount_sat, None, labels, route_hints)
+            .await
+    }
+
+    /// Creates a hold invoice for the given payment hash.
+    /// Payments to this invoice will not be claimed automatically,
+    /// they are held until [`Node::settle_hold_invoice`] or [`Node::cancel_hold_invoice`] is called.
+    pub async fn create_hold_invoice(
+        &self,
+        payment_hash: PaymentHash,
+        amount_sat: Option<u64>,
+        labels: Vec<String>,
+        route_hints: Option<Vec<PhantomRouteHints>>,
+    ) -> Result<Invoice, MutinyError> {
+        self.create_invoice_for_hash(amount_sat, Some(payment_hash), labels, route_hints)
+            .await
+    }
+
+    async fn create_invoice_for_hash(
+        &self,
+        amount_sat: Option<u64>,
+        payment_hash: Option<PaymentHash>,
+        labels: Vec<String>,
+        route_hints: Option<Vec<PhantomRouteHints>>,
+    ) -> Result<Invoice, MutinyError> {
+        if self.lsp_clients.is_empty() {
+            return self
+                .create_internal_invoice(amount_sat, payment_hash, None, None, labels, route_hints)
+                .await;
+        }
+
+        // LSP requires an amount:
+        let amount_sat = amount_sat.ok_or(MutinyError::BadAmountError)?;
+
+        let mut last_error = MutinyError::LspGenericError;
+        let candidates = self.lsp_candidates(amount_sat, &mut last_error).await;
+
+        for (lsp, quote) in candidates {
+            let quote = match quote {
+                Some(quote) => quote,
+                None => match self.get_lsp_quote(lsp, amount_sat).await {
+                    Ok(quote) => quote,
+                    Err(e) => {
+                        log_warn!(
+                            self.logger,
+                            "could not get quote from lsp {}, trying next: {e}",
+                            lsp.pubkey
+                        );
+                        last_error = e;
+                        continue;
+                    }
+                },
             };
-            if amount_sat < min_amount_sat {
-                return Err(MutinyError::BadAmountError);
+
+            match self
+                .create_lsp_invoice(
+                    lsp,
+                    quote,
+                    amount_sat,
+                    payment_hash,
+                    labels.clone(),
+                    route_hints.clone(),
+                )
+                .await
+            {
+                Ok(invoice) => return Ok(invoice),
+                Err(e) => {
+                    log_warn!(
+                        self.logger,
+                        "could not create invoice with lsp {}, trying next: {e}",
+                        lsp.pubkey
+                    );
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// The LSPs to try for an invoice, in the order to try them.
+    ///
+    /// With the cheapest policy we get a quote from every LSP up front and try
+    /// them from cheapest to most expensive, otherwise we try them in order of
+    /// preference and only get quotes from the ones we try.
+    async fn lsp_candidates(
+        &self,
+        amount_sat: u64,
+        last_error: &mut MutinyError,
+    ) -> Vec<(&LspClient, Option<LspQuote>)> {
+        match self.lsp_selection_policy {
+            LspSelectionPolicy::Preferred => {
+                self.lsp_clients.iter().map(|lsp| (lsp, None)).collect()
             }
+            LspSelectionPolicy::Cheapest => {
+                let quotes = futures::future::join_all(
+                    self.lsp_clients
+                        .iter()
+                        .map(|lsp| self.get_lsp_quote(lsp, amount_sat)),
+                )
+                .await;
 
-            // check the fee from the LSP
-            let lsp_fee_msat = lsp
-                .get_lsp_fee_msat(FeeRequest {
-                    pubkey: self.pubkey.to_hex(),
-                    amount_msat: amount_sat * 1000,
-                })
-                .await?;
-
-            // Convert the fee from msat to sat for comparison and subtraction
-            let lsp_fee_sat = lsp_fee_msat / 1000;
-
-            // Ensure that the fee is less than the amount being requested.
-            // If it isn't, we don't subtract it.
-            // This prevents amount from being subtracted down to 0.
-            // This will mean that the LSP fee will be paid by the payer instead.
-            let amount_minus_fee = if lsp_fee_sat < amount_sat {
-                amount_sat
-                    .checked_sub(lsp_fee_sat)
-                    .ok_or(MutinyError::BadAmountError)?
-            } else {
-                amount_sat
-            };
+                let mut quoted = vec![];
+                for (lsp, quote) in self.lsp_clients.iter().zip(quotes) {
+                    match quote {
+                        Ok(quote) => quoted.push((lsp, Some(quote))),
+                        Err(e) => {
+                            log_warn!(
+                                self.logger,
+                                "could not get quote from lsp {}: {e}",
+                                lsp.pubkey
+                            );
+                            *last_error = e;
+                        }
+                    }
+                }
+                quoted.sort_by_key(|(_, quote)| quote.as_ref().map(|q| q.fee_msat));
+                quoted
+            }
+        }
+    }
+
+    /// Gets a quote for receiving the given amount, in satoshis. The quote says
+    /// which LSP will be used, its fee and what we will receive after the fee.
+    /// Use [`Node::create_invoice_from_quote`] to create the invoice for it.
+    pub async fn quote_invoice(&self, amount_sat: u64) -> Result<InvoiceQuote, MutinyError> {
+        if amount_sat == 0 {
+            return Err(MutinyError::BadAmountError);
+        }
 
-            (Some(amount_minus_fee), Some(lsp_fee_msat))
+        let lsp = if self.lsp_clients.is_empty() {
+            None
         } else {
-            (amount_sat, None)
+            let mut last_error = MutinyError::LspGenericError;
+            let mut found = None;
+            for (lsp, quote) in self.lsp_candidates(amount_sat, &mut last_error).await {
+                let quote = match quote {
+                    Some(quote) => quote,
+                    None => match self.get_lsp_quote(lsp, amount_sat).await {
+                        Ok(quote) => quote,
+                        Err(e) => {
+                            log_warn!(
+                                self.logger,
+                                "could not get quote from lsp {}, trying next: {e}",
+                                lsp.pubkey
+                            );
+                            last_error = e;
+                            continue;
+                        }
+                    },
+                };
+                found = Some((lsp.clone(), quote));
+                break;
+            }
+            Some(found.ok_or(last_error)?)
         };
 
-        let invoice = self
-            .create_internal_invoice(amount_sat, lsp_fee_msat, labels, route_hints)
-            .await?;
+        let quote = self.invoice_quote(amount_sat, lsp.as_ref());
+        self.invoice_quotes.lock()?.insert(
+            quote.id.clone(),
+            PendingInvoiceQuote {
+                quote: quote.clone(),
+                amount_sat,
+                lsp,
+            },
+        );
+
+        Ok(quote)
+    }
+
+    /// Builds the quote we give the user from what the LSP will charge us
+    fn invoice_quote(&self, amount_sat: u64, lsp: Option<&(LspClient, LspQuote)>) -> InvoiceQuote {
+        let (amount_sats, fee_sats, new_channel) = match lsp {
+            None => (amount_sat, 0, false),
+            Some((lsp, quote)) => match &lsp.protocol {
+                LspProtocol::Voltage(_) => {
+                    // the fee is paid by the payer if it is not less than the amount,
+                    // see Node::create_voltage_invoice
+                    let fee_sat = quote.fee_msat / 1000;
+                    let amount_sats = if fee_sat < amount_sat {
+                        amount_sat
+                    } else {
+                        amount_sat + fee_sat
+                    };
+                    let new_channel = !self.has_inbound_liquidity(&lsp.pubkey, amount_sat);
+                    (amount_sats, fee_sat, new_channel)
+                }
+                LspProtocol::Lsps { .. } => {
+                    // round the fee up, we only receive whole sats
+                    let fee_sat = (quote.fee_msat + 999) / 1000;
+                    (amount_sat, fee_sat, quote.jit_channel.is_some())
+                }
+            },
+        };
+
+        InvoiceQuote {
+            id: Uuid::new_v4().to_string(),
+            lsp_pubkey: lsp.map(|(lsp, _)| lsp.pubkey),
+            amount_sats,
+            fee_sats,
+            receivable_sats: amount_sats - fee_sats,
+            new_channel,
+            expires_at: utils::now().as_secs() + INVOICE_QUOTE_EXPIRY_SECS,
+        }
+    }
+
+    /// Creates an invoice for a quote from [`Node::quote_invoice`]. Each quote can
+    /// only be used once. Fails with [`MutinyError::NotFound`] if the quote is
+    /// unknown or expired and with [`MutinyError::LspQuoteChangedError`] if the
+    /// LSP's fee went up by more than our tolerance since the quote.
+    pub async fn create_invoice_from_quote(
+        &self,
+        quote_id: &str,
+        labels: Vec<String>,
+        route_hints: Option<Vec<PhantomRouteHints>>,
+    ) -> Result<Invoice, MutinyError> {
+        let pending = self
+            .invoice_quotes
+            .lock()?
+            .remove(quote_id)
+            .ok_or(MutinyError::NotFound)?;
+        if pending.quote.expires_at < utils::now().as_secs() {
+            return Err(MutinyError::NotFound);
+        }
+
+        match pending.lsp {
+            None => {
+                self.create_internal_invoice(
+                    Some(pending.amount_sat),
+                    None,
+                    None,
+                    None,
+                    labels,
+                    route_hints,
+                )
+                .await
+            }
+            Some((lsp, quote)) => {
+                let quote = self
+                    .refresh_lsp_quote(&lsp, quote, pending.amount_sat)
+                    .await?;
+                self.create_lsp_invoice(&lsp, quote, pending.amount_sat, None, labels, route_hints)
+                    .await
+            }
+        }
+    }
+
+    /// Makes sure the LSP will still honor a quote. A JIT channel's fee is fixed
+    /// until the LSP's offer expires, otherwise we get a new quote and check the
+    /// fee did not go up by more than [`INVOICE_QUOTE_FEE_TOLERANCE_PPM`].
+    async fn refresh_lsp_quote(
+        &self,
+        lsp: &LspClient,
+        quote: LspQuote,
+        amount_sat: u64,
+    ) -> Result<LspQuote, MutinyError> {
+        let now = utils::now().as_secs();
+        if quote
+            .jit_channel
+            .as_ref()
+            .is_some_and(|jit_channel| jit_channel.opening_fee_params.is_valid(now))
+        {
+            return Ok(quote);
+        }
+
+        let new_quote = self.get_lsp_quote(lsp, amount_sat).await?;
+        if !quoted_fee_still_valid(quote.fee_msat, new_quote.fee_msat) {
+            log_warn!(
+                self.logger,
+                "lsp {} fee went from {} to {} msats since the quote",
+                lsp.pubkey,
+                quote.fee_msat,
+                new_quote.fee_msat
+            );
+            return Err(MutinyError::LspQuoteChangedError);
+        }
+
+        Ok(new_quote)
+    }
+
+    /// Checks if we have a channel with the peer that can receive the amount
+    fn has_inbound_liquidity(&self, pubkey: &PublicKey, amount_sat: u64) -> bool {
+        self.channel_manager
+            .list_channels_with_counterparty(pubkey)
+            .iter()
+            .any(|c| c.inbound_capacity_msat >= amount_sat * 1000)
+    }
+
+    /// Gets what the LSP will charge us to receive a payment of the given amount.
+    /// Fails if the fee is above the max fee configured for the LSP.
+    async fn get_lsp_quote(
+        &self,
+        lsp: &LspClient,
+        amount_sat: u64,
+    ) -> Result<LspQuote, MutinyError> {
+        let has_usable_channel = self.has_inbound_liquidity(&lsp.pubkey, amount_sat);
+
+        let quote = match &lsp.protocol {
+            LspProtocol::Voltage(_) => {
+                // Needs any amount over 0 if channel exists
+                // Needs amount over 10k if no channel
+                let min_amount_sat = if has_usable_channel {
+                    1
+                } else {
+                    utils::min_lightning_amount(self.network)
+                };
+                if amount_sat < min_amount_sat {
+                    return Err(MutinyError::BadAmountError);
+                }
 
-        if let Some(lsp) = self.lsp_client.clone() {
-            self.connect_peer(PubkeyConnectionInfo::new(&lsp.connection_string)?, None)
-                .await?;
-            let lsp_invoice_str = lsp.get_lsp_invoice(invoice.to_string()).await?;
-            let lsp_invoice = Invoice::from_str(&lsp_invoice_str)?;
+                // check the fee from the LSP
+                let fee_msat = lsp
+                    .get_lsp_fee_msat(FeeRequest {
+                        pubkey: self.pubkey.to_hex(),
+                        amount_msat: amount_sat * 1000,
+                    })
+                    .await?;
+
+                LspQuote {
+                    fee_msat,
+                    jit_channel: None,
+                }
+            }
+            LspProtocol::Lsps { token } => {
+                if amount_sat == 0 {
+                    return Err(MutinyError::BadAmountError);
+                }
 
-            if invoice.network() != self.network {
-                return Err(MutinyError::IncorrectNetwork(invoice.network()));
+                // we can receive over our existing channel for free
+                if has_usable_channel {
+                    LspQuote {
+                        fee_msat: 0,
+                        jit_channel: None,
+                    }
+                } else {
+                    self.connect_peer(PubkeyConnectionInfo::new(&lsp.connection_string)?, None)
+                        .await?;
+                    let jit_channel = lsps2::get_jit_channel_quote(
+                        &self.lsps_message_handler,
+                        &lsp.pubkey,
+                        token.clone(),
+                        amount_sat * 1000,
+                    )
+                    .await?;
+
+                    LspQuote {
+                        fee_msat: jit_channel.opening_fee_msat,
+                        jit_channel: Some(jit_channel),
+                    }
+                }
             }
+        };
 
-            if lsp_invoice.payment_hash() != invoice.payment_hash()
-                || lsp_invoice.recover_payee_pub_key() != lsp.pubkey
-            {
-                return Err(MutinyError::InvoiceCreationFailed);
+        lsp.check_fee(quote.fee_msat)?;
+
+        Ok(quote)
+    }
+
+    /// Creates an invoice with the LSP for a quote from [`Node::get_lsp_quote`]
+    async fn create_lsp_invoice(
+        &self,
+        lsp: &LspClient,
+        quote: LspQuote,
+        amount_sat: u64,
+        payment_hash: Option<PaymentHash>,
+        labels: Vec<String>,
+        route_hints: Option<Vec<PhantomRouteHints>>,
+    ) -> Result<Invoice, MutinyError> {
+        match (&lsp.protocol, quote.jit_channel) {
+            (LspProtocol::Voltage(_), _) => {
+                self.create_voltage_invoice(
+                    lsp,
+                    quote.fee_msat,
+                    amount_sat,
+                    payment_hash,
+                    labels,
+                    route_hints,
+                )
+                .await
             }
+            (LspProtocol::Lsps { .. }, Some(jit_channel)) => {
+                self.create_jit_channel_invoice(lsp, jit_channel, payment_hash, labels)
+                    .await
+            }
+            (LspProtocol::Lsps { .. }, None) => {
+                self.create_internal_invoice(
+                    Some(amount_sat),
+                    payment_hash,
+                    None,
+                    Some(lsp.pubkey),
+                    labels,
+                    route_hints,
+                )
+                .await
+            }
+        }
+    }
 
-            Ok(lsp_invoice)
+    /// Creates an invoice and has the Voltage style LSP wrap it, the LSP
+    /// opens a channel to us if needed when the wrapped invoice is paid.
+    async fn create_voltage_invoice(
+        &self,
+        lsp: &LspClient,
+        lsp_fee_msat: u64,
+        amount_sat: u64,
+        payment_hash: Option<PaymentHash>,
+        labels: Vec<String>,
+        route_hints: Option<Vec<PhantomRouteHints>>,
+    ) -> Result<Invoice, MutinyError> {
+        // Convert the fee from msat to sat for comparison and subtraction
+        let lsp_fee_sat = lsp_fee_msat / 1000;
+
+        // Ensure that the fee is less than the amount being requested.
+        // If it isn't, we don't subtract it.
+        // This prevents amount from being subtracted down to 0.
+        // This will mean that the LSP fee will be paid by the payer instead.
+        let amount_minus_fee = if lsp_fee_sat < amount_sat {
+            amount_sat
+                .checked_sub(lsp_fee_sat)
+                .ok_or(MutinyError::BadAmountError)?
         } else {
-            Ok(invoice)
+            amount_sat
+        };
+
+        // the invoice is only saved once the LSP has wrapped it, so trying
+        // another LSP after a failure doesn't leave an unusable invoice behind
+        let invoice = self
+            .generate_internal_invoice(Some(amount_minus_fee), payment_hash, route_hints)
+            .await?;
+
+        self.connect_peer(PubkeyConnectionInfo::new(&lsp.connection_string)?, None)
+            .await?;
+        let lsp_invoice_str = lsp.get_lsp_invoice(invoice.to_string()).await?;
+        let lsp_invoice = Invoice::from_str(&lsp_invoice_str)?;
+
+        if lsp_invoice.network() != self.network {
+            return Err(MutinyError::IncorrectNetwork(lsp_invoice.network()));
+        }
+
+        if lsp_invoice.payment_hash() != invoice.payment_hash()
+            || lsp_invoice.recover_payee_pub_key() != lsp.pubkey
+        {
+            return Err(MutinyError::InvoiceCreationFailed);
+        }
+
+        self.save_invoice_payment_info(
+            &invoice,
+            Some(amount_minus_fee * 1_000),
+            Some(lsp_fee_msat),
+            Some(lsp.pubkey),
+            labels,
+        )?;
+
+        Ok(lsp_invoice)
+    }
+
+    /// Buys a JIT channel from an LSPS2 LSP and creates an invoice for it.
+    /// The LSP opens the channel when the invoice is paid and takes its fee
+    /// out of the payment.
+    async fn create_jit_channel_invoice(
+        &self,
+        lsp: &LspClient,
+        quote: JitChannelQuote,
+        payment_hash: Option<PaymentHash>,
+        labels: Vec<String>,
+    ) -> Result<Invoice, MutinyError> {
+        self.connect_peer(PubkeyConnectionInfo::new(&lsp.connection_string)?, None)
+            .await?;
+
+        let jit_channel = lsps2::buy_jit_channel(&self.lsps_message_handler, quote).await?;
+        log_debug!(
+            self.logger,
+            "bought jit channel from lsp {} with opening fee of {} msats",
+            lsp.pubkey,
+            jit_channel.opening_fee_msat
+        );
+
+        // we only receive what is left after the LSP takes its fee
+        let amount_msat = jit_channel.payment_size_msat - jit_channel.opening_fee_msat;
+        let (payment_hash, payment_secret) = match payment_hash {
+            Some(payment_hash) => self
+                .channel_manager
+                .create_inbound_payment_for_hash(payment_hash, Some(amount_msat), 1500, Some(40))
+                .map(|secret| (payment_hash, secret)),
+            None => self
+                .channel_manager
+                .create_inbound_payment(Some(amount_msat), 1500, Some(40)),
         }
+        .map_err(|_| MutinyError::InvoiceCreationFailed)?;
+
+        // Set description to empty string to make smallest possible invoice/QR code
+        let raw_invoice = InvoiceBuilder::new(self.network.into())
+            .description("".to_string())
+            .payment_hash(Sha256::from_inner(payment_hash.0))
+            .payment_secret(payment_secret)
+            .duration_since_epoch(utils::now())
+            .min_final_cltv_expiry_delta(40)
+            .expiry_time(Duration::from_secs(1500))
+            .amount_milli_satoshis(jit_channel.payment_size_msat)
+            .basic_mpp()
+            .private_route(jit_channel.route_hint())
+            .build_raw()
+            .map_err(|e| {
+                log_error!(self.logger, "ERROR: could not generate invoice: {e}");
+                MutinyError::InvoiceCreationFailed
+            })?;
+
+        let hrp_str = raw_invoice.hrp.to_string();
+        let data = raw_invoice.data.to_base32();
+        let invoice = raw_invoice
+            .sign(|_| {
+                self.keys_manager
+                    .sign_invoice(hrp_str.as_bytes(), &data, Recipient::Node)
+            })
+            .ok()
+            .and_then(|signed| Invoice::from_signed(signed).ok())
+            .ok_or(MutinyError::InvoiceCreationFailed)?;
+
+        self.save_invoice_payment_info(
+            &invoice,
+            Some(amount_msat),
+            Some(jit_channel.opening_fee_msat),
+            Some(lsp.pubkey),
+            labels,
+        )?;
+
+        log_info!(
+            self.logger,
+            "SUCCESS: generated jit channel invoice: {invoice}"
+        );
+
+        Ok(invoice)
     }
 
     async fn create_internal_invoice(
         &self,
         amount_sat: Option<u64>,
+        payment_hash: Option<PaymentHash>,
         fee_amount_msat: Option<u64>,
+        lsp_pubkey: Option<PublicKey>,
         labels: Vec<String>,
         route_hints: Option<Vec<PhantomRouteHints>>,
+    ) -> Result<Invoice, MutinyError> {
+        let invoice = self
+            .generate_internal_invoice(amount_sat, payment_hash, route_hints)
+            .await?;
+
+        let amount_msat = amount_sat.map(|s| s * 1_000);
+        self.save_invoice_payment_info(&invoice, amount_msat, fee_amount_msat, lsp_pubkey, labels)?;
+
+        log_info!(self.logger, "SUCCESS: generated invoice: {invoice}");
+
+        Ok(invoice)
+    }
+
+    /// Creates an invoice from our channel manager without saving it
+    async fn generate_internal_invoice(
+        &self,
+        amount_sat: Option<u64>,
+        payment_hash: Option<PaymentHash>,
+        route_hints: Option<Vec<PhantomRouteHints>>,
     ) -> Result<Invoice, MutinyError> {
         let amount_msat = amount_sat.map(|s| s * 1_000);
         // Set description to empty string to make smallest possible invoice/QR code
@@ -735,8 +1437,8 @@ impl<S: MutinyStorage> Node<S> {
             sleep(1_000).await;
         }
 
-        let invoice_res = match route_hints {
-            None => {
+        let invoice_res = match (route_hints, payment_hash) {
+            (None, None) => {
                 let now = crate::utils::now();
                 create_invoice_from_channelmanager_and_duration_since_epoch(
                     &self.channel_manager.clone(),
@@ -750,9 +1452,24 @@ impl<S: MutinyStorage> Node<S> {
                     Some(40),
                 )
             }
-            Some(r) => create_phantom_invoice(
+            (None, Some(payment_hash)) => {
+                let now = crate::utils::now();
+                create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_hash(
+                    &self.channel_manager.clone(),
+                    self.keys_manager.clone(),
+                    self.logger.clone(),
+                    self.network.into(),
+                    amount_msat,
+                    description,
+                    now,
+                    1500,
+                    payment_hash,
+                    Some(40),
+                )
+            }
+            (Some(r), payment_hash) => create_phantom_invoice(
                 amount_msat,
-                None,
+                payment_hash,
                 description,
                 1500,
                 r,
@@ -764,11 +1481,21 @@ impl<S: MutinyStorage> Node<S> {
                 crate::utils::now(),
             ),
         };
-        let invoice = invoice_res.map_err(|e| {
+        invoice_res.map_err(|e| {
             log_error!(self.logger, "ERROR: could not generate invoice: {e}");
             MutinyError::InvoiceCreationFailed
-        })?;
+        })
+    }
 
+    /// Saves the payment info and labels for an invoice we created
+    fn save_invoice_payment_info(
+        &self,
+        invoice: &Invoice,
+        amount_msat: Option<u64>,
+        fee_amount_msat: Option<u64>,
+        lsp_pubkey: Option<PublicKey>,
+        labels: Vec<String>,
+    ) -> Result<(), MutinyError> {
         let last_update = crate::utils::now().as_secs();
         let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
         let payment_info = PaymentInfo {
@@ -779,6 +1506,8 @@ impl<S: MutinyStorage> Node<S> {
             fee_paid_msat: fee_amount_msat,
             bolt11: Some(invoice.clone()),
             payee_pubkey: None,
+            lsp_pubkey,
+            claim_deadline: None,
             last_update,
         };
         self.persister
@@ -792,15 +1521,78 @@ impl<S: MutinyStorage> Node<S> {
             .storage
             .set_invoice_labels(invoice.clone(), labels)?;
 
-        log_info!(self.logger, "SUCCESS: generated invoice: {invoice}");
+        Ok(())
+    }
 
-        Ok(invoice)
+    /// Gets our LSP and its token if it speaks LSPS, and makes sure we are connected to it
+    async fn connect_lsps_lsp(&self) -> Result<(PublicKey, Option<String>), MutinyError> {
+        let lsp = self
+            .lsp_client
+            .as_ref()
+            .ok_or(MutinyError::LspGenericError)?;
+        let LspProtocol::Lsps { token } = &lsp.protocol else {
+            return Err(MutinyError::LspGenericError);
+        };
+
+        self.connect_peer(PubkeyConnectionInfo::new(&lsp.connection_string)?, None)
+            .await?;
+
+        Ok((lsp.pubkey, token.clone()))
+    }
+
+    /// Gets the limits of the inbound liquidity our LSP will sell us with LSPS1
+    pub async fn get_liquidity_options(&self) -> Result<LiquidityOptions, MutinyError> {
+        let (lsp_pubkey, _) = self.connect_lsps_lsp().await?;
+        lsps1::get_liquidity_options(&self.lsps_message_handler, &lsp_pubkey).await
+    }
+
+    /// Orders a channel with the given amount of inbound liquidity from our LSP with LSPS1.
+    /// The LSP will open the channel once the order's invoice is paid.
+    pub async fn create_liquidity_order(
+        &self,
+        lsp_balance_sat: u64,
+        channel_expiry_blocks: Option<u32>,
+    ) -> Result<LiquidityOrder, MutinyError> {
+        let (lsp_pubkey, token) = self.connect_lsps_lsp().await?;
+        lsps1::create_liquidity_order(
+            &self.lsps_message_handler,
+            &lsp_pubkey,
+            lsp_balance_sat,
+            channel_expiry_blocks,
+            token,
+            None,
+        )
+        .await
+    }
+
+    /// Gets the current state of an LSPS1 liquidity order from our LSP
+    pub async fn get_liquidity_order(
+        &self,
+        order_id: String,
+    ) -> Result<LiquidityOrder, MutinyError> {
+        let (lsp_pubkey, _) = self.connect_lsps_lsp().await?;
+        lsps1::get_liquidity_order(&self.lsps_message_handler, &lsp_pubkey, order_id).await
     }
 
     pub fn get_invoice(&self, invoice: &Invoice) -> Result<MutinyInvoice, MutinyError> {
         self.get_invoice_by_hash(invoice.payment_hash())
     }
 
+    /// Converts a freshly created invoice into a [`MutinyInvoice`], keeping
+    /// track of which LSP (if any) was used to create it.
+    pub(crate) fn created_invoice(&self, invoice: Invoice) -> MutinyInvoice {
+        let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+        let lsp_pubkey = self
+            .persister
+            .read_payment_info(&payment_hash, true, &self.logger)
+            .and_then(|info| info.lsp_pubkey);
+
+        MutinyInvoice {
+            lsp_pubkey,
+            ..invoice.into()
+        }
+    }
+
     pub fn get_invoice_by_hash(&self, payment_hash: &Sha256) -> Result<MutinyInvoice, MutinyError> {
         let (payment_info, inbound) = self.get_payment_info_from_persisters(payment_hash)?;
         let labels_map = self.persister.storage.get_invoice_labels()?;
@@ -846,12 +1638,105 @@ impl<S: MutinyStorage> Node<S> {
                 // filter out expired invoices
                 mutiny_invoice.filter(|invoice| {
                     !invoice.bolt11.as_ref().is_some_and(|b| b.would_expire(now))
-                        || matches!(i.status, HTLCStatus::Succeeded | HTLCStatus::InFlight)
+                        || matches!(
+                            i.status,
+                            HTLCStatus::Succeeded | HTLCStatus::InFlight | HTLCStatus::Held
+                        )
                 })
             })
             .collect())
     }
 
+    /// Settles a held payment for a hold invoice by claiming it with the given preimage.
+    /// Returns [`MutinyError::NotFound`] if this node is not holding a payment for the preimage.
+    pub fn settle_hold_invoice(&self, preimage: [u8; 32]) -> Result<(), MutinyError> {
+        let payment_hash = PaymentHash(Sha256::hash(&preimage).into_inner());
+        match self
+            .persister
+            .read_payment_info(&payment_hash, true, &self.logger)
+        {
+            Some(mut payment_info) if payment_info.status == HTLCStatus::Held => {
+                log_info!(
+                    self.logger,
+                    "Settling hold invoice: {}",
+                    payment_hash.0.to_hex()
+                );
+                // save the preimage now, the PaymentClaimed event does not give it to us
+                // for a hold invoice we only had the payment hash of
+                payment_info.preimage = Some(preimage);
+                payment_info.last_update = utils::now().as_secs();
+                self.persister
+                    .persist_payment_info(&payment_hash, &payment_info, true)?;
+
+                // the status is updated once we get the PaymentClaimed event
+                self.channel_manager.claim_funds(PaymentPreimage(preimage));
+                Ok(())
+            }
+            _ => Err(MutinyError::NotFound),
+        }
+    }
+
+    /// Cancels a hold invoice, failing back any held payment.
+    /// Any payment that arrives for the invoice after this will be failed back as well.
+    pub fn cancel_hold_invoice(&self, payment_hash: &Sha256) -> Result<(), MutinyError> {
+        let payment_hash = PaymentHash(payment_hash.into_inner());
+        let mut payment_info = self
+            .persister
+            .read_payment_info(&payment_hash, true, &self.logger)
+            .ok_or(MutinyError::NotFound)?;
+
+        match payment_info.status {
+            HTLCStatus::Succeeded => return Err(MutinyError::InvalidArgumentsError),
+            HTLCStatus::Failed => return Ok(()),
+            _ => {}
+        }
+
+        log_info!(
+            self.logger,
+            "Cancelling hold invoice: {}",
+            payment_hash.0.to_hex()
+        );
+        self.channel_manager.fail_htlc_backwards(&payment_hash);
+
+        payment_info.status = HTLCStatus::Failed;
+        payment_info.last_update = utils::now().as_secs();
+        self.persister
+            .persist_payment_info(&payment_hash, &payment_info, true)?;
+
+        Ok(())
+    }
+
+    /// Cancels any held payments that are within [`HOLD_INVOICE_CANCEL_SAFETY_MARGIN`]
+    /// blocks of their claim deadline, so our channels are not force closed.
+    pub fn cancel_expiring_hold_invoices(&self) -> Result<(), MutinyError> {
+        let current_height = self.channel_manager.current_best_block().height();
+        for (payment_hash, payment_info) in self.persister.list_payment_info(true)? {
+            if payment_info.status != HTLCStatus::Held {
+                continue;
+            }
+
+            if payment_info
+                .claim_deadline
+                .is_some_and(|d| current_height + HOLD_INVOICE_CANCEL_SAFETY_MARGIN >= d)
+            {
+                log_warn!(
+                    self.logger,
+                    "Hold invoice {} is close to its claim deadline, cancelling",
+                    payment_hash.0.to_hex()
+                );
+                if let Err(e) = self.cancel_hold_invoice(&Sha256::from_inner(payment_hash.0)) {
+                    log_error!(
+                        self.logger,
+                        "Failed to cancel hold invoice {}: {e}",
+                        payment_hash.0.to_hex()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Gets all the closed channels for this node
     pub fn get_channel_closure(
         &self,
@@ -877,6 +1762,75 @@ impl<S: MutinyStorage> Node<S> {
             .collect())
     }
 
+    /// Starts a cooperative close of the given channel.
+    ///
+    /// If a destination is given, the closing output will pay to it instead of our on-chain wallet.
+    /// The destination must be a segwit address.
+    ///
+    /// The fee rate is in sats per vbyte and is used as the target for the closing fee negotiation.
+    /// The max fee is how many sats above that target we are willing to pay to close the channel.
+    pub(crate) fn cooperative_close_channel(
+        &self,
+        channel: &ChannelDetails,
+        destination: Option<Address>,
+        fee_rate: Option<f32>,
+        max_fee_sats: Option<u64>,
+    ) -> Result<(), MutinyError> {
+        let (shutdown_script, target_feerate_sats_per_1000_weight) =
+            cooperative_close_args(destination.as_ref(), fee_rate, self.network, &self.logger)?;
+
+        if let Some(max_fee_sats) = max_fee_sats {
+            let mut config = channel
+                .config
+                .unwrap_or_else(|| self.channel_policy.channel_config());
+            config.force_close_avoidance_max_fee_satoshis = max_fee_sats;
+            self.channel_manager
+                .update_channel_config(
+                    &channel.counterparty.node_id,
+                    &[channel.channel_id],
+                    &config,
+                )
+                .map_err(|e| {
+                    log_error!(
+                        self.logger,
+                        "could not set max close fee for channel {}: {e:?}",
+                        channel.channel_id.to_hex()
+                    );
+                    MutinyError::ChannelClosingFailed
+                })?;
+        }
+
+        // save before closing so the ChannelClosed event can find it
+        if destination.is_some() || max_fee_sats.is_some() {
+            let params = ChannelCloseParams {
+                destination,
+                max_fee_sats,
+            };
+            self.persister
+                .persist_channel_close_params(channel.user_channel_id, params)?;
+        }
+
+        if let Err(e) = self.channel_manager.close_channel_with_feerate_and_script(
+            &channel.channel_id,
+            &channel.counterparty.node_id,
+            target_feerate_sats_per_1000_weight,
+            shutdown_script,
+        ) {
+            log_error!(
+                self.logger,
+                "had an error closing channel {} with node {} : {e:?}",
+                channel.channel_id.to_hex(),
+                channel.counterparty.node_id.to_hex()
+            );
+            let _ = self
+                .persister
+                .delete_channel_close_params(channel.user_channel_id);
+            return Err(MutinyError::ChannelClosingFailed);
+        }
+
+        Ok(())
+    }
+
     fn get_payment_info_from_persisters(
         &self,
         payment_hash: &bitcoin::hashes::sha256::Hash,
@@ -904,6 +1858,193 @@ impl<S: MutinyStorage> Node<S> {
         Retry::Attempts(15)
     }
 
+    /// Creates an invoice to receive liquidity we are moving from another of our nodes or channels.
+    pub(crate) async fn create_rebalance_invoice(
+        &self,
+        amount_sat: u64,
+    ) -> Result<Invoice, MutinyError> {
+        self.create_internal_invoice(
+            Some(amount_sat),
+            None,
+            None,
+            None,
+            vec![REBALANCE_LABEL.to_string()],
+            None,
+        )
+        .await
+    }
+
+    /// Pays one of our own invoices along a route that ends with `last_hop` into the receiving node.
+    /// If `first_hop` is given, the payment leaves through that channel only.
+    ///
+    /// Fails without paying if routing would cost more than `max_fee_sats`.
+    pub(crate) async fn pay_rebalance_invoice(
+        &self,
+        invoice: &Invoice,
+        receiver: PublicKey,
+        receiver_features: NodeFeatures,
+        first_hop: Option<&ChannelDetails>,
+        last_hop: &ChannelDetails,
+        max_fee_sats: u64,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let amount_msat = invoice
+            .amount_milli_satoshis()
+            .ok_or(MutinyError::InvoiceInvalid)?;
+        let route = self.rebalance_route(
+            amount_msat,
+            invoice.min_final_cltv_expiry_delta() as u32,
+            receiver,
+            receiver_features,
+            first_hop,
+            last_hop,
+        )?;
+
+        let fee_msat = route.get_total_fees();
+        if fee_msat > max_fee_sats * 1_000 {
+            log_warn!(
+                self.logger,
+                "rebalance would cost {fee_msat} msats, more than the max of {max_fee_sats} sats"
+            );
+            return Err(MutinyError::RoutingFailed);
+        }
+
+        let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+        let labels = vec![REBALANCE_LABEL.to_string()];
+        if let Err(e) = self
+            .persister
+            .storage
+            .set_invoice_labels(invoice.clone(), labels.clone())
+        {
+            log_error!(self.logger, "could not set invoice label: {e}");
+        }
+
+        let mut payment_info = PaymentInfo {
+            preimage: None,
+            secret: None,
+            status: HTLCStatus::InFlight,
+            amt_msat: MillisatAmount(Some(amount_msat)),
+            fee_paid_msat: None,
+            bolt11: Some(invoice.clone()),
+            payee_pubkey: Some(receiver),
+            lsp_pubkey: None,
+            claim_deadline: None,
+            last_update: utils::now().as_secs(),
+        };
+        self.persister
+            .persist_payment_info(&payment_hash, &payment_info, false)?;
+
+        if let Err(e) = self.channel_manager.send_payment_with_route(
+            &route,
+            payment_hash,
+            RecipientOnionFields::secret_only(*invoice.payment_secret()),
+            PaymentId(payment_hash.0),
+        ) {
+            log_error!(self.logger, "failed to send rebalance payment: {e:?}");
+            payment_info.status = HTLCStatus::Failed;
+            self.persister
+                .persist_payment_info(&payment_hash, &payment_info, false)?;
+            return Err(MutinyError::RoutingFailed);
+        }
+
+        self.await_payment(payment_hash, DEFAULT_PAYMENT_TIMEOUT, labels)
+            .await
+    }
+
+    /// LDK will not find a route to ourselves, so this finds a route to the peer of
+    /// `last_hop` and then continues it over `last_hop` to the receiver.
+    ///
+    /// When `last_hop` is a channel with us, we pay over it directly.
+    fn rebalance_route(
+        &self,
+        amount_msat: u64,
+        min_final_cltv_expiry_delta: u32,
+        receiver: PublicKey,
+        receiver_features: NodeFeatures,
+        first_hop: Option<&ChannelDetails>,
+        last_hop: &ChannelDetails,
+    ) -> Result<Route, MutinyError> {
+        if last_hop.counterparty.node_id == self.pubkey {
+            // our side of the same channel
+            let short_channel_id = self
+                .channel_manager
+                .list_usable_channels()
+                .into_iter()
+                .find(|c| c.channel_id == last_hop.channel_id)
+                .and_then(|c| c.get_outbound_payment_scid())
+                .ok_or(MutinyError::RoutingFailed)?;
+            return Ok(direct_route(
+                amount_msat,
+                min_final_cltv_expiry_delta,
+                receiver,
+                receiver_features,
+                short_channel_id,
+            ));
+        }
+
+        let forwarding_info = last_hop
+            .counterparty
+            .forwarding_info
+            .clone()
+            .ok_or_else(|| {
+                log_error!(
+                    self.logger,
+                    "no forwarding info from peer of channel {}",
+                    last_hop.channel_id.to_hex()
+                );
+                MutinyError::RoutingFailed
+            })?;
+        let short_channel_id = last_hop
+            .get_inbound_payment_scid()
+            .ok_or(MutinyError::RoutingFailed)?;
+        let hop_fee_msat = forwarding_info.fee_base_msat as u64
+            + amount_msat * forwarding_info.fee_proportional_millionths as u64 / 1_000_000;
+        let hop_cltv_expiry_delta = forwarding_info.cltv_expiry_delta as u32;
+
+        let payment_params = PaymentParameters::from_node_id(
+            last_hop.counterparty.node_id,
+            hop_cltv_expiry_delta + min_final_cltv_expiry_delta,
+        )
+        .with_max_path_count(1);
+        let route_params = RouteParameters {
+            payment_params,
+            final_value_msat: amount_msat + hop_fee_msat,
+        };
+
+        let usable_channels = match first_hop {
+            Some(channel) => vec![channel.clone()],
+            None => self.channel_manager.list_usable_channels(),
+        };
+        let first_hops: Vec<&ChannelDetails> = usable_channels.iter().collect();
+        let mut route = self
+            .router
+            .find_route(
+                &self.pubkey,
+                &route_params,
+                Some(&first_hops),
+                self.channel_manager.compute_inflight_htlcs(),
+            )
+            .map_err(|e| {
+                log_error!(self.logger, "could not find rebalance route: {}", e.err);
+                MutinyError::RoutingFailed
+            })?;
+
+        extend_route(
+            &mut route,
+            hop_fee_msat,
+            hop_cltv_expiry_delta,
+            RouteHop {
+                pubkey: receiver,
+                node_features: receiver_features,
+                short_channel_id,
+                channel_features: ChannelFeatures::empty(),
+                fee_msat: amount_msat,
+                cltv_expiry_delta: min_final_cltv_expiry_delta,
+            },
+        )?;
+
+        Ok(route)
+    }
+
     /// init_invoice_payment sends off the payment but does not wait for results
     /// use pay_invoice_with_timeout to wait for results
     pub async fn init_invoice_payment(
@@ -988,6 +2129,8 @@ impl<S: MutinyStorage> Node<S> {
             fee_paid_msat: None,
             bolt11: Some(invoice.clone()),
             payee_pubkey: None,
+            lsp_pubkey: None,
+            claim_deadline: None,
             last_update,
         };
 
@@ -1134,6 +2277,8 @@ impl<S: MutinyStorage> Node<S> {
             fee_paid_msat: None,
             bolt11: None,
             payee_pubkey: Some(to_node),
+            lsp_pubkey: None,
+            claim_deadline: None,
             last_update,
         };
 
@@ -1232,50 +2377,41 @@ impl<S: MutinyStorage> Node<S> {
         }
     }
 
+    /// Opens a channel to the given peer, pushing `push_msat` to them on open.
+    ///
+    /// The label and note are kept with the channel and its closure.
+    #[allow(clippy::too_many_arguments)]
     pub async fn init_open_channel(
         &self,
         pubkey: PublicKey,
         amount_sat: u64,
+        push_msat: u64,
         fee_rate: Option<f32>,
         user_channel_id: Option<u128>,
+        label: Option<String>,
+        note: Option<String>,
     ) -> Result<u128, MutinyError> {
-        let mut config = default_user_config();
-
-        // if we are opening channel to LSP, turn off SCID alias until CLN is updated
-        // LSP protects all invoice information anyways, so no UTXO leakage
-        if let Some(lsp) = self.lsp_client.clone() {
-            if pubkey == lsp.pubkey {
-                config.channel_handshake_config.negotiate_scid_privacy = false;
-            }
+        if push_msat > amount_sat * 1_000 {
+            return Err(MutinyError::InvalidArgumentsError);
         }
 
-        let user_channel_id = user_channel_id.unwrap_or_else(|| {
-            // generate random user channel id
-            let mut user_channel_id_bytes = [0u8; 16];
-            getrandom::getrandom(&mut user_channel_id_bytes).unwrap();
-            u128::from_be_bytes(user_channel_id_bytes)
-        });
+        let config = self.open_channel_config(pubkey, amount_sat)?;
 
-        let sats_per_vbyte = if let Some(sats_vbyte) = fee_rate {
-            sats_vbyte
-        } else {
-            let sats_per_kw = self
-                .wallet
-                .fees
-                .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+        let user_channel_id = user_channel_id.unwrap_or_else(random_user_channel_id);
 
-            FeeRate::from_sat_per_kwu(sats_per_kw as f32).as_sat_per_vb()
-        };
+        let sats_per_vbyte = self.open_channel_fee_rate(fee_rate);
 
         // save params to db
-        let params = ChannelOpenParams::new(sats_per_vbyte);
+        let mut params = ChannelOpenParams::new(sats_per_vbyte);
+        params.label = label;
+        params.note = note;
         self.persister
             .persist_channel_open_params(user_channel_id, params)?;
 
         match self.channel_manager.create_channel(
             pubkey,
             amount_sat,
-            0,
+            push_msat,
             user_channel_id,
             Some(config),
         ) {
@@ -1296,16 +2432,233 @@ impl<S: MutinyStorage> Node<S> {
         }
     }
 
+    /// Opens channels to all the given peers, funded by a single transaction.
+    ///
+    /// If any of the channels fails before it is funded, all of them are closed
+    /// without broadcasting anything.
+    pub async fn init_open_channels(
+        &self,
+        channels: &[(PublicKey, u64)],
+        fee_rate: Option<f32>,
+    ) -> Result<Vec<u128>, MutinyError> {
+        if channels.is_empty() {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+
+        let total_sats: u64 = channels.iter().map(|(_, amount)| amount).sum();
+        let configs = channels
+            .iter()
+            .map(|(pubkey, _)| self.open_channel_config(*pubkey, total_sats))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sats_per_vbyte = self.open_channel_fee_rate(fee_rate);
+        let batch: Vec<u128> = channels.iter().map(|_| random_user_channel_id()).collect();
+
+        // save all the params before creating any channel so none of them is funded on its own
+        self.persister
+            .persist_channel_batch(&ChannelBatch::new(batch.clone()))?;
+        for user_channel_id in batch.iter() {
+            let params = ChannelOpenParams::new_batch(sats_per_vbyte, batch.clone());
+            self.persister
+                .persist_channel_open_params(*user_channel_id, params)?;
+        }
+
+        for (i, ((pubkey, amount_sat), config)) in channels.iter().zip(configs).enumerate() {
+            if let Err(e) =
+                self.channel_manager
+                    .create_channel(*pubkey, *amount_sat, 0, batch[i], Some(config))
+            {
+                log_error!(
+                    self.logger,
+                    "ERROR: failed to open batched channel to pubkey {pubkey:?}: {e:?}"
+                );
+
+                // the rest of the batch was never created, so no event will clean up after them
+                for user_channel_id in &batch[i..] {
+                    let _ = self.persister.delete_channel_open_params(*user_channel_id);
+                }
+                abort_channel_batch(
+                    &self.channel_manager,
+                    &self.persister,
+                    &self.wallet,
+                    &batch,
+                    &self.logger,
+                );
+                return Err(MutinyError::ChannelCreationFailed);
+            }
+        }
+
+        log_info!(
+            self.logger,
+            "SUCCESS: batch of {} channels initiated",
+            batch.len()
+        );
+        Ok(batch)
+    }
+
+    async fn await_chan_batch_funding_tx(
+        &self,
+        batch: &[u128],
+        timeout: u64,
+    ) -> Result<Vec<OutPoint>, MutinyError> {
+        let start = utils::now().as_secs();
+        loop {
+            if self.stop.load(Ordering::Relaxed) {
+                return Err(MutinyError::NotRunning);
+            }
+
+            if channel_batch_state(&self.persister, batch).is_aborted() {
+                return Err(MutinyError::ChannelCreationFailed);
+            }
+
+            let channels = self.channel_manager.list_channels();
+            let mut outpoints = Vec::with_capacity(batch.len());
+            for user_channel_id in batch {
+                // we delete the channel open params on channel pending event,
+                // or when the channel failed to open
+                let pending = self
+                    .persister
+                    .get_channel_open_params(*user_channel_id)?
+                    .is_none();
+                match channels
+                    .iter()
+                    .find(|c| c.user_channel_id == *user_channel_id)
+                {
+                    Some(c) if pending => {
+                        if let Some(outpoint) = c.funding_txo {
+                            outpoints.push(outpoint.into_bitcoin_outpoint());
+                        }
+                    }
+                    None if pending => return Err(MutinyError::ChannelCreationFailed),
+                    _ => {}
+                }
+            }
+
+            if outpoints.len() == batch.len() {
+                log_info!(self.logger, "Channel batch funding tx found");
+                return Ok(outpoints);
+            }
+
+            let now = utils::now().as_secs();
+            if now - start > timeout {
+                return Err(MutinyError::ChannelCreationFailed);
+            }
+
+            sleep(250).await;
+        }
+    }
+
+    pub async fn open_channels_with_timeout(
+        &self,
+        channels: &[(PublicKey, u64)],
+        fee_rate: Option<f32>,
+        timeout: u64,
+    ) -> Result<Vec<OutPoint>, MutinyError> {
+        let batch = self.init_open_channels(channels, fee_rate).await?;
+
+        match self.await_chan_batch_funding_tx(&batch, timeout).await {
+            Ok(outpoints) => Ok(outpoints),
+            Err(e) => {
+                abort_channel_batch(
+                    &self.channel_manager,
+                    &self.persister,
+                    &self.wallet,
+                    &batch,
+                    &self.logger,
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// Our view of the channel, with the label and note it was opened with
+    pub(crate) fn mutiny_channel(&self, channel: &ChannelDetails) -> MutinyChannel {
+        let mut mutiny_channel = MutinyChannel::from(channel);
+        match self.persister.get_channel_metadata(channel.user_channel_id) {
+            Ok(Some(metadata)) => {
+                mutiny_channel.label = metadata.label;
+                mutiny_channel.note = metadata.note;
+            }
+            Ok(None) => {}
+            Err(e) => log_warn!(self.logger, "could not get channel metadata: {e}"),
+        }
+        mutiny_channel
+    }
+
+    /// The config for a new outbound channel to the given peer
+    fn open_channel_config(
+        &self,
+        pubkey: PublicKey,
+        amount_sat: u64,
+    ) -> Result<UserConfig, MutinyError> {
+        let mut config = self.channel_policy.user_config();
+
+        // if we are opening channel to LSP, turn off SCID alias until CLN is updated
+        // LSP protects all invoice information anyways, so no UTXO leakage
+        if self.lsp_clients.iter().any(|lsp| lsp.pubkey == pubkey) {
+            config.channel_handshake_config.negotiate_scid_privacy = false;
+        }
+
+        if config
+            .channel_handshake_config
+            .negotiate_anchors_zero_fee_htlc_tx
+            && !self.can_reserve_anchor_channel(amount_sat)?
+        {
+            log_info!(
+                self.logger,
+                "Not enough on-chain funds left to bump an anchor channel, opening without anchors"
+            );
+            config
+                .channel_handshake_config
+                .negotiate_anchors_zero_fee_htlc_tx = false;
+        }
+
+        Ok(config)
+    }
+
+    /// The given fee rate, or our normal fee estimate, in sats per vbyte
+    fn open_channel_fee_rate(&self, fee_rate: Option<f32>) -> f32 {
+        if let Some(sats_vbyte) = fee_rate {
+            sats_vbyte
+        } else {
+            let sats_per_kw = self
+                .wallet
+                .fees
+                .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+
+            FeeRate::from_sat_per_kwu(sats_per_kw as f32).as_sat_per_vb()
+        }
+    }
+
+    /// If we would still have the on-chain funds to fee bump the anchor
+    /// channels of all of our nodes and a new one after spending `spend_sats`
+    fn can_reserve_anchor_channel(&self, spend_sats: u64) -> Result<bool, MutinyError> {
+        self.wallet
+            .can_reserve_anchors(spend_sats, self.channel_policy.anchor_reserve_sats)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn open_channel_with_timeout(
         &self,
         pubkey: PublicKey,
         amount_sat: u64,
+        push_msat: u64,
         fee_rate: Option<f32>,
         user_channel_id: Option<u128>,
+        label: Option<String>,
+        note: Option<String>,
         timeout: u64,
     ) -> Result<OutPoint, MutinyError> {
         let init = self
-            .init_open_channel(pubkey, amount_sat, fee_rate, user_channel_id)
+            .init_open_channel(
+                pubkey,
+                amount_sat,
+                push_msat,
+                fee_rate,
+                user_channel_id,
+                label,
+                note,
+            )
             .await?;
 
         self.await_chan_funding_tx(init, &pubkey, timeout).await
@@ -1348,21 +2701,28 @@ impl<S: MutinyStorage> Node<S> {
         // channel size is the total value of the utxos minus the fee
         let channel_value_satoshis = utxo_value - expected_fee;
 
-        let mut config = default_user_config();
+        let mut config = self.channel_policy.user_config();
         // if we are opening channel to LSP, turn off SCID alias until CLN is updated
         // LSP protects all invoice information anyways, so no UTXO leakage
-        if let Some(lsp) = self.lsp_client.clone() {
-            if pubkey == lsp.pubkey {
-                config.channel_handshake_config.negotiate_scid_privacy = false;
-            }
+        if self.lsp_clients.iter().any(|lsp| lsp.pubkey == pubkey) {
+            config.channel_handshake_config.negotiate_scid_privacy = false;
         }
 
-        let user_channel_id = user_chan_id.unwrap_or_else(|| {
-            // generate random user channel id
-            let mut user_channel_id_bytes = [0u8; 16];
-            getrandom::getrandom(&mut user_channel_id_bytes).unwrap();
-            u128::from_be_bytes(user_channel_id_bytes)
-        });
+        if config
+            .channel_handshake_config
+            .negotiate_anchors_zero_fee_htlc_tx
+            && !self.can_reserve_anchor_channel(utxo_value)?
+        {
+            log_info!(
+                self.logger,
+                "Not enough on-chain funds left to bump an anchor channel, opening without anchors"
+            );
+            config
+                .channel_handshake_config
+                .negotiate_anchors_zero_fee_htlc_tx = false;
+        }
+
+        let user_channel_id = user_chan_id.unwrap_or_else(random_user_channel_id);
 
         let sats_per_vbyte = FeeRate::from_sat_per_kwu(sats_per_kw as f32).as_sat_per_vb();
         // save params to db
@@ -1469,6 +2829,171 @@ impl<S: MutinyStorage> Node<S> {
 
         Ok(())
     }
+
+    /// Has our connected channel peers store an encrypted copy of this node's
+    /// channel backup, so we can get it back from them if we lose it.
+    pub(crate) fn update_peer_storage(
+        &self,
+        encryption_key: &SecretKey,
+    ) -> Result<(), MutinyError> {
+        let backup = self.create_static_channel_backup()?;
+        let backup_hash = Sha256::hash(&backup.encode());
+
+        if !self.scb_message_handler.has_peer_storage(&backup_hash) {
+            let counterparties: Vec<PublicKey> = self
+                .channel_manager
+                .list_channels()
+                .iter()
+                .map(|c| c.counterparty.node_id)
+                .collect();
+            let peer_connections = get_all_peers(&self.persister.storage)?
+                .into_iter()
+                .filter_map(|(n, p)| Some((n.as_pubkey().ok()?, p.connection_string?)))
+                .filter(|(pk, _)| counterparties.contains(pk))
+                .collect();
+
+            let scb = StaticChannelBackupStorage {
+                backups: HashMap::from([(self.pubkey, (self.node_index(), backup))]),
+                peer_connections,
+            };
+            let data = scb.encrypt(encryption_key).encode();
+            if data.len() > MAX_PEER_STORAGE_SIZE {
+                log_warn!(
+                    self.logger,
+                    "Channel backup of {} bytes is too large for peer storage",
+                    data.len()
+                );
+                return Err(MutinyError::PeerStorageTooLarge);
+            }
+            self.scb_message_handler.set_peer_storage(backup_hash, data);
+        }
+
+        let connected = self.peer_manager.get_peer_node_ids();
+        let peers: Vec<PublicKey> = self
+            .channel_manager
+            .list_channels()
+            .iter()
+            .map(|c| c.counterparty.node_id)
+            .filter(|pk| connected.contains(pk))
+            .collect();
+        if self.scb_message_handler.send_peer_storage(&peers) {
+            log_debug!(self.logger, "Sending channel backup to peer storage");
+            self.peer_manager.process_events();
+        }
+
+        Ok(())
+    }
+
+    /// Asks our LSP and the peers we know about for the backups they stored for us.
+    /// Peers give them back when we connect, so we connect to the ones we aren't
+    /// connected to yet. This is only done once, and only if this node has no
+    /// channels, like after being recovered from its seed.
+    pub(crate) async fn request_peer_storage(&self) -> Result<(), MutinyError> {
+        if !self.chain_monitor.list_monitors().is_empty()
+            || self.peer_storage_requested.swap(true, Ordering::Relaxed)
+        {
+            return Ok(());
+        }
+
+        let our_node_id = self.pubkey.to_hex();
+        let mut connection_strings: Vec<String> = get_all_peers(&self.persister.storage)?
+            .into_values()
+            .filter(|p| p.nodes.contains(&our_node_id))
+            .filter_map(|p| p.connection_string)
+            .collect();
+        connection_strings.extend(
+            self.lsp_client
+                .iter()
+                .map(|lsp| lsp.connection_string.clone()),
+        );
+
+        let connected = self.peer_manager.get_peer_node_ids();
+        for connection_string in connection_strings {
+            let peer_connection_info = match PubkeyConnectionInfo::new(&connection_string) {
+                Ok(info) => info,
+                Err(e) => {
+                    log_warn!(self.logger, "Invalid peer connection string: {e}");
+                    continue;
+                }
+            };
+            if connected.contains(&peer_connection_info.pubkey) {
+                continue;
+            }
+
+            log_debug!(
+                self.logger,
+                "Connecting to {} for our peer storage",
+                peer_connection_info.pubkey
+            );
+            // keep going so one unreachable peer doesn't stop us asking the others
+            if let Err(e) = self.connect_peer(peer_connection_info, None).await {
+                log_warn!(
+                    self.logger,
+                    "Could not connect to peer for peer storage: {e}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the backups our peers gave back to us and recovers
+    /// any channels in them that this node does not know about.
+    pub(crate) async fn restore_from_peer_storage(
+        &self,
+        encryption_key: &SecretKey,
+    ) -> Result<(), MutinyError> {
+        for (peer, data) in self.scb_message_handler.take_your_peer_storage() {
+            let scb = match EncryptedSCB::read(&mut lightning::io::Cursor::new(data))
+                .map_err(MutinyError::from)
+                .and_then(|scb| scb.decrypt(encryption_key))
+            {
+                Ok(scb) => scb,
+                Err(e) => {
+                    log_warn!(self.logger, "Could not read peer storage from {peer}: {e}");
+                    continue;
+                }
+            };
+
+            let Some((_, backup)) = scb.backups.get(&self.pubkey) else {
+                continue;
+            };
+
+            let known = self.chain_monitor.list_monitors();
+            let monitors: HashMap<OutPoint, Vec<u8>> = backup
+                .monitors
+                .iter()
+                .filter(|(outpoint, _)| {
+                    !known
+                        .iter()
+                        .any(|k| k.into_bitcoin_outpoint() == **outpoint)
+                })
+                .map(|(outpoint, monitor)| (*outpoint, monitor.clone()))
+                .collect();
+            if monitors.is_empty() {
+                continue;
+            }
+
+            log_info!(
+                self.logger,
+                "Recovering {} channels from peer storage from {peer}",
+                monitors.len()
+            );
+            self.recover_from_static_channel_backup(
+                StaticChannelBackup { monitors },
+                &scb.peer_connections,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn random_user_channel_id() -> u128 {
+    let mut user_channel_id_bytes = [0u8; 16];
+    getrandom::getrandom(&mut user_channel_id_bytes).unwrap();
+    u128::from_be_bytes(user_channel_id_bytes)
 }
 
 pub(crate) fn scoring_params() -> ProbabilisticScoringFeeParameters {
@@ -1487,11 +3012,61 @@ pub(crate) fn scoring_params() -> ProbabilisticScoringFeeParameters {
     }
 }
 
+/// Starts accepting peer connections and announcing our addresses, if we have any
+#[cfg(not(target_arch = "wasm32"))]
+async fn start_peer_listener(
+    config: &PeerListenerConfig,
+    child_index: u32,
+    peer_man: Arc<dyn PeerManager>,
+    logger: Arc<MutinyLogger>,
+    stop: Arc<AtomicBool>,
+    stopped_components: Arc<RwLock<Vec<bool>>>,
+) -> Result<(), MutinyError> {
+    // don't fail starting the node if we can't listen
+    let listener = match bind_peer_listener(config, child_index).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log_error!(logger, "could not listen for peer connections: {e}");
+            return Ok(());
+        }
+    };
+    let announced_addrs = config.announced_addrs_for(child_index)?;
+
+    let listener_peer_man = peer_man.clone();
+    let listener_stop = stop.clone();
+    let listener_stopped_components = stopped_components.clone();
+    let max_connections_per_ip = config.max_connections_per_ip;
+    stopped_components.try_write()?.push(false);
+    utils::spawn(async move {
+        accept_peer_connections(
+            listener,
+            max_connections_per_ip,
+            listener_peer_man,
+            logger,
+            listener_stop,
+        )
+        .await;
+        stop_component(&listener_stopped_components);
+    });
+
+    if !announced_addrs.is_empty() {
+        let alias = config.alias_bytes();
+        stopped_components.try_write()?.push(false);
+        utils::spawn(async move {
+            announce_node(alias, announced_addrs, peer_man, stop).await;
+            stop_component(&stopped_components);
+        });
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn start_reconnection_handling<S: MutinyStorage>(
     storage: &impl MutinyStorage,
     node_pubkey: PublicKey,
     #[cfg(target_arch = "wasm32")] websocket_proxy_addr: String,
+    #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<Socks5Proxy>,
     peer_man: Arc<dyn PeerManager>,
     fee_estimator: Arc<MutinyFeeEstimator<S>>,
     logger: &Arc<MutinyLogger>,
@@ -1523,6 +3098,8 @@ async fn start_reconnection_handling<S: MutinyStorage>(
     // Attempt initial connections first in the background
     #[cfg(target_arch = "wasm32")]
     let websocket_proxy_addr_copy_proxy = websocket_proxy_addr.clone();
+    #[cfg(not(target_arch = "wasm32"))]
+    let socks5_proxy_copy = socks5_proxy.clone();
 
     let proxy_logger = logger.clone();
     let peer_man_proxy = peer_man.clone();
@@ -1536,9 +3113,12 @@ async fn start_reconnection_handling<S: MutinyStorage>(
         if let Some(lsp) = lsp_client_copy.clone() {
             let node_id = NodeId::from_pubkey(&lsp.pubkey);
 
+            let start = utils::now();
             let connect_res = connect_peer_if_necessary(
                 #[cfg(target_arch = "wasm32")]
                 &websocket_proxy_addr_copy_proxy,
+                #[cfg(not(target_arch = "wasm32"))]
+                socks5_proxy_copy.as_ref(),
                 &PubkeyConnectionInfo::new(lsp.connection_string.as_str()).unwrap(),
                 proxy_logger.clone(),
                 peer_man_proxy.clone(),
@@ -1546,11 +3126,12 @@ async fn start_reconnection_handling<S: MutinyStorage>(
                 stop_copy.clone(),
             )
             .await;
+            let latency = utils::now().saturating_sub(start);
             match connect_res {
                 Ok(_) => {
                     log_trace!(proxy_logger, "auto connected lsp: {node_id}");
                 }
-                Err(e) => {
+                Err(ref e) => {
                     log_trace!(proxy_logger, "could not connect to lsp {node_id}: {e}");
                 }
             }
@@ -1564,18 +3145,23 @@ async fn start_reconnection_handling<S: MutinyStorage>(
             ) {
                 log_error!(proxy_logger, "could not save connection to lsp: {e}");
             }
+
+            let record_res = match connect_res {
+                Ok(_) => record_peer_connected(&storage_copy, &uuid_copy, &node_id, latency),
+                Err(_) => record_peer_connection_failed(&storage_copy, &uuid_copy, &node_id),
+            };
+            if let Err(e) = record_res {
+                log_warn!(proxy_logger, "could not save lsp connection history: {e}");
+            }
         };
     });
 
-    // keep trying to connect each lightning peer if they get disconnected
+    // keep our channel peers connected, backing off from the ones we can't reach
     let connect_peer_man = peer_man.clone();
     let connect_fee_estimator = fee_estimator.clone();
     let connect_logger = logger.clone();
     let connect_storage = storage.clone();
     utils::spawn(async move {
-        // hashMap to store backoff times for each pubkey
-        let mut backoff_times = HashMap::new();
-
         loop {
             for _ in 0..5 {
                 if stop.load(Ordering::Relaxed) {
@@ -1599,13 +3185,14 @@ async fn start_reconnection_handling<S: MutinyStorage>(
             let peer_connections = get_all_peers(&connect_storage).unwrap_or_default();
             let current_connections = connect_peer_man.get_peer_node_ids();
 
-            let not_connected: Vec<(NodeId, String)> = peer_connections
+            let not_connected: Vec<(NodeId, LnPeerMetadata)> = peer_connections
                 .into_iter()
                 .filter(|(_, d)| {
-                    d.connection_string.is_some()
+                    // peers without a saved connection string can still be
+                    // reached through the addresses they announced
+                    (d.connection_string.is_some() || !d.addresses.is_empty())
                         && d.nodes.binary_search(&uuid.to_string()).is_ok()
                 })
-                .map(|(n, d)| (n, d.connection_string.unwrap()))
                 .filter(|(n, _)| {
                     !current_connections
                         .iter()
@@ -1613,58 +3200,165 @@ async fn start_reconnection_handling<S: MutinyStorage>(
                 })
                 .collect();
 
-            for (pubkey, conn_str) in not_connected.into_iter() {
-                let now = crate::utils::now();
-
-                // initialize backoff time and last attempt time if they do not exist
-                let backoff_entry = backoff_times
-                    .entry(pubkey)
-                    .or_insert((INITIAL_RECONNECTION_DELAY, now));
-
-                // skip this pubkey if not enough time has passed since the last attempt
-                if now - backoff_entry.1 < Duration::from_secs(backoff_entry.0) {
+            let now = utils::now().as_secs();
+            for (node_id, metadata) in not_connected.into_iter() {
+                // skip this peer if not enough time has passed since we last failed
+                if !should_reconnect(&metadata.connection_history(&uuid), now) {
                     continue;
                 }
 
-                // Update the last attempt time
-                backoff_entry.1 = now;
+                log_trace!(connect_logger, "going to auto connect to peer: {node_id}");
 
-                log_trace!(connect_logger, "going to auto connect to peer: {pubkey}");
-                let peer_connection_info = match PubkeyConnectionInfo::new(&conn_str) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        log_error!(connect_logger, "could not parse connection info: {e}");
-                        continue;
+                // try the saved connection string first, then the announced addresses
+                let mut connected = None;
+                for conn_str in reconnection_candidates(&node_id, &metadata) {
+                    let peer_connection_info = match PubkeyConnectionInfo::new(&conn_str) {
+                        Ok(p) => p,
+                        Err(e) => {
+                            log_error!(connect_logger, "could not parse connection info: {e}");
+                            continue;
+                        }
+                    };
+
+                    let start = utils::now();
+                    let connect_res = connect_peer_if_necessary(
+                        #[cfg(target_arch = "wasm32")]
+                        &websocket_proxy_addr,
+                        #[cfg(not(target_arch = "wasm32"))]
+                        socks5_proxy.as_ref(),
+                        &peer_connection_info,
+                        connect_logger.clone(),
+                        connect_peer_man.clone(),
+                        connect_fee_estimator.clone(),
+                        stop.clone(),
+                    )
+                    .await;
+                    match connect_res {
+                        Ok(_) => {
+                            connected = Some((conn_str, utils::now().saturating_sub(start)));
+                            break;
+                        }
+                        Err(e) => {
+                            log_warn!(
+                                connect_logger,
+                                "could not auto connect peer {node_id} at {conn_str}: {e}"
+                            );
+                        }
                     }
-                };
+                }
 
-                let connect_res = connect_peer_if_necessary(
-                    #[cfg(target_arch = "wasm32")]
-                    &websocket_proxy_addr,
-                    &peer_connection_info,
-                    connect_logger.clone(),
-                    connect_peer_man.clone(),
-                    connect_fee_estimator.clone(),
-                    stop.clone(),
-                )
-                .await;
-                match connect_res {
-                    Ok(_) => {
-                        log_trace!(connect_logger, "auto connected peer: {pubkey}");
-                        // reset backoff time to initial value if connection is successful
-                        backoff_entry.0 = INITIAL_RECONNECTION_DELAY;
-                    }
-                    Err(e) => {
-                        log_warn!(connect_logger, "could not auto connect peer: {e}");
-                        // double the backoff time if connection fails, but do not exceed max
-                        backoff_entry.0 = (backoff_entry.0 * 2).min(MAX_RECONNECTION_DELAY);
+                let record_res = match connected {
+                    Some((conn_str, latency)) => {
+                        log_trace!(connect_logger, "auto connected peer: {node_id}");
+                        // remember the address that worked for next time
+                        if metadata.connection_string.as_ref() != Some(&conn_str) {
+                            if let Err(e) = save_peer_connection_info(
+                                &connect_storage,
+                                &uuid,
+                                &node_id,
+                                &conn_str,
+                                None,
+                            ) {
+                                log_warn!(connect_logger, "could not save peer connection: {e}");
+                            }
+                        }
+                        record_peer_connected(&connect_storage, &uuid, &node_id, latency)
                     }
+                    None => record_peer_connection_failed(&connect_storage, &uuid, &node_id),
+                };
+                if let Err(e) = record_res {
+                    log_warn!(
+                        connect_logger,
+                        "could not save peer connection history: {e}"
+                    );
                 }
             }
         }
     });
 }
 
+/// The channel policy for a node, its override or else the default.
+/// An invalid override is logged and ignored so it can't stop the node from starting.
+fn node_channel_policy(
+    channel_policy_override: Option<&ChannelPolicy>,
+    default_channel_policy: &ChannelPolicy,
+    logger: &MutinyLogger,
+) -> ChannelPolicy {
+    match channel_policy_override {
+        Some(policy) => match policy.validate() {
+            Ok(()) => policy.clone(),
+            Err(e) => {
+                log_error!(
+                    logger,
+                    "Ignoring the node's channel policy, using the default: {e}"
+                );
+                default_channel_policy.clone()
+            }
+        },
+        None => default_channel_policy.clone(),
+    }
+}
+
+/// Checks the destination and fee rate of a cooperative close, returning the
+/// shutdown script to pay to and the target fee rate in sats per 1000 weight.
+fn cooperative_close_args(
+    destination: Option<&Address>,
+    fee_rate: Option<f32>,
+    network: Network,
+    logger: &MutinyLogger,
+) -> Result<(Option<ShutdownScript>, Option<u32>), MutinyError> {
+    if let Some(address) = destination {
+        if !address.is_valid_for_network(network) {
+            return Err(MutinyError::IncorrectNetwork(address.network));
+        }
+    }
+    let shutdown_script = destination
+        .map(|a| ShutdownScript::try_from(a.script_pubkey()))
+        .transpose()
+        .map_err(|_| {
+            log_error!(logger, "close destination must be a segwit address");
+            MutinyError::InvalidArgumentsError
+        })?;
+
+    let target_feerate_sats_per_1000_weight = match fee_rate {
+        Some(rate) if !rate.is_finite() || rate < 1.0 => {
+            return Err(MutinyError::InvalidArgumentsError)
+        }
+        Some(rate) => Some((rate * 250.0) as u32),
+        None => None,
+    };
+
+    Ok((shutdown_script, target_feerate_sats_per_1000_weight))
+}
+
+/// If enough time has passed since we last failed to connect to the peer
+fn should_reconnect(history: &PeerConnectionHistory, now: u64) -> bool {
+    match history.last_failure {
+        Some(last_failure) if history.consecutive_failures > 0 => {
+            now.saturating_sub(last_failure) >= reconnection_delay(history.consecutive_failures)
+        }
+        _ => true,
+    }
+}
+
+/// How long to wait before reconnecting, doubling with each failure in a row
+fn reconnection_delay(consecutive_failures: u32) -> u64 {
+    let doublings = consecutive_failures.saturating_sub(1).min(16);
+    (INITIAL_RECONNECTION_DELAY << doublings).min(MAX_RECONNECTION_DELAY)
+}
+
+/// The connection strings to try for a peer, the saved one first
+fn reconnection_candidates(node_id: &NodeId, metadata: &LnPeerMetadata) -> Vec<String> {
+    let mut candidates: Vec<String> = metadata.connection_string.iter().cloned().collect();
+    for address in metadata.addresses.iter() {
+        let conn_str = format!("{node_id}@{address}");
+        if !candidates.contains(&conn_str) {
+            candidates.push(conn_str);
+        }
+    }
+    candidates
+}
+
 fn stop_component(stopped_components: &Arc<RwLock<Vec<bool>>>) {
     let mut stopped = stopped_components
         .try_write()
@@ -1706,6 +3400,12 @@ pub(crate) fn parse_peer_info(
     Ok((pubkey, peer_addr_str_with_port))
 }
 
+/// If a peer address is a websocket url rather than a `host:port`
+pub(crate) fn is_websocket_url(peer_addr: &str) -> bool {
+    let peer_addr = peer_addr.to_lowercase();
+    peer_addr.starts_with("ws://") || peer_addr.starts_with("wss://")
+}
+
 pub(crate) fn split_peer_connection_string(
     peer_pubkey_and_ip_addr: &str,
 ) -> Result<(PublicKey, String), MutinyError> {
@@ -1720,40 +3420,75 @@ pub(crate) fn split_peer_connection_string(
     Ok((pubkey, peer_addr_str.to_string()))
 }
 
-pub(crate) fn default_user_config() -> UserConfig {
-    UserConfig {
-        channel_handshake_limits: ChannelHandshakeLimits {
-            // lnd's max to_self_delay is 2016, so we want to be compatible.
-            their_to_self_delay: 2016,
-            ..Default::default()
-        },
-        channel_handshake_config: ChannelHandshakeConfig {
-            minimum_depth: 1,
-            announced_channel: false,
-            negotiate_scid_privacy: true,
-            commit_upfront_shutdown_pubkey: false,
-            max_inbound_htlc_value_in_flight_percent_of_channel: 100,
-            ..Default::default()
-        },
-        manually_accept_inbound_channels: true,
-        channel_config: ChannelConfig {
-            // 20k sats, 4x more than normal due to high fee rates
-            // Any lightning payment above this, but below current
-            // HTLC fees will have issues paying until anchor outputs
-            max_dust_htlc_exposure_msat: 20_000_000,
-            ..Default::default()
-        },
-        ..Default::default()
+/// If a new fee from an LSP is close enough to the fee we quoted the user.
+/// The fee can go up by [`INVOICE_QUOTE_FEE_TOLERANCE_PPM`], or at least
+/// [`INVOICE_QUOTE_MIN_FEE_TOLERANCE_MSAT`].
+fn quoted_fee_still_valid(quoted_fee_msat: u64, new_fee_msat: u64) -> bool {
+    let tolerance_msat = (quoted_fee_msat * INVOICE_QUOTE_FEE_TOLERANCE_PPM / 1_000_000)
+        .max(INVOICE_QUOTE_MIN_FEE_TOLERANCE_MSAT);
+    new_fee_msat <= quoted_fee_msat + tolerance_msat
+}
+
+/// A route paying the receiver over a channel we have with it, without any fees
+pub(crate) fn direct_route(
+    amount_msat: u64,
+    min_final_cltv_expiry_delta: u32,
+    receiver: PublicKey,
+    receiver_features: NodeFeatures,
+    short_channel_id: u64,
+) -> Route {
+    let hop = RouteHop {
+        pubkey: receiver,
+        node_features: receiver_features,
+        short_channel_id,
+        channel_features: ChannelFeatures::empty(),
+        fee_msat: amount_msat,
+        cltv_expiry_delta: min_final_cltv_expiry_delta,
+    };
+    Route {
+        paths: vec![Path {
+            hops: vec![hop],
+            blinded_tail: None,
+        }],
+        payment_params: None,
     }
 }
 
+/// Continues a route with one more hop. The last hop of the route now forwards to it,
+/// charging `hop_fee_msat` and with `hop_cltv_expiry_delta`.
+pub(crate) fn extend_route(
+    route: &mut Route,
+    hop_fee_msat: u64,
+    hop_cltv_expiry_delta: u32,
+    hop: RouteHop,
+) -> Result<(), MutinyError> {
+    let path = route.paths.first_mut().ok_or(MutinyError::RoutingFailed)?;
+    let peer_hop = path.hops.last_mut().ok_or(MutinyError::RoutingFailed)?;
+    peer_hop.fee_msat = hop_fee_msat;
+    peer_hop.cltv_expiry_delta = hop_cltv_expiry_delta;
+    path.hops.push(hop);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test_utils::*;
     use bitcoin::secp256k1::PublicKey;
+    use bitcoin::{Address, Network, Script};
     use std::str::FromStr;
 
-    use crate::node::parse_peer_info;
+    use crate::channelpolicy::ChannelPolicy;
+    use crate::error::MutinyError;
+    use crate::gossip::{LnPeerMetadata, PeerConnectionHistory};
+    use crate::logging::MutinyLogger;
+    use crate::node::{
+        cooperative_close_args, direct_route, extend_route, node_channel_policy, parse_peer_info,
+        quoted_fee_still_valid, reconnection_candidates, reconnection_delay, should_reconnect,
+        ConnectionType, PubkeyConnectionInfo, MAX_RECONNECTION_DELAY,
+    };
+    use lightning::ln::features::{ChannelFeatures, NodeFeatures};
+    use lightning::routing::gossip::NodeId;
+    use lightning::routing::router::RouteHop;
 
     use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
 
@@ -1791,4 +3526,216 @@ mod tests {
         assert_eq!(pub_key, peer_pubkey);
         assert_eq!(format!("{addr}:{port}"), peer_addr);
     }
+
+    #[test]
+    async fn test_pubkey_connection_info_websocket() {
+        log!("test pubkey connection info with a websocket");
+
+        let pub_key = PublicKey::from_str(
+            "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+        )
+        .unwrap();
+
+        let info = PubkeyConnectionInfo::new(&format!("{pub_key}@wss://lsp.com/LN")).unwrap();
+        assert_eq!(info.pubkey, pub_key);
+        assert_eq!(
+            info.connection_type,
+            ConnectionType::Websocket("wss://lsp.com/LN".to_string())
+        );
+        assert_eq!(
+            info.original_connection_string,
+            format!("{pub_key}@wss://lsp.com/LN")
+        );
+
+        let info = PubkeyConnectionInfo::new(&format!("{pub_key}@127.0.0.1")).unwrap();
+        assert_eq!(
+            info.connection_type,
+            ConnectionType::Tcp("127.0.0.1:9735".to_string())
+        );
+    }
+
+    #[test]
+    async fn test_quoted_fee_still_valid() {
+        log!("test quoted fee still valid");
+
+        // small fees can go up by a sat
+        assert!(quoted_fee_still_valid(0, 1_000));
+        assert!(!quoted_fee_still_valid(0, 1_001));
+
+        // larger fees can go up by 1%
+        assert!(quoted_fee_still_valid(1_000_000, 1_010_000));
+        assert!(!quoted_fee_still_valid(1_000_000, 1_010_001));
+
+        // the fee going down is always fine
+        assert!(quoted_fee_still_valid(1_000_000, 500_000));
+    }
+
+    #[test]
+    async fn test_reconnection_backoff() {
+        log!("test reconnection backoff");
+
+        assert_eq!(reconnection_delay(1), 5);
+        assert_eq!(reconnection_delay(2), 10);
+        assert_eq!(reconnection_delay(3), 20);
+        assert_eq!(reconnection_delay(100), MAX_RECONNECTION_DELAY);
+
+        // never failed, or connected since
+        assert!(should_reconnect(&PeerConnectionHistory::default(), 0));
+
+        let history = PeerConnectionHistory {
+            last_failure: Some(1_000),
+            consecutive_failures: 2,
+            ..Default::default()
+        };
+        assert!(!should_reconnect(&history, 1_009));
+        assert!(should_reconnect(&history, 1_010));
+    }
+
+    #[test]
+    async fn test_node_channel_policy() {
+        log!("test node channel policy");
+
+        let logger = MutinyLogger::default();
+        let default = ChannelPolicy::default();
+        let valid = ChannelPolicy {
+            minimum_depth: 6,
+            ..Default::default()
+        };
+        let invalid = ChannelPolicy {
+            cltv_expiry_delta: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(node_channel_policy(None, &default, &logger), default);
+        assert_eq!(node_channel_policy(Some(&valid), &default, &logger), valid);
+        // an invalid override falls back to the default instead of failing
+        assert_eq!(
+            node_channel_policy(Some(&invalid), &default, &logger),
+            default
+        );
+    }
+
+    #[test]
+    async fn test_cooperative_close_args() {
+        log!("test cooperative close args");
+
+        let logger = MutinyLogger::default();
+        let segwit = Address::p2wsh(&Script::new(), Network::Regtest);
+
+        let (script, feerate) =
+            cooperative_close_args(Some(&segwit), Some(2.0), Network::Regtest, &logger).unwrap();
+        assert_eq!(script.map(|s| s.into_inner()), Some(segwit.script_pubkey()));
+        assert_eq!(feerate, Some(500));
+
+        let (script, feerate) =
+            cooperative_close_args(None, None, Network::Regtest, &logger).unwrap();
+        assert!(script.is_none());
+        assert!(feerate.is_none());
+
+        // the destination must be for our network
+        let mainnet = Address::p2wsh(&Script::new(), Network::Bitcoin);
+        assert!(matches!(
+            cooperative_close_args(Some(&mainnet), None, Network::Regtest, &logger),
+            Err(MutinyError::IncorrectNetwork(Network::Bitcoin))
+        ));
+
+        // and a segwit address
+        let legacy = Address::p2sh(&Script::new(), Network::Regtest).unwrap();
+        assert!(matches!(
+            cooperative_close_args(Some(&legacy), None, Network::Regtest, &logger),
+            Err(MutinyError::InvalidArgumentsError)
+        ));
+
+        // the fee rate must be a number of at least 1 sat/vbyte
+        for rate in [0.5, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                cooperative_close_args(None, Some(rate), Network::Regtest, &logger),
+                Err(MutinyError::InvalidArgumentsError)
+            ));
+        }
+    }
+
+    #[test]
+    async fn test_reconnection_candidates() {
+        log!("test reconnection candidates");
+
+        let node_id =
+            NodeId::from_str("0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166")
+                .unwrap();
+        let metadata = LnPeerMetadata {
+            connection_string: Some(format!("{node_id}@127.0.0.1:9735")),
+            addresses: vec!["127.0.0.1:9735".to_string(), "10.0.0.1:9735".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            reconnection_candidates(&node_id, &metadata),
+            vec![
+                format!("{node_id}@127.0.0.1:9735"),
+                format!("{node_id}@10.0.0.1:9735"),
+            ]
+        );
+
+        // without a saved connection string only the announced addresses are tried
+        let metadata = LnPeerMetadata {
+            addresses: vec!["10.0.0.1:9735".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            reconnection_candidates(&node_id, &metadata),
+            vec![format!("{node_id}@10.0.0.1:9735")]
+        );
+    }
+
+    #[test]
+    async fn test_rebalance_route() {
+        log!("test rebalance route");
+
+        let receiver = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let peer = PublicKey::from_str(
+            "0218845781f631c48f1c9709e23092067d06837f30aa0cd0544ac887fe91ddd166",
+        )
+        .unwrap();
+        let amount_msat = 10_000_000;
+
+        // over a channel between our nodes there is nothing to pay
+        let route = direct_route(amount_msat, 40, receiver, NodeFeatures::empty(), 42);
+        assert_eq!(route.paths.len(), 1);
+        let hops = &route.paths[0].hops;
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].pubkey, receiver);
+        assert_eq!(hops[0].short_channel_id, 42);
+        assert_eq!(hops[0].cltv_expiry_delta, 40);
+        assert_eq!(route.get_total_fees(), 0);
+        assert_eq!(route.get_total_amount(), amount_msat);
+
+        // otherwise the peer of the receiving channel forwards to the receiver for a fee,
+        // what LDK found to the peer pays it the amount and its fee
+        let mut route = direct_route(amount_msat + 1_000, 184, peer, NodeFeatures::empty(), 1);
+        let receiver_hop = RouteHop {
+            pubkey: receiver,
+            node_features: NodeFeatures::empty(),
+            short_channel_id: 42,
+            channel_features: ChannelFeatures::empty(),
+            fee_msat: amount_msat,
+            cltv_expiry_delta: 40,
+        };
+        extend_route(&mut route, 1_000, 144, receiver_hop.clone()).unwrap();
+        let hops = &route.paths[0].hops;
+        assert_eq!(hops.len(), 2);
+        assert_eq!(hops[0].pubkey, peer);
+        assert_eq!(hops[0].fee_msat, 1_000);
+        assert_eq!(hops[0].cltv_expiry_delta, 144);
+        assert_eq!(hops[1], receiver_hop);
+        assert_eq!(route.get_total_fees(), 1_000);
+        assert_eq!(route.get_total_amount(), amount_msat);
+
+        // nothing to extend
+        let mut route = direct_route(amount_msat, 40, receiver, NodeFeatures::empty(), 42);
+        route.paths.clear();
+        assert!(extend_route(&mut route, 1_000, 144, receiver_hop).is_err());
+    }
 }
@@ -1,17 +1,41 @@
 use anyhow::anyhow;
 use lightning::sign::{NodeSigner, Recipient};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::{collections::HashMap, ops::Deref, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    sync::Arc,
+};
 
+use crate::activity::{
+    ActivityFilter, ActivityIndexItem, ActivityPage, ActivityStorage, ACTIVITY_INDEX_BUILT_KEY,
+};
+use crate::channelpolicy::InboundChannelApprover;
+use crate::diagnostics::{
+    redact_invoices, storage_key_counts, DiagnosticsBundle, FeeEstimates, NodeDiagnostics,
+    RECENT_LOGS_LIMIT,
+};
+use crate::export::{activity_csv_row, Bip329Label, Bip329Target, Bip329Type, ACTIVITY_CSV_HEADER};
 use crate::gossip::*;
+use crate::keymanager::{create_keys_manager, pubkey_from_keys_manager, ChannelValueReader};
 use crate::lnurlauth::AuthManager;
-use crate::logging::LOGGING_KEY;
+use crate::logging::{LogQuery, LogRecord, LOGGING_KEY, ROTATED_LOGGING_KEY};
+use crate::pay_onchain::{PayOnchainManager, PayOnchainStorage};
+use crate::rebalance::{Rebalance, RebalanceStatus, RebalanceStorage, REBALANCE_LABEL};
 use crate::redshift::{RedshiftManager, RedshiftStatus, RedshiftStorage};
+use crate::scb::backup::{
+    get_scb_backup_history, get_scb_backup_status, is_scb_backup_needed, request_scb_backup,
+    save_scb_backup, set_scb_backup_succeeded, ScbBackupDestination, ScbBackupRecord,
+    ScbBackupStatus,
+};
 use crate::scb::{
-    EncryptedSCB, StaticChannelBackup, StaticChannelBackupStorage,
-    SCB_ENCRYPTION_KEY_DERIVATION_PATH,
+    EncryptedSCB, ScbChannelDetails, ScbInspection, ScbNodeDetails, StaticChannelBackup,
+    StaticChannelBackupStorage, SCB_ENCRYPTION_KEY_DERIVATION_PATH,
 };
 use crate::storage::{MutinyStorage, KEYCHAIN_STORE_KEY};
+use crate::swaps::{
+    swap_activity_ids, HttpSwapProvider, Swap, SwapManager, SwapProvider, SwapStorage, SWAP_LABEL,
+};
 use crate::utils::sleep;
 use crate::MutinyWalletConfig;
 use crate::{
@@ -21,7 +45,8 @@ use crate::{
     fees::MutinyFeeEstimator,
     gossip,
     logging::MutinyLogger,
-    lspclient::LspClient,
+    lspclient::{LspClient, LspSelectionPolicy},
+    lsps::lsps1::{LiquidityOptions, LiquidityOrder},
     node::{Node, ProbScorer, PubkeyConnectionInfo, RapidGossipSync},
     onchain::get_esplora_url,
     onchain::OnChainWallet,
@@ -32,37 +57,46 @@ use crate::{
     lnurlauth::make_lnurl_auth_connection,
 };
 use crate::{labels::LabelStorage, subscription::MutinySubscriptionClient};
+use crate::{ChannelPolicy, InboundChannelPolicy};
 use bdk::chain::{BlockId, ConfirmationTime};
 use bdk::{wallet::AddressIndex, LocalUtxo};
 use bdk_esplora::esplora_client::AsyncClient;
 use bitcoin::blockdata::script;
 use bitcoin::hashes::hex::ToHex;
 use bitcoin::hashes::{sha256, Hash};
-use bitcoin::secp256k1::{rand, PublicKey, Secp256k1, SecretKey};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
-use bitcoin::{Address, Network, OutPoint, Transaction, Txid};
+use bitcoin::{Address, BlockHash, Network, OutPoint, Transaction, Txid};
 use core::time::Duration;
 use futures::{future::join_all, lock::Mutex};
 use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::chain::channelmonitor::ChannelMonitor;
 use lightning::chain::Confirm;
 use lightning::events::ClosureReason;
-use lightning::io::Read;
+use lightning::io::{Cursor, Read};
 use lightning::ln::channelmanager::{ChannelDetails, PhantomRouteHints};
 use lightning::ln::msgs::DecodeError;
 use lightning::ln::PaymentHash;
 use lightning::routing::gossip::NodeId;
+use lightning::sign::InMemorySigner;
 use lightning::util::logger::*;
-use lightning::util::ser::{Readable, Writeable, Writer};
+use lightning::util::ser::{Readable, ReadableArgs, Writeable, Writer};
 use lightning::{log_debug, log_error, log_info, log_warn};
 use lightning_invoice::{Invoice, InvoiceDescription};
 use lnurl::lnurl::LnUrl;
 use lnurl::{AsyncClient as LnUrlClient, LnUrlResponse, Response};
+#[cfg(target_arch = "wasm32")]
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::str::FromStr;
 use uuid::Uuid;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::socks5::{http_client, Socks5Proxy};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::tcp_listener::PeerListenerConfig;
+
 const BITCOIN_PRICE_CACHE_SEC: u64 = 300;
 
 // This is the NodeStorage object saved to the DB
@@ -79,6 +113,10 @@ pub struct NodeIndex {
     pub child_index: u32,
     pub lsp: Option<String>,
     pub archived: Option<bool>,
+    /// The channel policy for this node, if it overrides the default.
+    /// This is not included in the binary encoding used for channel backups.
+    #[serde(default)]
+    pub channel_policy: Option<ChannelPolicy>,
 }
 
 impl NodeIndex {
@@ -140,6 +178,7 @@ impl Readable for NodeIndex {
             child_index,
             lsp,
             archived: Some(archived),
+            channel_policy: None,
         })
     }
 }
@@ -166,9 +205,13 @@ pub struct MutinyInvoice {
     pub payment_hash: sha256::Hash,
     pub preimage: Option<String>,
     pub payee_pubkey: Option<PublicKey>,
+    /// The LSP used to create the invoice, if any
+    pub lsp_pubkey: Option<PublicKey>,
     pub amount_sats: Option<u64>,
     pub expire: u64,
     pub paid: bool,
+    /// If a payment for this hold invoice is being held, waiting to be settled or cancelled
+    pub held: bool,
     pub fees_paid: Option<u64>,
     pub inbound: bool,
     pub labels: Vec<String>,
@@ -201,9 +244,11 @@ impl From<Invoice> for MutinyInvoice {
             payment_hash,
             preimage: None,
             payee_pubkey,
+            lsp_pubkey: None,
             amount_sats,
             expire: expiry,
             paid: false,
+            held: false,
             fees_paid: None,
             inbound: true,
             labels: vec![],
@@ -235,9 +280,11 @@ impl MutinyInvoice {
                     inbound,
                     last_updated: i.last_update,
                     paid: i.status == HTLCStatus::Succeeded,
+                    held: i.status == HTLCStatus::Held,
                     labels,
                     amount_sats,
                     payee_pubkey: i.payee_pubkey,
+                    lsp_pubkey: i.lsp_pubkey,
                     preimage: i.preimage.map(|p| p.to_hex()),
                     fees_paid: i.fee_paid_msat.map(|f| f / 1_000),
                     ..invoice.into()
@@ -245,6 +292,7 @@ impl MutinyInvoice {
             }
             None => {
                 let paid = i.status == HTLCStatus::Succeeded;
+                let held = i.status == HTLCStatus::Held;
                 let amount_sats: Option<u64> = i.amt_msat.0.map(|s| s / 1_000);
                 let fees_paid = i.fee_paid_msat.map(|f| f / 1_000);
                 let preimage = i.preimage.map(|p| p.to_hex());
@@ -255,9 +303,11 @@ impl MutinyInvoice {
                     payment_hash,
                     preimage,
                     payee_pubkey: i.payee_pubkey,
+                    lsp_pubkey: i.lsp_pubkey,
                     amount_sats,
                     expire: i.last_update,
                     paid,
+                    held,
                     fees_paid,
                     inbound,
                     labels,
@@ -269,6 +319,25 @@ impl MutinyInvoice {
     }
 }
 
+/// A quote for receiving a lightning payment, from [`NodeManager::quote_invoice`].
+/// Any LSP fee is taken out of what the payer pays.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct InvoiceQuote {
+    pub id: String,
+    /// The LSP the invoice will be created with, if any
+    pub lsp_pubkey: Option<PublicKey>,
+    /// The amount the payer will pay, in satoshis
+    pub amount_sats: u64,
+    /// The fee the LSP will take, in satoshis
+    pub fee_sats: u64,
+    /// What we will receive after the LSP's fee, in satoshis
+    pub receivable_sats: u64,
+    /// If the LSP needs to open a new channel for us to receive the payment
+    pub new_channel: bool,
+    /// When the quote expires, in seconds since the epoch
+    pub expires_at: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct MutinyPeer {
     pub pubkey: PublicKey,
@@ -277,6 +346,12 @@ pub struct MutinyPeer {
     pub color: Option<String>,
     pub label: Option<String>,
     pub is_connected: bool,
+    /// The last time we connected to the peer, in seconds since the epoch
+    pub last_connected: Option<u64>,
+    /// How many times in a row we have failed to connect to the peer
+    pub connection_failures: u32,
+    /// How long our last connection took to open, in milliseconds
+    pub latency_ms: Option<u64>,
 }
 
 impl PartialOrd for MutinyPeer {
@@ -305,6 +380,12 @@ pub struct MutinyChannel {
     pub peer: PublicKey,
     pub confirmations_required: Option<u32>,
     pub confirmations: u32,
+    /// The label the channel was opened with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The note the channel was opened with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
 }
 
 impl From<&ChannelDetails> for MutinyChannel {
@@ -318,6 +399,8 @@ impl From<&ChannelDetails> for MutinyChannel {
             peer: c.counterparty.node_id,
             confirmations_required: c.confirmations_required,
             confirmations: c.confirmations.unwrap_or(0),
+            label: None,
+            note: None,
         }
     }
 }
@@ -393,6 +476,15 @@ pub struct ChannelClosure {
     pub node_id: Option<PublicKey>,
     pub reason: String,
     pub timestamp: u64,
+    /// The address we asked to be paid to in a cooperative close, if one was chosen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<Address>,
+    /// The label the channel was opened with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The note the channel was opened with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
 }
 
 impl ChannelClosure {
@@ -408,6 +500,9 @@ impl ChannelClosure {
             node_id,
             reason: reason.to_string(),
             timestamp: utils::now().as_secs(),
+            destination: None,
+            label: None,
+            note: None,
         }
     }
 }
@@ -429,6 +524,8 @@ pub enum ActivityItem {
     OnChain(TransactionDetails),
     Lightning(Box<MutinyInvoice>),
     ChannelClosed(ChannelClosure),
+    Rebalance(Rebalance),
+    Swap(Swap),
 }
 
 impl ActivityItem {
@@ -440,6 +537,8 @@ impl ActivityItem {
             },
             ActivityItem::Lightning(i) => Some(i.last_updated),
             ActivityItem::ChannelClosed(c) => Some(c.timestamp),
+            ActivityItem::Rebalance(r) => Some(r.last_updated),
+            ActivityItem::Swap(s) => Some(s.last_updated),
         }
     }
 
@@ -448,6 +547,8 @@ impl ActivityItem {
             ActivityItem::OnChain(t) => t.labels.clone(),
             ActivityItem::Lightning(i) => i.labels.clone(),
             ActivityItem::ChannelClosed(_) => vec![],
+            ActivityItem::Rebalance(_) => vec![REBALANCE_LABEL.to_string()],
+            ActivityItem::Swap(_) => vec![SWAP_LABEL.to_string()],
         }
     }
 
@@ -458,6 +559,8 @@ impl ActivityItem {
             }
             ActivityItem::Lightning(_) => false,
             ActivityItem::ChannelClosed(_) => false,
+            ActivityItem::Rebalance(_) => false,
+            ActivityItem::Swap(_) => false,
         }
     }
 }
@@ -515,12 +618,18 @@ pub struct Plan {
 pub struct NodeManager<S: MutinyStorage> {
     pub(crate) stop: Arc<AtomicBool>,
     pub(crate) xprivkey: ExtendedPrivKey,
-    network: Network,
+    pub(crate) network: Network,
     #[cfg(target_arch = "wasm32")]
     websocket_proxy_addr: String,
-    esplora: Arc<AsyncClient>,
-    wallet: Arc<OnChainWallet<S>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    socks5_proxy: Option<Socks5Proxy>,
+    #[cfg(not(target_arch = "wasm32"))]
+    peer_listener: Option<PeerListenerConfig>,
+    pub(crate) esplora: Arc<AsyncClient>,
+    pub(crate) wallet: Arc<OnChainWallet<S>>,
     gossip_sync: Arc<RapidGossipSync>,
+    #[cfg(not(target_arch = "wasm32"))]
+    p2p_utxo_lookup: Option<Arc<gossip::EsploraUtxoLookup>>,
     scorer: Arc<utils::Mutex<ProbScorer>>,
     chain: Arc<MutinyChain<S>>,
     fee_estimator: Arc<MutinyFeeEstimator<S>>,
@@ -530,6 +639,12 @@ pub struct NodeManager<S: MutinyStorage> {
     auth: AuthManager,
     lnurl_client: Arc<LnUrlClient>,
     pub(crate) lsp_clients: Vec<LspClient>,
+    lsp_selection_policy: LspSelectionPolicy,
+    channel_policy: ChannelPolicy,
+    inbound_channel_policy: InboundChannelPolicy,
+    inbound_channel_approver: Option<Arc<dyn InboundChannelApprover>>,
+    scb_backup_destinations: Mutex<Vec<Arc<dyn ScbBackupDestination>>>,
+    pub(crate) swap_provider: Option<Arc<dyn SwapProvider>>,
     pub(crate) subscription_client: Option<Arc<MutinySubscriptionClient>>,
     pub(crate) logger: Arc<MutinyLogger>,
     bitcoin_price_cache: Arc<Mutex<Option<(f32, Duration)>>>,
@@ -547,6 +662,9 @@ impl<S: MutinyStorage> NodeManager<S> {
     /// The mnemonic seed is read from storage, unless one is provided.
     /// If no mnemonic is provided, a new one is generated and stored.
     pub async fn new(c: MutinyWalletConfig, storage: S) -> Result<NodeManager<S>, MutinyError> {
+        c.channel_policy.validate()?;
+        c.inbound_channel_policy.validate()?;
+
         let stop = Arc::new(AtomicBool::new(false));
 
         #[cfg(target_arch = "wasm32")]
@@ -554,10 +672,25 @@ impl<S: MutinyStorage> NodeManager<S> {
             .websocket_proxy_addr
             .unwrap_or_else(|| String::from("wss://p.mutinywallet.com"));
 
-        let logger = Arc::new(MutinyLogger::with_writer(stop.clone(), storage.clone()));
+        let logger = Arc::new(
+            MutinyLogger::with_writer(stop.clone(), storage.clone()).with_min_level(c.log_level),
+        );
 
         let esplora_server_url = get_esplora_url(c.network, c.user_esplora_url);
+        #[cfg(target_arch = "wasm32")]
         let tx_sync = Arc::new(EsploraSyncClient::new(esplora_server_url, logger.clone()));
+        #[cfg(not(target_arch = "wasm32"))]
+        let tx_sync = {
+            let mut builder = bdk_esplora::esplora_client::Builder::new(&esplora_server_url);
+            if let Some(proxy) = c.socks5_proxy.as_ref() {
+                proxy.check_url(&esplora_server_url)?;
+                builder = builder.proxy(&proxy.url());
+            }
+            let client = builder
+                .build_async()
+                .map_err(|_| MutinyError::ChainAccessFailed)?;
+            Arc::new(EsploraSyncClient::from_client(client, logger.clone()))
+        };
 
         let esplora = Arc::new(tx_sync.client().clone());
         let fee_estimator = Arc::new(MutinyFeeEstimator::new(
@@ -578,36 +711,63 @@ impl<S: MutinyStorage> NodeManager<S> {
 
         let chain = Arc::new(MutinyChain::new(tx_sync, wallet.clone(), logger.clone()));
 
-        let (gossip_sync, scorer) =
-            get_gossip_sync(&storage, c.user_rgs_url, c.network, logger.clone()).await?;
+        let (gossip_sync, scorer) = get_gossip_sync(
+            &storage,
+            c.user_rgs_url,
+            c.network,
+            #[cfg(not(target_arch = "wasm32"))]
+            c.socks5_proxy.as_ref(),
+            #[cfg(not(target_arch = "wasm32"))]
+            c.p2p_gossip,
+            logger.clone(),
+        )
+        .await?;
 
         let scorer = Arc::new(utils::Mutex::new(scorer));
 
         let gossip_sync = Arc::new(gossip_sync);
 
+        // the P2P gossip of all our nodes is checked against the chain together
+        #[cfg(not(target_arch = "wasm32"))]
+        let p2p_utxo_lookup = c.p2p_gossip.then(|| {
+            Arc::new(gossip::EsploraUtxoLookup::new(
+                esplora.clone(),
+                gossip_sync.network_graph().clone(),
+                logger.clone(),
+            ))
+        });
+
         // load lsp clients, if any
-        let lsp_clients: Vec<LspClient> = match c.lsp_url.clone() {
-            // check if string is some and not an empty string
-            Some(lsp_urls) if !lsp_urls.is_empty() => {
-                let urls: Vec<&str> = lsp_urls.split(',').collect();
-
-                let futs = urls.into_iter().map(|url| LspClient::new(url.trim()));
-
-                let results = futures::future::join_all(futs).await;
-
-                results
-                    .into_iter()
-                    .flat_map(|res| match res {
-                        Ok(client) => Some(client),
-                        Err(e) => {
-                            log_warn!(logger, "Error starting up lsp client: {e}");
-                            None
-                        }
-                    })
-                    .collect()
-            }
-            _ => Vec::new(),
-        };
+        let lsp_configs = c.lsp_configs();
+        let futs = lsp_configs.iter().map(|config| {
+            LspClient::new(
+                config,
+                #[cfg(not(target_arch = "wasm32"))]
+                c.socks5_proxy.as_ref(),
+            )
+        });
+        let lsp_clients: Vec<LspClient> = futures::future::join_all(futs)
+            .await
+            .into_iter()
+            .flat_map(|res| match res {
+                Ok(client) => Some(client),
+                Err(e) => {
+                    log_warn!(logger, "Error starting up lsp client: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        let swap_provider: Option<Arc<dyn SwapProvider>> =
+            match (c.swap_provider.clone(), c.swap_provider_url.as_deref()) {
+                (Some(provider), _) => Some(provider),
+                (None, Some(url)) => Some(Arc::new(HttpSwapProvider::new(
+                    url,
+                    #[cfg(not(target_arch = "wasm32"))]
+                    c.socks5_proxy.as_ref(),
+                )?)),
+                (None, None) => None,
+            };
 
         let node_storage = storage.get_nodes()?;
 
@@ -627,6 +787,8 @@ impl<S: MutinyStorage> NodeManager<S> {
                 c.xprivkey,
                 storage.clone(),
                 gossip_sync.clone(),
+                #[cfg(not(target_arch = "wasm32"))]
+                p2p_utxo_lookup.clone(),
                 scorer.clone(),
                 chain.clone(),
                 fee_estimator.clone(),
@@ -634,11 +796,19 @@ impl<S: MutinyStorage> NodeManager<S> {
                 c.network,
                 esplora.clone(),
                 &lsp_clients,
+                c.lsp_selection_policy,
+                &c.channel_policy,
+                &c.inbound_channel_policy,
+                c.inbound_channel_approver.clone(),
                 logger.clone(),
                 c.do_not_connect_peers,
                 false,
                 #[cfg(target_arch = "wasm32")]
                 websocket_proxy_addr.clone(),
+                #[cfg(not(target_arch = "wasm32"))]
+                c.socks5_proxy.clone(),
+                #[cfg(not(target_arch = "wasm32"))]
+                c.peer_listener.clone(),
             )
             .await?;
 
@@ -667,14 +837,54 @@ impl<S: MutinyStorage> NodeManager<S> {
 
         log_info!(logger, "inserted updated nodes");
 
+        // build the activity index for payments made before it existed
+        if !storage
+            .get_data::<bool>(ACTIVITY_INDEX_BUILT_KEY)?
+            .unwrap_or(false)
+        {
+            for node in nodes_map.values() {
+                for inbound in [true, false] {
+                    let payments = node.persister.list_payment_info(inbound)?;
+                    storage.index_payments(&node._uuid, inbound, payments)?;
+                }
+            }
+            storage.set_data(ACTIVITY_INDEX_BUILT_KEY, true, None)?;
+            log_info!(logger, "built activity index");
+        }
+
+        // make sure we have a channel backup from the start
+        if get_scb_backup_history(&storage)?.is_empty() {
+            request_scb_backup(&storage)?;
+        }
+
         let nodes = Arc::new(Mutex::new(nodes_map));
 
+        #[allow(unused_mut)]
+        let mut lnurl_builder = lnurl::Builder::default();
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(proxy) = c.socks5_proxy.as_ref() {
+            lnurl_builder = lnurl_builder.proxy(&proxy.url());
+        }
         let lnurl_client = Arc::new(
-            lnurl::Builder::default()
+            lnurl_builder
                 .build_async()
                 .expect("failed to make lnurl client"),
         );
 
+        // the auth client is also used by the subscription and VSS clients,
+        // it has to go through our proxy too so none of them leak over clearnet
+        #[cfg(not(target_arch = "wasm32"))]
+        if let (Some(proxy), Some(auth_client)) = (c.socks5_proxy.as_ref(), c.auth_client.as_ref())
+        {
+            if auth_client.socks5_proxy() != Some(proxy) {
+                log_error!(
+                    logger,
+                    "Auth client does not use our proxy, build it with MutinyAuthClient::with_socks5_proxy"
+                );
+                return Err(MutinyError::ClearnetConnectionRefused);
+            }
+        }
+
         let (subscription_client, auth) = if let Some(auth_client) = c.auth_client {
             if let Some(subscription_url) = c.subscription_url {
                 let auth = auth_client.auth.clone();
@@ -698,6 +908,8 @@ impl<S: MutinyStorage> NodeManager<S> {
             network: c.network,
             wallet,
             gossip_sync,
+            #[cfg(not(target_arch = "wasm32"))]
+            p2p_utxo_lookup,
             scorer,
             chain,
             fee_estimator,
@@ -706,10 +918,20 @@ impl<S: MutinyStorage> NodeManager<S> {
             nodes,
             #[cfg(target_arch = "wasm32")]
             websocket_proxy_addr,
+            #[cfg(not(target_arch = "wasm32"))]
+            socks5_proxy: c.socks5_proxy,
+            #[cfg(not(target_arch = "wasm32"))]
+            peer_listener: c.peer_listener,
             esplora,
             auth,
             lnurl_client,
             lsp_clients,
+            lsp_selection_policy: c.lsp_selection_policy,
+            channel_policy: c.channel_policy,
+            inbound_channel_policy: c.inbound_channel_policy,
+            inbound_channel_approver: c.inbound_channel_approver,
+            scb_backup_destinations: Mutex::new(c.scb_backup_destinations),
+            swap_provider,
             subscription_client,
             logger,
             bitcoin_price_cache: Arc::new(Mutex::new(None)),
@@ -829,6 +1051,60 @@ impl<S: MutinyStorage> NodeManager<S> {
         });
     }
 
+    /// Starts a background task that moves our swaps forward until they are done.
+    ///
+    /// This picks up the swaps that were in progress when the node manager was shut down,
+    /// claiming or refunding their HTLCs when needed.
+    pub(crate) fn start_swaps(nm: Arc<NodeManager<S>>) {
+        utils::spawn(async move {
+            loop {
+                if nm.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                // use unwrap_or_default() to handle errors
+                let all = nm.storage.get_swaps().unwrap_or_default();
+                for swap in all {
+                    if swap.status.is_in_progress() {
+                        let id = swap.id.clone();
+                        if let Err(e) = nm.process_swap(swap).await {
+                            log_error!(nm.logger, "Error processing swap {id}: {e}");
+                        }
+                    }
+                }
+
+                // sleep 30 seconds
+                sleep(30_000).await;
+            }
+        });
+    }
+
+    /// Creates a background process that moves our payments to on-chain
+    /// addresses from our lightning balance forward.
+    pub(crate) fn start_pay_onchain_jobs(nm: Arc<NodeManager<S>>) {
+        utils::spawn(async move {
+            loop {
+                if nm.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                // use unwrap_or_default() to handle errors
+                let all = nm.storage.get_pay_onchain_jobs().unwrap_or_default();
+                for job in all {
+                    if job.status.is_in_progress() {
+                        let id = job.id.clone();
+                        if let Err(e) = nm.process_pay_onchain_job(job).await {
+                            log_error!(nm.logger, "Error processing pay on-chain job {id}: {e}");
+                        }
+                    }
+                }
+
+                // sleep 30 seconds
+                sleep(30_000).await;
+            }
+        });
+    }
+
     /// Creates a background process that will sync the wallet with the blockchain.
     /// This will also update the fee estimates every 10 minutes.
     pub fn start_sync(nm: Arc<NodeManager<S>>) {
@@ -861,6 +1137,18 @@ impl<S: MutinyStorage> NodeManager<S> {
                     synced = true;
                 }
 
+                if let Err(e) = nm.cancel_expiring_hold_invoices().await {
+                    log_error!(nm.logger, "Failed to cancel expiring hold invoices: {e}");
+                }
+
+                if let Err(e) = nm.backup_static_channels_if_needed().await {
+                    log_error!(nm.logger, "Failed to back up channels: {e}");
+                }
+
+                if let Err(e) = nm.sync_peer_storage().await {
+                    log_error!(nm.logger, "Failed to sync peer storage: {e}");
+                }
+
                 // sleep for 1 minute, checking graceful shutdown check each 1s.
                 for _ in 0..60 {
                     if nm.stop.load(Ordering::Relaxed) {
@@ -978,7 +1266,8 @@ impl<S: MutinyStorage> NodeManager<S> {
         self.wallet.send(send_to, amount, labels, fee_rate).await
     }
 
-    /// Sweeps all the funds from the wallet to the given address.
+    /// Sweeps all the funds from the wallet to the given address, except for
+    /// the reserve kept to fee bump our anchor channels.
     /// The fee rate is in sat/vbyte.
     ///
     /// If a fee rate is not provided, one will be used from the fee estimator.
@@ -995,6 +1284,14 @@ impl<S: MutinyStorage> NodeManager<S> {
         self.wallet.sweep(send_to, labels, fee_rate).await
     }
 
+    /// The on-chain funds we keep to fee bump the anchor channels of all
+    /// of our nodes when they close, in sats.
+    ///
+    /// Sending on-chain will fail if it would leave less than this.
+    pub async fn anchor_reserve_sats(&self) -> u64 {
+        self.wallet.anchor_reserve_sats()
+    }
+
     /// Estimates the onchain fee for a transaction sending to the given address.
     /// The amount is in satoshis and the fee rate is in sat/vbyte.
     pub fn estimate_tx_fee(
@@ -1142,19 +1439,36 @@ impl<S: MutinyStorage> NodeManager<S> {
             })
             .unwrap_or(vec![]);
 
+        let rebalances = self.get_rebalances().await?;
+        let rebalance_hashes: HashSet<sha256::Hash> =
+            rebalances.iter().map(|r| r.payment_hash).collect();
+        let swaps = self.storage.get_swaps()?;
+        let (swap_hashes, swap_txids) = swap_activity_ids(&swaps);
+
         let mut activity = Vec::with_capacity(lightning.len() + onchain.len());
         for ln in lightning {
-            // Only show paid invoices
-            if ln.paid {
+            // Only show paid invoices, the payments of a rebalance or swap are shown as that
+            if ln.paid
+                && !rebalance_hashes.contains(&ln.payment_hash)
+                && !swap_hashes.contains(&ln.payment_hash)
+            {
                 activity.push(ActivityItem::Lightning(Box::new(ln)));
             }
         }
         for on in onchain {
-            activity.push(ActivityItem::OnChain(on));
+            if !swap_txids.contains(&on.txid) {
+                activity.push(ActivityItem::OnChain(on));
+            }
         }
         for chan in closures {
             activity.push(ActivityItem::ChannelClosed(chan));
         }
+        for rebalance in rebalances {
+            activity.push(ActivityItem::Rebalance(rebalance));
+        }
+        for swap in swaps {
+            activity.push(ActivityItem::Swap(swap));
+        }
 
         // Newest first
         activity.sort_by(|a, b| b.cmp(a));
@@ -1162,6 +1476,114 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(activity)
     }
 
+    /// Returns a page of activity that matches the given filter, newest first.
+    ///
+    /// Lightning payments are filtered using the activity index so that only
+    /// the payments in the requested page need to be loaded.
+    pub async fn query_activity(
+        &self,
+        filter: ActivityFilter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<ActivityPage, MutinyError> {
+        enum Candidate {
+            Indexed(ActivityIndexItem),
+            Loaded(ActivityItem),
+        }
+
+        let node_ids: HashSet<String> = self
+            .nodes
+            .lock()
+            .await
+            .values()
+            .map(|n| n._uuid.clone())
+            .collect();
+        let now = utils::now().as_secs();
+
+        let rebalances = self.get_rebalances().await?;
+        let rebalance_hashes: HashSet<sha256::Hash> =
+            rebalances.iter().map(|r| r.payment_hash).collect();
+        let swaps = self.storage.get_swaps()?;
+        let (swap_hashes, swap_txids) = swap_activity_ids(&swaps);
+
+        let mut candidates: Vec<(Option<u64>, Candidate)> = vec![];
+        for item in self.storage.list_activity_index()? {
+            if node_ids.contains(&item.node_id)
+                && !item.is_expired(now)
+                && !rebalance_hashes.contains(&item.payment_hash)
+                && !swap_hashes.contains(&item.payment_hash)
+                && filter.matches(&(&item).into())
+            {
+                candidates.push((Some(item.last_updated), Candidate::Indexed(item)));
+            }
+        }
+
+        let onchain = self
+            .list_onchain()
+            .map_err(|e| {
+                log_warn!(self.logger, "Failed to get bdk history: {e}");
+                e
+            })
+            .unwrap_or(vec![]);
+        for tx in onchain {
+            if !swap_txids.contains(&tx.txid) && filter.matches(&(&tx).into()) {
+                let item = ActivityItem::OnChain(tx);
+                candidates.push((item.last_updated(), Candidate::Loaded(item)));
+            }
+        }
+
+        for chan in self.list_channel_closures().await? {
+            if filter.matches(&(&chan).into()) {
+                let item = ActivityItem::ChannelClosed(chan);
+                candidates.push((item.last_updated(), Candidate::Loaded(item)));
+            }
+        }
+
+        for rebalance in rebalances {
+            if filter.matches(&(&rebalance).into()) {
+                let item = ActivityItem::Rebalance(rebalance);
+                candidates.push((item.last_updated(), Candidate::Loaded(item)));
+            }
+        }
+
+        for swap in swaps {
+            if filter.matches(&(&swap).into()) {
+                let item = ActivityItem::Swap(swap);
+                candidates.push((item.last_updated(), Candidate::Loaded(item)));
+            }
+        }
+
+        // Newest first, with pending transactions at the top
+        candidates.sort_by_key(|(time, _)| core::cmp::Reverse(time.unwrap_or(u64::MAX)));
+
+        let total = candidates.len();
+        let mut items = Vec::with_capacity(limit.min(total));
+        for (_, candidate) in candidates.into_iter().skip(offset).take(limit) {
+            match candidate {
+                Candidate::Indexed(item) => {
+                    let Some(payment_info) = self.storage.get_indexed_payment_info(&item)? else {
+                        log_warn!(
+                            self.logger,
+                            "Could not find payment for activity index item: {}",
+                            item.payment_hash.to_hex()
+                        );
+                        continue;
+                    };
+                    let invoice = MutinyInvoice::from(
+                        payment_info,
+                        PaymentHash(item.payment_hash.into_inner()),
+                        item.inbound,
+                        item.labels,
+                    )?;
+                    items.push(ActivityItem::Lightning(Box::new(invoice)));
+                }
+                Candidate::Loaded(item) => items.push(item),
+            }
+        }
+
+        Ok(ActivityPage { items, total })
+    }
+
     /// Adds labels to the TransactionDetails based on the address labels.
     /// This will panic if the TransactionDetails does not have a transaction.
     /// Make sure you flag `include_raw` when calling `list_transactions` to
@@ -1378,6 +1800,35 @@ impl<S: MutinyStorage> NodeManager<S> {
         }
     }
 
+    /// Sets the channel policy for a node, overriding the default one from the config.
+    /// Passing `None` removes the override so the node uses the default again.
+    ///
+    /// The policy is saved with the node and takes effect the next time it is started.
+    pub async fn set_node_channel_policy(
+        &self,
+        pubkey: &PublicKey,
+        channel_policy: Option<ChannelPolicy>,
+    ) -> Result<(), MutinyError> {
+        if let Some(policy) = channel_policy.as_ref() {
+            policy.validate()?;
+        }
+
+        let node_uuid = self.get_node(pubkey).await?._uuid.clone();
+
+        let mut node_mutex = self.node_storage.lock().await;
+        let mut existing_nodes = self.storage.get_nodes()?;
+        match existing_nodes.nodes.get_mut(&node_uuid) {
+            None => return Err(MutinyError::NotFound),
+            Some(node_index) => node_index.channel_policy = channel_policy,
+        }
+        existing_nodes.version += 1;
+
+        self.storage.insert_nodes(existing_nodes.clone())?;
+        node_mutex.nodes = existing_nodes.nodes;
+
+        Ok(())
+    }
+
     /// Lists the pubkeys of the lightning node in the manager.
     pub async fn list_nodes(&self) -> Result<Vec<PublicKey>, MutinyError> {
         let nodes = self.nodes.lock().await;
@@ -1474,6 +1925,32 @@ impl<S: MutinyStorage> NodeManager<S> {
         &self,
         amount: Option<u64>,
         labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        self.create_invoice_for_hash(amount, None, labels).await
+    }
+
+    /// Creates a hold invoice for the given payment hash. The amount should be in satoshis.
+    ///
+    /// Payments to a hold invoice are not claimed automatically. Once a payment
+    /// arrives the invoice will be marked as held and the caller must either call
+    /// [`NodeManager::settle_hold_invoice`] with the preimage or [`NodeManager::cancel_hold_invoice`].
+    /// If neither happens before the payment's claim deadline, it will be cancelled automatically.
+    pub async fn create_hold_invoice(
+        &self,
+        payment_hash: sha256::Hash,
+        amount: Option<u64>,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let payment_hash = PaymentHash(payment_hash.into_inner());
+        self.create_invoice_for_hash(amount, Some(payment_hash), labels)
+            .await
+    }
+
+    async fn create_invoice_for_hash(
+        &self,
+        amount: Option<u64>,
+        payment_hash: Option<PaymentHash>,
+        labels: Vec<String>,
     ) -> Result<MutinyInvoice, MutinyError> {
         let nodes = self.nodes.lock().await;
         let use_phantom = nodes.len() > 1 && self.lsp_clients.is_empty();
@@ -1497,11 +1974,158 @@ impl<S: MutinyStorage> NodeManager<S> {
         } else {
             return Err(MutinyError::WalletOperationFailed);
         };
-        let invoice = first_node
-            .create_invoice(amount, labels, route_hints)
-            .await?;
+        let invoice = match payment_hash {
+            Some(payment_hash) => {
+                first_node
+                    .create_hold_invoice(payment_hash, amount, labels, route_hints)
+                    .await?
+            }
+            None => {
+                first_node
+                    .create_invoice(amount, labels, route_hints)
+                    .await?
+            }
+        };
 
-        Ok(invoice.into())
+        Ok(first_node.created_invoice(invoice))
+    }
+
+    /// Gets a quote for receiving the given amount, in satoshis, with the LSP's fee
+    /// and what we will receive after it. Nothing is paid to the LSP until the
+    /// invoice is created with [`NodeManager::create_invoice_from_quote`].
+    pub async fn quote_invoice(&self, amount: u64) -> Result<InvoiceQuote, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let first_node = nodes
+            .values()
+            .next()
+            .ok_or(MutinyError::WalletOperationFailed)?;
+        first_node.quote_invoice(amount).await
+    }
+
+    /// Creates an invoice for a quote from [`NodeManager::quote_invoice`].
+    /// Each quote can only be used once.
+    ///
+    /// Fails if the quote is unknown or expired, or if the LSP's fee
+    /// went up by more than our tolerance since the quote.
+    pub async fn create_invoice_from_quote(
+        &self,
+        quote_id: &str,
+        labels: Vec<String>,
+    ) -> Result<MutinyInvoice, MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let use_phantom = nodes.len() > 1 && self.lsp_clients.is_empty();
+        let route_hints: Option<Vec<PhantomRouteHints>> = if use_phantom {
+            Some(
+                nodes
+                    .iter()
+                    .map(|(_, n)| n.get_phantom_route_hint())
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        for node in nodes.values() {
+            match node
+                .create_invoice_from_quote(quote_id, labels.clone(), route_hints.clone())
+                .await
+            {
+                Ok(invoice) => return Ok(node.created_invoice(invoice)),
+                Err(MutinyError::NotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(MutinyError::NotFound)
+    }
+
+    /// Settles a held payment for a hold invoice with the given preimage.
+    /// Returns an error if no node is currently holding a payment for it.
+    pub async fn settle_hold_invoice(&self, preimage: [u8; 32]) -> Result<(), MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for node in nodes.values() {
+            match node.settle_hold_invoice(preimage) {
+                Ok(()) => return Ok(()),
+                Err(MutinyError::NotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(MutinyError::NotFound)
+    }
+
+    /// Cancels a hold invoice, failing back any held payment for it.
+    pub async fn cancel_hold_invoice(
+        &self,
+        payment_hash: &sha256::Hash,
+    ) -> Result<(), MutinyError> {
+        let nodes = self.nodes.lock().await;
+        let mut found = false;
+        // with phantom invoices the payment could be held by any of our nodes
+        for node in nodes.values() {
+            match node.cancel_hold_invoice(payment_hash) {
+                Ok(()) => found = true,
+                Err(MutinyError::NotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if found {
+            Ok(())
+        } else {
+            Err(MutinyError::NotFound)
+        }
+    }
+
+    /// Cancels any held payments that are close to their claim deadline.
+    async fn cancel_expiring_hold_invoices(&self) -> Result<(), MutinyError> {
+        let nodes = self.nodes.lock().await;
+        for node in nodes.values() {
+            // keep going so one node's error doesn't leave the others' payments held
+            if let Err(e) = node.cancel_expiring_hold_invoices() {
+                log_error!(
+                    self.logger,
+                    "Failed to cancel expiring hold invoices for node {}: {e}",
+                    node.pubkey.to_hex()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the limits of the inbound liquidity the selected node's LSP will sell.
+    /// The LSP must support LSPS1.
+    pub async fn get_liquidity_options(
+        &self,
+        from_node: &PublicKey,
+    ) -> Result<LiquidityOptions, MutinyError> {
+        let node = self.get_node(from_node).await?;
+        node.get_liquidity_options().await
+    }
+
+    /// Orders a channel with the given amount of inbound liquidity, in satoshis,
+    /// from the selected node's LSP. The order's invoice must be paid before
+    /// the LSP opens the channel. The LSP must support LSPS1.
+    pub async fn create_liquidity_order(
+        &self,
+        from_node: &PublicKey,
+        lsp_balance_sat: u64,
+        channel_expiry_blocks: Option<u32>,
+    ) -> Result<LiquidityOrder, MutinyError> {
+        let node = self.get_node(from_node).await?;
+        node.create_liquidity_order(lsp_balance_sat, channel_expiry_blocks)
+            .await
+    }
+
+    /// Gets the current state of a liquidity order from the selected node's LSP.
+    pub async fn get_liquidity_order(
+        &self,
+        from_node: &PublicKey,
+        order_id: String,
+    ) -> Result<LiquidityOrder, MutinyError> {
+        let node = self.get_node(from_node).await?;
+        node.get_liquidity_order(order_id).await
     }
 
     /// Pays a lightning invoice from the selected node.
@@ -1561,6 +2185,7 @@ impl<S: MutinyStorage> NodeManager<S> {
             });
         }
 
+        self.check_lnurl_url(&lnurl.url)?;
         let response = self.lnurl_client.make_request(&lnurl.url).await?;
 
         let params = match response {
@@ -1584,6 +2209,16 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(params)
     }
 
+    /// Errors if our proxy does not allow a request to the LNURL's url,
+    /// the LNURL client goes through the proxy but can't check this itself.
+    fn check_lnurl_url(&self, _url: &str) -> Result<(), MutinyError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(proxy) = self.socks5_proxy.as_ref() {
+            proxy.check_url(_url)?;
+        }
+        Ok(())
+    }
+
     /// Calls upon a LNURL and pays it.
     /// This will fail if the LNURL is not a LNURL pay.
     pub async fn lnurl_pay(
@@ -1593,10 +2228,12 @@ impl<S: MutinyStorage> NodeManager<S> {
         amount_sats: u64,
         labels: Vec<String>,
     ) -> Result<MutinyInvoice, MutinyError> {
+        self.check_lnurl_url(&lnurl.url)?;
         let response = self.lnurl_client.make_request(&lnurl.url).await?;
 
         match response {
             LnUrlResponse::LnUrlPayResponse(pay) => {
+                self.check_lnurl_url(&pay.callback)?;
                 let msats = amount_sats * 1000;
                 let invoice = self.lnurl_client.get_invoice(&pay, msats).await?;
 
@@ -1615,12 +2252,14 @@ impl<S: MutinyStorage> NodeManager<S> {
         lnurl: &LnUrl,
         amount_sats: u64,
     ) -> Result<bool, MutinyError> {
+        self.check_lnurl_url(&lnurl.url)?;
         let response = self.lnurl_client.make_request(&lnurl.url).await?;
 
         match response {
             LnUrlResponse::LnUrlPayResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
             LnUrlResponse::LnUrlChannelResponse(_) => Err(MutinyError::IncorrectLnUrlFunction),
             LnUrlResponse::LnUrlWithdrawResponse(withdraw) => {
+                self.check_lnurl_url(&withdraw.callback)?;
                 // fixme: do we need to use this description?
                 let _description = withdraw.default_description.clone();
                 let mutiny_invoice = self
@@ -1641,6 +2280,7 @@ impl<S: MutinyStorage> NodeManager<S> {
 
     /// Authenticate with a LNURL-auth
     pub async fn lnurl_auth(&self, lnurl: LnUrl) -> Result<(), MutinyError> {
+        self.check_lnurl_url(&lnurl.url)?;
         make_lnurl_auth_connection(
             self.auth.clone(),
             self.lnurl_client.clone(),
@@ -1719,15 +2359,23 @@ impl<S: MutinyStorage> NodeManager<S> {
     /// Opens a channel from our selected node to the given pubkey.
     /// The amount is in satoshis.
     ///
+    /// The push amount, in millisatoshis, is given to the peer when the channel opens,
+    /// for example to pay an LSP for inbound liquidity.
+    /// The label and note are kept with the channel and its eventual closure.
+    ///
     /// The node must be online and have a connection to the peer.
     /// The wallet much have enough funds to open the channel.
+    #[allow(clippy::too_many_arguments)]
     pub async fn open_channel(
         &self,
         from_node: &PublicKey,
         to_pubkey: Option<PublicKey>,
         amount: u64,
+        push_msat: Option<u64>,
         fee_rate: Option<f32>,
         user_channel_id: Option<u128>,
+        label: Option<String>,
+        note: Option<String>,
     ) -> Result<MutinyChannel, MutinyError> {
         let node = self.get_node(from_node).await?;
 
@@ -1742,7 +2390,16 @@ impl<S: MutinyStorage> NodeManager<S> {
         };
 
         let outpoint = node
-            .open_channel_with_timeout(to_pubkey, amount, fee_rate, user_channel_id, 60)
+            .open_channel_with_timeout(
+                to_pubkey,
+                amount,
+                push_msat.unwrap_or(0),
+                fee_rate,
+                user_channel_id,
+                label,
+                note,
+                60,
+            )
             .await?;
 
         let all_channels = node.channel_manager.list_channels();
@@ -1751,22 +2408,54 @@ impl<S: MutinyStorage> NodeManager<S> {
             .find(|chan| chan.funding_txo.map(|a| a.into_bitcoin_outpoint()) == Some(outpoint));
 
         match found_channel {
-            Some(channel) => Ok(channel.into()),
+            Some(channel) => Ok(node.mutiny_channel(channel)),
             None => Err(MutinyError::ChannelCreationFailed), // what should we do here?
         }
     }
 
-    /// Opens a channel from our selected node to the given pubkey.
-    /// It will spend the given utxos in full to fund the channel.
+    /// Opens channels from our selected node to all the given pubkeys, funded by one transaction.
+    /// The amounts are in satoshis.
     ///
-    /// The node must be online and have a connection to the peer.
-    /// The UTXOs must all exist in the wallet.
-    pub async fn sweep_utxos_to_channel(
+    /// The node must be online and have a connection to every peer.
+    /// If any peer rejects its channel, none of the channels are opened.
+    pub async fn open_channels(
         &self,
-        user_chan_id: Option<u128>,
         from_node: &PublicKey,
-        utxos: &[OutPoint],
-        to_pubkey: Option<PublicKey>,
+        channels: Vec<(PublicKey, u64)>,
+        fee_rate: Option<f32>,
+    ) -> Result<Vec<MutinyChannel>, MutinyError> {
+        let node = self.get_node(from_node).await?;
+
+        let outpoints = node
+            .open_channels_with_timeout(&channels, fee_rate, 60)
+            .await?;
+
+        let all_channels = node.channel_manager.list_channels();
+        outpoints
+            .iter()
+            .map(|outpoint| {
+                all_channels
+                    .iter()
+                    .find(|chan| {
+                        chan.funding_txo.map(|a| a.into_bitcoin_outpoint()) == Some(*outpoint)
+                    })
+                    .map(|chan| node.mutiny_channel(chan))
+                    .ok_or(MutinyError::ChannelCreationFailed)
+            })
+            .collect()
+    }
+
+    /// Opens a channel from our selected node to the given pubkey.
+    /// It will spend the given utxos in full to fund the channel.
+    ///
+    /// The node must be online and have a connection to the peer.
+    /// The UTXOs must all exist in the wallet.
+    pub async fn sweep_utxos_to_channel(
+        &self,
+        user_chan_id: Option<u128>,
+        from_node: &PublicKey,
+        utxos: &[OutPoint],
+        to_pubkey: Option<PublicKey>,
     ) -> Result<MutinyChannel, MutinyError> {
         let node = self.get_node(from_node).await?;
 
@@ -1877,17 +2566,7 @@ impl<S: MutinyStorage> NodeManager<S> {
                             MutinyError::ChannelClosingFailed
                         })?;
                 } else {
-                    node.channel_manager
-                        .close_channel(&channel.channel_id, &channel.counterparty.node_id)
-                        .map_err(|e| {
-                            log_error!(
-                                self.logger,
-                                "had an error closing channel {} with node {} : {e:?}",
-                                &channel.channel_id.to_hex(),
-                                &channel.counterparty.node_id.to_hex()
-                            );
-                            MutinyError::ChannelClosingFailed
-                        })?;
+                    node.cooperative_close_channel(&channel, None, None, None)?;
                 }
 
                 Ok(())
@@ -1902,17 +2581,234 @@ impl<S: MutinyStorage> NodeManager<S> {
         }
     }
 
+    /// Cooperatively closes a channel with the given outpoint.
+    ///
+    /// If a destination address is given, our side of the channel will be paid to it
+    /// instead of our on-chain wallet. It must be a segwit address.
+    ///
+    /// The fee rate is in sats per vbyte and is the target for the closing transaction.
+    /// The max fee is how many sats above that target we are willing to pay to close.
+    pub async fn close_channel_to_address(
+        &self,
+        outpoint: &OutPoint,
+        destination: Option<Address>,
+        fee_rate: Option<f32>,
+        max_fee_sats: Option<u64>,
+    ) -> Result<(), MutinyError> {
+        if let Some(address) = destination.as_ref() {
+            if !address.is_valid_for_network(self.network) {
+                return Err(MutinyError::IncorrectNetwork(address.network));
+            }
+        }
+
+        let nodes = self.nodes.lock().await;
+        let (node, channel) = nodes
+            .iter()
+            .find_map(|(_, n)| {
+                n.channel_manager
+                    .list_channels()
+                    .iter()
+                    .find(|c| c.funding_txo.map(|f| f.into_bitcoin_outpoint()) == Some(*outpoint))
+                    .map(|c| (n.clone(), c.clone()))
+            })
+            .ok_or_else(|| {
+                log_error!(
+                    self.logger,
+                    "Channel not found with this transaction: {outpoint}",
+                );
+                MutinyError::NotFound
+            })?;
+
+        node.cooperative_close_channel(&channel, destination, fee_rate, max_fee_sats)
+    }
+
+    /// Moves Lightning liquidity from one of our nodes to another by paying an invoice
+    /// of the receiving node. The amount is in satoshis.
+    ///
+    /// Fails without paying if routing would cost more than `max_fee_sats`.
+    pub async fn rebalance(
+        &self,
+        from_node: &PublicKey,
+        to_node: &PublicKey,
+        amount_sats: u64,
+        max_fee_sats: u64,
+    ) -> Result<Rebalance, MutinyError> {
+        if from_node == to_node {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        let from = self.get_node(from_node).await?;
+        let to = self.get_node(to_node).await?;
+
+        // receive directly from the other node when they share a channel, it is free,
+        // otherwise through the channel with the most room for it
+        let amount_msat = amount_sats * 1_000;
+        let incoming = to
+            .channel_manager
+            .list_usable_channels()
+            .into_iter()
+            .filter(|c| {
+                c.inbound_capacity_msat >= amount_msat
+                    && (c.counterparty.node_id == from.pubkey
+                        || c.counterparty.forwarding_info.is_some())
+            })
+            .max_by_key(|c| {
+                (
+                    c.counterparty.node_id == from.pubkey,
+                    c.inbound_capacity_msat,
+                )
+            })
+            .ok_or(MutinyError::InsufficientBalance)?;
+
+        self.move_liquidity(&from, &to, None, &incoming, amount_sats, max_fee_sats)
+            .await
+    }
+
+    /// Moves Lightning liquidity between two channels of the same node by paying ourselves,
+    /// out through `from_channel` and back in through `to_channel`. The amount is in satoshis.
+    ///
+    /// Fails without paying if routing would cost more than `max_fee_sats`.
+    pub async fn rebalance_channels(
+        &self,
+        node: &PublicKey,
+        from_channel: &OutPoint,
+        to_channel: &OutPoint,
+        amount_sats: u64,
+        max_fee_sats: u64,
+    ) -> Result<Rebalance, MutinyError> {
+        if from_channel == to_channel {
+            return Err(MutinyError::InvalidArgumentsError);
+        }
+        let node = self.get_node(node).await?;
+
+        let channels = node.channel_manager.list_usable_channels();
+        let find_channel = |outpoint: &OutPoint| {
+            channels
+                .iter()
+                .find(|c| c.funding_txo.map(|f| f.into_bitcoin_outpoint()) == Some(*outpoint))
+                .ok_or(MutinyError::NotFound)
+        };
+        let outgoing = find_channel(from_channel)?;
+        let incoming = find_channel(to_channel)?;
+
+        if outgoing.outbound_capacity_msat < amount_sats * 1_000 {
+            return Err(MutinyError::InsufficientBalance);
+        }
+
+        self.move_liquidity(
+            &node,
+            &node,
+            Some(outgoing),
+            incoming,
+            amount_sats,
+            max_fee_sats,
+        )
+        .await
+    }
+
+    async fn move_liquidity(
+        &self,
+        from: &Node<S>,
+        to: &Node<S>,
+        outgoing: Option<&ChannelDetails>,
+        incoming: &ChannelDetails,
+        amount_sats: u64,
+        max_fee_sats: u64,
+    ) -> Result<Rebalance, MutinyError> {
+        let invoice = to.create_rebalance_invoice(amount_sats).await?;
+
+        let mut rebalance = Rebalance {
+            payment_hash: *invoice.payment_hash(),
+            from_node: from.pubkey,
+            to_node: to.pubkey,
+            outgoing_channel: outgoing
+                .and_then(|c| c.funding_txo)
+                .map(|f| f.into_bitcoin_outpoint()),
+            incoming_channel: incoming.funding_txo.map(|f| f.into_bitcoin_outpoint()),
+            amount_sats,
+            fees_paid: None,
+            status: RebalanceStatus::InFlight,
+            last_updated: utils::now().as_secs(),
+        };
+        self.storage.persist_rebalance(rebalance.clone())?;
+
+        let result = from
+            .pay_rebalance_invoice(
+                &invoice,
+                to.pubkey,
+                to.channel_manager.node_features(),
+                outgoing,
+                incoming,
+                max_fee_sats,
+            )
+            .await;
+
+        rebalance.last_updated = utils::now().as_secs();
+        match result {
+            Ok(paid) => {
+                rebalance.fees_paid = paid.fees_paid;
+                rebalance.status = RebalanceStatus::Succeeded;
+                self.storage.persist_rebalance(rebalance.clone())?;
+                Ok(rebalance)
+            }
+            // the payment could still go through, it is updated when we list rebalances
+            Err(MutinyError::PaymentTimeout) => {
+                log_warn!(self.logger, "rebalance still in flight after timeout");
+                self.storage.persist_rebalance(rebalance)?;
+                Err(MutinyError::PaymentTimeout)
+            }
+            Err(e) => {
+                log_error!(self.logger, "rebalance failed: {e}");
+                rebalance.fail(e.to_string());
+                self.storage.persist_rebalance(rebalance)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Lists all the rebalances between our nodes and channels, newest first.
+    pub async fn list_rebalances(&self) -> Result<Vec<Rebalance>, MutinyError> {
+        let mut rebalances = self.get_rebalances().await?;
+        rebalances.sort_by(|a, b| b.cmp(a));
+        Ok(rebalances)
+    }
+
+    /// All of our rebalances, with the ones still in flight updated from their payment
+    async fn get_rebalances(&self) -> Result<Vec<Rebalance>, MutinyError> {
+        let mut rebalances = self.storage.get_rebalances()?;
+        let nodes = self.nodes.lock().await;
+        for rebalance in rebalances
+            .iter_mut()
+            .filter(|r| r.status == RebalanceStatus::InFlight)
+        {
+            let Some(node) = nodes.values().find(|n| n.pubkey == rebalance.from_node) else {
+                continue;
+            };
+            let payment_hash = PaymentHash(rebalance.payment_hash.into_inner());
+            let payment = node
+                .persister
+                .read_payment_info(&payment_hash, false, &self.logger);
+            if payment.is_some_and(|p| rebalance.update_from_payment(&p)) {
+                self.storage.persist_rebalance(rebalance.clone())?;
+            }
+        }
+
+        Ok(rebalances)
+    }
+
     /// Lists all the channels for all the nodes in the node manager.
     pub async fn list_channels(&self) -> Result<Vec<MutinyChannel>, MutinyError> {
         let nodes = self.nodes.lock().await;
-        let channels: Vec<ChannelDetails> = nodes
+        let mutiny_channels: Vec<MutinyChannel> = nodes
             .iter()
-            .flat_map(|(_, n)| n.channel_manager.list_channels())
+            .flat_map(|(_, n)| {
+                n.channel_manager
+                    .list_channels()
+                    .iter()
+                    .map(|c| n.mutiny_channel(c))
+                    .collect::<Vec<_>>()
+            })
             .collect();
 
-        let mutiny_channels: Vec<MutinyChannel> =
-            channels.iter().map(MutinyChannel::from).collect();
-
         Ok(mutiny_channels)
     }
 
@@ -1929,6 +2825,20 @@ impl<S: MutinyStorage> NodeManager<S> {
     /// Creates a static channel backup for all the nodes in the node manager.
     /// The backup is encrypted with the SCB key.
     pub async fn create_static_channel_backup(&self) -> Result<EncryptedSCB, MutinyError> {
+        let scb = self.build_static_channel_backup().await?;
+
+        // encrypt
+        let encryption_key = self.get_scb_key();
+        let scb = scb.encrypt(&encryption_key);
+        log_debug!(
+            self.logger,
+            "Created SCB with a size of {} bytes",
+            scb.encode().len()
+        );
+        Ok(scb)
+    }
+
+    async fn build_static_channel_backup(&self) -> Result<StaticChannelBackupStorage, MutinyError> {
         let nodes = self.nodes.lock().await;
         let mut backups: HashMap<PublicKey, (NodeIndex, StaticChannelBackup)> = HashMap::new();
         for (_, node) in nodes.iter() {
@@ -1943,20 +2853,232 @@ impl<S: MutinyStorage> NodeManager<S> {
             .filter_map(|(n, p)| p.connection_string.map(|str| (n.as_pubkey().unwrap(), str)))
             .collect::<HashMap<_, _>>();
 
-        let scb = StaticChannelBackupStorage {
+        Ok(StaticChannelBackupStorage {
             backups,
             peer_connections,
-        };
+        })
+    }
 
-        // encrypt
-        let encryption_key = self.get_scb_key();
-        let scb = scb.encrypt(&encryption_key);
-        log_debug!(
+    /// Makes a new static channel backup, saves it to storage and pushes it
+    /// to all of our backup destinations.
+    pub async fn backup_static_channels(&self) -> Result<ScbBackupRecord, MutinyError> {
+        let scb = self.build_static_channel_backup().await?;
+        let num_channels = scb.backups.values().map(|(_, b)| b.monitors.len()).sum();
+        let scb = scb.encrypt(&self.get_scb_key());
+
+        let record = save_scb_backup(&self.storage, &scb, num_channels)?;
+        log_info!(
             self.logger,
-            "Created SCB with a size of {} bytes",
-            scb.encode().len()
+            "Backed up {num_channels} channels to a new static channel backup"
         );
-        Ok(scb)
+
+        self.push_scb_backup(&scb, record.created_at).await?;
+        Ok(record)
+    }
+
+    /// Makes a new backup if our channels changed, otherwise retries
+    /// any destinations that missed the latest backup.
+    async fn backup_static_channels_if_needed(&self) -> Result<(), MutinyError> {
+        if is_scb_backup_needed(&self.storage)? {
+            self.backup_static_channels().await?;
+        } else if let Some(latest) = get_scb_backup_history(&self.storage)?.first() {
+            let scb = EncryptedSCB::from_str(&latest.scb)?;
+            self.push_scb_backup(&scb, latest.created_at).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes a backup to each destination that does not have it yet
+    async fn push_scb_backup(
+        &self,
+        scb: &EncryptedSCB,
+        created_at: u64,
+    ) -> Result<(), MutinyError> {
+        let status = get_scb_backup_status(&self.storage)?;
+        let destinations = self.scb_backup_destinations.lock().await.clone();
+        for destination in destinations {
+            let name = destination.name();
+            if status
+                .last_successful_backups
+                .get(&name)
+                .is_some_and(|time| *time >= created_at)
+            {
+                continue;
+            }
+
+            match destination.backup(scb).await {
+                Ok(()) => {
+                    set_scb_backup_succeeded(&self.storage, name, utils::now().as_secs())?;
+                }
+                Err(e) => {
+                    log_warn!(self.logger, "Failed to push channel backup to {name}: {e}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recovers channels from any backups our peers gave back to us, then
+    /// makes sure our peers have the latest backups of our nodes' channels.
+    async fn sync_peer_storage(&self) -> Result<(), MutinyError> {
+        let encryption_key = self.get_scb_key();
+        let nodes: Vec<Arc<Node<S>>> = self.nodes.lock().await.values().cloned().collect();
+        for node in nodes {
+            // keep going so one node's error doesn't stop the others' sync
+            if let Err(e) = node.request_peer_storage().await {
+                log_error!(
+                    self.logger,
+                    "Failed to request peer storage for node {}: {e}",
+                    node.pubkey.to_hex()
+                );
+            }
+
+            // restore first so we never replace a peer's copy with a backup
+            // that is missing channels we just need to recover
+            if let Err(e) = node.restore_from_peer_storage(&encryption_key).await {
+                log_error!(
+                    self.logger,
+                    "Failed to restore from peer storage for node {}: {e}",
+                    node.pubkey.to_hex()
+                );
+                continue;
+            }
+            if let Err(e) = node.update_peer_storage(&encryption_key) {
+                log_error!(
+                    self.logger,
+                    "Failed to update peer storage for node {}: {e}",
+                    node.pubkey.to_hex()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a destination to push our static channel backups to, replacing any
+    /// with the same name. It will get the latest backup on the next sync.
+    pub async fn add_scb_backup_destination(&self, destination: Arc<dyn ScbBackupDestination>) {
+        let mut destinations = self.scb_backup_destinations.lock().await;
+        destinations.retain(|d| d.name() != destination.name());
+        destinations.push(destination);
+    }
+
+    /// When the latest static channel backup was made and when
+    /// each destination last got a backup.
+    pub fn get_scb_backup_status(&self) -> Result<ScbBackupStatus, MutinyError> {
+        get_scb_backup_status(&self.storage)
+    }
+
+    /// The static channel backups we have made, newest first
+    pub fn get_scb_backup_history(&self) -> Result<Vec<ScbBackupRecord>, MutinyError> {
+        get_scb_backup_history(&self.storage)
+    }
+
+    /// Decrypts a static channel backup and lists the nodes and channels in it,
+    /// without recovering anything. Our current channels that are not in the
+    /// backup are returned as missing, so the backup can be checked before it
+    /// is needed. If the backup is encrypted with a different key, it will fail.
+    pub async fn inspect_static_channel_backup(
+        &self,
+        scb: EncryptedSCB,
+    ) -> Result<ScbInspection, MutinyError> {
+        let scb = scb.decrypt(&self.get_scb_key())?;
+
+        let live_channels: HashMap<PublicKey, Vec<ChannelDetails>> = {
+            let nodes = self.nodes.lock().await;
+            nodes
+                .iter()
+                .map(|(pk, n)| (*pk, n.channel_manager.list_channels()))
+                .collect()
+        };
+
+        let mut nodes = Vec::with_capacity(scb.backups.len());
+        for (pubkey, (node_index, backup)) in scb.backups.iter() {
+            let keys_manager = create_keys_manager(
+                self.wallet.clone(),
+                self.xprivkey,
+                node_index.child_index,
+                self.logger.clone(),
+            )?;
+            if pubkey_from_keys_manager(&keys_manager) != *pubkey {
+                log_error!(self.logger, "Backup has the wrong index for node {pubkey}");
+                return Err(MutinyError::InvalidArgumentsError);
+            }
+
+            let live = live_channels.get(pubkey);
+            let mut channels = Vec::with_capacity(backup.monitors.len());
+            for (outpoint, monitor_bytes) in backup.monitors.iter() {
+                // the monitor doesn't have the channel value, so read it from its signer
+                let signer_reader = ChannelValueReader::new(&keys_manager);
+                let reader = &mut Cursor::new(monitor_bytes);
+                let (_, monitor) = <(BlockHash, ChannelMonitor<InMemorySigner>)>::read(
+                    reader,
+                    (&keys_manager, &signer_reader),
+                )?;
+                let capacity_sats = signer_reader
+                    .channel_value_sats()
+                    .ok_or(MutinyError::LnDecodeError)?;
+                // safe for ldk > 0.0.110
+                let counterparty = monitor
+                    .get_counterparty_node_id()
+                    .ok_or(MutinyError::LnDecodeError)?;
+                let balance_sats = monitor
+                    .get_claimable_balances()
+                    .iter()
+                    .map(|b| b.claimable_amount_satoshis())
+                    .sum();
+                let is_live = live.is_some_and(|chans| {
+                    chans.iter().any(|c| {
+                        c.funding_txo.map(|f| f.into_bitcoin_outpoint()) == Some(*outpoint)
+                    })
+                });
+
+                channels.push(ScbChannelDetails {
+                    outpoint: *outpoint,
+                    counterparty,
+                    capacity_sats,
+                    balance_sats,
+                    peer_connection: scb.peer_connections.get(&counterparty).cloned(),
+                    is_live,
+                });
+            }
+            channels.sort_by_key(|c| c.outpoint);
+
+            nodes.push(ScbNodeDetails {
+                pubkey: *pubkey,
+                child_index: node_index.child_index,
+                is_live: live.is_some(),
+                channels,
+            });
+        }
+        nodes.sort_by_key(|n| n.child_index);
+
+        let missing_channels = live_channels
+            .iter()
+            .flat_map(|(pubkey, chans)| {
+                let scb = &scb;
+                chans.iter().filter(move |c| {
+                    c.funding_txo
+                        .is_some_and(|f| !scb.has_channel(pubkey, &f.into_bitcoin_outpoint()))
+                })
+            })
+            .map(MutinyChannel::from)
+            .collect::<Vec<_>>();
+
+        if !missing_channels.is_empty() {
+            log_warn!(
+                self.logger,
+                "{} of our channels are missing from the backup",
+                missing_channels.len()
+            );
+        }
+
+        Ok(ScbInspection {
+            nodes,
+            missing_channels,
+        })
     }
 
     /// Takes an encrypted static channel backup and recovers the channels from it.
@@ -1981,7 +3103,12 @@ impl<S: MutinyStorage> NodeManager<S> {
                 let current = node_mutex
                     .nodes
                     .iter()
-                    .find(|(_, n)| *n == &node_index)
+                    .find(|(_, n)| {
+                        // backups don't have the channel policy, so don't compare it
+                        n.child_index == node_index.child_index
+                            && n.lsp == node_index.lsp
+                            && n.archived == node_index.archived
+                    })
                     .map(|(uuid, _)| uuid.clone());
 
                 match current {
@@ -2008,6 +3135,9 @@ impl<S: MutinyStorage> NodeManager<S> {
                 self.xprivkey,
                 self.storage.clone(),
                 self.gossip_sync.clone(),
+                // only used to recover the channels, it doesn't need to sync gossip
+                #[cfg(not(target_arch = "wasm32"))]
+                None,
                 self.scorer.clone(),
                 self.chain.clone(),
                 self.fee_estimator.clone(),
@@ -2015,11 +3145,20 @@ impl<S: MutinyStorage> NodeManager<S> {
                 self.network,
                 self.esplora.clone(),
                 &self.lsp_clients,
+                self.lsp_selection_policy,
+                &self.channel_policy,
+                &self.inbound_channel_policy,
+                self.inbound_channel_approver.clone(),
                 self.logger.clone(),
                 true,
                 true,
                 #[cfg(target_arch = "wasm32")]
                 self.websocket_proxy_addr.clone(),
+                #[cfg(not(target_arch = "wasm32"))]
+                self.socks5_proxy.clone(),
+                // only used to recover the channels, nobody needs to connect to it
+                #[cfg(not(target_arch = "wasm32"))]
+                None,
             )
             .await;
 
@@ -2052,14 +3191,20 @@ impl<S: MutinyStorage> NodeManager<S> {
         // get peers saved in storage
         let mut storage_peers: Vec<MutinyPeer> = peer_data
             .iter()
-            .map(|(node_id, metadata)| MutinyPeer {
-                // node id should be safe here
-                pubkey: PublicKey::from_slice(node_id.as_slice()).expect("Invalid pubkey"),
-                connection_string: metadata.connection_string.clone(),
-                alias: metadata.alias.clone(),
-                color: metadata.color.clone(),
-                label: metadata.label.clone(),
-                is_connected: false,
+            .map(|(node_id, metadata)| {
+                let history = metadata.latest_connection_history();
+                MutinyPeer {
+                    // node id should be safe here
+                    pubkey: PublicKey::from_slice(node_id.as_slice()).expect("Invalid pubkey"),
+                    connection_string: metadata.connection_string.clone(),
+                    alias: metadata.alias.clone(),
+                    color: metadata.color.clone(),
+                    label: metadata.label.clone(),
+                    is_connected: false,
+                    last_connected: history.last_connected,
+                    connection_failures: history.consecutive_failures,
+                    latency_ms: history.latency_ms,
+                }
             })
             .collect();
 
@@ -2090,6 +3235,9 @@ impl<S: MutinyStorage> NodeManager<S> {
                     color: None,
                     label: None,
                     is_connected: true,
+                    last_connected: None,
+                    connection_failures: 0,
+                    latency_ms: None,
                 };
                 missing.push(new);
             }
@@ -2173,12 +3321,16 @@ impl<S: MutinyStorage> NodeManager<S> {
     async fn fetch_bitcoin_price(&self) -> Result<f32, MutinyError> {
         log_debug!(self.logger, "fetching new bitcoin price");
 
+        let url = "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd";
+        #[cfg(target_arch = "wasm32")]
         let client = Client::builder()
             .build()
             .map_err(|_| MutinyError::BitcoinPriceError)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        let client = http_client(self.socks5_proxy.as_ref(), url)?;
 
         let resp = client
-            .get("https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd")
+            .get(url)
             .send()
             .await
             .map_err(|_| MutinyError::BitcoinPriceError)?;
@@ -2193,6 +3345,47 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(response.bitcoin.usd)
     }
 
+    /// Fetches the bitcoin price in USD between the given unix times, as a list
+    /// of `(unix time, price)` sorted by time.
+    async fn fetch_historical_bitcoin_prices(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<(u64, f32)>, MutinyError> {
+        log_debug!(self.logger, "fetching historical bitcoin prices");
+
+        let url = format!("https://api.coingecko.com/api/v3/coins/bitcoin/market_chart/range?vs_currency=usd&from={from}&to={to}");
+        #[cfg(target_arch = "wasm32")]
+        let client = Client::builder()
+            .build()
+            .map_err(|_| MutinyError::BitcoinPriceError)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        let client = http_client(self.socks5_proxy.as_ref(), &url)?;
+
+        let resp = client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|_| MutinyError::BitcoinPriceError)?;
+
+        let response: CoingeckoMarketChart = resp
+            .error_for_status()
+            .map_err(|_| MutinyError::BitcoinPriceError)?
+            .json()
+            .await
+            .map_err(|_| MutinyError::BitcoinPriceError)?;
+
+        // coingecko gives the times in milliseconds
+        let mut prices = response
+            .prices
+            .into_iter()
+            .map(|(time, price)| (time as u64 / 1_000, price))
+            .collect::<Vec<_>>();
+        prices.sort_by_key(|(time, _)| *time);
+
+        Ok(prices)
+    }
+
     /// Retrieves the logs from storage.
     pub fn get_logs(
         storage: S,
@@ -2201,6 +3394,76 @@ impl<S: MutinyStorage> NodeManager<S> {
         logger.get_logs(&storage)
     }
 
+    /// Retrieves the structured log records from storage that match the query.
+    pub fn query_logs(
+        storage: S,
+        logger: Arc<MutinyLogger>,
+        query: &LogQuery,
+    ) -> Result<Option<Vec<LogRecord>>, MutinyError> {
+        logger.query_logs(&storage, query)
+    }
+
+    /// Creates a bundle of diagnostic information to help debug issues like
+    /// stuck payments. This includes the state of every node and its channels,
+    /// pending and failed payments, sync status, fee estimates and recent logs.
+    ///
+    /// Preimages, payment secrets and invoices are not included, so the bundle
+    /// can be shared for support.
+    pub async fn diagnostics(&self) -> Result<DiagnosticsBundle, MutinyError> {
+        let node_indices = self.node_storage.lock().await.nodes.clone();
+
+        let mut nodes = Vec::with_capacity(node_indices.len());
+        {
+            let running_nodes = self.nodes.lock().await;
+            for (uuid, index) in node_indices.iter() {
+                let mut node_diagnostics = NodeDiagnostics::new(uuid.clone(), index);
+                if let Some(node) = running_nodes.values().find(|n| &n._uuid == uuid) {
+                    node_diagnostics.add_node_state(node)?;
+                }
+                nodes.push(node_diagnostics);
+            }
+        }
+        nodes.sort_by_key(|n| n.child_index);
+
+        let channel_closures = self.list_channel_closures().await?;
+
+        let last_gossip_sync: Option<u32> = self.storage.get_data(GOSSIP_SYNC_TIME_KEY)?;
+
+        let fee_estimates = FeeEstimates {
+            background: self
+                .fee_estimator
+                .get_est_sat_per_1000_weight(ConfirmationTarget::Background),
+            normal: self.estimate_fee_normal(),
+            high_priority: self.estimate_fee_high(),
+        };
+
+        let node_uuids: Vec<String> = node_indices.into_keys().collect();
+        let storage_key_counts = storage_key_counts(&self.storage, &node_uuids)?;
+
+        let log_query = LogQuery {
+            limit: Some(RECENT_LOGS_LIMIT),
+            ..Default::default()
+        };
+        let mut logs = self
+            .logger
+            .query_logs(&self.storage, &log_query)?
+            .unwrap_or_default();
+        for record in logs.iter_mut() {
+            record.message = redact_invoices(&record.message);
+        }
+
+        Ok(DiagnosticsBundle {
+            created_at: utils::now().as_secs(),
+            network: self.network,
+            nodes,
+            channel_closures,
+            last_gossip_sync,
+            fee_estimates,
+            storage_key_counts,
+            logs,
+        })
+    }
+
     /// Resets the scorer and network graph. This can be useful if you get stuck in a bad state.
     pub async fn reset_router(&self) -> Result<(), MutinyError> {
         // if we're not connected to the db, start it up
@@ -2242,6 +3505,194 @@ impl<S: MutinyStorage> NodeManager<S> {
         Ok(())
     }
 
+    /// Exports the wallet's activity as a CSV file for accounting.
+    ///
+    /// Each row contains the date, type, direction, amount, fee, fiat value,
+    /// labels and the txid or payment hash of a transaction or payment.
+    /// The fiat value is calculated using the bitcoin price in USD at the time of
+    /// each item, it is left empty if we could not get the price for then.
+    pub async fn export_activity_csv(&self) -> Result<String, MutinyError> {
+        let activity = self.get_activity().await?;
+        let bitcoin_price = self
+            .get_bitcoin_price()
+            .await
+            .map_err(|e| {
+                log_warn!(self.logger, "Could not get bitcoin price for export: {e}");
+                e
+            })
+            .ok();
+
+        let historical_prices = match activity.iter().filter_map(|a| a.last_updated()).min() {
+            Some(from) => self
+                .fetch_historical_bitcoin_prices(from, utils::now().as_secs())
+                .await
+                .unwrap_or_else(|e| {
+                    log_warn!(
+                        self.logger,
+                        "Could not get historical bitcoin prices for export: {e}"
+                    );
+                    vec![]
+                }),
+            None => vec![],
+        };
+
+        let contact_names: HashMap<String, String> = self
+            .get_contacts()?
+            .into_iter()
+            .map(|(id, contact)| (id, contact.name))
+            .collect();
+
+        let mut csv = String::from(ACTIVITY_CSV_HEADER);
+        for item in activity.iter() {
+            if let Some(row) =
+                activity_csv_row(item, &contact_names, &historical_prices, bitcoin_price)
+            {
+                csv.push('\n');
+                csv.push_str(&row);
+            }
+        }
+
+        Ok(csv)
+    }
+
+    /// Exports all the address, transaction and invoice labels in the
+    /// [BIP-329](https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki) JSONL format.
+    ///
+    /// Invoice labels use the non-standard `invoice` type, other wallets will ignore them.
+    pub fn export_bip329_labels(&self) -> Result<String, MutinyError> {
+        let mut records = vec![];
+
+        for tx in self.list_onchain()? {
+            if !tx.labels.is_empty() {
+                records.push(Bip329Label::new(
+                    Bip329Type::Tx,
+                    tx.txid.to_string(),
+                    &tx.labels,
+                ));
+            }
+        }
+
+        for (address, labels) in self.get_address_labels()? {
+            if !labels.is_empty() {
+                records.push(Bip329Label::new(Bip329Type::Addr, address, &labels));
+            }
+        }
+
+        for (invoice, labels) in self.get_invoice_labels()? {
+            if !labels.is_empty() {
+                records.push(Bip329Label::new(
+                    Bip329Type::Invoice,
+                    invoice.to_string(),
+                    &labels,
+                ));
+            }
+        }
+
+        let lines = records
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Imports labels in the [BIP-329](https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki) JSONL format.
+    ///
+    /// Address and invoice labels replace any existing labels. Transaction labels are
+    /// applied to our own addresses in the transaction's outputs. Records for unsupported
+    /// types, other networks or unknown transactions are skipped.
+    ///
+    /// Returns the number of records that were imported. Nothing is imported if any
+    /// line is not a valid record.
+    pub fn import_bip329_labels(&self, jsonl: &str) -> Result<usize, MutinyError> {
+        // parse every record first so a bad line doesn't leave a partial import
+        let records = jsonl
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str::<Bip329Label>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| MutinyError::InvalidArgumentsError)?;
+
+        let mut imports = Vec::with_capacity(records.len());
+        for record in records {
+            let labels = record.labels();
+            if labels.is_empty() {
+                continue;
+            }
+
+            let target = match record.label_type {
+                Bip329Type::Addr => match Address::from_str(&record.reference) {
+                    Ok(address) if address.is_valid_for_network(self.network) => {
+                        Bip329Target::Addresses(vec![address])
+                    }
+                    _ => {
+                        log_warn!(self.logger, "Skipping invalid address label record");
+                        continue;
+                    }
+                },
+                Bip329Type::Tx => {
+                    let Ok(txid) = Txid::from_str(&record.reference) else {
+                        log_warn!(self.logger, "Skipping invalid tx label record");
+                        continue;
+                    };
+                    let tx = self
+                        .wallet
+                        .get_transaction(txid, true)?
+                        .and_then(|t| t.transaction);
+                    let Some(tx) = tx else {
+                        log_debug!(self.logger, "Skipping label for unknown tx: {txid}");
+                        continue;
+                    };
+                    // only label our own outputs, not the counterparty's
+                    let mut addresses = vec![];
+                    for output in tx.output {
+                        if !self.wallet.is_mine(&output.script_pubkey)? {
+                            continue;
+                        }
+                        if let Ok(address) =
+                            Address::from_script(&output.script_pubkey, self.network)
+                        {
+                            addresses.push(address);
+                        }
+                    }
+                    Bip329Target::Addresses(addresses)
+                }
+                Bip329Type::Invoice => match Invoice::from_str(&record.reference) {
+                    Ok(invoice) if invoice.network() == self.network => {
+                        Bip329Target::Invoice(invoice)
+                    }
+                    _ => {
+                        log_warn!(self.logger, "Skipping invalid invoice label record");
+                        continue;
+                    }
+                },
+                _ => {
+                    log_debug!(
+                        self.logger,
+                        "Skipping unsupported label record type: {:?}",
+                        record.label_type
+                    );
+                    continue;
+                }
+            };
+            imports.push((target, labels));
+        }
+
+        let imported = imports.len();
+        for (target, labels) in imports {
+            match target {
+                Bip329Target::Addresses(addresses) => {
+                    for address in addresses {
+                        self.set_address_labels(address, labels.clone())?;
+                    }
+                }
+                Bip329Target::Invoice(invoice) => self.set_invoice_labels(invoice, labels)?,
+            }
+        }
+
+        Ok(imported)
+    }
+
     /// Exports the current state of the node manager to a json object.
     pub async fn export_json(storage: S) -> Result<Value, MutinyError> {
         let needs_db_connection = !storage.clone().connected().unwrap_or(true);
@@ -2254,7 +3705,10 @@ impl<S: MutinyStorage> NodeManager<S> {
         let serde_map = serde_json::map::Map::from_iter(map.into_iter().filter(|(k, _)| {
             // filter out logs and network graph
             // these are really big and not needed for export
-            !matches!(k.as_str(), LOGGING_KEY | NETWORK_GRAPH_KEY)
+            !matches!(
+                k.as_str(),
+                LOGGING_KEY | ROTATED_LOGGING_KEY | NETWORK_GRAPH_KEY
+            )
         }));
 
         // shut back down after reading if it was already closed
@@ -2276,6 +3730,11 @@ struct CoingeckoPrice {
     pub usd: f32,
 }
 
+#[derive(Deserialize, Clone, Debug)]
+struct CoingeckoMarketChart {
+    pub prices: Vec<(f64, f32)>,
+}
+
 // This will create a new node with a node manager and return the PublicKey of the node created.
 pub(crate) async fn create_new_node_from_node_manager<S: MutinyStorage>(
     node_manager: &NodeManager<S>,
@@ -2302,24 +3761,32 @@ pub(crate) async fn create_new_node_from_node_manager<S: MutinyStorage>(
     // Create and save a new node using the next child index
     let next_node_uuid = Uuid::new_v4().to_string();
 
-    let lsp = if node_manager.lsp_clients.is_empty() {
-        log_info!(
-            node_manager.logger,
-            "no lsp saved and no lsp clients available"
-        );
-        None
-    } else {
-        log_info!(node_manager.logger, "no lsp saved, picking random one");
-        // If we don't have an lsp saved we should pick a random
-        // one from our client list and save it for next time
-        let rand = rand::random::<usize>() % node_manager.lsp_clients.len();
-        Some(node_manager.lsp_clients[rand].url.clone())
+    let lsp = match node_manager
+        .lsp_selection_policy
+        .select_node_lsp(&node_manager.lsp_clients)
+    {
+        None => {
+            log_info!(
+                node_manager.logger,
+                "no lsp saved and no lsp clients available"
+            );
+            None
+        }
+        Some(lsp) => {
+            log_info!(
+                node_manager.logger,
+                "no lsp saved, picking one with {:?}",
+                node_manager.lsp_selection_policy
+            );
+            Some(lsp.url.clone())
+        }
     };
 
     let next_node = NodeIndex {
         child_index: next_node_index,
         lsp,
         archived: Some(false),
+        channel_policy: None,
     };
 
     existing_nodes.version += 1;
@@ -2337,6 +3804,8 @@ pub(crate) async fn create_new_node_from_node_manager<S: MutinyStorage>(
         node_manager.xprivkey,
         node_manager.storage.clone(),
         node_manager.gossip_sync.clone(),
+        #[cfg(not(target_arch = "wasm32"))]
+        node_manager.p2p_utxo_lookup.clone(),
         node_manager.scorer.clone(),
         node_manager.chain.clone(),
         node_manager.fee_estimator.clone(),
@@ -2344,11 +3813,19 @@ pub(crate) async fn create_new_node_from_node_manager<S: MutinyStorage>(
         node_manager.network,
         node_manager.esplora.clone(),
         &node_manager.lsp_clients,
+        node_manager.lsp_selection_policy,
+        &node_manager.channel_policy,
+        &node_manager.inbound_channel_policy,
+        node_manager.inbound_channel_approver.clone(),
         node_manager.logger.clone(),
         node_manager.do_not_connect_peers,
         false,
         #[cfg(target_arch = "wasm32")]
         node_manager.websocket_proxy_addr.clone(),
+        #[cfg(not(target_arch = "wasm32"))]
+        node_manager.socks5_proxy.clone(),
+        #[cfg(not(target_arch = "wasm32"))]
+        node_manager.peer_listener.clone(),
     )
     .await;
 
@@ -2373,6 +3850,7 @@ pub(crate) async fn create_new_node_from_node_manager<S: MutinyStorage>(
 
 #[cfg(test)]
 mod tests {
+    use crate::error::MutinyError;
     use crate::{
         encrypt::encryption_key_from_pass,
         nodemanager::{
@@ -2385,11 +3863,13 @@ mod tests {
     use bitcoin::hashes::{sha256, Hash};
     use bitcoin::secp256k1::PublicKey;
     use bitcoin::util::bip32::ExtendedPrivKey;
-    use bitcoin::{Network, PackedLockTime, Transaction, TxOut, Txid};
-    use lightning::ln::PaymentHash;
+    use bitcoin::{Address, Network, OutPoint, PackedLockTime, Script, Transaction, TxOut, Txid};
+    use lightning::events::{Event, PaymentPurpose};
+    use lightning::ln::{PaymentHash, PaymentSecret};
     use lightning_invoice::Invoice;
     use std::str::FromStr;
 
+    use crate::scb::test::CHAIN_MONITOR_BYTES;
     use crate::test_utils::*;
 
     use crate::event::{HTLCStatus, MillisatAmount, PaymentInfo};
@@ -2479,6 +3959,302 @@ mod tests {
         }
     }
 
+    #[test]
+    async fn inspect_static_channel_backup() {
+        let test_name = "inspect_static_channel_backup";
+        log!("{}", test_name);
+
+        let pass = uuid::Uuid::new_v4().to_string();
+        let cipher = encryption_key_from_pass(&pass).unwrap();
+        let storage = MemoryStorage::new(Some(pass), Some(cipher));
+        let seed = generate_seed(12).expect("Failed to gen seed");
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &seed.to_seed("")).unwrap();
+        let c = MutinyWalletConfig::new(
+            xpriv,
+            #[cfg(target_arch = "wasm32")]
+            None,
+            Network::Regtest,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let nm = NodeManager::new(c, storage)
+            .await
+            .expect("node manager should initialize");
+        let node_identity = nm.new_node().await.expect("should create new node");
+
+        let scb = nm.create_static_channel_backup().await.unwrap();
+        let inspection = nm.inspect_static_channel_backup(scb).await.unwrap();
+
+        assert_eq!(inspection.nodes.len(), 1);
+        let node = &inspection.nodes[0];
+        assert_eq!(node.pubkey, node_identity.pubkey);
+        assert_eq!(node.child_index, 0);
+        assert!(node.is_live);
+        assert!(node.channels.is_empty());
+        assert!(inspection.missing_channels.is_empty());
+
+        // a backup with a channel that isn't one of our live channels
+        let outpoint = OutPoint {
+            txid: Txid::from_hex(
+                "830b1c110ef6c78312a8f4c798da0bfbacdfc9c80c7d458ca614e7b1543f5b03",
+            )
+            .unwrap(),
+            vout: 1,
+        };
+        let counterparty = PublicKey::from_str(
+            "0366abc8eb4da61e31a8d2c4520d31cabdf58cc5250f855657397f3dd62493938a",
+        )
+        .unwrap();
+        let connection = format!("{counterparty}@127.0.0.1:9735");
+        let mut scb = nm.build_static_channel_backup().await.unwrap();
+        scb.backups
+            .get_mut(&node_identity.pubkey)
+            .unwrap()
+            .1
+            .monitors
+            .insert(outpoint, CHAIN_MONITOR_BYTES.to_vec());
+        scb.peer_connections
+            .insert(counterparty, connection.clone());
+        assert!(scb.has_channel(&node_identity.pubkey, &outpoint));
+
+        let inspection = nm
+            .inspect_static_channel_backup(scb.encrypt(&nm.get_scb_key()))
+            .await
+            .unwrap();
+        let channels = &inspection.nodes[0].channels;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].outpoint, outpoint);
+        assert_eq!(channels[0].counterparty, counterparty);
+        // from the channel monitor, without looking up the funding transaction
+        assert_eq!(channels[0].capacity_sats, 197_500);
+        assert_eq!(channels[0].peer_connection, Some(connection));
+        assert!(!channels[0].is_live);
+
+        // a channel missing from the backup
+        let missing = OutPoint {
+            txid: outpoint.txid,
+            vout: 0,
+        };
+        assert!(!scb.has_channel(&node_identity.pubkey, &missing));
+        assert!(!scb.has_channel(&counterparty, &outpoint));
+    }
+
+    #[test]
+    async fn import_bip329_labels() {
+        let test_name = "import_bip329_labels";
+        log!("{}", test_name);
+
+        let pass = uuid::Uuid::new_v4().to_string();
+        let cipher = encryption_key_from_pass(&pass).unwrap();
+        let storage = MemoryStorage::new(Some(pass), Some(cipher));
+        let seed = generate_seed(12).expect("Failed to gen seed");
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &seed.to_seed("")).unwrap();
+        let c = MutinyWalletConfig::new(
+            xpriv,
+            #[cfg(target_arch = "wasm32")]
+            None,
+            Network::Regtest,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let nm = NodeManager::new(c, storage)
+            .await
+            .expect("node manager should initialize");
+
+        let address = nm.get_new_address(vec![]).unwrap();
+        let record = format!(r#"{{"type":"addr","ref":"{address}","label":"rent"}}"#);
+
+        // a bad line fails the whole import, even after a valid record
+        let jsonl = format!("{record}\nnot a label");
+        assert!(nm.import_bip329_labels(&jsonl).is_err());
+        let labels = nm.get_address_labels().unwrap();
+        assert!(labels
+            .get(&address.to_string())
+            .map_or(true, |l| l.is_empty()));
+
+        // records we can't use are skipped
+        let xpub_record = r#"{"type":"xpub","ref":"xpub","label":"x"}"#;
+        let jsonl = format!("{record}\n{xpub_record}");
+        assert_eq!(nm.import_bip329_labels(&jsonl).unwrap(), 1);
+        assert_eq!(
+            nm.get_address_labels().unwrap().get(&address.to_string()),
+            Some(&vec!["rent".to_string()])
+        );
+    }
+
+    #[test]
+    async fn hold_invoices() {
+        let test_name = "hold_invoices";
+        log!("{}", test_name);
+
+        let pass = uuid::Uuid::new_v4().to_string();
+        let cipher = encryption_key_from_pass(&pass).unwrap();
+        let storage = MemoryStorage::new(Some(pass), Some(cipher));
+        let seed = generate_seed(12).expect("Failed to gen seed");
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &seed.to_seed("")).unwrap();
+        let c = MutinyWalletConfig::new(
+            xpriv,
+            #[cfg(target_arch = "wasm32")]
+            None,
+            Network::Regtest,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let nm = NodeManager::new(c, storage)
+            .await
+            .expect("node manager should initialize");
+        let node_identity = nm.new_node().await.expect("should create new node");
+        let node = nm
+            .nodes
+            .lock()
+            .await
+            .get(&node_identity.pubkey)
+            .cloned()
+            .unwrap();
+
+        let payment_secret = PaymentSecret([2; 32]);
+        let claimable = |payment_hash: PaymentHash| Event::PaymentClaimable {
+            receiver_node_id: Some(node.pubkey),
+            payment_hash,
+            onion_fields: None,
+            amount_msat: 1_000_000,
+            counterparty_skimmed_fee_msat: 0,
+            purpose: PaymentPurpose::InvoicePayment {
+                payment_preimage: None,
+                payment_secret,
+            },
+            via_channel_id: None,
+            via_user_channel_id: None,
+            claim_deadline: Some(800_000),
+        };
+        let payment_info = |payment_hash: &PaymentHash| -> PaymentInfo {
+            node.persister
+                .read_payment_info(payment_hash, true, &nm.logger)
+                .unwrap()
+        };
+
+        // settling a held payment keeps its preimage once it is claimed
+        let preimage = [1; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let payment_hash = PaymentHash(hash.into_inner());
+        nm.create_hold_invoice(hash, Some(1_000), vec![])
+            .await
+            .unwrap();
+        assert!(matches!(
+            nm.settle_hold_invoice(preimage).await,
+            Err(MutinyError::NotFound)
+        ));
+
+        node.event_handler
+            .handle_event(claimable(payment_hash))
+            .await;
+        let info = payment_info(&payment_hash);
+        assert_eq!(info.status, HTLCStatus::Held);
+        assert_eq!(info.amt_msat, MillisatAmount(Some(1_000_000)));
+        assert_eq!(info.claim_deadline, Some(800_000));
+        assert_eq!(info.preimage, None);
+
+        nm.settle_hold_invoice(preimage).await.unwrap();
+        assert_eq!(payment_info(&payment_hash).preimage, Some(preimage));
+
+        node.event_handler
+            .handle_event(Event::PaymentClaimed {
+                receiver_node_id: Some(node.pubkey),
+                payment_hash,
+                purpose: PaymentPurpose::InvoicePayment {
+                    payment_preimage: None,
+                    payment_secret,
+                },
+                amount_msat: 1_000_000,
+            })
+            .await;
+        let info = payment_info(&payment_hash);
+        assert_eq!(info.status, HTLCStatus::Succeeded);
+        assert_eq!(info.preimage, Some(preimage));
+        assert!(matches!(
+            nm.cancel_hold_invoice(&hash).await,
+            Err(MutinyError::InvalidArgumentsError)
+        ));
+
+        // cancelling a held payment fails it
+        let hash = sha256::Hash::hash(&[3; 32]);
+        let payment_hash = PaymentHash(hash.into_inner());
+        nm.create_hold_invoice(hash, Some(1_000), vec![])
+            .await
+            .unwrap();
+        node.event_handler
+            .handle_event(claimable(payment_hash))
+            .await;
+        assert_eq!(payment_info(&payment_hash).status, HTLCStatus::Held);
+        nm.cancel_hold_invoice(&hash).await.unwrap();
+        assert_eq!(payment_info(&payment_hash).status, HTLCStatus::Failed);
+
+        // a payment for a hold invoice cancelled before it arrived is not held
+        let hash = sha256::Hash::hash(&[4; 32]);
+        let payment_hash = PaymentHash(hash.into_inner());
+        nm.create_hold_invoice(hash, Some(1_000), vec![])
+            .await
+            .unwrap();
+        nm.cancel_hold_invoice(&hash).await.unwrap();
+        node.event_handler
+            .handle_event(claimable(payment_hash))
+            .await;
+        assert_eq!(payment_info(&payment_hash).status, HTLCStatus::Failed);
+    }
+
+    #[test]
+    async fn close_channel_to_address() {
+        let test_name = "close_channel_to_address";
+        log!("{}", test_name);
+
+        let pass = uuid::Uuid::new_v4().to_string();
+        let cipher = encryption_key_from_pass(&pass).unwrap();
+        let storage = MemoryStorage::new(Some(pass), Some(cipher));
+        let seed = generate_seed(12).expect("Failed to gen seed");
+        let xpriv = ExtendedPrivKey::new_master(Network::Regtest, &seed.to_seed("")).unwrap();
+        let c = MutinyWalletConfig::new(
+            xpriv,
+            #[cfg(target_arch = "wasm32")]
+            None,
+            Network::Regtest,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let nm = NodeManager::new(c, storage)
+            .await
+            .expect("node manager should initialize");
+        nm.new_node().await.expect("should create new node");
+
+        let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+
+        // an address for another network is rejected before looking for the channel
+        let mainnet = Address::p2wsh(&Script::new(), Network::Bitcoin);
+        assert!(matches!(
+            nm.close_channel_to_address(&outpoint, Some(mainnet), None, Some(1_000))
+                .await,
+            Err(MutinyError::IncorrectNetwork(Network::Bitcoin))
+        ));
+
+        let address = nm.get_new_address(vec![]).unwrap();
+        assert!(matches!(
+            nm.close_channel_to_address(&outpoint, Some(address), Some(2.0), Some(1_000))
+                .await,
+            Err(MutinyError::NotFound)
+        ));
+    }
+
     #[test]
     async fn created_label_transaction() {
         let test_name = "created_new_nodes";
@@ -2574,6 +4350,8 @@ mod tests {
             fee_paid_msat: None,
             bolt11: Some(invoice.clone()),
             payee_pubkey: None,
+            lsp_pubkey: None,
+            claim_deadline: None,
             last_update: 1681781585,
         };
 
@@ -2583,9 +4361,11 @@ mod tests {
             payment_hash,
             preimage: Some(preimage.to_hex()),
             payee_pubkey: None,
+            lsp_pubkey: None,
             amount_sats: Some(100_000),
             expire: 1681781649 + 86400,
             paid: true,
+            held: false,
             fees_paid: None,
             inbound: true,
             labels: labels.clone(),
@@ -2627,6 +4407,8 @@ mod tests {
             fee_paid_msat: Some(1_000),
             bolt11: None,
             payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
+            claim_deadline: None,
             last_update: 1681781585,
         };
 
@@ -2636,9 +4418,11 @@ mod tests {
             payment_hash,
             preimage: Some(preimage.to_hex()),
             payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
             amount_sats: Some(100),
             expire: 1681781585,
             paid: true,
+            held: false,
             fees_paid: Some(1),
             inbound: false,
             labels: vec![],
@@ -2656,6 +4440,59 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_held_payment_info_into_mutiny_invoice() {
+        let payment_hash = sha256::Hash::from_hex(
+            "55ecf9169a6fa07e8ba181fdddf5b0bcc7860176659fa22a7cca9da2a359a33b",
+        )
+        .unwrap();
+
+        let pubkey = PublicKey::from_str(
+            "02465ed5be53d04fde66c9418ff14a5f2267723810176c9212b722e542dc1afb1b",
+        )
+        .unwrap();
+
+        let payment_info = PaymentInfo {
+            preimage: None,
+            secret: Some([1; 32]),
+            status: HTLCStatus::Held,
+            amt_msat: MillisatAmount(Some(100_000)),
+            fee_paid_msat: None,
+            bolt11: None,
+            payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
+            claim_deadline: Some(800_000),
+            last_update: 1681781585,
+        };
+
+        let expected: MutinyInvoice = MutinyInvoice {
+            bolt11: None,
+            description: None,
+            payment_hash,
+            preimage: None,
+            payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
+            amount_sats: Some(100),
+            expire: 1681781585,
+            paid: false,
+            held: true,
+            fees_paid: None,
+            inbound: true,
+            labels: vec![],
+            last_updated: 1681781585,
+        };
+
+        let actual = MutinyInvoice::from(
+            payment_info,
+            PaymentHash(payment_hash.into_inner()),
+            true,
+            vec![],
+        )
+        .unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
     #[test]
     fn test_sort_activity_item() {
         let preimage: [u8; 32] =
@@ -2678,6 +4515,9 @@ mod tests {
             node_id: None,
             reason: "".to_string(),
             timestamp: 1686258926,
+            destination: None,
+            label: None,
+            note: None,
         };
 
         let tx1: TransactionDetails = TransactionDetails {
@@ -2709,9 +4549,11 @@ mod tests {
             payment_hash,
             preimage: Some(preimage.to_hex()),
             payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
             amount_sats: Some(100),
             expire: 1681781585,
             paid: true,
+            held: false,
             fees_paid: Some(1),
             inbound: false,
             labels: vec![],
@@ -2724,9 +4566,11 @@ mod tests {
             payment_hash,
             preimage: Some(preimage.to_hex()),
             payee_pubkey: Some(pubkey),
+            lsp_pubkey: None,
             amount_sats: Some(100),
             expire: 1681781585,
             paid: true,
+            held: false,
             fees_paid: Some(1),
             inbound: false,
             labels: vec![],
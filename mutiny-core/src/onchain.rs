@@ -1,5 +1,5 @@
 use anyhow::anyhow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
@@ -7,13 +7,19 @@ use std::sync::{Arc, RwLock};
 use bdk::chain::{BlockId, ConfirmationTime};
 use bdk::psbt::PsbtUtils;
 use bdk::template::DescriptorTemplateOut;
+use bdk::wallet::AddressIndex;
 use bdk::{FeeRate, LocalUtxo, SignOptions, TransactionDetails, Wallet};
 use bdk_esplora::{esplora_client, EsploraAsyncExt};
 use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
+use bitcoin::util::sighash::SighashCache;
 use bitcoin::{Address, Network, OutPoint, Script, Transaction, Txid};
+use bitcoin::{EcdsaSighashType, PackedLockTime, SchnorrSighashType, Sequence};
+use bitcoin::{TxIn, TxOut, Witness};
 use esplora_client::AsyncClient;
 use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::events::bump_transaction::{Utxo, WalletSource};
 use lightning::util::logger::Logger;
 use lightning::{log_debug, log_error, log_warn};
 
@@ -24,6 +30,23 @@ use crate::logging::MutinyLogger;
 use crate::storage::{MutinyStorage, OnChainStorage};
 use crate::utils::{now, sleep};
 
+/// The weight to spend one of our taproot outputs with a key path spend:
+/// the empty script sig length (4 weight units), the witness item count,
+/// and the length byte of a signature with its sighash flag.
+const P2TR_SATISFACTION_WEIGHT: u64 = 4 + 1 + 1 + 65;
+
+/// How we spend the output of a swap HTLC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HtlcSpendPath {
+    /// With the preimage of the payment hash
+    Claim([u8; 32]),
+    /// Without the preimage, once the block height reached the given timeout
+    Refund(u32),
+}
+
+/// Gives the on-chain funds a node needs to keep to fee bump its anchor channels
+pub(crate) type AnchorReserve = Box<dyn Fn() -> u64>;
+
 #[derive(Clone)]
 pub struct OnChainWallet<S: MutinyStorage> {
     pub wallet: Arc<RwLock<Wallet<OnChainStorage<S>>>>,
@@ -32,6 +55,10 @@ pub struct OnChainWallet<S: MutinyStorage> {
     pub blockchain: Arc<AsyncClient>,
     pub fees: Arc<MutinyFeeEstimator<S>>,
     pub(crate) stop: Arc<AtomicBool>,
+    /// The anchor reserve of each of the nodes sharing this wallet
+    anchor_reserves: Arc<RwLock<HashMap<PublicKey, AnchorReserve>>>,
+    /// Transactions LDK asked us to broadcast that we are holding back
+    held_broadcasts: Arc<RwLock<HashSet<Txid>>>,
     logger: Arc<MutinyLogger>,
 }
 
@@ -55,6 +82,7 @@ impl<S: MutinyStorage> OnChainWallet<S> {
             OnChainStorage(db.clone()),
             network,
         )?;
+        let held_broadcasts = db.get_held_broadcasts()?;
 
         Ok(OnChainWallet {
             wallet: Arc::new(RwLock::new(wallet)),
@@ -63,6 +91,8 @@ impl<S: MutinyStorage> OnChainWallet<S> {
             blockchain: esplora,
             fees,
             stop,
+            anchor_reserves: Arc::new(RwLock::new(HashMap::new())),
+            held_broadcasts: Arc::new(RwLock::new(held_broadcasts)),
             logger,
         })
     }
@@ -205,6 +235,125 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         Ok(self.wallet.try_read()?.list_unspent().collect())
     }
 
+    /// If the script is one of our wallet's addresses
+    pub(crate) fn is_mine(&self, script: &Script) -> Result<bool, MutinyError> {
+        Ok(self.wallet.try_read()?.is_mine(script))
+    }
+
+    /// Our confirmed balance, the only funds a fee bump can spend, in sats
+    pub(crate) fn confirmed_balance(&self) -> Result<u64, MutinyError> {
+        Ok(self.wallet.try_read()?.get_balance().confirmed)
+    }
+
+    /// Holds back a transaction when LDK asks us to broadcast it, until it is
+    /// released with [`OnChainWallet::release_broadcast`] or cancelled.
+    ///
+    /// A batch funding transaction must not be broadcast before every channel
+    /// it funds has been signed by its peer, LDK does not know about the batch.
+    /// What is held is saved, so it is still held back after a restart.
+    pub(crate) fn hold_broadcast(&self, txid: Txid) {
+        if let Ok(mut held) = self.held_broadcasts.write() {
+            held.insert(txid);
+            self.persist_held_broadcasts(&held);
+        }
+    }
+
+    /// If LDK should not broadcast this transaction yet
+    pub(crate) fn is_broadcast_held(&self, txid: &Txid) -> bool {
+        self.held_broadcasts
+            .read()
+            .is_ok_and(|held| held.contains(txid))
+    }
+
+    /// Stops holding back a transaction without broadcasting it
+    pub(crate) fn cancel_broadcast(&self, txid: &Txid) {
+        if let Ok(mut held) = self.held_broadcasts.write() {
+            if held.remove(txid) {
+                self.persist_held_broadcasts(&held);
+            }
+        }
+    }
+
+    fn persist_held_broadcasts(&self, held: &HashSet<Txid>) {
+        if let Err(e) = self.storage.insert_held_broadcasts(held) {
+            log_error!(self.logger, "Failed to save held broadcasts: {e}");
+        }
+    }
+
+    /// Stops holding back a transaction and broadcasts it
+    pub(crate) async fn release_broadcast(&self, tx: Transaction) -> Result<(), MutinyError> {
+        self.cancel_broadcast(&tx.txid());
+        self.broadcast_transaction(tx).await
+    }
+
+    /// Registers the anchor reserve of a node that uses this wallet, replacing
+    /// the one it registered before.
+    pub(crate) fn register_anchor_reserve(&self, node: PublicKey, reserve: AnchorReserve) {
+        if let Ok(mut reserves) = self.anchor_reserves.write() {
+            reserves.insert(node, reserve);
+        }
+    }
+
+    /// The on-chain funds we keep to fee bump the anchor channels of all
+    /// of the nodes using this wallet, in sats
+    pub(crate) fn anchor_reserve_sats(&self) -> u64 {
+        self.anchor_reserves
+            .read()
+            .map(|reserves| reserves.values().map(|reserve| reserve()).sum())
+            .unwrap_or_default()
+    }
+
+    /// If after spending `spend_sats` we would still have the funds to fee bump
+    /// the anchor channels of all of our nodes, plus `extra_reserve_sats`
+    pub(crate) fn can_reserve_anchors(
+        &self,
+        spend_sats: u64,
+        extra_reserve_sats: u64,
+    ) -> Result<bool, MutinyError> {
+        let reserve = self.anchor_reserve_sats() + extra_reserve_sats;
+        let balance = self.confirmed_balance()?;
+        Ok(balance.saturating_sub(spend_sats) >= reserve)
+    }
+
+    /// Signs our inputs of a transaction that also spends outputs that aren't ours,
+    /// like the anchor output of a commitment transaction.
+    ///
+    /// We don't know the other outputs being spent, so we sign with
+    /// `SIGHASH_ALL|ANYONECANPAY` which only commits to our own inputs.
+    pub(crate) fn sign_foreign_tx(&self, tx: Transaction) -> Result<Transaction, MutinyError> {
+        let wallet = self.wallet.try_read()?;
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx.clone())
+            .map_err(|_| MutinyError::WalletSigningFailed)?;
+        let mut ours = vec![];
+        for (index, input) in tx.input.iter().enumerate() {
+            if let Some(utxo) = wallet.get_utxo(input.previous_output) {
+                psbt.inputs[index].witness_utxo = Some(utxo.txout);
+                psbt.inputs[index].sighash_type =
+                    Some(SchnorrSighashType::AllPlusAnyoneCanPay.into());
+                ours.push(index);
+            }
+        }
+
+        let options = SignOptions {
+            trust_witness_utxo: true,
+            allow_all_sighashes: true,
+            ..Default::default()
+        };
+        wallet.sign(&mut psbt, options)?;
+
+        // only take the witnesses of our inputs, the rest are signed by LDK
+        let mut signed = tx;
+        for index in ours {
+            signed.input[index].witness = psbt.inputs[index]
+                .final_script_witness
+                .clone()
+                .ok_or(MutinyError::WalletSigningFailed)?;
+        }
+
+        Ok(signed)
+    }
+
     pub fn list_transactions(
         &self,
         include_raw: bool,
@@ -357,6 +506,15 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         spk: Script,
         amount: u64,
         fee_rate: Option<f32>,
+    ) -> Result<PartiallySignedTransaction, MutinyError> {
+        self.create_signed_psbt_to_outputs(vec![(spk, amount)], fee_rate)
+    }
+
+    /// Creates a signed PSBT paying to all the given outputs in a single transaction.
+    pub fn create_signed_psbt_to_outputs(
+        &self,
+        outputs: Vec<(Script, u64)>,
+        fee_rate: Option<f32>,
     ) -> Result<PartiallySignedTransaction, MutinyError> {
         let mut wallet = self.wallet.try_write()?;
 
@@ -371,7 +529,7 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         let (mut psbt, details) = {
             let mut builder = wallet.build_tx();
             builder
-                .add_recipient(spk, amount)
+                .set_recipients(outputs)
                 .enable_rbf()
                 .fee_rate(fee_rate);
             builder.finish()?
@@ -391,6 +549,15 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         fee_rate: Option<f32>,
     ) -> Result<Txid, MutinyError> {
         let psbt = self.create_signed_psbt(destination_address, amount, fee_rate)?;
+
+        // we need to keep enough to bump our anchor channels
+        let fee = psbt
+            .fee_amount()
+            .ok_or(MutinyError::WalletOperationFailed)?;
+        if !self.can_reserve_anchors(amount + fee, 0)? {
+            return Err(MutinyError::AnchorReserveRequired);
+        }
+
         self.label_psbt(&psbt, labels)?;
 
         let raw_transaction = psbt.extract_tx();
@@ -401,12 +568,18 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         Ok(txid)
     }
 
+    /// Creates a PSBT that spends everything in our wallet to the given script,
+    /// except for the anchor reserve, which comes back to us as change.
     pub fn create_sweep_psbt(
         &self,
         spk: Script,
         fee_rate: Option<f32>,
     ) -> Result<PartiallySignedTransaction, MutinyError> {
+        let reserve = self.anchor_reserve_sats();
         let mut wallet = self.wallet.try_write()?;
+        let reserve_spk = wallet
+            .get_internal_address(AddressIndex::LastUnused)
+            .script_pubkey();
 
         let fee_rate = if let Some(rate) = fee_rate {
             FeeRate::from_sat_per_vb(rate)
@@ -423,6 +596,9 @@ impl<S: MutinyStorage> OnChainWallet<S> {
                 .drain_to(spk)
                 .enable_rbf()
                 .fee_rate(fee_rate);
+            if reserve > 0 {
+                builder.add_recipient(reserve_spk, reserve);
+            }
             builder.finish()?
         };
         log_debug!(self.logger, "Transaction details: {details:#?}");
@@ -442,6 +618,11 @@ impl<S: MutinyStorage> OnChainWallet<S> {
             return Err(MutinyError::IncorrectNetwork(destination_address.network));
         }
 
+        // we sweep down to the reserve, so it must be there to begin with
+        if !self.can_reserve_anchors(0, 0)? {
+            return Err(MutinyError::AnchorReserveRequired);
+        }
+
         let psbt = self.create_sweep_psbt(destination_address.script_pubkey(), fee_rate)?;
         self.label_psbt(&psbt, labels)?;
 
@@ -481,6 +662,133 @@ impl<S: MutinyStorage> OnChainWallet<S> {
         Ok(psbt)
     }
 
+    /// Creates a signed PSBT that pays an amount from only the given utxos,
+    /// with the change going back to our wallet.
+    pub(crate) fn create_signed_psbt_from_utxos(
+        &self,
+        utxos: &[OutPoint],
+        spk: Script,
+        amount: u64,
+        fee_rate: Option<f32>,
+    ) -> Result<PartiallySignedTransaction, MutinyError> {
+        let mut wallet = self.wallet.try_write()?;
+
+        let fee_rate = if let Some(rate) = fee_rate {
+            FeeRate::from_sat_per_vb(rate)
+        } else {
+            let sat_per_kwu = self
+                .fees
+                .get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+            FeeRate::from_sat_per_kwu(sat_per_kwu as f32)
+        };
+        let (mut psbt, details) = {
+            let mut builder = wallet.build_tx();
+            builder
+                .manually_selected_only()
+                .add_utxos(utxos)?
+                .add_recipient(spk, amount)
+                .enable_rbf()
+                .fee_rate(fee_rate);
+            builder.finish()?
+        };
+        log_debug!(self.logger, "Transaction details: {details:#?}");
+        log_debug!(self.logger, "Unsigned PSBT: {psbt}");
+        let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+        log_debug!(self.logger, "finalized: {finalized}");
+        Ok(psbt)
+    }
+
+    /// Creates a transaction spending the output of a swap HTLC into a new address of ours.
+    /// The HTLC is signed for with the given key, which must match the path being used.
+    ///
+    /// If a payment is given it is made first and only what is left comes back to us.
+    /// The payment is never made for less than asked, when the HTLC can't cover it and the
+    /// fee this fails with [`MutinyError::InsufficientBalance`].
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create_htlc_spend_tx(
+        &self,
+        htlc: OutPoint,
+        value: u64,
+        witness_script: &Script,
+        secret_key: &SecretKey,
+        path: HtlcSpendPath,
+        payment: Option<TxOut>,
+        fee_rate: Option<f32>,
+    ) -> Result<Transaction, MutinyError> {
+        let destination = self
+            .wallet
+            .try_write()?
+            .get_address(AddressIndex::New)
+            .address;
+
+        // an empty item takes the refund branch of the script
+        let (preimage, lock_time) = match path {
+            HtlcSpendPath::Claim(preimage) => (preimage.to_vec(), 0),
+            HtlcSpendPath::Refund(timeout) => (vec![], timeout),
+        };
+
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(lock_time),
+            input: vec![TxIn {
+                previous_output: htlc,
+                script_sig: Script::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                // a placeholder the size of the real witness, for the fee calculation
+                witness: Witness::from_vec(vec![
+                    vec![0; 73],
+                    preimage.clone(),
+                    witness_script.to_bytes(),
+                ]),
+            }],
+            output: payment
+                .iter()
+                .cloned()
+                .chain(std::iter::once(TxOut {
+                    value,
+                    script_pubkey: destination.script_pubkey(),
+                }))
+                .collect(),
+        };
+
+        let sat_per_kw = match fee_rate {
+            Some(rate) => (rate * 250.0) as u64,
+            None => self
+                .fees
+                .get_est_sat_per_1000_weight(ConfirmationTarget::Normal) as u64,
+        };
+        let fee = tx.weight() as u64 * sat_per_kw / 1000;
+        let paid = payment.as_ref().map(|p| p.value).unwrap_or_default();
+        let dust_limit = destination.script_pubkey().dust_value().to_sat();
+        if value >= paid + fee + dust_limit {
+            let change = tx.output.last_mut().expect("always has our output");
+            change.value = value - paid - fee;
+        } else if payment.is_some() {
+            // too little left to come back to us, so it all goes to the payment and fee
+            tx.output.pop();
+            let fee = tx.weight() as u64 * sat_per_kw / 1000;
+            if value.saturating_sub(fee) < paid {
+                return Err(MutinyError::InsufficientBalance);
+            }
+        } else {
+            return Err(MutinyError::InsufficientBalance);
+        }
+
+        let sighash = SighashCache::new(&tx)
+            .segwit_signature_hash(0, witness_script, value, EcdsaSighashType::All)
+            .map_err(|_| MutinyError::WalletSigningFailed)?;
+        let message =
+            Message::from_slice(&sighash[..]).map_err(|_| MutinyError::WalletSigningFailed)?;
+        let signature = Secp256k1::signing_only().sign_ecdsa(&message, secret_key);
+        let mut signature = signature.serialize_der().to_vec();
+        signature.push(EcdsaSighashType::All as u8);
+
+        tx.input[0].witness =
+            Witness::from_vec(vec![signature, preimage, witness_script.to_bytes()]);
+
+        Ok(tx)
+    }
+
     pub fn estimate_tx_fee(
         &self,
         spk: Script,
@@ -503,6 +811,35 @@ impl<S: MutinyStorage> OnChainWallet<S> {
     }
 }
 
+impl<S: MutinyStorage> WalletSource for OnChainWallet<S> {
+    fn list_confirmed_utxos(&self) -> Result<Vec<Utxo>, ()> {
+        let utxos = self.list_utxos().map_err(|_| ())?;
+        Ok(utxos
+            .into_iter()
+            .filter(|u| matches!(u.confirmation_time, ConfirmationTime::Confirmed { .. }))
+            .map(|u| Utxo {
+                outpoint: u.outpoint,
+                output: u.txout,
+                satisfaction_weight: P2TR_SATISFACTION_WEIGHT,
+            })
+            .collect())
+    }
+
+    fn get_change_script(&self) -> Result<Script, ()> {
+        let mut wallet = self.wallet.try_write().map_err(|_| ())?;
+        Ok(wallet
+            .get_internal_address(AddressIndex::New)
+            .address
+            .script_pubkey())
+    }
+
+    fn sign_tx(&self, tx: Transaction) -> Result<Transaction, ()> {
+        self.sign_foreign_tx(tx).map_err(|e| {
+            log_error!(self.logger, "Failed to sign fee bump transaction: {e}");
+        })
+    }
+}
+
 fn get_tr_descriptors_for_extended_key(
     master_xprv: ExtendedPrivKey,
     network: Network,
@@ -550,10 +887,15 @@ pub(crate) fn get_esplora_url(network: Network, user_provided_url: Option<String
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::swaps::htlc_script;
     use crate::test_utils::*;
     use crate::{encrypt::encryption_key_from_pass, storage::MemoryStorage};
     use bip39::Mnemonic;
-    use bitcoin::Address;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::secp256k1::ecdsa::Signature;
+    use bitcoin::secp256k1::{schnorr, XOnlyPublicKey};
+    use bitcoin::util::sighash::Prevouts;
+    use bitcoin::{Address, BlockHash};
     use esplora_client::Builder;
     use std::str::FromStr;
     use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
@@ -581,6 +923,58 @@ mod tests {
         OnChainWallet::new(xpriv, db, Network::Testnet, esplora, fees, stop, logger).unwrap()
     }
 
+    /// Gives the wallet a confirmed output of `amount` sats, or an unconfirmed one
+    /// that isn't our own change if `confirmed` is false
+    async fn fund_wallet(
+        wallet: &OnChainWallet<MemoryStorage>,
+        amount: u64,
+        confirmed: bool,
+    ) -> OutPoint {
+        let address = wallet
+            .wallet
+            .try_write()
+            .unwrap()
+            .get_address(AddressIndex::New)
+            .address;
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_slice(&[amount as u8; 32]).unwrap(), 0),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: amount,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let outpoint = OutPoint::new(tx.txid(), 0);
+
+        if confirmed {
+            let block_id = BlockId {
+                height: 1,
+                hash: BlockHash::all_zeros(),
+            };
+            let position = ConfirmationTime::Confirmed {
+                height: 1,
+                time: 1690000000,
+            };
+            wallet
+                .insert_tx(tx, position, Some(block_id))
+                .await
+                .unwrap();
+        } else {
+            let position = ConfirmationTime::Unconfirmed {
+                last_seen: 1690000000,
+            };
+            wallet.insert_tx(tx, position, None).await.unwrap();
+        }
+
+        outpoint
+    }
+
     #[test]
     async fn test_create_wallet() {
         let test_name = "create_wallet";
@@ -630,4 +1024,350 @@ mod tests {
         assert!(label.clone().unwrap().addresses.contains(&send_to_addr));
         assert!(label.unwrap().addresses.contains(&change_addr));
     }
+
+    #[test]
+    async fn test_create_htlc_spend_tx() {
+        let test_name = "create_htlc_spend_tx";
+        log!("{}", test_name);
+        let wallet = create_wallet().await;
+
+        let secp = Secp256k1::new();
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let pubkey = key.public_key(&secp);
+        let preimage = [2; 32];
+        let payment_hash = sha256::Hash::hash(&preimage);
+        let script = htlc_script(&payment_hash, &pubkey, &pubkey, 800_144);
+        let htlc = OutPoint::new(Txid::all_zeros(), 0);
+
+        let claim = wallet
+            .create_htlc_spend_tx(
+                htlc,
+                100_000,
+                &script,
+                &key,
+                HtlcSpendPath::Claim(preimage),
+                None,
+                Some(1.0),
+            )
+            .unwrap();
+        assert_eq!(claim.lock_time, PackedLockTime(0));
+        assert_eq!(claim.input[0].witness.to_vec()[1], preimage.to_vec());
+        assert!(claim.output[0].value < 100_000);
+
+        let refund = wallet
+            .create_htlc_spend_tx(
+                htlc,
+                100_000,
+                &script,
+                &key,
+                HtlcSpendPath::Refund(800_144),
+                None,
+                Some(1.0),
+            )
+            .unwrap();
+        assert_eq!(refund.lock_time, PackedLockTime(800_144));
+        assert!(refund.input[0].sequence.enables_absolute_lock_time());
+        let witness = refund.input[0].witness.to_vec();
+        assert!(witness[1].is_empty());
+        assert_eq!(witness[2], script.to_bytes());
+
+        // the signature commits to the value of the HTLC
+        let sighash = SighashCache::new(&refund)
+            .segwit_signature_hash(0, &script, 100_000, EcdsaSighashType::All)
+            .unwrap();
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        let signature = Signature::from_der(&witness[0][..witness[0].len() - 1]).unwrap();
+        assert!(secp.verify_ecdsa(&message, &signature, &pubkey).is_ok());
+
+        // too small to pay for its own spend
+        assert!(matches!(
+            wallet.create_htlc_spend_tx(
+                htlc,
+                500,
+                &script,
+                &key,
+                HtlcSpendPath::Refund(800_144),
+                None,
+                Some(1.0),
+            ),
+            Err(MutinyError::InsufficientBalance)
+        ));
+
+        // a payment is made exactly and the rest comes back to us
+        let payment = TxOut {
+            value: 60_000,
+            script_pubkey: Address::p2wsh(&script, Network::Regtest).script_pubkey(),
+        };
+        let claim = wallet
+            .create_htlc_spend_tx(
+                htlc,
+                100_000,
+                &script,
+                &key,
+                HtlcSpendPath::Claim(preimage),
+                Some(payment.clone()),
+                Some(1.0),
+            )
+            .unwrap();
+        assert_eq!(claim.output.len(), 2);
+        assert_eq!(claim.output[0], payment);
+        assert!(claim.output[1].value < 40_000);
+
+        // too little left to come back to us goes to the fee, the payment is still exact
+        let claim = wallet
+            .create_htlc_spend_tx(
+                htlc,
+                60_300,
+                &script,
+                &key,
+                HtlcSpendPath::Claim(preimage),
+                Some(payment.clone()),
+                Some(1.0),
+            )
+            .unwrap();
+        assert_eq!(claim.output, vec![payment.clone()]);
+
+        // without enough for the fee the payment is not made for less
+        assert!(matches!(
+            wallet.create_htlc_spend_tx(
+                htlc,
+                60_000,
+                &script,
+                &key,
+                HtlcSpendPath::Claim(preimage),
+                Some(payment),
+                Some(1.0),
+            ),
+            Err(MutinyError::InsufficientBalance)
+        ));
+    }
+
+    #[test]
+    async fn test_anchor_reserve() {
+        let test_name = "anchor_reserve";
+        log!("{}", test_name);
+        let wallet = create_wallet().await;
+        fund_wallet(&wallet, 100_000, true).await;
+        assert_eq!(wallet.confirmed_balance().unwrap(), 100_000);
+
+        // nothing to reserve without anchor channels
+        assert_eq!(wallet.anchor_reserve_sats(), 0);
+        assert!(wallet.can_reserve_anchors(100_000, 0).unwrap());
+
+        // the reserve is for all of the nodes sharing the wallet
+        let secp = Secp256k1::new();
+        let node_a = SecretKey::from_slice(&[1; 32]).unwrap().public_key(&secp);
+        let node_b = SecretKey::from_slice(&[2; 32]).unwrap().public_key(&secp);
+        wallet.register_anchor_reserve(node_a, Box::new(|| 30_000));
+        wallet.register_anchor_reserve(node_b, Box::new(|| 20_000));
+        assert_eq!(wallet.anchor_reserve_sats(), 50_000);
+
+        assert!(wallet.can_reserve_anchors(50_000, 0).unwrap());
+        assert!(!wallet.can_reserve_anchors(50_001, 0).unwrap());
+        // room for another anchor channel
+        assert!(wallet.can_reserve_anchors(40_000, 10_000).unwrap());
+        assert!(!wallet.can_reserve_anchors(40_000, 20_000).unwrap());
+
+        // a node registering again replaces its reserve
+        wallet.register_anchor_reserve(node_b, Box::new(|| 0));
+        assert_eq!(wallet.anchor_reserve_sats(), 30_000);
+
+        // unconfirmed funds can't pay for a fee bump
+        fund_wallet(&wallet, 50_000, false).await;
+        assert_eq!(wallet.confirmed_balance().unwrap(), 100_000);
+        assert!(wallet.can_reserve_anchors(70_000, 0).unwrap());
+        assert!(!wallet.can_reserve_anchors(70_001, 0).unwrap());
+    }
+
+    #[test]
+    async fn test_send_keeps_anchor_reserve() {
+        let test_name = "send_keeps_anchor_reserve";
+        log!("{}", test_name);
+        let wallet = create_wallet().await;
+        fund_wallet(&wallet, 100_000, true).await;
+
+        let secp = Secp256k1::new();
+        let node = SecretKey::from_slice(&[1; 32]).unwrap().public_key(&secp);
+        wallet.register_anchor_reserve(node, Box::new(|| 25_000));
+
+        let send_to_addr = Address::from_str("mrKjeffvbnmKJURrLNdqLkfrptLrFtnkFx").unwrap();
+
+        // the fee would take us below the reserve
+        let result = wallet
+            .send(send_to_addr.clone(), 75_000, vec![], Some(1.0))
+            .await;
+        assert!(matches!(result, Err(MutinyError::AnchorReserveRequired)));
+
+        // sweeping leaves the reserve in our wallet
+        let psbt = wallet
+            .create_sweep_psbt(send_to_addr.script_pubkey(), Some(1.0))
+            .unwrap();
+        let tx = psbt.extract_tx();
+        let fee = psbt.fee_amount().unwrap();
+        assert_eq!(tx.output.len(), 2);
+        let reserve = tx
+            .output
+            .iter()
+            .find(|o| wallet.is_mine(&o.script_pubkey).unwrap())
+            .unwrap();
+        assert_eq!(reserve.value, 25_000);
+        let swept = tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == send_to_addr.script_pubkey())
+            .unwrap();
+        assert_eq!(swept.value, 100_000 - 25_000 - fee);
+
+        // without the reserve to begin with there is nothing to sweep
+        wallet.register_anchor_reserve(node, Box::new(|| 100_001));
+        let result = wallet.sweep(send_to_addr, vec![], Some(1.0)).await;
+        assert!(matches!(result, Err(MutinyError::AnchorReserveRequired)));
+
+        // nothing was spent
+        assert_eq!(wallet.confirmed_balance().unwrap(), 100_000);
+        assert_eq!(wallet.list_transactions(false).unwrap().len(), 1);
+    }
+
+    #[test]
+    async fn test_sign_foreign_tx() {
+        let test_name = "sign_foreign_tx";
+        log!("{}", test_name);
+        let wallet = create_wallet().await;
+        let ours = fund_wallet(&wallet, 100_000, true).await;
+        let our_txout = wallet
+            .list_utxos()
+            .unwrap()
+            .into_iter()
+            .find(|u| u.outpoint == ours)
+            .unwrap()
+            .txout;
+
+        // an anchor output that isn't ours, plus our utxo to pay the fee
+        let foreign = OutPoint::new(Txid::from_slice(&[3; 32]).unwrap(), 1);
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: [foreign, ours]
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 99_000,
+                script_pubkey: our_txout.script_pubkey.clone(),
+            }],
+        };
+
+        let signed = wallet.sign_foreign_tx(tx.clone()).unwrap();
+        assert_eq!(signed.txid(), tx.txid());
+        // the foreign input is left for LDK to sign
+        assert!(signed.input[0].witness.is_empty());
+
+        // our input is signed with SIGHASH_ALL|ANYONECANPAY
+        let witness = signed.input[1].witness.to_vec();
+        assert_eq!(witness.len(), 1);
+        assert_eq!(witness[0].len(), 65);
+        assert_eq!(
+            witness[0][64],
+            SchnorrSighashType::AllPlusAnyoneCanPay as u8
+        );
+
+        // which stays valid whatever the foreign output spent is
+        let sighash = SighashCache::new(&signed)
+            .taproot_key_spend_signature_hash(
+                1,
+                &Prevouts::One(1, &our_txout),
+                SchnorrSighashType::AllPlusAnyoneCanPay,
+            )
+            .unwrap();
+        let message = Message::from_slice(&sighash[..]).unwrap();
+        let signature = schnorr::Signature::from_slice(&witness[0][..64]).unwrap();
+        let output_key = XOnlyPublicKey::from_slice(&our_txout.script_pubkey[2..]).unwrap();
+        let secp = Secp256k1::verification_only();
+        assert!(secp
+            .verify_schnorr(&signature, &message, &output_key)
+            .is_ok());
+    }
+
+    #[test]
+    async fn test_wallet_source() {
+        let test_name = "wallet_source";
+        log!("{}", test_name);
+        let wallet = create_wallet().await;
+        let confirmed = fund_wallet(&wallet, 100_000, true).await;
+        let unconfirmed = fund_wallet(&wallet, 50_000, false).await;
+
+        // only confirmed utxos can be used to bump fees
+        let utxos = wallet.list_confirmed_utxos().unwrap();
+        assert_eq!(utxos.len(), 1);
+        assert_eq!(utxos[0].outpoint, confirmed);
+        assert_eq!(utxos[0].output.value, 100_000);
+        assert_eq!(utxos[0].satisfaction_weight, P2TR_SATISFACTION_WEIGHT);
+        assert!(utxos.iter().all(|u| u.outpoint != unconfirmed));
+
+        // change goes back to a new internal address of ours
+        let change = wallet.get_change_script().unwrap();
+        assert!(wallet.is_mine(&change).unwrap());
+        assert_ne!(change, wallet.get_change_script().unwrap());
+
+        // signing goes through sign_foreign_tx
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: confirmed,
+                script_sig: Script::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: 99_000,
+                script_pubkey: change,
+            }],
+        };
+        let signed = WalletSource::sign_tx(&wallet, tx).unwrap();
+        assert_eq!(signed.input[0].witness.len(), 1);
+    }
+
+    #[test]
+    async fn test_hold_broadcast() {
+        let test_name = "hold_broadcast";
+        log!("{}", test_name);
+        let wallet = create_wallet().await;
+
+        let txid = Txid::from_slice(&[1; 32]).unwrap();
+        let other = Txid::from_slice(&[2; 32]).unwrap();
+        assert!(!wallet.is_broadcast_held(&txid));
+
+        wallet.hold_broadcast(txid);
+        assert!(wallet.is_broadcast_held(&txid));
+        assert!(!wallet.is_broadcast_held(&other));
+
+        // clones of the wallet share what is held
+        let clone = wallet.clone();
+        assert!(clone.is_broadcast_held(&txid));
+
+        // a wallet loaded from the same storage still holds it after a restart
+        let mnemonic = Mnemonic::from_str("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").unwrap();
+        let xpriv = ExtendedPrivKey::new_master(Network::Testnet, &mnemonic.to_seed("")).unwrap();
+        let restarted = OnChainWallet::new(
+            xpriv,
+            wallet.storage.clone(),
+            wallet.network,
+            wallet.blockchain.clone(),
+            wallet.fees.clone(),
+            wallet.stop.clone(),
+            wallet.logger.clone(),
+        )
+        .unwrap();
+        assert!(restarted.is_broadcast_held(&txid));
+
+        wallet.cancel_broadcast(&txid);
+        assert!(!wallet.is_broadcast_held(&txid));
+        assert!(!clone.is_broadcast_held(&txid));
+    }
 }
@@ -0,0 +1,1237 @@
+use crate::error::MutinyError;
+use crate::fees::{P2WSH_OUTPUT_SIZE, TAPROOT_OUTPUT_SIZE};
+use crate::nodemanager::NodeManager;
+use crate::storage::MutinyStorage;
+use crate::swaps::{LoopOutQuoteRequest, Swap, SwapManager, SwapStatus, SwapStorage};
+use crate::utils;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{Address, OutPoint, Transaction, Txid};
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::util::logger::Logger;
+use lightning::{log_error, log_info, log_warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Label given to the on-chain transactions of paying an address from our lightning balance
+pub const PAY_ONCHAIN_LABEL: &str = "Pay on-chain";
+
+/// The weight of a cooperative close with an output for each side,
+/// for estimating the closing fee before we close
+const CLOSING_TX_WEIGHT: u64 = 770;
+
+/// What is left after paying the address only goes back into a channel if it is at least
+/// this much, otherwise it stays in our on-chain wallet
+const MIN_REOPEN_CHANNEL_SATS: u64 = 100_000;
+
+/// A close that has not reached our wallet after this long is given up on,
+/// the channel may have been force closed instead, in seconds
+const CLOSE_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+/// A payment from a close that has not been broadcast after this long is given up on,
+/// the funds stay in our on-chain wallet, in seconds
+const PAY_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+/// We stop trying to reopen a channel with what is left after this long,
+/// the funds stay in our on-chain wallet, in seconds
+const REOPEN_TIMEOUT_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayOnchainMethod {
+    /// A loop out with our swap provider, its HTLC is claimed straight to the address.
+    SwapOut { node: PublicKey },
+    /// A channel is cooperatively closed to our wallet, the address is paid from it,
+    /// and what is left is opened as a new channel with the same peer.
+    CloseAndReopen {
+        node: PublicKey,
+        channel: OutPoint,
+        peer: PublicKey,
+    },
+}
+
+/// The cost of paying an on-chain address from our lightning balance, in satoshis.
+/// Routing fees for a swap out are paid on top of this.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayOnchainQuote {
+    pub method: PayOnchainMethod,
+    /// What the swap provider charges, for a swap out
+    pub provider_fee_sats: u64,
+    /// The on-chain fees we expect to pay. The HTLC claim for a swap out,
+    /// or the close, payment and reopen for a close and reopen.
+    pub onchain_fee_sats: u64,
+    /// What goes back into a new channel, for a close and reopen
+    pub reopen_amount_sats: u64,
+}
+
+impl PayOnchainQuote {
+    pub fn total_fee_sats(&self) -> u64 {
+        self.provider_fee_sats + self.onchain_fee_sats
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayOnchainStatus {
+    /// Waiting on the swap out to complete, with its claim to the address confirmed.
+    Swapping,
+    /// Waiting on the closed channel's funds to reach our wallet.
+    Closing,
+    /// The funds are in our wallet and the address is being paid from them.
+    Paying,
+    /// The address was paid and what is left is going into a new channel.
+    Reopening,
+    /// The address was paid.
+    Completed,
+    /// The job failed. The error is given.
+    Failed(String),
+}
+
+impl PayOnchainStatus {
+    /// If the job still needs to be moved forward
+    pub fn is_in_progress(&self) -> bool {
+        matches!(
+            self,
+            PayOnchainStatus::Swapping
+                | PayOnchainStatus::Closing
+                | PayOnchainStatus::Paying
+                | PayOnchainStatus::Reopening
+        )
+    }
+}
+
+/// A payment to an on-chain address from our lightning balance.
+/// It takes several steps, so it is saved and moved forward in the background.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayOnchainJob {
+    pub id: String,
+    pub address: Address,
+    pub amount_sats: u64,
+    /// The method used and what we expected it to cost
+    pub quote: PayOnchainQuote,
+    pub status: PayOnchainStatus,
+    /// The loop out paying the address, for a swap out
+    pub swap_id: Option<String>,
+    /// The address of ours the channel is closed to, for a close and reopen
+    pub close_address: Option<Address>,
+    /// Our output of the close
+    pub close_outpoint: Option<OutPoint>,
+    /// The transaction that paid the address
+    pub payment_txid: Option<Txid>,
+    /// The signed payment from the close, saved before it is broadcast
+    /// so it can be broadcast again after a restart
+    #[serde(default)]
+    pub(crate) payment_tx: Option<Transaction>,
+    /// What was left after paying the address
+    pub change_outpoint: Option<OutPoint>,
+    /// The channel opened with what was left
+    pub reopened_channel: Option<OutPoint>,
+    pub last_updated: u64,
+}
+
+impl PayOnchainJob {
+    pub fn fail(&mut self, error: String) {
+        self.status = PayOnchainStatus::Failed(error);
+    }
+}
+
+impl PartialOrd for PayOnchainJob {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PayOnchainJob {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.last_updated.cmp(&other.last_updated)
+    }
+}
+
+pub trait PayOnchainStorage {
+    fn get_pay_onchain_job(&self, id: &str) -> Result<Option<PayOnchainJob>, MutinyError>;
+    fn get_pay_onchain_jobs(&self) -> Result<Vec<PayOnchainJob>, MutinyError>;
+    fn persist_pay_onchain_job(&self, job: PayOnchainJob) -> Result<(), MutinyError>;
+}
+
+const PAY_ONCHAIN_KEY_PREFIX: &str = "pay_onchain/";
+
+fn get_pay_onchain_key(id: &str) -> String {
+    format!("{PAY_ONCHAIN_KEY_PREFIX}{id}")
+}
+
+impl<S: MutinyStorage> PayOnchainStorage for S {
+    fn get_pay_onchain_job(&self, id: &str) -> Result<Option<PayOnchainJob>, MutinyError> {
+        self.get_data(get_pay_onchain_key(id))
+    }
+
+    fn get_pay_onchain_jobs(&self) -> Result<Vec<PayOnchainJob>, MutinyError> {
+        let map: HashMap<String, PayOnchainJob> = self.scan(PAY_ONCHAIN_KEY_PREFIX, None)?;
+        Ok(map.into_values().collect())
+    }
+
+    fn persist_pay_onchain_job(&self, job: PayOnchainJob) -> Result<(), MutinyError> {
+        self.set_data(get_pay_onchain_key(&job.id), job, None)
+    }
+}
+
+/// Picks the cheapest of the quotes we could get
+fn best_quote(quotes: Vec<PayOnchainQuote>) -> Result<PayOnchainQuote, MutinyError> {
+    quotes
+        .into_iter()
+        .min_by_key(|q| q.total_fee_sats())
+        .ok_or(MutinyError::InsufficientBalance)
+}
+
+/// Checks a fresh quote for the method the user accepted still costs no more
+/// than the quote they accepted
+fn check_fresh_quote(
+    accepted: &PayOnchainQuote,
+    fresh: PayOnchainQuote,
+) -> Result<PayOnchainQuote, MutinyError> {
+    if fresh.method != accepted.method || fresh.total_fee_sats() > accepted.total_fee_sats() {
+        return Err(MutinyError::PayOnchainQuoteChangedError);
+    }
+
+    Ok(fresh)
+}
+
+pub trait PayOnchainManager {
+    /// Works out the cheapest way to pay `amount_sats` to an on-chain address from our
+    /// lightning balance, and what it would cost.
+    ///
+    /// Fails if no node has a channel or swap provider that can make the payment.
+    async fn quote_pay_onchain_from_lightning(
+        &self,
+        address: &Address,
+        amount_sats: u64,
+    ) -> Result<PayOnchainQuote, MutinyError>;
+
+    /// Pays `amount_sats` to an on-chain address from our lightning balance, with a quote
+    /// from [`PayOnchainManager::quote_pay_onchain_from_lightning`] the user accepted.
+    ///
+    /// The quote's method is quoted again first, and this fails with
+    /// [`MutinyError::PayOnchainQuoteChangedError`] if it now costs more than the user accepted.
+    /// This only starts the payment, the job is moved forward in the background.
+    async fn pay_onchain_from_lightning(
+        &self,
+        address: Address,
+        amount_sats: u64,
+        quote: PayOnchainQuote,
+    ) -> Result<PayOnchainJob, MutinyError>;
+
+    fn get_pay_onchain_job(&self, id: &str) -> Result<Option<PayOnchainJob>, MutinyError>;
+
+    /// Lists all our payments to on-chain addresses from our lightning balance, newest first
+    fn list_pay_onchain_jobs(&self) -> Result<Vec<PayOnchainJob>, MutinyError>;
+
+    /// Runs the next step of a pay on-chain job, e.g. creating its swap out or paying its
+    /// invoice. The job is saved if its state changed and returned either way.
+    async fn process_pay_onchain_job(
+        &self,
+        job: PayOnchainJob,
+    ) -> Result<PayOnchainJob, MutinyError>;
+}
+
+impl<S: MutinyStorage> PayOnchainManager for NodeManager<S> {
+    async fn quote_pay_onchain_from_lightning(
+        &self,
+        address: &Address,
+        amount_sats: u64,
+    ) -> Result<PayOnchainQuote, MutinyError> {
+        if !address.is_valid_for_network(self.network) {
+            return Err(MutinyError::IncorrectNetwork(address.network));
+        }
+
+        let mut quotes = vec![];
+        match self.quote_swap_out(amount_sats, None).await {
+            Ok(Some(quote)) => quotes.push(quote),
+            Ok(None) => {}
+            Err(e) => log_warn!(self.logger, "could not get a swap out quote: {e}"),
+        }
+        if let Some(quote) = self
+            .quote_close_and_reopen(address, amount_sats, None)
+            .await
+        {
+            quotes.push(quote);
+        }
+
+        best_quote(quotes)
+    }
+
+    async fn pay_onchain_from_lightning(
+        &self,
+        address: Address,
+        amount_sats: u64,
+        quote: PayOnchainQuote,
+    ) -> Result<PayOnchainJob, MutinyError> {
+        if !address.is_valid_for_network(self.network) {
+            return Err(MutinyError::IncorrectNetwork(address.network));
+        }
+
+        // fees may have moved since the user saw the quote
+        let fresh = match quote.method {
+            PayOnchainMethod::SwapOut { node } => {
+                self.quote_swap_out(amount_sats, Some(node)).await?
+            }
+            PayOnchainMethod::CloseAndReopen { channel, .. } => {
+                self.quote_close_and_reopen(&address, amount_sats, Some(channel))
+                    .await
+            }
+        }
+        .ok_or(MutinyError::InsufficientBalance)?;
+        let quote = check_fresh_quote(&quote, fresh.clone()).map_err(|e| {
+            log_warn!(
+                self.logger,
+                "pay on-chain fee went from {} to {} sats since the quote",
+                quote.total_fee_sats(),
+                fresh.total_fee_sats()
+            );
+            e
+        })?;
+
+        let mut job = PayOnchainJob {
+            id: Uuid::new_v4().to_string(),
+            address: address.clone(),
+            amount_sats,
+            quote: quote.clone(),
+            status: PayOnchainStatus::Swapping,
+            swap_id: None,
+            close_address: None,
+            close_outpoint: None,
+            payment_txid: None,
+            payment_tx: None,
+            change_outpoint: None,
+            reopened_channel: None,
+            last_updated: utils::now().as_secs(),
+        };
+
+        match quote.method {
+            PayOnchainMethod::SwapOut { node } => {
+                // hold the provider to the fee it quoted
+                let swap = self
+                    .loop_out_to_address(&node, address, amount_sats, Some(quote.provider_fee_sats))
+                    .await?;
+                job.swap_id = Some(swap.id);
+                self.storage.persist_pay_onchain_job(job.clone())?;
+            }
+            PayOnchainMethod::CloseAndReopen { channel, .. } => {
+                let close_address = self.get_new_address(vec![PAY_ONCHAIN_LABEL.to_string()])?;
+                job.close_address = Some(close_address.clone());
+                job.status = PayOnchainStatus::Closing;
+                // save before closing so we still follow the close if we are shut down
+                self.storage.persist_pay_onchain_job(job.clone())?;
+
+                // the close can't cost more than all the on-chain fees we quoted
+                if let Err(e) = self
+                    .close_channel_to_address(
+                        &channel,
+                        Some(close_address),
+                        None,
+                        Some(quote.onchain_fee_sats),
+                    )
+                    .await
+                {
+                    log_error!(self.logger, "failed to close channel for {}: {e}", job.id);
+                    job.fail(e.to_string());
+                    job.last_updated = utils::now().as_secs();
+                    self.storage.persist_pay_onchain_job(job)?;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(job)
+    }
+
+    fn get_pay_onchain_job(&self, id: &str) -> Result<Option<PayOnchainJob>, MutinyError> {
+        self.storage.get_pay_onchain_job(id)
+    }
+
+    fn list_pay_onchain_jobs(&self) -> Result<Vec<PayOnchainJob>, MutinyError> {
+        let mut jobs = self.storage.get_pay_onchain_jobs()?;
+        jobs.sort_by(|a, b| b.cmp(a));
+        Ok(jobs)
+    }
+
+    async fn process_pay_onchain_job(
+        &self,
+        mut job: PayOnchainJob,
+    ) -> Result<PayOnchainJob, MutinyError> {
+        let previous = job.clone();
+
+        advance_pay_onchain(self, &mut job).await?;
+
+        if job != previous {
+            log_info!(
+                self.logger,
+                "pay on-chain {} is now {:?}",
+                job.id,
+                job.status
+            );
+            job.last_updated = utils::now().as_secs();
+            self.storage.persist_pay_onchain_job(job.clone())?;
+        }
+
+        Ok(job)
+    }
+}
+
+impl<S: MutinyStorage> NodeManager<S> {
+    /// Quotes a loop out from the given node, or the node with the most outbound liquidity,
+    /// if we have a swap provider and that node can afford it
+    async fn quote_swap_out(
+        &self,
+        amount_sats: u64,
+        from_node: Option<PublicKey>,
+    ) -> Result<Option<PayOnchainQuote>, MutinyError> {
+        let provider = match self.swap_provider.as_ref() {
+            Some(provider) => provider,
+            None => return Ok(None),
+        };
+
+        let request = LoopOutQuoteRequest {
+            amount_sats: self.swap_htlc_amount(amount_sats),
+        };
+        let provider_fee = provider.quote_loop_out(&request).await?.fee_sats;
+        let needed_msat = (request.amount_sats + provider_fee) * 1_000;
+
+        let nodes = self.nodes.lock().await;
+        let node = nodes
+            .values()
+            .filter(|n| from_node.map_or(true, |node| n.pubkey == node))
+            .map(|n| {
+                let outbound: u64 = n
+                    .channel_manager
+                    .list_usable_channels()
+                    .iter()
+                    .map(|c| c.outbound_capacity_msat)
+                    .sum();
+                (n.pubkey, outbound)
+            })
+            .filter(|(_, outbound)| *outbound >= needed_msat)
+            .max_by_key(|(_, outbound)| *outbound)
+            .map(|(pubkey, _)| pubkey);
+
+        Ok(node.map(|node| PayOnchainQuote {
+            method: PayOnchainMethod::SwapOut { node },
+            provider_fee_sats: provider_fee,
+            onchain_fee_sats: self.estimate_swap_claim_fee(),
+            reopen_amount_sats: 0,
+        }))
+    }
+
+    /// Quotes closing the given channel, or the smallest of our channels that can cover the payment
+    async fn quote_close_and_reopen(
+        &self,
+        address: &Address,
+        amount_sats: u64,
+        only_channel: Option<OutPoint>,
+    ) -> Option<PayOnchainQuote> {
+        let fees = &self.wallet.fees;
+        let sat_per_kw = fees.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+        let close_fee = CLOSING_TX_WEIGHT * sat_per_kw as u64 / 1000;
+        let payment_output_size = address.script_pubkey().len() + 9;
+        let payment_fee = fees.calculate_expected_fee(
+            1,
+            payment_output_size,
+            Some(TAPROOT_OUTPUT_SIZE),
+            Some(sat_per_kw),
+        );
+        let reopen_fee = fees.calculate_expected_fee(1, P2WSH_OUTPUT_SIZE, None, Some(sat_per_kw));
+        let needed = amount_sats + close_fee + payment_fee;
+
+        let nodes = self.nodes.lock().await;
+        let (node, channel, peer, close_value) = nodes
+            .values()
+            .flat_map(|n| {
+                n.channel_manager
+                    .list_usable_channels()
+                    .into_iter()
+                    .filter_map(|c| {
+                        // we get our reserve back when the channel closes
+                        let close_value = c.outbound_capacity_msat / 1_000
+                            + c.unspendable_punishment_reserve.unwrap_or(0);
+                        c.funding_txo.map(|f| {
+                            (
+                                n.pubkey,
+                                f.into_bitcoin_outpoint(),
+                                c.counterparty.node_id,
+                                close_value,
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|(_, channel, _, _)| only_channel.map_or(true, |c| *channel == c))
+            .filter(|(_, _, _, close_value)| *close_value >= needed)
+            .min_by_key(|(_, _, _, close_value)| *close_value)?;
+
+        let left_over = close_value - needed;
+        let (onchain_fee, reopen_amount) = if left_over >= MIN_REOPEN_CHANNEL_SATS + reopen_fee {
+            (close_fee + payment_fee + reopen_fee, left_over - reopen_fee)
+        } else {
+            (close_fee + payment_fee, 0)
+        };
+
+        Some(PayOnchainQuote {
+            method: PayOnchainMethod::CloseAndReopen {
+                node,
+                channel,
+                peer,
+            },
+            provider_fee_sats: 0,
+            onchain_fee_sats: onchain_fee,
+            reopen_amount_sats: reopen_amount,
+        })
+    }
+}
+
+/// What moving a payment to an on-chain address forward needs from
+/// the chain, our wallet, our nodes and our swaps
+#[async_trait(?Send)]
+pub(crate) trait PayOnchainBackend {
+    /// The loop out paying the address, for a swap out
+    fn get_swap_out(&self, id: &str) -> Result<Option<Swap>, MutinyError>;
+
+    /// Our output of a channel close to the given address, once the close is seen
+    async fn find_close_output(&self, address: &Address) -> Result<Option<OutPoint>, MutinyError>;
+
+    /// The value of an unspent output, if it is in our wallet
+    fn wallet_utxo_value(&self, outpoint: &OutPoint) -> Result<Option<u64>, MutinyError>;
+
+    /// If a transaction is in our wallet or has been seen by the chain
+    async fn transaction_seen(&self, txid: Txid) -> Result<bool, MutinyError>;
+
+    /// Creates and signs the payment to the address from our output of the close
+    fn create_payment_tx(
+        &self,
+        job: &PayOnchainJob,
+        close_outpoint: OutPoint,
+    ) -> Result<Transaction, MutinyError>;
+
+    async fn broadcast_transaction(&self, tx: Transaction) -> Result<(), MutinyError>;
+
+    fn persist_pay_onchain_job(&self, job: &PayOnchainJob) -> Result<(), MutinyError>;
+
+    /// Opens a channel to the peer with the given output, returning its funding outpoint
+    async fn reopen_channel(
+        &self,
+        node: &PublicKey,
+        utxo: OutPoint,
+        peer: PublicKey,
+    ) -> Result<Option<OutPoint>, MutinyError>;
+}
+
+#[async_trait(?Send)]
+impl<S: MutinyStorage> PayOnchainBackend for NodeManager<S> {
+    fn get_swap_out(&self, id: &str) -> Result<Option<Swap>, MutinyError> {
+        self.storage.get_swap(id)
+    }
+
+    async fn find_close_output(&self, address: &Address) -> Result<Option<OutPoint>, MutinyError> {
+        let script = address.script_pubkey();
+
+        // this also adds the close to our wallet
+        let close_outpoint = self
+            .check_address(address)
+            .await?
+            .and_then(|details| details.transaction)
+            .and_then(|tx| {
+                tx.output
+                    .iter()
+                    .position(|o| o.script_pubkey == script)
+                    .map(|vout| OutPoint::new(tx.txid(), vout as u32))
+            });
+
+        Ok(close_outpoint)
+    }
+
+    fn wallet_utxo_value(&self, outpoint: &OutPoint) -> Result<Option<u64>, MutinyError> {
+        Ok(self
+            .wallet
+            .list_utxos()?
+            .into_iter()
+            .find(|u| u.outpoint == *outpoint)
+            .map(|u| u.txout.value))
+    }
+
+    async fn transaction_seen(&self, txid: Txid) -> Result<bool, MutinyError> {
+        if self.wallet.get_transaction(txid, false)?.is_some() {
+            return Ok(true);
+        }
+
+        Ok(self.esplora.get_tx(&txid).await?.is_some())
+    }
+
+    fn create_payment_tx(
+        &self,
+        job: &PayOnchainJob,
+        close_outpoint: OutPoint,
+    ) -> Result<Transaction, MutinyError> {
+        let psbt = self
+            .wallet
+            .create_signed_psbt_from_utxos(
+                &[close_outpoint],
+                job.address.script_pubkey(),
+                job.amount_sats,
+                None,
+            )
+            .map_err(|e| {
+                log_error!(self.logger, "could not pay from close for {}: {e}", job.id);
+                e
+            })?;
+        self.wallet
+            .label_psbt(&psbt, vec![PAY_ONCHAIN_LABEL.to_string()])?;
+
+        Ok(psbt.extract_tx())
+    }
+
+    async fn broadcast_transaction(&self, tx: Transaction) -> Result<(), MutinyError> {
+        self.wallet.broadcast_transaction(tx).await
+    }
+
+    fn persist_pay_onchain_job(&self, job: &PayOnchainJob) -> Result<(), MutinyError> {
+        self.storage.persist_pay_onchain_job(job.clone())
+    }
+
+    async fn reopen_channel(
+        &self,
+        node: &PublicKey,
+        utxo: OutPoint,
+        peer: PublicKey,
+    ) -> Result<Option<OutPoint>, MutinyError> {
+        let channel = self
+            .sweep_utxos_to_channel(None, node, &[utxo], Some(peer))
+            .await
+            .map_err(|e| {
+                log_warn!(self.logger, "could not reopen channel with {peer}: {e}");
+                e
+            })?;
+        Ok(channel.outpoint)
+    }
+}
+
+/// Moves a job as far forward as it can go right now
+pub(crate) async fn advance_pay_onchain(
+    backend: &impl PayOnchainBackend,
+    job: &mut PayOnchainJob,
+) -> Result<(), MutinyError> {
+    match job.quote.method {
+        PayOnchainMethod::SwapOut { .. } => advance_swap_out(backend, job),
+        PayOnchainMethod::CloseAndReopen { node, peer, .. } => {
+            advance_close_and_reopen(backend, job, &node, peer).await
+        }
+    }
+}
+
+fn advance_swap_out(
+    backend: &impl PayOnchainBackend,
+    job: &mut PayOnchainJob,
+) -> Result<(), MutinyError> {
+    let id = job
+        .swap_id
+        .as_deref()
+        .ok_or_else(|| MutinyError::Other(anyhow!("Swap out is missing its swap")))?;
+
+    match backend.get_swap_out(id)? {
+        Some(swap) => match swap.status {
+            SwapStatus::Claimed | SwapStatus::Completed => {
+                // when fees rose too far the HTLC is claimed to our wallet instead
+                let underpaid = swap
+                    .destination
+                    .and_then(|d| d.paid_sats)
+                    .is_some_and(|paid| paid < job.amount_sats);
+                if underpaid {
+                    job.fail(
+                        "The swap out could not pay the address, the funds are in our on-chain wallet"
+                            .to_string(),
+                    );
+                } else if swap.status == SwapStatus::Completed {
+                    // a swap is only completed once its claim confirmed
+                    job.payment_txid = swap.spend_txid;
+                    job.status = PayOnchainStatus::Completed;
+                }
+            }
+            SwapStatus::Failed(e) => job.fail(e),
+            _ => {}
+        },
+        None => job.fail("The swap out was not found".to_string()),
+    }
+
+    Ok(())
+}
+
+async fn advance_close_and_reopen(
+    backend: &impl PayOnchainBackend,
+    job: &mut PayOnchainJob,
+    node: &PublicKey,
+    peer: PublicKey,
+) -> Result<(), MutinyError> {
+    if job.status == PayOnchainStatus::Closing {
+        let close_address = job
+            .close_address
+            .as_ref()
+            .ok_or_else(|| MutinyError::Other(anyhow!("Close is missing its address")))?;
+
+        if let Some(outpoint) = backend.find_close_output(close_address).await? {
+            job.close_outpoint = Some(outpoint);
+            job.status = PayOnchainStatus::Paying;
+        } else if utils::now().as_secs() > job.last_updated + CLOSE_TIMEOUT_SECS {
+            job.fail("The channel close never reached our wallet".to_string());
+        }
+    }
+
+    if job.status == PayOnchainStatus::Paying {
+        advance_payment(backend, job).await?;
+    }
+
+    if job.status == PayOnchainStatus::Reopening {
+        reopen_from_change(backend, job, node, peer).await;
+    }
+
+    Ok(())
+}
+
+/// Pays the address from our output of the close. The payment is saved before it is
+/// broadcast, so after a restart we look for it before making another.
+async fn advance_payment(
+    backend: &impl PayOnchainBackend,
+    job: &mut PayOnchainJob,
+) -> Result<(), MutinyError> {
+    let timed_out = utils::now().as_secs() > job.last_updated + PAY_TIMEOUT_SECS;
+
+    let tx = match job.payment_tx.clone() {
+        Some(tx) => {
+            if backend.transaction_seen(tx.txid()).await? {
+                job.status = payment_made_status(job);
+                return Ok(());
+            }
+            if timed_out {
+                job.fail(
+                    "The payment was never broadcast, the funds are in our on-chain wallet"
+                        .to_string(),
+                );
+                return Ok(());
+            }
+            tx
+        }
+        None => {
+            let close_outpoint = job
+                .close_outpoint
+                .ok_or_else(|| MutinyError::Other(anyhow!("Close is missing its output")))?;
+            // wait for the wallet to have the close before spending it
+            if backend.wallet_utxo_value(&close_outpoint)?.is_none() {
+                if timed_out {
+                    job.fail("The channel close never reached our wallet".to_string());
+                }
+                return Ok(());
+            }
+
+            let tx = match backend.create_payment_tx(job, close_outpoint) {
+                Ok(tx) => tx,
+                Err(MutinyError::InsufficientBalance) => {
+                    job.fail("The channel close could not cover the payment".to_string());
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+
+            let txid = tx.txid();
+            let script = job.address.script_pubkey();
+            job.payment_txid = Some(txid);
+            job.change_outpoint = tx
+                .output
+                .iter()
+                .position(|o| o.script_pubkey != script)
+                .map(|vout| OutPoint::new(txid, vout as u32));
+            job.payment_tx = Some(tx.clone());
+            backend.persist_pay_onchain_job(job)?;
+            tx
+        }
+    };
+
+    // a failed broadcast is retried next time until the timeout
+    backend.broadcast_transaction(tx).await?;
+    job.status = payment_made_status(job);
+
+    Ok(())
+}
+
+/// Where a job goes once the address is paid
+fn payment_made_status(job: &PayOnchainJob) -> PayOnchainStatus {
+    if job.change_outpoint.is_some() {
+        PayOnchainStatus::Reopening
+    } else {
+        PayOnchainStatus::Completed
+    }
+}
+
+/// Opens a channel with what was left after paying the address, when it is worth it.
+/// The peer may be offline for a while, so this is retried until the timeout.
+async fn reopen_from_change(
+    backend: &impl PayOnchainBackend,
+    job: &mut PayOnchainJob,
+    node: &PublicKey,
+    peer: PublicKey,
+) {
+    let change = match job.change_outpoint {
+        Some(change) => change,
+        None => {
+            job.status = PayOnchainStatus::Completed;
+            return;
+        }
+    };
+    let value = backend.wallet_utxo_value(&change).unwrap_or_default();
+
+    match value {
+        Some(value) if value >= MIN_REOPEN_CHANNEL_SATS => {
+            match backend.reopen_channel(node, change, peer).await {
+                Ok(channel) => {
+                    job.reopened_channel = channel;
+                    job.status = PayOnchainStatus::Completed;
+                }
+                Err(e) if utils::now().as_secs() > job.last_updated + REOPEN_TIMEOUT_SECS => {
+                    job.fail(format!(
+                        "Could not reopen the channel, the funds are on-chain: {e}"
+                    ));
+                }
+                // the peer may come back online
+                Err(_) => {}
+            }
+        }
+        // too small for a channel, it stays on-chain
+        Some(_) => job.status = PayOnchainStatus::Completed,
+        // not in the wallet yet
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::swaps::{SwapDestination, SwapType};
+    use crate::test_utils::*;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::{Network, PackedLockTime, Script, Sequence, TxIn, TxOut, Witness};
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn swap_out_quote(provider_fee_sats: u64) -> PayOnchainQuote {
+        PayOnchainQuote {
+            method: PayOnchainMethod::SwapOut { node: our_pubkey() },
+            provider_fee_sats,
+            onchain_fee_sats: 500,
+            reopen_amount_sats: 0,
+        }
+    }
+
+    fn close_and_reopen_quote() -> PayOnchainQuote {
+        PayOnchainQuote {
+            method: PayOnchainMethod::CloseAndReopen {
+                node: our_pubkey(),
+                channel: OutPoint::new(Txid::all_zeros(), 0),
+                peer: our_pubkey(),
+            },
+            provider_fee_sats: 0,
+            onchain_fee_sats: 1_500,
+            reopen_amount_sats: 200_000,
+        }
+    }
+
+    /// Our chain, wallet, nodes and swaps as a job sees them
+    #[derive(Default)]
+    struct MockPayOnchainBackend {
+        swap: Option<Swap>,
+        close: Option<OutPoint>,
+        utxos: HashMap<OutPoint, u64>,
+        seen: HashSet<Txid>,
+        broadcast_fails: bool,
+        broadcasts: RefCell<Vec<Txid>>,
+        persisted: RefCell<Vec<PayOnchainJob>>,
+        reopened: RefCell<Vec<OutPoint>>,
+    }
+
+    #[async_trait(?Send)]
+    impl PayOnchainBackend for MockPayOnchainBackend {
+        fn get_swap_out(&self, _id: &str) -> Result<Option<Swap>, MutinyError> {
+            Ok(self.swap.clone())
+        }
+
+        async fn find_close_output(
+            &self,
+            _address: &Address,
+        ) -> Result<Option<OutPoint>, MutinyError> {
+            Ok(self.close)
+        }
+
+        fn wallet_utxo_value(&self, outpoint: &OutPoint) -> Result<Option<u64>, MutinyError> {
+            Ok(self.utxos.get(outpoint).copied())
+        }
+
+        async fn transaction_seen(&self, txid: Txid) -> Result<bool, MutinyError> {
+            Ok(self.seen.contains(&txid))
+        }
+
+        fn create_payment_tx(
+            &self,
+            job: &PayOnchainJob,
+            close_outpoint: OutPoint,
+        ) -> Result<Transaction, MutinyError> {
+            let value = self.utxos[&close_outpoint];
+            let fee = 200;
+            if value < job.amount_sats + fee {
+                return Err(MutinyError::InsufficientBalance);
+            }
+
+            Ok(Transaction {
+                version: 2,
+                lock_time: PackedLockTime(0),
+                input: vec![TxIn {
+                    previous_output: close_outpoint,
+                    script_sig: Script::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                }],
+                output: vec![
+                    TxOut {
+                        value: job.amount_sats,
+                        script_pubkey: job.address.script_pubkey(),
+                    },
+                    TxOut {
+                        value: value - job.amount_sats - fee,
+                        script_pubkey: change_address().script_pubkey(),
+                    },
+                ],
+            })
+        }
+
+        async fn broadcast_transaction(&self, tx: Transaction) -> Result<(), MutinyError> {
+            if self.broadcast_fails {
+                return Err(MutinyError::Other(anyhow!("Failed to broadcast")));
+            }
+            self.broadcasts.borrow_mut().push(tx.txid());
+            Ok(())
+        }
+
+        fn persist_pay_onchain_job(&self, job: &PayOnchainJob) -> Result<(), MutinyError> {
+            self.persisted.borrow_mut().push(job.clone());
+            Ok(())
+        }
+
+        async fn reopen_channel(
+            &self,
+            _node: &PublicKey,
+            utxo: OutPoint,
+            _peer: PublicKey,
+        ) -> Result<Option<OutPoint>, MutinyError> {
+            self.reopened.borrow_mut().push(utxo);
+            Ok(Some(OutPoint::new(Txid::all_zeros(), 7)))
+        }
+    }
+
+    fn address() -> Address {
+        Address::from_str("bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c").unwrap()
+    }
+
+    fn change_address() -> Address {
+        Address::p2wsh(&Script::new(), Network::Bitcoin)
+    }
+
+    fn pay_onchain_job(quote: PayOnchainQuote, status: PayOnchainStatus) -> PayOnchainJob {
+        PayOnchainJob {
+            id: "job".to_string(),
+            address: address(),
+            amount_sats: 50_000,
+            quote,
+            status,
+            swap_id: Some("loop-out".to_string()),
+            close_address: Some(change_address()),
+            close_outpoint: None,
+            payment_txid: None,
+            payment_tx: None,
+            change_outpoint: None,
+            reopened_channel: None,
+            last_updated: utils::now().as_secs(),
+        }
+    }
+
+    /// A completed loop out that paid the address `paid_sats`
+    fn completed_swap_out(paid_sats: u64) -> Swap {
+        Swap {
+            id: "loop-out".to_string(),
+            status: SwapStatus::Completed,
+            payment_hash: sha256::Hash::hash(&[1; 32]),
+            amount_sats: 51_000,
+            address: change_address(),
+            onchain_amount_sats: 50_500,
+            lockup_outpoint: Some(OutPoint::new(Txid::all_zeros(), 1)),
+            spend_txid: Some(Txid::all_zeros()),
+            fees_paid: Some(1_000),
+            destination: Some(SwapDestination {
+                address: address(),
+                amount_sats: 50_000,
+                paid_sats: Some(paid_sats),
+            }),
+            preimage: Some([1; 32]),
+            ..swap_fixture(SwapType::LoopOut)
+        }
+    }
+
+    #[test]
+    fn test_check_fresh_quote() {
+        let test_name = "test_check_fresh_quote";
+        log!("{}", test_name);
+
+        let accepted = swap_out_quote(500);
+        assert_eq!(
+            check_fresh_quote(&accepted, accepted.clone()).unwrap(),
+            accepted
+        );
+
+        // cheaper than the user accepted is fine
+        let cheaper = swap_out_quote(400);
+        assert_eq!(
+            check_fresh_quote(&accepted, cheaper.clone()).unwrap(),
+            cheaper
+        );
+
+        assert!(matches!(
+            check_fresh_quote(&accepted, swap_out_quote(501)),
+            Err(MutinyError::PayOnchainQuoteChangedError)
+        ));
+        assert!(matches!(
+            check_fresh_quote(&accepted, close_and_reopen_quote()),
+            Err(MutinyError::PayOnchainQuoteChangedError)
+        ));
+    }
+
+    #[test]
+    async fn test_advance_swap_out() {
+        let test_name = "test_advance_swap_out";
+        log!("{}", test_name);
+
+        let job = pay_onchain_job(swap_out_quote(500), PayOnchainStatus::Swapping);
+
+        // waiting on the swap
+        let mut swap = completed_swap_out(50_000);
+        swap.status = SwapStatus::Funded;
+        let backend = MockPayOnchainBackend {
+            swap: Some(swap),
+            ..Default::default()
+        };
+        let mut waiting = job.clone();
+        advance_pay_onchain(&backend, &mut waiting).await.unwrap();
+        assert_eq!(waiting, job);
+
+        // claimed to the address, waiting on the claim to confirm
+        let mut swap = completed_swap_out(50_000);
+        swap.status = SwapStatus::Claimed;
+        let backend = MockPayOnchainBackend {
+            swap: Some(swap),
+            ..Default::default()
+        };
+        let mut claimed = job.clone();
+        advance_pay_onchain(&backend, &mut claimed).await.unwrap();
+        assert_eq!(claimed, job);
+
+        // the address was paid in full
+        let backend = MockPayOnchainBackend {
+            swap: Some(completed_swap_out(50_000)),
+            ..Default::default()
+        };
+        let mut paid = job.clone();
+        advance_pay_onchain(&backend, &mut paid).await.unwrap();
+        assert_eq!(paid.status, PayOnchainStatus::Completed);
+        assert_eq!(paid.payment_txid, Some(Txid::all_zeros()));
+
+        // fees rose too far, the HTLC was claimed to our wallet instead
+        let backend = MockPayOnchainBackend {
+            swap: Some(completed_swap_out(0)),
+            ..Default::default()
+        };
+        let mut underpaid = job.clone();
+        advance_pay_onchain(&backend, &mut underpaid).await.unwrap();
+        assert!(matches!(underpaid.status, PayOnchainStatus::Failed(_)));
+        assert_eq!(underpaid.payment_txid, None);
+
+        let mut swap = completed_swap_out(0);
+        swap.fail("The lightning payment failed".to_string());
+        let backend = MockPayOnchainBackend {
+            swap: Some(swap),
+            ..Default::default()
+        };
+        let mut failed = job.clone();
+        advance_pay_onchain(&backend, &mut failed).await.unwrap();
+        assert_eq!(
+            failed.status,
+            PayOnchainStatus::Failed("The lightning payment failed".to_string())
+        );
+
+        let mut missing = job;
+        advance_pay_onchain(&MockPayOnchainBackend::default(), &mut missing)
+            .await
+            .unwrap();
+        assert!(matches!(missing.status, PayOnchainStatus::Failed(_)));
+    }
+
+    #[test]
+    async fn test_advance_close_and_reopen() {
+        let test_name = "test_advance_close_and_reopen";
+        log!("{}", test_name);
+
+        let close = OutPoint::new(Txid::all_zeros(), 2);
+        let mut job = pay_onchain_job(close_and_reopen_quote(), PayOnchainStatus::Closing);
+
+        // waiting on the close
+        advance_pay_onchain(&MockPayOnchainBackend::default(), &mut job)
+            .await
+            .unwrap();
+        assert_eq!(job.status, PayOnchainStatus::Closing);
+
+        // the close is seen, but not in our wallet yet
+        let mut backend = MockPayOnchainBackend {
+            close: Some(close),
+            ..Default::default()
+        };
+        advance_pay_onchain(&backend, &mut job).await.unwrap();
+        assert_eq!(job.status, PayOnchainStatus::Paying);
+        assert_eq!(job.close_outpoint, Some(close));
+        assert!(job.payment_tx.is_none());
+
+        // the payment is saved before it is broadcast, so a failed broadcast keeps it
+        backend.utxos.insert(close, 200_000);
+        backend.broadcast_fails = true;
+        assert!(advance_pay_onchain(&backend, &mut job).await.is_err());
+        let persisted = backend.persisted.borrow().last().cloned().unwrap();
+        let txid = persisted.payment_txid.unwrap();
+        assert_eq!(persisted.status, PayOnchainStatus::Paying);
+        assert_eq!(
+            persisted.payment_tx.as_ref().map(|tx| tx.txid()),
+            Some(txid)
+        );
+        assert_eq!(persisted.change_outpoint, Some(OutPoint::new(txid, 1)));
+        assert!(backend.broadcasts.borrow().is_empty());
+
+        // after a restart the same payment is broadcast again, not a new one
+        let mut resumed = persisted.clone();
+        backend.broadcast_fails = false;
+        advance_pay_onchain(&backend, &mut resumed).await.unwrap();
+        assert_eq!(resumed.payment_txid, Some(txid));
+        assert_eq!(backend.broadcasts.borrow().as_slice(), &[txid]);
+        // and what is left goes back into a channel
+        assert!(backend.reopened.borrow().is_empty());
+        assert_eq!(resumed.status, PayOnchainStatus::Reopening);
+
+        // if it was broadcast before the restart we only move on
+        let mut resumed = persisted.clone();
+        let backend = MockPayOnchainBackend {
+            seen: HashSet::from([txid]),
+            utxos: HashMap::from([(OutPoint::new(txid, 1), 149_800)]),
+            ..Default::default()
+        };
+        advance_pay_onchain(&backend, &mut resumed).await.unwrap();
+        assert!(backend.broadcasts.borrow().is_empty());
+        assert_eq!(resumed.status, PayOnchainStatus::Completed);
+        assert_eq!(
+            backend.reopened.borrow().as_slice(),
+            &[OutPoint::new(txid, 1)]
+        );
+        assert_eq!(
+            resumed.reopened_channel,
+            Some(OutPoint::new(Txid::all_zeros(), 7))
+        );
+
+        // a payment that never makes it out is given up on
+        let mut stuck = persisted;
+        stuck.last_updated -= PAY_TIMEOUT_SECS + 1;
+        let backend = MockPayOnchainBackend::default();
+        advance_pay_onchain(&backend, &mut stuck).await.unwrap();
+        assert!(matches!(stuck.status, PayOnchainStatus::Failed(_)));
+        assert!(backend.broadcasts.borrow().is_empty());
+
+        // a close too small to pay the address
+        let mut job = pay_onchain_job(close_and_reopen_quote(), PayOnchainStatus::Paying);
+        job.close_outpoint = Some(close);
+        let backend = MockPayOnchainBackend {
+            utxos: HashMap::from([(close, 50_000)]),
+            ..Default::default()
+        };
+        advance_pay_onchain(&backend, &mut job).await.unwrap();
+        assert_eq!(
+            job.status,
+            PayOnchainStatus::Failed("The channel close could not cover the payment".to_string())
+        );
+        assert!(backend.persisted.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_best_quote() {
+        let test_name = "test_best_quote";
+        log!("{}", test_name);
+
+        assert!(matches!(
+            best_quote(vec![]),
+            Err(MutinyError::InsufficientBalance)
+        ));
+
+        let close = close_and_reopen_quote();
+        assert_eq!(close.total_fee_sats(), 1_500);
+        assert_eq!(best_quote(vec![close.clone()]).unwrap(), close);
+
+        // a cheap swap out beats closing a channel
+        let cheap_swap = swap_out_quote(500);
+        assert_eq!(
+            best_quote(vec![close.clone(), cheap_swap.clone()]).unwrap(),
+            cheap_swap
+        );
+
+        let expensive_swap = swap_out_quote(5_000);
+        assert_eq!(
+            best_quote(vec![expensive_swap, close.clone()]).unwrap(),
+            close
+        );
+    }
+
+    #[test]
+    fn test_persist_pay_onchain_job() {
+        let test_name = "test_persist_pay_onchain_job";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let address = Address::from_str("bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c").unwrap();
+
+        let mut job = PayOnchainJob {
+            id: "job".to_string(),
+            address,
+            amount_sats: 50_000,
+            quote: close_and_reopen_quote(),
+            status: PayOnchainStatus::Closing,
+            swap_id: None,
+            close_address: None,
+            close_outpoint: None,
+            payment_txid: None,
+            payment_tx: None,
+            change_outpoint: None,
+            reopened_channel: None,
+            last_updated: 1686258926,
+        };
+        assert!(job.status.is_in_progress());
+        assert_eq!(storage.get_pay_onchain_job(&job.id).unwrap(), None);
+
+        storage.persist_pay_onchain_job(job.clone()).unwrap();
+        assert_eq!(
+            storage.get_pay_onchain_job(&job.id).unwrap(),
+            Some(job.clone())
+        );
+
+        job.fail("The channel close never reached our wallet".to_string());
+        storage.persist_pay_onchain_job(job.clone()).unwrap();
+        assert!(!job.status.is_in_progress());
+        assert_eq!(storage.get_pay_onchain_jobs().unwrap(), vec![job]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::node::NetworkGraph;
+use crate::node::{NetworkGraph, P2PGossipSync};
 use crate::storage::MutinyStorage;
 use crate::{error::MutinyError, fees::MutinyFeeEstimator};
 use crate::{gossip, ldkstorage::PhantomChannelManager, logging::MutinyLogger};
@@ -11,9 +11,8 @@ use lightning::{
 };
 use std::{net::SocketAddr, sync::atomic::AtomicBool};
 
+use crate::messagehandler::MutinyMessageHandler;
 use crate::networking::socket::{schedule_descriptor_read, MutinySocketDescriptor};
-use crate::scb::message_handler::SCBMessageHandler;
-use bitcoin::BlockHash;
 use lightning::events::{MessageSendEvent, MessageSendEventsProvider};
 use lightning::ln::features::{InitFeatures, NodeFeatures};
 use lightning::ln::msgs;
@@ -22,7 +21,6 @@ use lightning::ln::peer_handler::PeerHandleError;
 use lightning::ln::peer_handler::{IgnoringMessageHandler, PeerManager as LdkPeerManager};
 use lightning::log_warn;
 use lightning::routing::gossip::NodeId;
-use lightning::routing::utxo::{UtxoLookup, UtxoLookupError, UtxoResult};
 use lightning::util::logger::Logger;
 use std::sync::Arc;
 
@@ -44,6 +42,9 @@ use tokio::net::TcpStream;
 #[cfg(not(target_arch = "wasm32"))]
 use crate::networking::tcp_socket::TcpSocketDescriptor;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::socks5::{is_onion_host, split_host_port, Socks5Proxy};
+
 pub trait PeerManager {
     fn get_peer_node_ids(&self) -> Vec<PublicKey>;
 
@@ -95,7 +96,7 @@ pub(crate) type PeerManagerImpl<S: MutinyStorage> = LdkPeerManager<
     Arc<GossipMessageHandler<S>>,
     Arc<IgnoringMessageHandler>,
     Arc<MutinyLogger>,
-    Arc<SCBMessageHandler>,
+    Arc<MutinyMessageHandler>,
     Arc<PhantomKeysManager<S>>,
 >;
 
@@ -170,22 +171,19 @@ impl<S: MutinyStorage> PeerManager for PeerManagerImpl<S> {
 pub struct GossipMessageHandler<S: MutinyStorage> {
     pub(crate) storage: S,
     pub(crate) network_graph: Arc<NetworkGraph>,
+    /// If set, gossip is synced with our peers through this instead of only from RGS
+    pub(crate) p2p_gossip_sync: Option<Arc<P2PGossipSync>>,
+    /// If set, only these peers can connect to us
+    pub(crate) allowed_inbound_peers: Option<Vec<PublicKey>>,
     pub(crate) logger: Arc<MutinyLogger>,
 }
 
 impl<S: MutinyStorage> MessageSendEventsProvider for GossipMessageHandler<S> {
     fn get_and_clear_pending_msg_events(&self) -> Vec<MessageSendEvent> {
-        Vec::new()
-    }
-}
-
-// I needed some type to implement RoutingMessageHandler, but I don't want to implement it
-// we don't need to lookup UTXOs, so we can just return an error
-// This should never actually be called because we are passing in None for the UTXO lookup
-struct ErroringUtxoLookup {}
-impl UtxoLookup for ErroringUtxoLookup {
-    fn get_utxo(&self, _genesis_hash: &BlockHash, _short_channel_id: u64) -> UtxoResult {
-        UtxoResult::Sync(Err(UtxoLookupError::UnknownTx))
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.get_and_clear_pending_msg_events(),
+            None => Vec::new(),
+        }
     }
 }
 
@@ -211,6 +209,10 @@ impl<S: MutinyStorage> RoutingMessageHandler for GossipMessageHandler<S> {
             }
         }
 
+        if let Some(p2p) = self.p2p_gossip_sync.as_ref() {
+            return p2p.handle_node_announcement(msg);
+        }
+
         // because we got the announcement, may as well update our network graph
         self.network_graph
             .update_node_from_unsigned_announcement(&msg.contents)?;
@@ -222,6 +224,11 @@ impl<S: MutinyStorage> RoutingMessageHandler for GossipMessageHandler<S> {
         &self,
         msg: &msgs::ChannelAnnouncement,
     ) -> Result<bool, LightningError> {
+        // with P2P gossip the channel's funding output is checked before we add it
+        if let Some(p2p) = self.p2p_gossip_sync.as_ref() {
+            return p2p.handle_channel_announcement(msg);
+        }
+
         // because we got the channel, may as well update our network graph
         self.network_graph
             .update_channel_from_announcement_no_lookup(msg)?;
@@ -229,6 +236,10 @@ impl<S: MutinyStorage> RoutingMessageHandler for GossipMessageHandler<S> {
     }
 
     fn handle_channel_update(&self, msg: &msgs::ChannelUpdate) -> Result<bool, LightningError> {
+        if let Some(p2p) = self.p2p_gossip_sync.as_ref() {
+            return p2p.handle_channel_update(msg);
+        }
+
         // because we got the update, may as well update our network graph
         self.network_graph.update_channel_unsigned(&msg.contents)?;
         Ok(false)
@@ -236,78 +247,121 @@ impl<S: MutinyStorage> RoutingMessageHandler for GossipMessageHandler<S> {
 
     fn get_next_channel_announcement(
         &self,
-        _starting_point: u64,
+        starting_point: u64,
     ) -> Option<(
         msgs::ChannelAnnouncement,
         Option<msgs::ChannelUpdate>,
         Option<msgs::ChannelUpdate>,
     )> {
-        None
+        self.p2p_gossip_sync
+            .as_ref()
+            .and_then(|p2p| p2p.get_next_channel_announcement(starting_point))
     }
 
     fn get_next_node_announcement(
         &self,
-        _starting_point: Option<&NodeId>,
+        starting_point: Option<&NodeId>,
     ) -> Option<msgs::NodeAnnouncement> {
-        None
+        self.p2p_gossip_sync
+            .as_ref()
+            .and_then(|p2p| p2p.get_next_node_announcement(starting_point))
     }
 
     fn peer_connected(
         &self,
-        _their_node_id: &PublicKey,
-        _init: &msgs::Init,
-        _inbound: bool,
+        their_node_id: &PublicKey,
+        init: &msgs::Init,
+        inbound: bool,
     ) -> Result<(), ()> {
-        Ok(())
+        // returning an error makes the peer manager disconnect them
+        if inbound
+            && self
+                .allowed_inbound_peers
+                .as_ref()
+                .is_some_and(|allowed| !allowed.contains(their_node_id))
+        {
+            log_warn!(
+                self.logger,
+                "refusing connection from peer not in allowlist: {their_node_id}"
+            );
+            return Err(());
+        }
+
+        // this is where P2P gossip asks the peer to start syncing with us
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.peer_connected(their_node_id, init, inbound),
+            None => Ok(()),
+        }
     }
 
     fn handle_reply_channel_range(
         &self,
-        _their_node_id: &PublicKey,
-        _msg: msgs::ReplyChannelRange,
+        their_node_id: &PublicKey,
+        msg: msgs::ReplyChannelRange,
     ) -> Result<(), LightningError> {
-        Ok(())
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.handle_reply_channel_range(their_node_id, msg),
+            None => Ok(()),
+        }
     }
 
     fn handle_reply_short_channel_ids_end(
         &self,
-        _their_node_id: &PublicKey,
-        _msg: msgs::ReplyShortChannelIdsEnd,
+        their_node_id: &PublicKey,
+        msg: msgs::ReplyShortChannelIdsEnd,
     ) -> Result<(), LightningError> {
-        Ok(())
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.handle_reply_short_channel_ids_end(their_node_id, msg),
+            None => Ok(()),
+        }
     }
 
     fn handle_query_channel_range(
         &self,
-        _their_node_id: &PublicKey,
-        _msg: msgs::QueryChannelRange,
+        their_node_id: &PublicKey,
+        msg: msgs::QueryChannelRange,
     ) -> Result<(), LightningError> {
-        Ok(())
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.handle_query_channel_range(their_node_id, msg),
+            None => Ok(()),
+        }
     }
 
     fn handle_query_short_channel_ids(
         &self,
-        _their_node_id: &PublicKey,
-        _msg: msgs::QueryShortChannelIds,
+        their_node_id: &PublicKey,
+        msg: msgs::QueryShortChannelIds,
     ) -> Result<(), LightningError> {
-        Ok(())
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.handle_query_short_channel_ids(their_node_id, msg),
+            None => Ok(()),
+        }
     }
 
     fn processing_queue_high(&self) -> bool {
-        false
+        self.p2p_gossip_sync
+            .as_ref()
+            .is_some_and(|p2p| p2p.processing_queue_high())
     }
 
     fn provided_node_features(&self) -> NodeFeatures {
-        NodeFeatures::empty()
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.provided_node_features(),
+            None => NodeFeatures::empty(),
+        }
     }
 
-    fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures {
-        InitFeatures::empty()
+    fn provided_init_features(&self, their_node_id: &PublicKey) -> InitFeatures {
+        match self.p2p_gossip_sync.as_ref() {
+            Some(p2p) => p2p.provided_init_features(their_node_id),
+            None => InitFeatures::empty(),
+        }
     }
 }
 
 pub(crate) async fn connect_peer_if_necessary<S: MutinyStorage>(
     #[cfg(target_arch = "wasm32")] websocket_proxy_addr: &str,
+    #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<&Socks5Proxy>,
     peer_connection_info: &PubkeyConnectionInfo,
     logger: Arc<MutinyLogger>,
     peer_manager: Arc<dyn PeerManager>,
@@ -328,6 +382,8 @@ pub(crate) async fn connect_peer_if_necessary<S: MutinyStorage>(
         connect_peer(
             #[cfg(target_arch = "wasm32")]
             websocket_proxy_addr,
+            #[cfg(not(target_arch = "wasm32"))]
+            socks5_proxy,
             peer_connection_info,
             logger,
             peer_manager,
@@ -339,6 +395,7 @@ pub(crate) async fn connect_peer_if_necessary<S: MutinyStorage>(
 
 async fn connect_peer(
     #[cfg(target_arch = "wasm32")] websocket_proxy_addr: &str,
+    #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<&Socks5Proxy>,
     peer_connection_info: &PubkeyConnectionInfo,
     logger: Arc<MutinyLogger>,
     peer_manager: Arc<dyn PeerManager>,
@@ -363,13 +420,29 @@ async fn connect_peer(
             #[cfg(not(target_arch = "wasm32"))]
             {
                 let (socket_addr, net_addr) = try_parse_addr_string(t);
-                let socket_addr = socket_addr.ok_or(MutinyError::ConnectionFailed)?;
 
-                let stream =
-                    time::timeout(Duration::from_secs(10), TcpStream::connect(&socket_addr))
-                        .await
-                        .map_err(|_| MutinyError::ConnectionFailed)?
-                        .map_err(|_| MutinyError::ConnectionFailed)?;
+                let stream = match socks5_proxy {
+                    Some(proxy) => {
+                        let (host, port) =
+                            split_host_port(t).ok_or(MutinyError::PeerInfoParseFailed)?;
+                        // connections over tor can take a while
+                        time::timeout(Duration::from_secs(30), proxy.connect(&host, port))
+                            .await
+                            .map_err(|_| MutinyError::ConnectionFailed)??
+                    }
+                    None => {
+                        if split_host_port(t).is_some_and(|(host, _)| is_onion_host(&host)) {
+                            log_warn!(logger, "a socks5 proxy is needed to connect to {t}");
+                            return Err(MutinyError::ConnectionFailed);
+                        }
+                        let socket_addr = socket_addr.ok_or(MutinyError::ConnectionFailed)?;
+
+                        time::timeout(Duration::from_secs(10), TcpStream::connect(&socket_addr))
+                            .await
+                            .map_err(|_| MutinyError::ConnectionFailed)?
+                            .map_err(|_| MutinyError::ConnectionFailed)?
+                    }
+                };
 
                 let stream = stream.into_std().unwrap();
                 (
@@ -380,6 +453,29 @@ async fn connect_peer(
                 )
             }
         }
+        ConnectionType::Websocket(ref _url) => {
+            #[cfg(target_arch = "wasm32")]
+            {
+                let ws = WsProxy::new(
+                    websocket_proxy_addr,
+                    peer_connection_info.clone(),
+                    logger.clone(),
+                )
+                .await?;
+                (
+                    MutinySocketDescriptor::Tcp(WsTcpSocketDescriptor::new(Arc::new(ws))),
+                    None,
+                )
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                log_warn!(
+                    logger,
+                    "websocket connections are only supported in the browser: {_url}"
+                );
+                return Err(MutinyError::ConnectionFailed);
+            }
+        }
     };
 
     // then give that connection to the peer manager
@@ -423,3 +519,56 @@ fn try_parse_addr_string(addr: &str) -> (Option<SocketAddr>, Option<NetAddress>)
     });
     (socket_addr, net_addr)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::Network;
+    use std::str::FromStr;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const ALLOWED: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const OTHER: &str = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+
+    fn gossip_handler(
+        allowed_inbound_peers: Option<Vec<PublicKey>>,
+    ) -> GossipMessageHandler<MemoryStorage> {
+        let logger = Arc::new(MutinyLogger::default());
+        GossipMessageHandler {
+            storage: MemoryStorage::default(),
+            network_graph: Arc::new(NetworkGraph::new(Network::Regtest, logger.clone())),
+            p2p_gossip_sync: None,
+            allowed_inbound_peers,
+            logger,
+        }
+    }
+
+    #[test]
+    fn test_inbound_peer_allowlist() {
+        let test_name = "test_inbound_peer_allowlist";
+        log!("{}", test_name);
+
+        let allowed = PublicKey::from_str(ALLOWED).unwrap();
+        let other = PublicKey::from_str(OTHER).unwrap();
+        let init = msgs::Init {
+            features: InitFeatures::empty(),
+            networks: None,
+            remote_network_address: None,
+        };
+
+        let handler = gossip_handler(Some(vec![allowed]));
+        assert!(handler.peer_connected(&allowed, &init, true).is_ok());
+        assert!(handler.peer_connected(&other, &init, true).is_err());
+        // the allowlist is only for inbound connections, we can still connect to anyone
+        assert!(handler.peer_connected(&other, &init, false).is_ok());
+
+        // without an allowlist anyone can connect to us
+        let handler = gossip_handler(None);
+        assert!(handler.peer_connected(&other, &init, true).is_ok());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::error::MutinyError;
+use crate::event::{HTLCStatus, PaymentInfo};
+use crate::storage::MutinyStorage;
+use crate::utils;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::sha256;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::OutPoint;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Label given to the payments that move liquidity between our own nodes or channels
+pub const REBALANCE_LABEL: &str = "Rebalance";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RebalanceStatus {
+    /// The payment is in flight.
+    InFlight,
+    /// The liquidity was moved.
+    Succeeded,
+    /// The rebalance failed. The error is given.
+    Failed(String),
+}
+
+/// Liquidity moved between two of our nodes, or between two channels of the same node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rebalance {
+    /// The hash of the payment that moved the liquidity
+    pub payment_hash: sha256::Hash,
+    pub from_node: PublicKey,
+    pub to_node: PublicKey,
+    /// The channel the liquidity left through, when one was chosen
+    pub outgoing_channel: Option<OutPoint>,
+    /// The channel the liquidity came back in through
+    pub incoming_channel: Option<OutPoint>,
+    pub amount_sats: u64,
+    /// The routing fees paid to move the liquidity
+    pub fees_paid: Option<u64>,
+    pub status: RebalanceStatus,
+    pub last_updated: u64,
+}
+
+impl Rebalance {
+    /// If the liquidity was moved between two channels of the same node
+    pub fn is_circular(&self) -> bool {
+        self.from_node == self.to_node
+    }
+
+    pub fn fail(&mut self, error: String) {
+        self.status = RebalanceStatus::Failed(error);
+    }
+
+    /// Updates a rebalance that is still in flight from the state of its payment.
+    /// Returns if it changed.
+    pub(crate) fn update_from_payment(&mut self, payment: &PaymentInfo) -> bool {
+        if self.status != RebalanceStatus::InFlight {
+            return false;
+        }
+
+        match payment.status {
+            HTLCStatus::Succeeded => {
+                self.fees_paid = payment.fee_paid_msat.map(|f| f / 1_000);
+                self.status = RebalanceStatus::Succeeded;
+            }
+            HTLCStatus::Failed => self.fail("payment failed".to_string()),
+            _ => return false,
+        }
+        self.last_updated = utils::now().as_secs();
+        true
+    }
+}
+
+impl PartialOrd for Rebalance {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rebalance {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.last_updated.cmp(&other.last_updated)
+    }
+}
+
+pub trait RebalanceStorage {
+    fn get_rebalance(&self, payment_hash: &sha256::Hash) -> Result<Option<Rebalance>, MutinyError>;
+    fn get_rebalances(&self) -> Result<Vec<Rebalance>, MutinyError>;
+    fn persist_rebalance(&self, rebalance: Rebalance) -> Result<(), MutinyError>;
+}
+
+const REBALANCE_KEY_PREFIX: &str = "rebalance/";
+
+fn get_rebalance_key(payment_hash: &sha256::Hash) -> String {
+    format!("{REBALANCE_KEY_PREFIX}{}", payment_hash.to_hex())
+}
+
+impl<S: MutinyStorage> RebalanceStorage for S {
+    fn get_rebalance(&self, payment_hash: &sha256::Hash) -> Result<Option<Rebalance>, MutinyError> {
+        self.get_data(get_rebalance_key(payment_hash))
+    }
+
+    fn get_rebalances(&self) -> Result<Vec<Rebalance>, MutinyError> {
+        let map: HashMap<String, Rebalance> = self.scan(REBALANCE_KEY_PREFIX, None)?;
+        Ok(map.into_values().collect())
+    }
+
+    fn persist_rebalance(&self, rebalance: Rebalance) -> Result<(), MutinyError> {
+        self.set_data(get_rebalance_key(&rebalance.payment_hash), rebalance, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event::MillisatAmount;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use std::str::FromStr;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    fn test_persist_rebalance() {
+        let test_name = "test_persist_rebalance";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let node = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+
+        let mut rebalance = Rebalance {
+            payment_hash: sha256::Hash::hash(&[0; 32]),
+            from_node: node,
+            to_node: node,
+            outgoing_channel: None,
+            incoming_channel: None,
+            amount_sats: 10_000,
+            fees_paid: None,
+            status: RebalanceStatus::InFlight,
+            last_updated: 1686258926,
+        };
+        assert!(rebalance.is_circular());
+        assert_eq!(
+            storage.get_rebalance(&rebalance.payment_hash).unwrap(),
+            None
+        );
+
+        storage.persist_rebalance(rebalance.clone()).unwrap();
+        assert_eq!(
+            storage.get_rebalance(&rebalance.payment_hash).unwrap(),
+            Some(rebalance.clone())
+        );
+
+        rebalance.fail("no route".to_string());
+        storage.persist_rebalance(rebalance.clone()).unwrap();
+        assert_eq!(storage.get_rebalances().unwrap(), vec![rebalance]);
+    }
+
+    #[test]
+    fn test_update_from_payment() {
+        let test_name = "test_update_from_payment";
+        log!("{}", test_name);
+
+        let node = PublicKey::from_str(
+            "02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54",
+        )
+        .unwrap();
+        let rebalance = Rebalance {
+            payment_hash: sha256::Hash::hash(&[0; 32]),
+            from_node: node,
+            to_node: node,
+            outgoing_channel: None,
+            incoming_channel: None,
+            amount_sats: 10_000,
+            fees_paid: None,
+            status: RebalanceStatus::InFlight,
+            last_updated: 1686258926,
+        };
+        let mut payment = PaymentInfo {
+            preimage: None,
+            secret: None,
+            status: HTLCStatus::InFlight,
+            amt_msat: MillisatAmount(Some(10_000_000)),
+            fee_paid_msat: None,
+            bolt11: None,
+            payee_pubkey: Some(node),
+            lsp_pubkey: None,
+            claim_deadline: None,
+            last_update: 1686258926,
+        };
+
+        // still waiting on the payment
+        let mut updated = rebalance.clone();
+        assert!(!updated.update_from_payment(&payment));
+        assert_eq!(updated, rebalance);
+
+        // the payment went through after we stopped waiting for it
+        payment.status = HTLCStatus::Succeeded;
+        payment.fee_paid_msat = Some(2_000);
+        assert!(updated.update_from_payment(&payment));
+        assert_eq!(updated.status, RebalanceStatus::Succeeded);
+        assert_eq!(updated.fees_paid, Some(2));
+
+        // a finished rebalance is not changed again
+        payment.status = HTLCStatus::Failed;
+        assert!(!updated.update_from_payment(&payment));
+        assert_eq!(updated.status, RebalanceStatus::Succeeded);
+
+        let mut updated = rebalance;
+        assert!(updated.update_from_payment(&payment));
+        assert_eq!(
+            updated.status,
+            RebalanceStatus::Failed("payment failed".to_string())
+        );
+    }
+}
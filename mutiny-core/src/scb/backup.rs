@@ -0,0 +1,296 @@
+use crate::error::MutinyError;
+use crate::scb::EncryptedSCB;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use crate::vss::{MutinyVssClient, VssKeyValueItem};
+use async_trait::async_trait;
+use nostr::prelude::encrypt;
+use nostr::{EventBuilder, Keys, Kind, Tag};
+use nostr_sdk::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Set when our channels changed since the last static channel backup
+pub(crate) const SCB_BACKUP_NEEDED_KEY: &str = "scb_backup_needed";
+/// The latest static channel backups we made, newest first
+pub(crate) const SCB_BACKUP_HISTORY_KEY: &str = "scb_backup_history";
+/// When each backup destination last got a backup
+pub(crate) const SCB_BACKUP_STATUS_KEY: &str = "scb_backup_status";
+/// How many old backups we keep in storage
+pub(crate) const MAX_SCB_BACKUP_HISTORY: usize = 10;
+
+/// The key the backup is stored under in VSS
+const VSS_SCB_BACKUP_KEY: &str = "scb_backup";
+/// NIP-78 application specific data, replaced by each new backup
+const NOSTR_SCB_BACKUP_KIND: u64 = 30078;
+const NOSTR_SCB_BACKUP_IDENTIFIER: &str = "mutiny-scb-backup";
+
+/// A static channel backup we made
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ScbBackupRecord {
+    /// The encoded [`EncryptedSCB`]
+    pub scb: String,
+    /// How many channels are in the backup
+    pub num_channels: usize,
+    /// When the backup was made, in seconds since the epoch
+    pub created_at: u64,
+}
+
+/// When the backups were last made and pushed to each destination
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScbBackupStatus {
+    /// When the latest backup was made, in seconds since the epoch
+    pub last_backup_time: Option<u64>,
+    /// When each destination last got a backup, in seconds since the epoch
+    pub last_successful_backups: HashMap<String, u64>,
+}
+
+/// Somewhere to push our static channel backups to
+#[async_trait(?Send)]
+pub trait ScbBackupDestination {
+    /// A unique name for the destination
+    fn name(&self) -> String;
+
+    /// Saves the backup, replacing any older one
+    async fn backup(&self, scb: &EncryptedSCB) -> Result<(), MutinyError>;
+}
+
+/// Marks that our channels changed and a new backup should be made
+pub(crate) fn request_scb_backup<S: MutinyStorage>(storage: &S) -> Result<(), MutinyError> {
+    storage.set_data(SCB_BACKUP_NEEDED_KEY, true, None)
+}
+
+pub(crate) fn is_scb_backup_needed<S: MutinyStorage>(storage: &S) -> Result<bool, MutinyError> {
+    Ok(storage
+        .get_data::<bool>(SCB_BACKUP_NEEDED_KEY)?
+        .unwrap_or(false))
+}
+
+/// Saves a new backup as the latest one, dropping the oldest if we have too many
+pub(crate) fn save_scb_backup<S: MutinyStorage>(
+    storage: &S,
+    scb: &EncryptedSCB,
+    num_channels: usize,
+) -> Result<ScbBackupRecord, MutinyError> {
+    let record = ScbBackupRecord {
+        scb: scb.to_string(),
+        num_channels,
+        created_at: utils::now().as_secs(),
+    };
+
+    let mut history = get_scb_backup_history(storage)?;
+    history.insert(0, record.clone());
+    history.truncate(MAX_SCB_BACKUP_HISTORY);
+    storage.set_data(SCB_BACKUP_HISTORY_KEY, history, None)?;
+    storage.set_data(SCB_BACKUP_NEEDED_KEY, false, None)?;
+
+    Ok(record)
+}
+
+/// The backups we have made, newest first
+pub(crate) fn get_scb_backup_history<S: MutinyStorage>(
+    storage: &S,
+) -> Result<Vec<ScbBackupRecord>, MutinyError> {
+    Ok(storage
+        .get_data(SCB_BACKUP_HISTORY_KEY)?
+        .unwrap_or_default())
+}
+
+pub(crate) fn get_scb_backup_status<S: MutinyStorage>(
+    storage: &S,
+) -> Result<ScbBackupStatus, MutinyError> {
+    let mut status: ScbBackupStatus = storage.get_data(SCB_BACKUP_STATUS_KEY)?.unwrap_or_default();
+    status.last_backup_time = get_scb_backup_history(storage)?
+        .first()
+        .map(|r| r.created_at);
+    Ok(status)
+}
+
+pub(crate) fn set_scb_backup_succeeded<S: MutinyStorage>(
+    storage: &S,
+    destination: String,
+    time: u64,
+) -> Result<(), MutinyError> {
+    let mut status = get_scb_backup_status(storage)?;
+    status.last_successful_backups.insert(destination, time);
+    storage.set_data(SCB_BACKUP_STATUS_KEY, status, None)
+}
+
+/// Backs up to VSS, each backup overwrites the last one
+pub struct VssScbDestination {
+    vss: Arc<MutinyVssClient>,
+}
+
+impl VssScbDestination {
+    pub fn new(vss: Arc<MutinyVssClient>) -> Self {
+        Self { vss }
+    }
+}
+
+#[async_trait(?Send)]
+impl ScbBackupDestination for VssScbDestination {
+    fn name(&self) -> String {
+        String::from("vss")
+    }
+
+    async fn backup(&self, scb: &EncryptedSCB) -> Result<(), MutinyError> {
+        // use the time as the version so newer backups always win
+        let item = VssKeyValueItem {
+            key: VSS_SCB_BACKUP_KEY.to_string(),
+            value: serde_json::Value::String(scb.to_string()),
+            version: utils::now().as_secs() as u32,
+        };
+        self.vss.put_objects(vec![item]).await
+    }
+}
+
+/// Backs up to nostr relays as a replaceable event encrypted to ourselves
+pub struct NostrScbDestination {
+    keys: Keys,
+    relays: Vec<String>,
+}
+
+impl NostrScbDestination {
+    pub fn new(keys: Keys, relays: Vec<String>) -> Self {
+        Self { keys, relays }
+    }
+}
+
+#[async_trait(?Send)]
+impl ScbBackupDestination for NostrScbDestination {
+    fn name(&self) -> String {
+        String::from("nostr")
+    }
+
+    async fn backup(&self, scb: &EncryptedSCB) -> Result<(), MutinyError> {
+        if self.relays.is_empty() {
+            return Err(MutinyError::Other(anyhow::anyhow!(
+                "No relays to back up to"
+            )));
+        }
+
+        let secret_key = self
+            .keys
+            .secret_key()
+            .map_err(|e| MutinyError::Other(anyhow::anyhow!("No nostr secret key: {e:?}")))?;
+        let content = encrypt(&secret_key, &self.keys.public_key(), scb.to_string())
+            .map_err(|e| MutinyError::Other(anyhow::anyhow!("Failed to encrypt backup: {e:?}")))?;
+
+        let event = EventBuilder::new(
+            Kind::from(NOSTR_SCB_BACKUP_KIND),
+            content,
+            &[Tag::Identifier(NOSTR_SCB_BACKUP_IDENTIFIER.to_string())],
+        )
+        .to_event(&self.keys)
+        .map_err(|e| MutinyError::Other(anyhow::anyhow!("Failed to create event: {e:?}")))?;
+
+        let client = Client::new(&self.keys);
+
+        #[cfg(target_arch = "wasm32")]
+        let add_relay_res = client.add_relays(self.relays.clone()).await;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let add_relay_res = client
+            .add_relays(self.relays.iter().map(|s| (s.clone(), None)).collect())
+            .await;
+
+        add_relay_res
+            .map_err(|e| MutinyError::Other(anyhow::anyhow!("Failed to add relays: {e:?}")))?;
+        client.connect().await;
+
+        let res = client.send_event(event).await;
+        let _ = client.disconnect().await;
+        res.map_err(|e| MutinyError::Other(anyhow::anyhow!("Failed to send backup: {e:?}")))?;
+
+        Ok(())
+    }
+}
+
+/// Hands each backup to a function, so it can be exported locally,
+/// for example saved to a file.
+pub struct LocalScbDestination {
+    export: Box<dyn Fn(&str) -> Result<(), MutinyError>>,
+}
+
+impl LocalScbDestination {
+    pub fn new(export: impl Fn(&str) -> Result<(), MutinyError> + 'static) -> Self {
+        Self {
+            export: Box::new(export),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl ScbBackupDestination for LocalScbDestination {
+    fn name(&self) -> String {
+        String::from("local")
+    }
+
+    async fn backup(&self, scb: &EncryptedSCB) -> Result<(), MutinyError> {
+        (self.export)(&scb.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[test]
+    async fn test_scb_backup_history() {
+        let test_name = "test_scb_backup_history";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        assert!(!is_scb_backup_needed(&storage).unwrap());
+        request_scb_backup(&storage).unwrap();
+        assert!(is_scb_backup_needed(&storage).unwrap());
+
+        for i in 0..MAX_SCB_BACKUP_HISTORY + 2 {
+            let scb = EncryptedSCB {
+                encrypted_scb: vec![i as u8],
+            };
+            save_scb_backup(&storage, &scb, i).unwrap();
+        }
+        assert!(!is_scb_backup_needed(&storage).unwrap());
+
+        let history = get_scb_backup_history(&storage).unwrap();
+        assert_eq!(history.len(), MAX_SCB_BACKUP_HISTORY);
+        assert_eq!(history[0].num_channels, MAX_SCB_BACKUP_HISTORY + 1);
+
+        let status = get_scb_backup_status(&storage).unwrap();
+        assert_eq!(status.last_backup_time, Some(history[0].created_at));
+        assert!(status.last_successful_backups.is_empty());
+
+        set_scb_backup_succeeded(&storage, "local".to_string(), 100).unwrap();
+        let status = get_scb_backup_status(&storage).unwrap();
+        assert_eq!(status.last_successful_backups.get("local"), Some(&100));
+    }
+
+    #[test]
+    async fn test_local_scb_destination() {
+        let test_name = "test_local_scb_destination";
+        log!("{}", test_name);
+
+        let exported = Rc::new(RefCell::new(None));
+        let exported_clone = exported.clone();
+        let destination = LocalScbDestination::new(move |scb| {
+            *exported_clone.borrow_mut() = Some(scb.to_string());
+            Ok(())
+        });
+
+        let scb = EncryptedSCB {
+            encrypted_scb: vec![1, 2, 3],
+        };
+        destination.backup(&scb).await.unwrap();
+        assert_eq!(*exported.borrow(), Some(scb.to_string()));
+    }
+}
@@ -1,20 +1,127 @@
 use crate::utils::Mutex;
+use bitcoin::hashes::sha256;
 use bitcoin::secp256k1::PublicKey;
+use lightning::io;
 use lightning::ln::peer_handler::CustomMessageHandler;
-use lightning::ln::wire::CustomMessageReader;
+use lightning::ln::wire::{CustomMessageReader, Type};
 use lightning::ln::{
     features::InitFeatures,
     msgs::{DecodeError, LightningError},
 };
 use lightning::ln::{features::NodeFeatures, msgs::ChannelReestablish};
-use std::collections::VecDeque;
+use lightning::util::ser::{Readable, Writeable, Writer};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Message type for asking a peer to store data for us
+pub const PEER_STORAGE_TYPE: u16 = 7;
+/// Message type for a peer giving us back the data it stored for us
+pub const YOUR_PEER_STORAGE_TYPE: u16 = 9;
+/// The most data a peer storage message can hold
+pub const MAX_PEER_STORAGE_SIZE: usize = 65531;
+
+/// A `peer_storage` message, asks the peer to store the blob for us
+/// and give it back with [`YourPeerStorage`] when we reconnect.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerStorage {
+    pub data: Vec<u8>,
+}
+
+/// A `your_peer_storage` message, the latest blob we asked the peer to store
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct YourPeerStorage {
+    pub data: Vec<u8>,
+}
+
+fn write_blob<W: Writer>(data: &[u8], writer: &mut W) -> Result<(), io::Error> {
+    (data.len() as u16).write(writer)?;
+    writer.write_all(data)
+}
+
+fn read_blob<R: io::Read>(reader: &mut R) -> Result<Vec<u8>, DecodeError> {
+    let len: u16 = Readable::read(reader)?;
+    let mut data = vec![0u8; len as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+impl Writeable for PeerStorage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        write_blob(&self.data, writer)
+    }
+}
+
+impl Readable for PeerStorage {
+    fn read<R: io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        Ok(Self {
+            data: read_blob(reader)?,
+        })
+    }
+}
+
+impl Writeable for YourPeerStorage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        write_blob(&self.data, writer)
+    }
+}
+
+impl Readable for YourPeerStorage {
+    fn read<R: io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        Ok(Self {
+            data: read_blob(reader)?,
+        })
+    }
+}
+
+/// The messages sent and received by the [`SCBMessageHandler`]
+#[derive(Clone, Debug)]
+pub enum SCBMessage {
+    ChannelReestablish(ChannelReestablish),
+    PeerStorage(PeerStorage),
+    YourPeerStorage(YourPeerStorage),
+}
+
+impl Type for SCBMessage {
+    fn type_id(&self) -> u16 {
+        match self {
+            SCBMessage::ChannelReestablish(msg) => msg.type_id(),
+            SCBMessage::PeerStorage(_) => PEER_STORAGE_TYPE,
+            SCBMessage::YourPeerStorage(_) => YOUR_PEER_STORAGE_TYPE,
+        }
+    }
+}
+
+impl Writeable for SCBMessage {
+    fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+        match self {
+            SCBMessage::ChannelReestablish(msg) => msg.write(writer),
+            SCBMessage::PeerStorage(msg) => msg.write(writer),
+            SCBMessage::YourPeerStorage(msg) => msg.write(writer),
+        }
+    }
+}
+
+/// The blob we want our peers to store for us
+struct OurPeerStorage {
+    /// Hash of the backup in the blob, the blob itself changes every
+    /// time it is encrypted so we can't compare those.
+    backup_hash: sha256::Hash,
+    data: Vec<u8>,
+    /// The peers we have sent this blob to
+    sent_to: HashSet<PublicKey>,
+}
 
 /// Custom message handler for Static Channel Backups.
 ///
 /// This will send bogus channel reestablish messages to the peer, which will
 /// trigger the peer to close the channel on our behalf.
+///
+/// It also asks our peers to store an encrypted copy of our backup with
+/// `peer_storage` messages and keeps the copies they give back to us.
 pub struct SCBMessageHandler {
-    msg_events: Mutex<VecDeque<(PublicKey, ChannelReestablish)>>,
+    msg_events: Mutex<VecDeque<(PublicKey, SCBMessage)>>,
+    peer_storage: Mutex<Option<OurPeerStorage>>,
+    /// The latest blob each peer gave back to us, not yet processed
+    your_peer_storage: Mutex<HashMap<PublicKey, Vec<u8>>>,
 }
 
 impl Default for SCBMessageHandler {
@@ -28,6 +135,8 @@ impl SCBMessageHandler {
     pub fn new() -> Self {
         SCBMessageHandler {
             msg_events: Mutex::new(VecDeque::new()),
+            peer_storage: Mutex::new(None),
+            your_peer_storage: Mutex::new(HashMap::new()),
         }
     }
 
@@ -46,37 +155,111 @@ impl SCBMessageHandler {
             my_current_per_commitment_point: dummy_pubkey,
             next_funding_txid: None,
         };
-        self.msg_events.lock().unwrap().push_back((node_id, msg));
+        self.msg_events
+            .lock()
+            .unwrap()
+            .push_back((node_id, SCBMessage::ChannelReestablish(msg)));
     }
 
     /// Returns whether the message handler has any message to be sent.
     pub fn has_pending_messages(&self) -> bool {
         !self.msg_events.lock().unwrap().is_empty()
     }
+
+    /// If the blob for our peers to store is for the backup with the given hash
+    pub fn has_peer_storage(&self, backup_hash: &sha256::Hash) -> bool {
+        self.peer_storage
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|s| s.backup_hash == *backup_hash)
+    }
+
+    /// Sets the blob for our peers to store, it will be sent to
+    /// every peer again on the next [`SCBMessageHandler::send_peer_storage`].
+    pub fn set_peer_storage(&self, backup_hash: sha256::Hash, data: Vec<u8>) {
+        debug_assert!(data.len() <= MAX_PEER_STORAGE_SIZE);
+        *self.peer_storage.lock().unwrap() = Some(OurPeerStorage {
+            backup_hash,
+            data,
+            sent_to: HashSet::new(),
+        });
+    }
+
+    /// Forgets that we sent our blob to the peer, so it is sent again once
+    /// they reconnect.
+    pub fn peer_disconnected(&self, peer: &PublicKey) {
+        if let Some(peer_storage) = self.peer_storage.lock().unwrap().as_mut() {
+            peer_storage.sent_to.remove(peer);
+        }
+    }
+
+    /// Queues our blob to be sent to the given connected peers that don't have it yet.
+    /// Peers we sent it to that aren't given anymore are treated as disconnected,
+    /// LDK doesn't tell custom message handlers about disconnects.
+    /// Returns if any messages were queued.
+    pub fn send_peer_storage(&self, peers: &[PublicKey]) -> bool {
+        let mut peer_storage = self.peer_storage.lock().unwrap();
+        let Some(peer_storage) = peer_storage.as_mut() else {
+            return false;
+        };
+        peer_storage.sent_to.retain(|pk| peers.contains(pk));
+
+        let mut msg_events = self.msg_events.lock().unwrap();
+        let mut queued = false;
+        for peer in peers {
+            if peer_storage.sent_to.insert(*peer) {
+                let msg = PeerStorage {
+                    data: peer_storage.data.clone(),
+                };
+                msg_events.push_back((*peer, SCBMessage::PeerStorage(msg)));
+                queued = true;
+            }
+        }
+        queued
+    }
+
+    /// Takes the blobs our peers have given back to us since the last call
+    pub fn take_your_peer_storage(&self) -> Vec<(PublicKey, Vec<u8>)> {
+        self.your_peer_storage.lock().unwrap().drain().collect()
+    }
 }
 
-/// Dummy implementation of [`CustomMessageReader`] for [`SCBMessageHandler`].
-/// We are just sending [`ChannelReestablish`] messages, which are not
-/// custom messages, but we need to implement this trait to be able to use
-/// the [`CustomMessageHandler`] trait.
 impl CustomMessageReader for SCBMessageHandler {
-    type CustomMessage = ChannelReestablish;
+    type CustomMessage = SCBMessage;
     fn read<R: lightning::io::Read>(
         &self,
-        _msg_type: u16,
-        _buffer: &mut R,
+        msg_type: u16,
+        buffer: &mut R,
     ) -> Result<Option<Self::CustomMessage>, DecodeError> {
-        Ok(None)
+        match msg_type {
+            PEER_STORAGE_TYPE => Ok(Some(SCBMessage::PeerStorage(Readable::read(buffer)?))),
+            YOUR_PEER_STORAGE_TYPE => {
+                Ok(Some(SCBMessage::YourPeerStorage(Readable::read(buffer)?)))
+            }
+            _ => Ok(None),
+        }
     }
 }
 
 impl CustomMessageHandler for SCBMessageHandler {
     fn handle_custom_message(
         &self,
-        _msg: ChannelReestablish,
-        _org: &PublicKey,
+        msg: SCBMessage,
+        org: &PublicKey,
     ) -> Result<(), LightningError> {
-        // We don't need to do anything here, since we are just sending
+        match msg {
+            SCBMessage::YourPeerStorage(msg) => {
+                self.your_peer_storage
+                    .lock()
+                    .unwrap()
+                    .insert(*org, msg.data);
+            }
+            // We don't store data for other nodes
+            SCBMessage::PeerStorage(_) => {}
+            // We only send these, so we don't need to do anything here
+            SCBMessage::ChannelReestablish(_) => {}
+        }
         Ok(())
     }
 
@@ -95,3 +278,87 @@ impl CustomMessageHandler for SCBMessageHandler {
         InitFeatures::empty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use bitcoin::hashes::Hash;
+    use std::str::FromStr;
+
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const PUBKEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    fn test_peer_storage_roundtrip() {
+        let test_name = "test_peer_storage_roundtrip";
+        log!("{}", test_name);
+
+        let handler = SCBMessageHandler::new();
+        let msg = PeerStorage {
+            data: vec![1, 2, 3],
+        };
+        let read = handler
+            .read(PEER_STORAGE_TYPE, &mut io::Cursor::new(msg.encode()))
+            .unwrap();
+        assert!(matches!(read, Some(SCBMessage::PeerStorage(m)) if m == msg));
+
+        let msg = YourPeerStorage {
+            data: vec![4, 5, 6],
+        };
+        let read = handler
+            .read(YOUR_PEER_STORAGE_TYPE, &mut io::Cursor::new(msg.encode()))
+            .unwrap()
+            .unwrap();
+
+        let peer = PublicKey::from_str(PUBKEY).unwrap();
+        handler.handle_custom_message(read, &peer).unwrap();
+        assert_eq!(
+            handler.take_your_peer_storage(),
+            vec![(peer, vec![4, 5, 6])]
+        );
+        assert!(handler.take_your_peer_storage().is_empty());
+    }
+
+    #[test]
+    fn test_send_peer_storage() {
+        let test_name = "test_send_peer_storage";
+        log!("{}", test_name);
+
+        let handler = SCBMessageHandler::new();
+        let peer = PublicKey::from_str(PUBKEY).unwrap();
+
+        // nothing to send yet
+        assert!(!handler.send_peer_storage(&[peer]));
+
+        let hash = sha256::Hash::hash(&[1]);
+        handler.set_peer_storage(hash, vec![1]);
+        assert!(handler.has_peer_storage(&hash));
+        assert!(handler.send_peer_storage(&[peer]));
+        // only sent once
+        assert!(!handler.send_peer_storage(&[peer]));
+
+        let msgs = handler.get_and_clear_pending_msg();
+        assert_eq!(msgs.len(), 1);
+        assert!(matches!(&msgs[0].1, SCBMessage::PeerStorage(m) if m.data == vec![1]));
+
+        // a new backup is sent again
+        let hash = sha256::Hash::hash(&[2]);
+        handler.set_peer_storage(hash, vec![2]);
+        assert!(handler.send_peer_storage(&[peer]));
+        handler.get_and_clear_pending_msg();
+
+        // sent again after the peer disconnects
+        handler.peer_disconnected(&peer);
+        assert!(handler.send_peer_storage(&[peer]));
+        assert!(!handler.send_peer_storage(&[peer]));
+
+        // or when it is not connected on a send
+        assert!(!handler.send_peer_storage(&[]));
+        assert!(handler.send_peer_storage(&[peer]));
+        assert_eq!(handler.get_and_clear_pending_msg().len(), 2);
+    }
+}
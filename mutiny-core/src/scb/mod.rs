@@ -1,8 +1,9 @@
+pub mod backup;
 pub mod message_handler;
 
 use crate::encrypt::{decrypt_with_key, encrypt_with_key};
 use crate::error::MutinyError;
-use crate::nodemanager::NodeIndex;
+use crate::nodemanager::{MutinyChannel, NodeIndex};
 use bitcoin::bech32::{FromBase32, ToBase32, Variant};
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{PublicKey, SecretKey};
@@ -10,6 +11,7 @@ use bitcoin::{bech32, OutPoint};
 use lightning::io::{Cursor, Read};
 use lightning::ln::msgs::DecodeError;
 use lightning::util::ser::{Readable, Writeable, Writer};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Formatter;
 use std::str::FromStr;
@@ -76,6 +78,13 @@ pub struct StaticChannelBackupStorage {
 }
 
 impl StaticChannelBackupStorage {
+    /// If the backup has the channel with the given funding outpoint for the node
+    pub(crate) fn has_channel(&self, node: &PublicKey, outpoint: &OutPoint) -> bool {
+        self.backups
+            .get(node)
+            .is_some_and(|(_, b)| b.monitors.contains_key(outpoint))
+    }
+
     pub(crate) fn encrypt(&self, secret_key: &SecretKey) -> EncryptedSCB {
         let bytes = self.encode();
         let encrypted_scb = encrypt_with_key(secret_key, &bytes);
@@ -117,7 +126,7 @@ impl Readable for StaticChannelBackupStorage {
         for _ in 0..len {
             let mut pk = [0u8; 33];
             reader.read_exact(&mut pk)?;
-            let public_key = PublicKey::from_slice(&pk).expect("public key is 33 bytes");
+            let public_key = PublicKey::from_slice(&pk).map_err(|_| DecodeError::InvalidValue)?;
             let node_index = Readable::read(reader)?;
             let backup = Readable::read(reader)?;
             backups.insert(public_key, (node_index, backup));
@@ -130,14 +139,15 @@ impl Readable for StaticChannelBackupStorage {
             // read public key
             let mut public_key = [0u8; 33];
             reader.read_exact(&mut public_key)?;
-            let public_key = PublicKey::from_slice(&public_key).expect("public key is 33 bytes");
+            let public_key =
+                PublicKey::from_slice(&public_key).map_err(|_| DecodeError::InvalidValue)?;
 
             // read peer connection
             let len: u32 = Readable::read(reader)?;
             let mut peer_connection = vec![0u8; len as usize];
             reader.read_exact(&mut peer_connection)?;
             let peer_connection =
-                String::from_utf8(peer_connection).expect("peer connection is utf8");
+                String::from_utf8(peer_connection).map_err(|_| DecodeError::InvalidValue)?;
             peer_connections.insert(public_key, peer_connection);
         }
 
@@ -148,6 +158,40 @@ impl Readable for StaticChannelBackupStorage {
     }
 }
 
+/// What a static channel backup contains, from
+/// [`crate::nodemanager::NodeManager::inspect_static_channel_backup`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ScbInspection {
+    pub nodes: Vec<ScbNodeDetails>,
+    /// Our current channels that are not in the backup
+    pub missing_channels: Vec<MutinyChannel>,
+}
+
+/// A node in a static channel backup
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ScbNodeDetails {
+    pub pubkey: PublicKey,
+    pub child_index: u32,
+    /// If the node is currently running
+    pub is_live: bool,
+    pub channels: Vec<ScbChannelDetails>,
+}
+
+/// A channel in a static channel backup
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ScbChannelDetails {
+    pub outpoint: OutPoint,
+    pub counterparty: PublicKey,
+    /// The size of the channel in satoshis
+    pub capacity_sats: u64,
+    /// What we could claim in satoshis if the channel closed now
+    pub balance_sats: u64,
+    /// How we would connect to the peer to recover the channel
+    pub peer_connection: Option<String>,
+    /// If the channel is one of our current channels
+    pub is_live: bool,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct EncryptedSCB {
     pub(crate) encrypted_scb: Vec<u8>,
@@ -160,7 +204,8 @@ impl EncryptedSCB {
     ) -> Result<StaticChannelBackupStorage, MutinyError> {
         let bytes = decrypt_with_key(secret_key, self.encrypted_scb.clone())?;
         let mut cursor = Cursor::new(bytes);
-        Ok(StaticChannelBackupStorage::read(&mut cursor).expect("decoding succeeds"))
+        // the backup could come from a peer, so don't trust it decodes
+        Ok(StaticChannelBackupStorage::read(&mut cursor)?)
     }
 }
 
@@ -206,14 +251,14 @@ impl core::fmt::Display for EncryptedSCB {
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use bitcoin::hashes::hex::FromHex;
     use std::str::FromStr;
 
     use super::*;
 
     // copied from ben's signet node
-    const CHAIN_MONITOR_BYTES: [u8; 6082] = [
+    pub(crate) const CHAIN_MONITOR_BYTES: [u8; 6082] = [
         1, 1, 0, 0, 0, 0, 0, 0, 0, 19, 84, 108, 201, 31, 61, 95, 0, 34, 81, 32, 142, 124, 212, 169,
         250, 144, 169, 131, 165, 4, 25, 54, 46, 161, 93, 129, 200, 231, 180, 98, 48, 45, 184, 196,
         39, 61, 130, 67, 109, 65, 227, 116, 1, 0, 22, 0, 20, 155, 222, 185, 143, 188, 53, 25, 92,
@@ -488,6 +533,7 @@ mod test {
             child_index: 0,
             lsp: None,
             archived: Some(false),
+            channel_policy: None,
         };
 
         let pk = PublicKey::from_str(
@@ -582,6 +628,7 @@ mod test {
             child_index: 0,
             lsp: Some("https://signet-lsp.mutinywallet.com".to_string()),
             archived: Some(false),
+            channel_policy: None,
         };
 
         let storage = StaticChannelBackupStorage {
@@ -624,6 +671,7 @@ mod test {
             child_index: 0,
             lsp: Some("https://signet-lsp.mutinywallet.com".to_string()),
             archived: Some(false),
+            channel_policy: None,
         };
 
         let storage = StaticChannelBackupStorage {
@@ -4,9 +4,10 @@ use crate::ldkstorage::CHANNEL_MANAGER_KEY;
 use crate::nodemanager::NodeStorage;
 use bdk::chain::{Append, PersistBackend};
 use bip39::Mnemonic;
+use bitcoin::Txid;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 
 pub const KEYCHAIN_STORE_KEY: &str = "bdk_keychain";
@@ -14,6 +15,7 @@ pub(crate) const MNEMONIC_KEY: &str = "mnemonic";
 pub const NODES_KEY: &str = "nodes";
 const FEE_ESTIMATES_KEY: &str = "fee_estimates";
 const FIRST_SYNC_KEY: &str = "first_sync";
+const HELD_BROADCASTS_KEY: &str = "held_broadcasts";
 
 fn needs_encryption(key: &str) -> bool {
     match key {
@@ -253,6 +255,16 @@ pub trait MutinyStorage: Clone + Sized + 'static {
     fn set_done_first_sync(&self) -> Result<(), MutinyError> {
         self.set_data(FIRST_SYNC_KEY, true, None)
     }
+
+    /// Get the transactions the on-chain wallet is holding back from broadcast
+    fn get_held_broadcasts(&self) -> Result<HashSet<Txid>, MutinyError> {
+        Ok(self.get_data(HELD_BROADCASTS_KEY)?.unwrap_or_default())
+    }
+
+    /// Inserts the transactions the on-chain wallet is holding back from broadcast
+    fn insert_held_broadcasts(&self, held: &HashSet<Txid>) -> Result<(), MutinyError> {
+        self.set_data(HELD_BROADCASTS_KEY, held, None)
+    }
 }
 
 #[derive(Clone)]
@@ -0,0 +1,1788 @@
+use crate::error::MutinyError;
+use crate::event::HTLCStatus;
+use crate::labels::LabelStorage;
+use crate::nodemanager::NodeManager;
+use crate::onchain::HtlcSpendPath;
+use crate::storage::MutinyStorage;
+use crate::utils;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use bdk::psbt::PsbtUtils;
+use bitcoin::blockdata::opcodes::all::{
+    OP_CHECKSIG, OP_CLTV, OP_DROP, OP_ELSE, OP_ENDIF, OP_EQUAL, OP_EQUALVERIFY, OP_HASH160, OP_IF,
+    OP_SIZE,
+};
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::{ripemd160, sha256, Hash};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
+use bitcoin::{Address, Network, OutPoint, Script, Transaction, TxOut, Txid};
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::ln::PaymentHash;
+use lightning::util::logger::Logger;
+use lightning::{log_error, log_info, log_warn};
+use lightning_invoice::{Currency, Invoice};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::networking::socks5::{http_client, Socks5Proxy};
+
+/// Label given to the on-chain transactions and lightning payments of our swaps
+pub const SWAP_LABEL: &str = "Swap";
+
+/// Our keys for the swap HTLCs are derived under this path, at the swap's key index
+const SWAP_KEY_DERIVATION_PATH: &str = "m/444'/445'";
+
+/// A loop in that has no funding transaction after this long is given up on, in seconds
+const LOOP_IN_FUNDING_TIMEOUT_SECS: u64 = 60 * 60;
+
+/// The furthest out we let a provider put the timeout of a loop in,
+/// our funds are locked until then if the provider does not pay us
+const MAX_LOOP_IN_TIMEOUT_BLOCKS: u32 = 1_008;
+
+/// The least time a loop in must give the provider to pay our invoice once our funding
+/// confirms, a timeout any sooner races the provider's claim against our refund
+const MIN_LOOP_IN_TIMEOUT_BLOCKS: u32 = 36;
+
+/// The least time we need before the timeout of a loop out to claim the HTLC
+const MIN_LOOP_OUT_TIMEOUT_BLOCKS: u32 = 36;
+
+/// The base of the most we let a provider charge when we are not given a max fee
+const DEFAULT_MAX_SWAP_FEE_BASE_SATS: u64 = 1_000;
+
+/// The percent of the amount the provider can charge on top of the base fee,
+/// when we are not given a max fee
+const DEFAULT_MAX_SWAP_FEE_PERCENT: u64 = 2;
+
+/// The weight of claiming a loop out HTLC into a payment and our change,
+/// for estimating the claim fee before we make it
+const HTLC_CLAIM_WEIGHT: u64 = 766;
+
+/// How much higher than our estimate the claim fee can go, as a percent of the estimate,
+/// before a loop out's HTLC can no longer pay its destination. What the claim does not
+/// use comes back to our wallet.
+const HTLC_CLAIM_FEE_BUFFER_PERCENT: u64 = 100;
+
+/// How many blocks before the timeout of a loop out we start replacing its
+/// unconfirmed claim with one paying a higher fee, once every block.
+const CLAIM_BUMP_BLOCKS: u32 = 12;
+
+const LOOP_IN_PATH: &str = "/v1/swap/in";
+const LOOP_OUT_PATH: &str = "/v1/swap/out";
+const LOOP_OUT_QUOTE_PATH: &str = "/v1/swap/out/quote";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapType {
+    /// We fund an on-chain HTLC and the provider pays our invoice to claim it.
+    LoopIn,
+    /// We pay the provider's invoice and it funds an on-chain HTLC for us to claim.
+    LoopOut,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapStatus {
+    /// The swap was agreed with the provider. A loop in is being funded,
+    /// a loop out is waiting on the provider to fund the HTLC.
+    Created,
+    /// The HTLC is funded. A loop in is waiting on the provider to pay our invoice,
+    /// a loop out is being claimed.
+    Funded,
+    /// We broadcast the claim of a loop out and are waiting on it to confirm
+    /// and our payment to finish.
+    Claimed,
+    /// The swap is done.
+    Completed,
+    /// The provider did not pay a loop in before the timeout, so we took the funds back.
+    Refunded,
+    /// The swap failed. The error is given.
+    Failed(String),
+}
+
+impl SwapStatus {
+    /// If the swap still needs to be moved forward
+    pub fn is_in_progress(&self) -> bool {
+        matches!(
+            self,
+            SwapStatus::Created | SwapStatus::Funded | SwapStatus::Claimed
+        )
+    }
+}
+
+/// Where a loop out pays when its HTLC is claimed, instead of only to our wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapDestination {
+    pub address: Address,
+    /// Paid exactly when the HTLC covers it and the claim fee, the rest comes back to us
+    pub amount_sats: u64,
+    /// What the claim paid the address. When the HTLC could not cover the payment
+    /// it is claimed to our wallet instead and this is zero.
+    #[serde(default)]
+    pub paid_sats: Option<u64>,
+}
+
+/// A swap between our on-chain and lightning balances through a swap provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Swap {
+    /// The id the provider gave the swap
+    pub id: String,
+    pub swap_type: SwapType,
+    pub status: SwapStatus,
+    /// The node that is paid by a loop in or pays for a loop out
+    pub node: PublicKey,
+    pub payment_hash: sha256::Hash,
+    /// Our invoice for a loop in, the provider's for a loop out
+    pub invoice: Invoice,
+    /// The amount of the invoice
+    pub amount_sats: u64,
+    /// The address of the HTLC
+    pub address: Address,
+    pub redeem_script: Script,
+    /// The block height after which the HTLC can be refunded
+    pub timeout_block_height: u32,
+    /// The amount locked in the HTLC
+    pub onchain_amount_sats: u64,
+    /// The HTLC output, once it is funded
+    pub lockup_outpoint: Option<OutPoint>,
+    /// The transaction that claimed or refunded the HTLC
+    pub spend_txid: Option<Txid>,
+    /// The provider's fee plus the on-chain and routing fees we paid
+    pub fees_paid: Option<u64>,
+    /// Where a loop out pays when it isn't only to our wallet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<SwapDestination>,
+    pub last_updated: u64,
+    /// The index our HTLC key is derived at
+    pub(crate) key_index: u32,
+    /// The preimage of a loop out, revealed when we claim the HTLC
+    pub(crate) preimage: Option<[u8; 32]>,
+    /// The block height we last broadcast the claim of a loop out at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) spend_height: Option<u32>,
+    /// The on-chain fee of the claim or refund we last broadcast
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) spend_fee_sats: Option<u64>,
+}
+
+impl Swap {
+    /// The amount that arrives on the other side of the swap, before on-chain fees
+    pub fn amount_received_sats(&self) -> u64 {
+        match self.swap_type {
+            SwapType::LoopIn => self.amount_sats,
+            SwapType::LoopOut => self.onchain_amount_sats,
+        }
+    }
+
+    pub fn fail(&mut self, error: String) {
+        self.status = SwapStatus::Failed(error);
+    }
+}
+
+impl PartialOrd for Swap {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Swap {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.last_updated.cmp(&other.last_updated)
+    }
+}
+
+/// Asks the provider to pay our invoice once we fund the HTLC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopInRequest {
+    pub invoice: String,
+    /// Our key for taking the funds back after the timeout
+    pub refund_pubkey: PublicKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopInResponse {
+    pub id: String,
+    /// The address of the HTLC for us to fund
+    pub address: String,
+    /// The provider's key for claiming the HTLC with the preimage
+    pub claim_pubkey: PublicKey,
+    pub timeout_block_height: u32,
+    /// The amount to send to the HTLC, the invoice amount plus the provider's fee
+    pub expected_amount_sats: u64,
+}
+
+impl LoopInResponse {
+    /// Checks the HTLC pays our invoice's payment hash and can be refunded to us
+    /// at a reasonable height, returning its script and address.
+    pub(crate) fn verify(
+        &self,
+        payment_hash: &sha256::Hash,
+        refund_pubkey: &PublicKey,
+        network: Network,
+        height: u32,
+    ) -> Result<(Script, Address), MutinyError> {
+        if self.timeout_block_height > height + MAX_LOOP_IN_TIMEOUT_BLOCKS
+            || self.timeout_block_height < height + MIN_LOOP_IN_TIMEOUT_BLOCKS
+        {
+            return Err(MutinyError::SwapHtlcMismatch);
+        }
+
+        let script = htlc_script(
+            payment_hash,
+            &self.claim_pubkey,
+            refund_pubkey,
+            self.timeout_block_height,
+        );
+        let address = check_htlc_address(&self.address, &script, network)?;
+
+        Ok((script, address))
+    }
+}
+
+/// Asks the provider for an invoice to our payment hash, it funds the HTLC once it is paid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopOutRequest {
+    pub payment_hash: sha256::Hash,
+    /// Our key for claiming the HTLC with the preimage
+    pub claim_pubkey: PublicKey,
+    /// The amount we want locked in the HTLC
+    pub amount_sats: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopOutResponse {
+    pub id: String,
+    /// The invoice for us to pay, the provider holds the payment until we claim the HTLC
+    pub invoice: String,
+    /// The address of the HTLC the provider funds
+    pub lockup_address: String,
+    /// The provider's key for taking the funds back after the timeout
+    pub refund_pubkey: PublicKey,
+    pub timeout_block_height: u32,
+    /// The amount the provider locks in the HTLC
+    pub onchain_amount_sats: u64,
+}
+
+impl LoopOutResponse {
+    /// Checks the invoice is for our payment hash and the HTLC can be claimed by us
+    /// with enough time left, returning the invoice and the HTLC's script and address.
+    pub(crate) fn verify(
+        &self,
+        request: &LoopOutRequest,
+        network: Network,
+        height: u32,
+    ) -> Result<(Invoice, Script, Address), MutinyError> {
+        let invoice = Invoice::from_str(&self.invoice).map_err(|_| MutinyError::InvoiceInvalid)?;
+        if invoice.currency() != Currency::from(network) {
+            return Err(MutinyError::IncorrectNetwork(network));
+        }
+        if *invoice.payment_hash() != request.payment_hash
+            || invoice.amount_milli_satoshis().is_none()
+            || self.onchain_amount_sats < request.amount_sats
+            || self.timeout_block_height < height + MIN_LOOP_OUT_TIMEOUT_BLOCKS
+        {
+            return Err(MutinyError::SwapHtlcMismatch);
+        }
+
+        let script = htlc_script(
+            &request.payment_hash,
+            &request.claim_pubkey,
+            &self.refund_pubkey,
+            self.timeout_block_height,
+        );
+        let address = check_htlc_address(&self.lockup_address, &script, network)?;
+
+        Ok((invoice, script, address))
+    }
+}
+
+/// Asks the provider what it would charge for a loop out, without creating one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopOutQuoteRequest {
+    /// The amount we want locked in the HTLC
+    pub amount_sats: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopOutQuoteResponse {
+    /// How much more than the HTLC amount the invoice would be for
+    pub fee_sats: u64,
+}
+
+/// A swap provider we make loop ins and loop outs with
+#[async_trait(?Send)]
+pub trait SwapProvider {
+    async fn create_loop_in(&self, request: &LoopInRequest) -> Result<LoopInResponse, MutinyError>;
+
+    async fn create_loop_out(
+        &self,
+        request: &LoopOutRequest,
+    ) -> Result<LoopOutResponse, MutinyError>;
+
+    async fn quote_loop_out(
+        &self,
+        request: &LoopOutQuoteRequest,
+    ) -> Result<LoopOutQuoteResponse, MutinyError>;
+}
+
+/// A [`SwapProvider`] with an HTTP API
+pub struct HttpSwapProvider {
+    url: String,
+    client: Client,
+}
+
+impl HttpSwapProvider {
+    pub fn new(
+        url: &str,
+        #[cfg(not(target_arch = "wasm32"))] socks5_proxy: Option<&Socks5Proxy>,
+    ) -> Result<Self, MutinyError> {
+        let url = url.trim_end_matches('/').to_string();
+        #[cfg(target_arch = "wasm32")]
+        let client = Client::new();
+        #[cfg(not(target_arch = "wasm32"))]
+        let client = http_client(socks5_proxy, &url)?;
+
+        Ok(Self { url, client })
+    }
+
+    async fn post<T: Serialize, R: DeserializeOwned>(
+        &self,
+        path: &str,
+        request: &T,
+    ) -> Result<R, MutinyError> {
+        let response = self
+            .client
+            .post(format!("{}{path}", self.url))
+            .json(request)
+            .send()
+            .await
+            .map_err(|_| MutinyError::SwapProviderError)?;
+
+        if !response.status().is_success() {
+            return Err(MutinyError::SwapProviderError);
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|_| MutinyError::SwapProviderError)
+    }
+}
+
+#[async_trait(?Send)]
+impl SwapProvider for HttpSwapProvider {
+    async fn create_loop_in(&self, request: &LoopInRequest) -> Result<LoopInResponse, MutinyError> {
+        self.post(LOOP_IN_PATH, request).await
+    }
+
+    async fn create_loop_out(
+        &self,
+        request: &LoopOutRequest,
+    ) -> Result<LoopOutResponse, MutinyError> {
+        self.post(LOOP_OUT_PATH, request).await
+    }
+
+    async fn quote_loop_out(
+        &self,
+        request: &LoopOutQuoteRequest,
+    ) -> Result<LoopOutQuoteResponse, MutinyError> {
+        self.post(LOOP_OUT_QUOTE_PATH, request).await
+    }
+}
+
+/// The script of a swap HTLC. It can be spent by `claim_pubkey` with the preimage of the
+/// payment hash, or by `refund_pubkey` once the block height reaches `timeout_block_height`.
+pub fn htlc_script(
+    payment_hash: &sha256::Hash,
+    claim_pubkey: &PublicKey,
+    refund_pubkey: &PublicKey,
+    timeout_block_height: u32,
+) -> Script {
+    let hash = ripemd160::Hash::hash(&payment_hash[..]);
+
+    Builder::new()
+        .push_opcode(OP_SIZE)
+        .push_int(32)
+        .push_opcode(OP_EQUAL)
+        .push_opcode(OP_IF)
+        .push_opcode(OP_HASH160)
+        .push_slice(&hash[..])
+        .push_opcode(OP_EQUALVERIFY)
+        .push_slice(&claim_pubkey.serialize())
+        .push_opcode(OP_ELSE)
+        .push_opcode(OP_DROP)
+        .push_int(timeout_block_height as i64)
+        .push_opcode(OP_CLTV)
+        .push_opcode(OP_DROP)
+        .push_slice(&refund_pubkey.serialize())
+        .push_opcode(OP_ENDIF)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Checks the address the provider gave us is the one for the HTLC we agreed on
+fn check_htlc_address(
+    address: &str,
+    script: &Script,
+    network: Network,
+) -> Result<Address, MutinyError> {
+    let address = Address::from_str(address).map_err(|_| MutinyError::SwapProviderError)?;
+    if !address.is_valid_for_network(network) {
+        return Err(MutinyError::IncorrectNetwork(address.network));
+    }
+    if address.script_pubkey() != script.to_v0_p2wsh() {
+        return Err(MutinyError::SwapHtlcMismatch);
+    }
+
+    Ok(address)
+}
+
+/// Checks the provider's fee for swapping `amount_sats` is at most `max_fee_sats`,
+/// or without one at most our default of a base fee plus a percent of the amount.
+fn check_swap_fee(
+    fee_sats: u64,
+    amount_sats: u64,
+    max_fee_sats: Option<u64>,
+) -> Result<(), MutinyError> {
+    let max_fee_sats = max_fee_sats.unwrap_or(
+        DEFAULT_MAX_SWAP_FEE_BASE_SATS + amount_sats * DEFAULT_MAX_SWAP_FEE_PERCENT / 100,
+    );
+    if fee_sats > max_fee_sats {
+        return Err(MutinyError::SwapFeeTooHighError);
+    }
+
+    Ok(())
+}
+
+/// Derives our key for the HTLC of a swap
+pub(crate) fn swap_key(
+    xprivkey: &ExtendedPrivKey,
+    key_index: u32,
+) -> Result<SecretKey, MutinyError> {
+    let path = DerivationPath::from_str(SWAP_KEY_DERIVATION_PATH)?
+        .child(ChildNumber::from_hardened_idx(key_index)?);
+    let key = xprivkey.derive_priv(&Secp256k1::new(), &path)?;
+    Ok(key.private_key)
+}
+
+/// A random index for a new swap's key
+fn new_key_index() -> Result<u32, MutinyError> {
+    let mut bytes = [0u8; 4];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|_| MutinyError::Other(anyhow!("Failed to generate swap key index")))?;
+    // hardened indexes only go up to 2^31
+    Ok(u32::from_be_bytes(bytes) & 0x7fff_ffff)
+}
+
+/// The payment hashes and transactions of our swaps, these are shown as part
+/// of the swap in the activity instead of on their own
+pub(crate) fn swap_activity_ids(swaps: &[Swap]) -> (HashSet<sha256::Hash>, HashSet<Txid>) {
+    let hashes = swaps.iter().map(|s| s.payment_hash).collect();
+    let txids = swaps
+        .iter()
+        .flat_map(|s| {
+            s.lockup_outpoint
+                .map(|o| o.txid)
+                .into_iter()
+                .chain(s.spend_txid)
+        })
+        .collect();
+    (hashes, txids)
+}
+
+pub trait SwapStorage {
+    fn get_swap(&self, id: &str) -> Result<Option<Swap>, MutinyError>;
+    fn get_swaps(&self) -> Result<Vec<Swap>, MutinyError>;
+    fn persist_swap(&self, swap: Swap) -> Result<(), MutinyError>;
+}
+
+const SWAP_KEY_PREFIX: &str = "swap/";
+
+fn get_swap_key(id: &str) -> String {
+    format!("{SWAP_KEY_PREFIX}{id}")
+}
+
+impl<S: MutinyStorage> SwapStorage for S {
+    fn get_swap(&self, id: &str) -> Result<Option<Swap>, MutinyError> {
+        self.get_data(get_swap_key(id))
+    }
+
+    fn get_swaps(&self) -> Result<Vec<Swap>, MutinyError> {
+        let map: HashMap<String, Swap> = self.scan(SWAP_KEY_PREFIX, None)?;
+        Ok(map.into_values().collect())
+    }
+
+    fn persist_swap(&self, swap: Swap) -> Result<(), MutinyError> {
+        self.set_data(get_swap_key(&swap.id), swap, None)
+    }
+}
+
+pub trait SwapManager {
+    /// Swaps on-chain funds for lightning funds on the given node. The provider pays
+    /// an invoice of `amount_sats` once we fund the HTLC with that plus its fee.
+    ///
+    /// Fails before funding if the provider's fee is more than `max_fee_sats`,
+    /// or without one, more than 1,000 sats plus 2% of the amount.
+    async fn loop_in(
+        &self,
+        node: &PublicKey,
+        amount_sats: u64,
+        fee_rate: Option<f32>,
+        max_fee_sats: Option<u64>,
+    ) -> Result<Swap, MutinyError>;
+
+    /// Swaps lightning funds from the given node for at least `amount_sats` on-chain.
+    /// We pay the provider's invoice and claim the HTLC it funds into our on-chain wallet.
+    ///
+    /// Fails before paying if the provider's fee is more than `max_fee_sats`,
+    /// or without one, more than 1,000 sats plus 2% of the amount.
+    async fn loop_out(
+        &self,
+        node: &PublicKey,
+        amount_sats: u64,
+        max_fee_sats: Option<u64>,
+    ) -> Result<Swap, MutinyError>;
+
+    /// Swaps lightning funds from the given node to pay `amount_sats` to an on-chain address.
+    /// The HTLC is made big enough to also pay for its claim, with room for fees to rise,
+    /// which pays the address and sends anything left over to our on-chain wallet.
+    /// If fees rose too far to pay the address in full, it is all claimed to our wallet.
+    ///
+    /// Fails before paying if the provider's fee is more than `max_fee_sats`,
+    /// or without one, more than 1,000 sats plus 2% of the amount.
+    async fn loop_out_to_address(
+        &self,
+        node: &PublicKey,
+        address: Address,
+        amount_sats: u64,
+        max_fee_sats: Option<u64>,
+    ) -> Result<Swap, MutinyError>;
+
+    fn get_swap(&self, id: &str) -> Result<Option<Swap>, MutinyError>;
+
+    /// Lists all our swaps, newest first
+    fn list_swaps(&self) -> Result<Vec<Swap>, MutinyError>;
+
+    /// Advances a swap at the current block height, e.g. paying its invoice, claiming or
+    /// refunding it on-chain. The swap is saved if anything changed and returned either way.
+    async fn process_swap(&self, swap: Swap) -> Result<Swap, MutinyError>;
+}
+
+impl<S: MutinyStorage> SwapManager for NodeManager<S> {
+    async fn loop_in(
+        &self,
+        node: &PublicKey,
+        amount_sats: u64,
+        fee_rate: Option<f32>,
+        max_fee_sats: Option<u64>,
+    ) -> Result<Swap, MutinyError> {
+        let provider = self
+            .swap_provider
+            .as_ref()
+            .ok_or(MutinyError::SwapProviderNotConfigured)?;
+        let node = self.get_node(node).await?;
+
+        let key_index = new_key_index()?;
+        let refund_key = swap_key(&self.xprivkey, key_index)?;
+        let refund_pubkey = refund_key.public_key(&Secp256k1::new());
+
+        let invoice = node
+            .create_invoice(Some(amount_sats), vec![SWAP_LABEL.to_string()], None)
+            .await?;
+        let payment_hash = *invoice.payment_hash();
+
+        let request = LoopInRequest {
+            invoice: invoice.to_string(),
+            refund_pubkey,
+        };
+        let response = provider.create_loop_in(&request).await?;
+
+        let height = self
+            .esplora
+            .get_height()
+            .await
+            .map_err(|_| MutinyError::ChainAccessFailed)?;
+        let (redeem_script, address) =
+            response.verify(&payment_hash, &refund_pubkey, self.network, height)?;
+        let provider_fee = response.expected_amount_sats.saturating_sub(amount_sats);
+        check_swap_fee(provider_fee, amount_sats, max_fee_sats)?;
+
+        let mut swap = Swap {
+            id: response.id,
+            swap_type: SwapType::LoopIn,
+            status: SwapStatus::Created,
+            node: node.pubkey,
+            payment_hash,
+            invoice,
+            amount_sats,
+            address,
+            redeem_script,
+            timeout_block_height: response.timeout_block_height,
+            onchain_amount_sats: response.expected_amount_sats,
+            lockup_outpoint: None,
+            spend_txid: None,
+            fees_paid: Some(provider_fee),
+            destination: None,
+            last_updated: utils::now().as_secs(),
+            key_index,
+            preimage: None,
+            spend_height: None,
+            spend_fee_sats: None,
+        };
+        // save before funding so we can still refund if we are shut down while broadcasting
+        self.storage.persist_swap(swap.clone())?;
+
+        let (tx, outpoint, fee) = match self.create_loop_in_funding_tx(&swap, fee_rate) {
+            Ok(signed) => signed,
+            Err(e) => {
+                log_error!(self.logger, "failed to fund loop in {}: {e}", swap.id);
+                swap.fail(e.to_string());
+                swap.last_updated = utils::now().as_secs();
+                self.storage.persist_swap(swap)?;
+                return Err(e);
+            }
+        };
+
+        // the transaction may have gone out anyway, so the swap stays created
+        // until processing finds the HTLC or gives up on it
+        if let Err(e) = self.wallet.broadcast_transaction(tx).await {
+            log_error!(self.logger, "failed to broadcast loop in {}: {e}", swap.id);
+            return Err(e);
+        }
+
+        log_info!(self.logger, "funded loop in {}: {outpoint}", swap.id);
+        swap.lockup_outpoint = Some(outpoint);
+        swap.fees_paid = Some(provider_fee + fee);
+        swap.status = SwapStatus::Funded;
+        swap.last_updated = utils::now().as_secs();
+        self.storage.persist_swap(swap.clone())?;
+        Ok(swap)
+    }
+
+    async fn loop_out(
+        &self,
+        node: &PublicKey,
+        amount_sats: u64,
+        max_fee_sats: Option<u64>,
+    ) -> Result<Swap, MutinyError> {
+        self.start_loop_out(node, amount_sats, None, max_fee_sats)
+            .await
+    }
+
+    async fn loop_out_to_address(
+        &self,
+        node: &PublicKey,
+        address: Address,
+        amount_sats: u64,
+        max_fee_sats: Option<u64>,
+    ) -> Result<Swap, MutinyError> {
+        if !address.is_valid_for_network(self.network) {
+            return Err(MutinyError::IncorrectNetwork(address.network));
+        }
+
+        let destination = SwapDestination {
+            address,
+            amount_sats,
+            paid_sats: None,
+        };
+        let htlc_amount = self.swap_htlc_amount(amount_sats);
+        self.start_loop_out(node, htlc_amount, Some(destination), max_fee_sats)
+            .await
+    }
+
+    fn get_swap(&self, id: &str) -> Result<Option<Swap>, MutinyError> {
+        self.storage.get_swap(id)
+    }
+
+    fn list_swaps(&self) -> Result<Vec<Swap>, MutinyError> {
+        let mut swaps = self.storage.get_swaps()?;
+        swaps.sort_by(|a, b| b.cmp(a));
+        Ok(swaps)
+    }
+
+    async fn process_swap(&self, mut swap: Swap) -> Result<Swap, MutinyError> {
+        let previous = swap.clone();
+        let height = self
+            .esplora
+            .get_height()
+            .await
+            .map_err(|_| MutinyError::ChainAccessFailed)?;
+
+        advance_swap(self, &mut swap, height).await?;
+
+        if swap != previous {
+            log_info!(self.logger, "swap {} is now {:?}", swap.id, swap.status);
+            swap.last_updated = utils::now().as_secs();
+            self.storage.persist_swap(swap.clone())?;
+        }
+
+        Ok(swap)
+    }
+}
+
+impl<S: MutinyStorage> NodeManager<S> {
+    /// What claiming a loop out HTLC should cost at the current fee rates
+    pub(crate) fn estimate_swap_claim_fee(&self) -> u64 {
+        let sat_per_kw =
+            self.wallet
+                .fees
+                .get_est_sat_per_1000_weight(ConfirmationTarget::Normal) as u64;
+        HTLC_CLAIM_WEIGHT * sat_per_kw / 1000
+    }
+
+    /// Signs the transaction funding the HTLC of a loop in, with its HTLC output and fee
+    fn create_loop_in_funding_tx(
+        &self,
+        swap: &Swap,
+        fee_rate: Option<f32>,
+    ) -> Result<(Transaction, OutPoint, u64), MutinyError> {
+        let psbt = self.wallet.create_signed_psbt(
+            swap.address.clone(),
+            swap.onchain_amount_sats,
+            fee_rate,
+        )?;
+        let fee = psbt
+            .fee_amount()
+            .ok_or(MutinyError::WalletOperationFailed)?;
+        self.wallet
+            .label_psbt(&psbt, vec![SWAP_LABEL.to_string()])?;
+
+        let tx = psbt.extract_tx();
+        let script = swap.address.script_pubkey();
+        let vout = tx
+            .output
+            .iter()
+            .position(|o| o.script_pubkey == script)
+            .ok_or(MutinyError::WalletOperationFailed)?;
+        let outpoint = OutPoint::new(tx.txid(), vout as u32);
+        Ok((tx, outpoint, fee))
+    }
+
+    /// The HTLC a loop out needs to pay `amount_sats` to an address, with room
+    /// for the claim fee to rise before we claim
+    pub(crate) fn swap_htlc_amount(&self, amount_sats: u64) -> u64 {
+        let claim_fee = self.estimate_swap_claim_fee();
+        amount_sats + claim_fee * (100 + HTLC_CLAIM_FEE_BUFFER_PERCENT) / 100
+    }
+
+    async fn start_loop_out(
+        &self,
+        node: &PublicKey,
+        amount_sats: u64,
+        destination: Option<SwapDestination>,
+        max_fee_sats: Option<u64>,
+    ) -> Result<Swap, MutinyError> {
+        let provider = self
+            .swap_provider
+            .as_ref()
+            .ok_or(MutinyError::SwapProviderNotConfigured)?;
+        let node = self.get_node(node).await?;
+
+        let key_index = new_key_index()?;
+        let claim_key = swap_key(&self.xprivkey, key_index)?;
+        let claim_pubkey = claim_key.public_key(&Secp256k1::new());
+
+        let mut preimage = [0u8; 32];
+        getrandom::getrandom(&mut preimage)
+            .map_err(|_| MutinyError::Other(anyhow!("Failed to generate swap preimage")))?;
+        let payment_hash = sha256::Hash::hash(&preimage);
+
+        let request = LoopOutRequest {
+            payment_hash,
+            claim_pubkey,
+            amount_sats,
+        };
+        let response = provider.create_loop_out(&request).await?;
+
+        let height = self
+            .esplora
+            .get_height()
+            .await
+            .map_err(|_| MutinyError::ChainAccessFailed)?;
+        let (invoice, redeem_script, address) = response.verify(&request, self.network, height)?;
+        // verify makes sure the invoice has an amount
+        let invoice_amount = invoice.amount_milli_satoshis().unwrap_or_default() / 1_000;
+        let provider_fee = invoice_amount.saturating_sub(response.onchain_amount_sats);
+        check_swap_fee(provider_fee, response.onchain_amount_sats, max_fee_sats)?;
+
+        let mut swap = Swap {
+            id: response.id,
+            swap_type: SwapType::LoopOut,
+            status: SwapStatus::Created,
+            node: node.pubkey,
+            payment_hash,
+            invoice: invoice.clone(),
+            amount_sats: invoice_amount,
+            address,
+            redeem_script,
+            timeout_block_height: response.timeout_block_height,
+            onchain_amount_sats: response.onchain_amount_sats,
+            lockup_outpoint: None,
+            spend_txid: None,
+            fees_paid: Some(provider_fee),
+            destination,
+            last_updated: utils::now().as_secs(),
+            key_index,
+            preimage: Some(preimage),
+            spend_height: None,
+            spend_fee_sats: None,
+        };
+        self.storage.persist_swap(swap.clone())?;
+
+        // the provider holds our payment until we claim the HTLC with the preimage,
+        // so we only start it here and the claim is done in the background
+        if let Err(e) = node
+            .init_invoice_payment(&invoice, None, vec![SWAP_LABEL.to_string()])
+            .await
+        {
+            log_error!(self.logger, "failed to pay for loop out {}: {e}", swap.id);
+            swap.fail(e.to_string());
+            swap.last_updated = utils::now().as_secs();
+            self.storage.persist_swap(swap)?;
+            return Err(e);
+        }
+
+        Ok(swap)
+    }
+}
+
+/// What moving a swap forward needs from the chain, our wallet and our nodes
+#[async_trait(?Send)]
+pub(crate) trait SwapBackend {
+    /// Finds the output funding the HTLC of a swap, with its value
+    async fn find_swap_htlc(
+        &self,
+        swap: &Swap,
+        confirmed_only: bool,
+    ) -> Result<Option<(OutPoint, u64)>, MutinyError>;
+
+    /// The status of the lightning payment of a swap, with the routing fee we paid in msats
+    async fn swap_payment(
+        &self,
+        swap: &Swap,
+    ) -> Result<Option<(HTLCStatus, Option<u64>)>, MutinyError>;
+
+    /// The transaction spending the HTLC of a swap, and if it is confirmed
+    async fn find_swap_htlc_spend(&self, swap: &Swap) -> Result<Option<(Txid, bool)>, MutinyError>;
+
+    /// Spends the HTLC of a swap into our on-chain wallet, paying its destination first.
+    /// Without a fee rate the current estimate is used.
+    async fn spend_swap_htlc(
+        &self,
+        swap: &mut Swap,
+        path: HtlcSpendPath,
+        fee_rate: Option<f32>,
+    ) -> Result<(), MutinyError>;
+}
+
+#[async_trait(?Send)]
+impl<S: MutinyStorage> SwapBackend for NodeManager<S> {
+    async fn find_swap_htlc(
+        &self,
+        swap: &Swap,
+        confirmed_only: bool,
+    ) -> Result<Option<(OutPoint, u64)>, MutinyError> {
+        let script = swap.address.script_pubkey();
+        let txs = self.esplora.scripthash_txs(&script, None).await?;
+
+        let found = txs
+            .iter()
+            .filter(|tx| !confirmed_only || tx.status.confirmed)
+            .find_map(|tx| {
+                tx.vout
+                    .iter()
+                    .enumerate()
+                    .find(|(_, output)| {
+                        output.scriptpubkey == script && output.value >= swap.onchain_amount_sats
+                    })
+                    .map(|(vout, output)| (OutPoint::new(tx.txid, vout as u32), output.value))
+            });
+
+        Ok(found)
+    }
+
+    async fn find_swap_htlc_spend(&self, swap: &Swap) -> Result<Option<(Txid, bool)>, MutinyError> {
+        let Some(outpoint) = swap.lockup_outpoint else {
+            return Ok(None);
+        };
+        let status = self
+            .esplora
+            .get_output_status(&outpoint.txid, outpoint.vout as u64)
+            .await?;
+
+        Ok(status.filter(|s| s.spent).and_then(|s| {
+            let confirmed = s.status.is_some_and(|status| status.confirmed);
+            s.txid.map(|txid| (txid, confirmed))
+        }))
+    }
+
+    async fn spend_swap_htlc(
+        &self,
+        swap: &mut Swap,
+        path: HtlcSpendPath,
+        fee_rate: Option<f32>,
+    ) -> Result<(), MutinyError> {
+        let outpoint = swap
+            .lockup_outpoint
+            .ok_or_else(|| MutinyError::Other(anyhow!("Swap HTLC is not funded")))?;
+        let key = swap_key(&self.xprivkey, swap.key_index)?;
+        // a refund takes everything back to us
+        let payment = match path {
+            HtlcSpendPath::Claim(_) => swap.destination.as_ref().map(|d| TxOut {
+                value: d.amount_sats,
+                script_pubkey: d.address.script_pubkey(),
+            }),
+            HtlcSpendPath::Refund(_) => None,
+        };
+
+        let tx = match self.wallet.create_htlc_spend_tx(
+            outpoint,
+            swap.onchain_amount_sats,
+            &swap.redeem_script,
+            &key,
+            path,
+            payment.clone(),
+            fee_rate,
+        ) {
+            // we must claim before the timeout, so when fees rose too far to
+            // pay the address in full we take it all to our wallet instead
+            Err(MutinyError::InsufficientBalance) if payment.is_some() => {
+                log_warn!(
+                    self.logger,
+                    "swap {} can't cover its payment, claiming to our wallet",
+                    swap.id
+                );
+                self.wallet.create_htlc_spend_tx(
+                    outpoint,
+                    swap.onchain_amount_sats,
+                    &swap.redeem_script,
+                    &key,
+                    path,
+                    None,
+                    fee_rate,
+                )?
+            }
+            res => res?,
+        };
+        let spent: u64 = tx.output.iter().map(|o| o.value).sum();
+        let fee = swap.onchain_amount_sats - spent;
+        for output in tx.output.iter() {
+            if let Ok(address) = Address::from_script(&output.script_pubkey, self.network) {
+                self.storage
+                    .set_address_labels(address, vec![SWAP_LABEL.to_string()])?;
+            }
+        }
+
+        let paid = payment.map(|p| {
+            tx.output
+                .iter()
+                .filter(|o| o.script_pubkey == p.script_pubkey)
+                .map(|o| o.value)
+                .sum::<u64>()
+        });
+
+        let txid = tx.txid();
+        self.wallet.broadcast_transaction(tx).await?;
+
+        if let (Some(destination), Some(paid)) = (swap.destination.as_mut(), paid) {
+            destination.paid_sats = Some(paid);
+        }
+        // a replaced claim never confirms, so only the fee of the last one counts
+        let replaced_fee = swap.spend_fee_sats.unwrap_or_default();
+        swap.spend_txid = Some(txid);
+        swap.spend_fee_sats = Some(fee);
+        swap.fees_paid = Some(swap.fees_paid.unwrap_or_default() - replaced_fee + fee);
+        Ok(())
+    }
+
+    async fn swap_payment(
+        &self,
+        swap: &Swap,
+    ) -> Result<Option<(HTLCStatus, Option<u64>)>, MutinyError> {
+        let node = self.get_node(&swap.node).await?;
+        match swap.swap_type {
+            SwapType::LoopIn => {
+                let invoice = node.get_invoice_by_hash(&swap.payment_hash)?;
+                let status = if invoice.paid {
+                    HTLCStatus::Succeeded
+                } else {
+                    HTLCStatus::Pending
+                };
+                Ok(Some((status, None)))
+            }
+            SwapType::LoopOut => {
+                let payment_hash = PaymentHash(swap.payment_hash.into_inner());
+                let payment = node
+                    .persister
+                    .read_payment_info(&payment_hash, false, &self.logger);
+                Ok(payment.map(|p| (p.status, p.fee_paid_msat)))
+            }
+        }
+    }
+}
+
+/// Moves a swap as far forward as it can go at the given block height
+pub(crate) async fn advance_swap(
+    backend: &impl SwapBackend,
+    swap: &mut Swap,
+    height: u32,
+) -> Result<(), MutinyError> {
+    match swap.swap_type {
+        SwapType::LoopIn => advance_loop_in(backend, swap, height).await,
+        SwapType::LoopOut => advance_loop_out(backend, swap, height).await,
+    }
+}
+
+async fn advance_loop_in(
+    backend: &impl SwapBackend,
+    swap: &mut Swap,
+    height: u32,
+) -> Result<(), MutinyError> {
+    if swap.status == SwapStatus::Created {
+        // we may have been shut down while funding, look for our transaction
+        if let Some((outpoint, _)) = backend.find_swap_htlc(swap, false).await? {
+            swap.lockup_outpoint = Some(outpoint);
+            swap.status = SwapStatus::Funded;
+        } else if utils::now().as_secs() > swap.last_updated + LOOP_IN_FUNDING_TIMEOUT_SECS {
+            swap.fail("The HTLC was never funded".to_string());
+        }
+    }
+
+    if swap.status == SwapStatus::Funded {
+        let paid = backend
+            .swap_payment(swap)
+            .await?
+            .is_some_and(|(status, _)| status == HTLCStatus::Succeeded);
+        if paid {
+            swap.status = SwapStatus::Completed;
+        } else if height >= swap.timeout_block_height {
+            let timeout = swap.timeout_block_height;
+            backend
+                .spend_swap_htlc(swap, HtlcSpendPath::Refund(timeout), None)
+                .await?;
+            swap.status = SwapStatus::Refunded;
+        }
+    }
+
+    Ok(())
+}
+
+async fn advance_loop_out(
+    backend: &impl SwapBackend,
+    swap: &mut Swap,
+    height: u32,
+) -> Result<(), MutinyError> {
+    let payment = backend.swap_payment(swap).await?;
+
+    if swap.status == SwapStatus::Created {
+        // only claim once the lockup is confirmed, otherwise the provider
+        // could learn the preimage and double spend it
+        if let Some((outpoint, value)) = backend.find_swap_htlc(swap, true).await? {
+            swap.lockup_outpoint = Some(outpoint);
+            swap.onchain_amount_sats = value;
+            swap.status = SwapStatus::Funded;
+        } else if payment
+            .as_ref()
+            .is_some_and(|(status, _)| *status == HTLCStatus::Failed)
+        {
+            swap.fail("The lightning payment failed".to_string());
+        } else if height >= swap.timeout_block_height {
+            swap.fail("The swap provider never funded the HTLC".to_string());
+        }
+    }
+
+    let preimage = match swap.status {
+        SwapStatus::Funded | SwapStatus::Claimed => swap
+            .preimage
+            .ok_or_else(|| MutinyError::Other(anyhow!("Loop out is missing its preimage")))?,
+        _ => return Ok(()),
+    };
+
+    if swap.status == SwapStatus::Funded {
+        backend
+            .spend_swap_htlc(swap, HtlcSpendPath::Claim(preimage), None)
+            .await?;
+        swap.spend_height = Some(height);
+        swap.status = SwapStatus::Claimed;
+        return Ok(());
+    }
+
+    // the provider can refund the HTLC after the timeout, so the claim must confirm before it
+    let new_block = swap.spend_height.map_or(true, |h| height > h);
+    let confirmed = match backend.find_swap_htlc_spend(swap).await? {
+        Some((txid, confirmed)) => {
+            // one of our earlier claims may be the one that confirms
+            swap.spend_txid = Some(txid);
+            if !confirmed && new_block && height + CLAIM_BUMP_BLOCKS >= swap.timeout_block_height {
+                let fee_rate = bump_claim_fee_rate(swap.spend_fee_sats.unwrap_or_default());
+                backend
+                    .spend_swap_htlc(swap, HtlcSpendPath::Claim(preimage), Some(fee_rate))
+                    .await?;
+                swap.spend_height = Some(height);
+            }
+            confirmed
+        }
+        None => {
+            // our claim was dropped, broadcast it again
+            if new_block {
+                backend
+                    .spend_swap_htlc(swap, HtlcSpendPath::Claim(preimage), None)
+                    .await?;
+                swap.spend_height = Some(height);
+            }
+            false
+        }
+    };
+
+    if confirmed {
+        // a failed payment still completes the swap, we have the on-chain funds
+        match payment {
+            Some((HTLCStatus::Succeeded, fee_paid_msat)) => {
+                let routing_fee = fee_paid_msat.unwrap_or_default() / 1_000;
+                swap.fees_paid = Some(swap.fees_paid.unwrap_or_default() + routing_fee);
+                swap.status = SwapStatus::Completed;
+            }
+            Some((HTLCStatus::Failed, _)) => swap.status = SwapStatus::Completed,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// A fee rate that replaces a claim that paid `fee` sats, in sats per vbyte
+fn bump_claim_fee_rate(fee: u64) -> f32 {
+    let rate = (fee * 4) as f32 / HTLC_CLAIM_WEIGHT as f32;
+    rate * 1.5 + 1.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::test_utils::*;
+    use lightning::ln::PaymentSecret;
+    use lightning_invoice::InvoiceBuilder;
+    use std::cell::RefCell;
+    use wasm_bindgen_test::{wasm_bindgen_test as test, wasm_bindgen_test_configure};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    const HEIGHT: u32 = 800_000;
+    const FEE: u64 = 500;
+
+    /// A provider that makes the HTLCs the way it should, unless it is dishonest
+    /// and makes them claimable with its own key by either path.
+    struct MockSwapProvider {
+        key: SecretKey,
+        dishonest: bool,
+    }
+
+    impl MockSwapProvider {
+        fn new(dishonest: bool) -> Self {
+            Self {
+                key: SecretKey::from_slice(&[7; 32]).unwrap(),
+                dishonest,
+            }
+        }
+
+        fn pubkey(&self) -> PublicKey {
+            self.key.public_key(&Secp256k1::new())
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl SwapProvider for MockSwapProvider {
+        async fn create_loop_in(
+            &self,
+            request: &LoopInRequest,
+        ) -> Result<LoopInResponse, MutinyError> {
+            let invoice = Invoice::from_str(&request.invoice).unwrap();
+            let refund_pubkey = if self.dishonest {
+                self.pubkey()
+            } else {
+                request.refund_pubkey
+            };
+            let timeout_block_height = HEIGHT + 144;
+            let script = htlc_script(
+                invoice.payment_hash(),
+                &self.pubkey(),
+                &refund_pubkey,
+                timeout_block_height,
+            );
+
+            Ok(LoopInResponse {
+                id: "loop-in".to_string(),
+                address: Address::p2wsh(&script, Network::Bitcoin).to_string(),
+                claim_pubkey: self.pubkey(),
+                timeout_block_height,
+                expected_amount_sats: invoice.amount_milli_satoshis().unwrap() / 1_000 + FEE,
+            })
+        }
+
+        async fn create_loop_out(
+            &self,
+            request: &LoopOutRequest,
+        ) -> Result<LoopOutResponse, MutinyError> {
+            let claim_pubkey = if self.dishonest {
+                self.pubkey()
+            } else {
+                request.claim_pubkey
+            };
+            let timeout_block_height = HEIGHT + 144;
+            let script = htlc_script(
+                &request.payment_hash,
+                &claim_pubkey,
+                &self.pubkey(),
+                timeout_block_height,
+            );
+
+            let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+                .description(String::new())
+                .payment_hash(request.payment_hash)
+                .payment_secret(PaymentSecret([0; 32]))
+                .duration_since_epoch(utils::now())
+                .min_final_cltv_expiry_delta(144)
+                .amount_milli_satoshis((request.amount_sats + FEE) * 1_000)
+                .build_signed(|hash| Secp256k1::new().sign_ecdsa_recoverable(hash, &self.key))
+                .unwrap();
+
+            Ok(LoopOutResponse {
+                id: "loop-out".to_string(),
+                invoice: invoice.to_string(),
+                lockup_address: Address::p2wsh(&script, Network::Bitcoin).to_string(),
+                refund_pubkey: self.pubkey(),
+                timeout_block_height,
+                onchain_amount_sats: request.amount_sats,
+            })
+        }
+
+        async fn quote_loop_out(
+            &self,
+            _request: &LoopOutQuoteRequest,
+        ) -> Result<LoopOutQuoteResponse, MutinyError> {
+            Ok(LoopOutQuoteResponse { fee_sats: FEE })
+        }
+    }
+
+    /// Our chain, wallet and nodes as a swap sees them
+    #[derive(Default)]
+    struct MockSwapBackend {
+        htlc: Option<(OutPoint, u64)>,
+        htlc_confirmed: bool,
+        /// The transaction spending the HTLC, and if it is confirmed
+        htlc_spend: Option<(Txid, bool)>,
+        payment: Option<(HTLCStatus, Option<u64>)>,
+        spends: RefCell<Vec<(HtlcSpendPath, Option<f32>)>>,
+    }
+
+    #[async_trait(?Send)]
+    impl SwapBackend for MockSwapBackend {
+        async fn find_swap_htlc(
+            &self,
+            _swap: &Swap,
+            confirmed_only: bool,
+        ) -> Result<Option<(OutPoint, u64)>, MutinyError> {
+            if confirmed_only && !self.htlc_confirmed {
+                return Ok(None);
+            }
+            Ok(self.htlc)
+        }
+
+        async fn swap_payment(
+            &self,
+            _swap: &Swap,
+        ) -> Result<Option<(HTLCStatus, Option<u64>)>, MutinyError> {
+            Ok(self.payment.clone())
+        }
+
+        async fn find_swap_htlc_spend(
+            &self,
+            _swap: &Swap,
+        ) -> Result<Option<(Txid, bool)>, MutinyError> {
+            Ok(self.htlc_spend)
+        }
+
+        async fn spend_swap_htlc(
+            &self,
+            swap: &mut Swap,
+            path: HtlcSpendPath,
+            fee_rate: Option<f32>,
+        ) -> Result<(), MutinyError> {
+            self.spends.borrow_mut().push((path, fee_rate));
+            let replaced_fee = swap.spend_fee_sats.unwrap_or_default();
+            swap.spend_txid = Some(Txid::all_zeros());
+            swap.spend_fee_sats = Some(200);
+            swap.fees_paid = Some(swap.fees_paid.unwrap_or_default() - replaced_fee + 200);
+            Ok(())
+        }
+    }
+
+    /// A loop in made with the provider, before we fund it
+    async fn create_loop_in_swap(provider: &MockSwapProvider) -> Swap {
+        let invoice = Invoice::from_str(INVOICE).unwrap();
+        let request = LoopInRequest {
+            invoice: INVOICE.to_string(),
+            refund_pubkey: our_pubkey(),
+        };
+        let response = provider.create_loop_in(&request).await.unwrap();
+        let (redeem_script, address) = response
+            .verify(
+                invoice.payment_hash(),
+                &our_pubkey(),
+                Network::Bitcoin,
+                HEIGHT,
+            )
+            .unwrap();
+
+        Swap {
+            id: response.id,
+            address,
+            redeem_script,
+            timeout_block_height: response.timeout_block_height,
+            onchain_amount_sats: response.expected_amount_sats,
+            fees_paid: Some(FEE),
+            ..swap_fixture(SwapType::LoopIn)
+        }
+    }
+
+    /// A loop out made with the provider, before it funds it
+    async fn create_loop_out_swap(provider: &MockSwapProvider) -> Swap {
+        let preimage = [1; 32];
+        let request = LoopOutRequest {
+            payment_hash: sha256::Hash::hash(&preimage),
+            claim_pubkey: our_pubkey(),
+            amount_sats: 100_000,
+        };
+        let response = provider.create_loop_out(&request).await.unwrap();
+        let (invoice, redeem_script, address) =
+            response.verify(&request, Network::Bitcoin, HEIGHT).unwrap();
+
+        Swap {
+            id: response.id,
+            payment_hash: request.payment_hash,
+            invoice,
+            amount_sats: 100_000 + FEE,
+            address,
+            redeem_script,
+            timeout_block_height: response.timeout_block_height,
+            onchain_amount_sats: response.onchain_amount_sats,
+            fees_paid: Some(FEE),
+            preimage: Some(preimage),
+            ..swap_fixture(SwapType::LoopOut)
+        }
+    }
+
+    #[test]
+    async fn test_loop_in_htlc() {
+        let test_name = "test_loop_in_htlc";
+        log!("{}", test_name);
+
+        let invoice = Invoice::from_str(INVOICE).unwrap();
+        let request = LoopInRequest {
+            invoice: INVOICE.to_string(),
+            refund_pubkey: our_pubkey(),
+        };
+
+        let provider = MockSwapProvider::new(false);
+        let response = provider.create_loop_in(&request).await.unwrap();
+        let (script, address) = response
+            .verify(
+                invoice.payment_hash(),
+                &our_pubkey(),
+                Network::Bitcoin,
+                HEIGHT,
+            )
+            .unwrap();
+        assert_eq!(address.script_pubkey(), script.to_v0_p2wsh());
+        assert_eq!(
+            script,
+            htlc_script(
+                invoice.payment_hash(),
+                &provider.pubkey(),
+                &our_pubkey(),
+                HEIGHT + 144
+            )
+        );
+
+        // a timeout too far out would lock up our funds
+        assert!(matches!(
+            response.verify(invoice.payment_hash(), &our_pubkey(), Network::Bitcoin, 0),
+            Err(MutinyError::SwapHtlcMismatch)
+        ));
+
+        // a timeout too soon does not give the provider time to pay us
+        assert!(matches!(
+            response.verify(
+                invoice.payment_hash(),
+                &our_pubkey(),
+                Network::Bitcoin,
+                HEIGHT + 144 - MIN_LOOP_IN_TIMEOUT_BLOCKS + 1
+            ),
+            Err(MutinyError::SwapHtlcMismatch)
+        ));
+        assert!(response
+            .verify(
+                invoice.payment_hash(),
+                &our_pubkey(),
+                Network::Bitcoin,
+                HEIGHT + 144 - MIN_LOOP_IN_TIMEOUT_BLOCKS
+            )
+            .is_ok());
+
+        // on the wrong network
+        assert!(matches!(
+            response.verify(
+                invoice.payment_hash(),
+                &our_pubkey(),
+                Network::Testnet,
+                HEIGHT
+            ),
+            Err(MutinyError::IncorrectNetwork(_))
+        ));
+
+        let dishonest = MockSwapProvider::new(true);
+        let response = dishonest.create_loop_in(&request).await.unwrap();
+        assert!(matches!(
+            response.verify(
+                invoice.payment_hash(),
+                &our_pubkey(),
+                Network::Bitcoin,
+                HEIGHT
+            ),
+            Err(MutinyError::SwapHtlcMismatch)
+        ));
+    }
+
+    #[test]
+    async fn test_loop_out_htlc() {
+        let test_name = "test_loop_out_htlc";
+        log!("{}", test_name);
+
+        let request = LoopOutRequest {
+            payment_hash: sha256::Hash::hash(&[1; 32]),
+            claim_pubkey: our_pubkey(),
+            amount_sats: 100_000,
+        };
+
+        let provider = MockSwapProvider::new(false);
+        let response = provider.create_loop_out(&request).await.unwrap();
+        let (invoice, script, address) =
+            response.verify(&request, Network::Bitcoin, HEIGHT).unwrap();
+        assert_eq!(*invoice.payment_hash(), request.payment_hash);
+        assert_eq!(invoice.amount_milli_satoshis(), Some(100_500_000));
+        assert_eq!(address.script_pubkey(), script.to_v0_p2wsh());
+
+        // not enough time left to claim
+        assert!(matches!(
+            response.verify(&request, Network::Bitcoin, HEIGHT + 144),
+            Err(MutinyError::SwapHtlcMismatch)
+        ));
+
+        // an invoice for a different payment hash
+        let mut wrong_invoice = response.clone();
+        wrong_invoice.invoice = INVOICE.to_string();
+        assert!(matches!(
+            wrong_invoice.verify(&request, Network::Bitcoin, HEIGHT),
+            Err(MutinyError::SwapHtlcMismatch)
+        ));
+
+        let dishonest = MockSwapProvider::new(true);
+        let response = dishonest.create_loop_out(&request).await.unwrap();
+        assert!(matches!(
+            response.verify(&request, Network::Bitcoin, HEIGHT),
+            Err(MutinyError::SwapHtlcMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_swap_fee() {
+        let test_name = "test_swap_fee";
+        log!("{}", test_name);
+
+        assert!(check_swap_fee(FEE, 100_000, None).is_ok());
+        assert!(check_swap_fee(FEE, 100_000, Some(FEE)).is_ok());
+        assert!(matches!(
+            check_swap_fee(FEE, 100_000, Some(FEE - 1)),
+            Err(MutinyError::SwapFeeTooHighError)
+        ));
+
+        // without a max fee the provider still can't charge whatever it wants
+        assert!(check_swap_fee(3_000, 100_000, None).is_ok());
+        assert!(matches!(
+            check_swap_fee(3_001, 100_000, None),
+            Err(MutinyError::SwapFeeTooHighError)
+        ));
+        assert!(matches!(
+            check_swap_fee(1_000_000, 100_000, None),
+            Err(MutinyError::SwapFeeTooHighError)
+        ));
+        // an explicit max fee can allow more
+        assert!(check_swap_fee(5_000, 100_000, Some(5_000)).is_ok());
+    }
+
+    #[test]
+    fn test_swap_key() {
+        let test_name = "test_swap_key";
+        log!("{}", test_name);
+
+        let xprivkey = ExtendedPrivKey::new_master(Network::Bitcoin, &[0; 32]).unwrap();
+        let key = swap_key(&xprivkey, 1).unwrap();
+        assert_eq!(key, swap_key(&xprivkey, 1).unwrap());
+        assert_ne!(key, swap_key(&xprivkey, 2).unwrap());
+        assert!(new_key_index().unwrap() < 0x8000_0000);
+    }
+
+    #[test]
+    fn test_persist_swap() {
+        let test_name = "test_persist_swap";
+        log!("{}", test_name);
+
+        let storage = MemoryStorage::default();
+        let invoice = Invoice::from_str(INVOICE).unwrap();
+        let script = htlc_script(
+            invoice.payment_hash(),
+            &MockSwapProvider::new(false).pubkey(),
+            &our_pubkey(),
+            HEIGHT + 144,
+        );
+
+        let mut swap = Swap {
+            id: "loop-in".to_string(),
+            address: Address::p2wsh(&script, Network::Bitcoin),
+            redeem_script: script,
+            timeout_block_height: HEIGHT + 144,
+            onchain_amount_sats: 92_372 + FEE,
+            fees_paid: Some(FEE),
+            last_updated: 1686258926,
+            ..swap_fixture(SwapType::LoopIn)
+        };
+        assert!(swap.status.is_in_progress());
+        assert_eq!(swap.amount_received_sats(), 92_372);
+        assert_eq!(storage.get_swap(&swap.id).unwrap(), None);
+
+        storage.persist_swap(swap.clone()).unwrap();
+        assert_eq!(storage.get_swap(&swap.id).unwrap(), Some(swap.clone()));
+
+        swap.status = SwapStatus::Refunded;
+        storage.persist_swap(swap.clone()).unwrap();
+        assert!(!swap.status.is_in_progress());
+        assert_eq!(storage.get_swaps().unwrap(), vec![swap]);
+    }
+
+    #[test]
+    async fn test_process_loop_in() {
+        let test_name = "test_process_loop_in";
+        log!("{}", test_name);
+
+        let provider = MockSwapProvider::new(false);
+        let storage = MemoryStorage::default();
+        let outpoint = OutPoint::new(Txid::all_zeros(), 1);
+
+        // waiting on our funding transaction
+        let mut swap = create_loop_in_swap(&provider).await;
+        let backend = MockSwapBackend::default();
+        advance_swap(&backend, &mut swap, HEIGHT).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Created);
+
+        // we were shut down while funding, after a restart we find the funding transaction
+        storage.persist_swap(swap.clone()).unwrap();
+        let mut swap = storage.get_swap(&swap.id).unwrap().unwrap();
+        let backend = MockSwapBackend {
+            htlc: Some((outpoint, swap.onchain_amount_sats)),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut swap, HEIGHT).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Funded);
+        assert_eq!(swap.lockup_outpoint, Some(outpoint));
+
+        // waiting on the provider to pay us
+        let backend = MockSwapBackend {
+            htlc: Some((outpoint, swap.onchain_amount_sats)),
+            payment: Some((HTLCStatus::Pending, None)),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut swap, HEIGHT + 1).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Funded);
+        assert!(backend.spends.borrow().is_empty());
+
+        // the provider paid
+        let mut paid = swap.clone();
+        let backend = MockSwapBackend {
+            payment: Some((HTLCStatus::Succeeded, None)),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut paid, HEIGHT + 1).await.unwrap();
+        assert_eq!(paid.status, SwapStatus::Completed);
+        assert!(backend.spends.borrow().is_empty());
+
+        // the provider never paid, we take the funds back after a restart
+        storage.persist_swap(swap.clone()).unwrap();
+        let mut swap = storage.get_swap(&swap.id).unwrap().unwrap();
+        let backend = MockSwapBackend {
+            payment: Some((HTLCStatus::Pending, None)),
+            ..Default::default()
+        };
+        let timeout = swap.timeout_block_height;
+        advance_swap(&backend, &mut swap, timeout).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Refunded);
+        assert_eq!(
+            backend.spends.borrow().as_slice(),
+            &[(HtlcSpendPath::Refund(timeout), None)]
+        );
+        assert!(swap.spend_txid.is_some());
+        assert_eq!(swap.fees_paid, Some(FEE + 200));
+
+        // a refunded swap is done
+        advance_swap(&backend, &mut swap, timeout + 1)
+            .await
+            .unwrap();
+        assert_eq!(swap.status, SwapStatus::Refunded);
+        assert_eq!(backend.spends.borrow().len(), 1);
+
+        // never funded
+        let mut swap = create_loop_in_swap(&provider).await;
+        swap.last_updated -= LOOP_IN_FUNDING_TIMEOUT_SECS + 1;
+        advance_swap(&MockSwapBackend::default(), &mut swap, HEIGHT)
+            .await
+            .unwrap();
+        assert!(matches!(swap.status, SwapStatus::Failed(_)));
+    }
+
+    #[test]
+    async fn test_process_loop_out() {
+        let test_name = "test_process_loop_out";
+        log!("{}", test_name);
+
+        let provider = MockSwapProvider::new(false);
+        let storage = MemoryStorage::default();
+        let outpoint = OutPoint::new(Txid::all_zeros(), 1);
+        let in_flight = Some((HTLCStatus::InFlight, None));
+
+        // the provider funded the HTLC but it is not confirmed, so we don't claim yet
+        let mut swap = create_loop_out_swap(&provider).await;
+        let backend = MockSwapBackend {
+            htlc: Some((outpoint, swap.onchain_amount_sats)),
+            payment: in_flight.clone(),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut swap, HEIGHT).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Created);
+        assert!(backend.spends.borrow().is_empty());
+
+        // after a restart it is confirmed, with more than we asked for
+        storage.persist_swap(swap.clone()).unwrap();
+        let mut swap = storage.get_swap(&swap.id).unwrap().unwrap();
+        let backend = MockSwapBackend {
+            htlc: Some((outpoint, swap.onchain_amount_sats + 1)),
+            htlc_confirmed: true,
+            payment: in_flight.clone(),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut swap, HEIGHT).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Claimed);
+        assert_eq!(swap.lockup_outpoint, Some(outpoint));
+        assert_eq!(swap.onchain_amount_sats, 100_001);
+        assert_eq!(swap.spend_height, Some(HEIGHT));
+        assert_eq!(
+            backend.spends.borrow().as_slice(),
+            &[(HtlcSpendPath::Claim([1; 32]), None)]
+        );
+
+        // the payment finished but the claim is not confirmed, we don't claim again yet
+        let claim = Txid::from_slice(&[2; 32]).unwrap();
+        let backend = MockSwapBackend {
+            htlc_spend: Some((claim, false)),
+            payment: Some((HTLCStatus::Succeeded, Some(3_000))),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut swap, HEIGHT + 1).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Claimed);
+        assert_eq!(swap.spend_txid, Some(claim));
+        assert!(backend.spends.borrow().is_empty());
+
+        // our claim was dropped, it is broadcast again once a block
+        let mut dropped = swap.clone();
+        let backend = MockSwapBackend {
+            payment: Some((HTLCStatus::Succeeded, Some(3_000))),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut dropped, HEIGHT + 1)
+            .await
+            .unwrap();
+        advance_swap(&backend, &mut dropped, HEIGHT + 2)
+            .await
+            .unwrap();
+        advance_swap(&backend, &mut dropped, HEIGHT + 2)
+            .await
+            .unwrap();
+        assert_eq!(dropped.status, SwapStatus::Claimed);
+        assert_eq!(
+            backend.spends.borrow().as_slice(),
+            &[
+                (HtlcSpendPath::Claim([1; 32]), None),
+                (HtlcSpendPath::Claim([1; 32]), None)
+            ]
+        );
+        assert_eq!(dropped.fees_paid, Some(FEE + 200));
+
+        // still not confirmed close to the timeout, so it is replaced with a higher fee once a block
+        let mut stuck = swap.clone();
+        let backend = MockSwapBackend {
+            htlc_spend: Some((claim, false)),
+            payment: Some((HTLCStatus::Succeeded, Some(3_000))),
+            ..Default::default()
+        };
+        let bump_height = swap.timeout_block_height - CLAIM_BUMP_BLOCKS;
+        advance_swap(&backend, &mut stuck, bump_height)
+            .await
+            .unwrap();
+        advance_swap(&backend, &mut stuck, bump_height)
+            .await
+            .unwrap();
+        assert_eq!(stuck.status, SwapStatus::Claimed);
+        assert_eq!(
+            backend.spends.borrow().as_slice(),
+            &[(
+                HtlcSpendPath::Claim([1; 32]),
+                Some(bump_claim_fee_rate(200))
+            )]
+        );
+        assert!(bump_claim_fee_rate(200) > (200 * 4) as f32 / HTLC_CLAIM_WEIGHT as f32);
+
+        // after another restart the claim confirmed and the payment finished
+        storage.persist_swap(swap.clone()).unwrap();
+        let mut swap = storage.get_swap(&swap.id).unwrap().unwrap();
+        let backend = MockSwapBackend {
+            htlc_spend: Some((claim, true)),
+            payment: Some((HTLCStatus::Succeeded, Some(3_000))),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut swap, HEIGHT + 2).await.unwrap();
+        assert_eq!(swap.status, SwapStatus::Completed);
+        assert_eq!(swap.fees_paid, Some(FEE + 200 + 3));
+        assert!(backend.spends.borrow().is_empty());
+
+        // our payment failed before the provider funded the HTLC
+        let mut swap = create_loop_out_swap(&provider).await;
+        let backend = MockSwapBackend {
+            payment: Some((HTLCStatus::Failed, None)),
+            ..Default::default()
+        };
+        advance_swap(&backend, &mut swap, HEIGHT).await.unwrap();
+        assert!(matches!(swap.status, SwapStatus::Failed(_)));
+
+        // the provider never funded the HTLC
+        let mut swap = create_loop_out_swap(&provider).await;
+        let backend = MockSwapBackend {
+            payment: in_flight,
+            ..Default::default()
+        };
+        let timeout = swap.timeout_block_height;
+        advance_swap(&backend, &mut swap, timeout).await.unwrap();
+        assert!(matches!(swap.status, SwapStatus::Failed(_)));
+        assert!(backend.spends.borrow().is_empty());
+    }
+}
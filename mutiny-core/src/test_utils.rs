@@ -19,3 +19,54 @@ use bitcoin::{util::bip32::ExtendedPrivKey, Network};
 pub(crate) use log;
 
 use crate::{generate_seed, lnurlauth::AuthManager};
+#[cfg(test)]
+use crate::{
+    swaps::{Swap, SwapStatus, SwapType},
+    utils,
+};
+#[cfg(test)]
+use bitcoin::{secp256k1::PublicKey, Address, Script};
+#[cfg(test)]
+use lightning_invoice::Invoice;
+#[cfg(test)]
+use std::str::FromStr;
+
+/// A mainnet invoice for 92,372 sats
+#[cfg(test)]
+pub(crate) const INVOICE: &str = "lnbc923720n1pj9nrefpp5pczykgk37af5388n8dzynljpkzs7sje4melqgazlwv9y3apay8jqhp5rd8saxz3juve3eejq7z5fjttxmpaq88d7l92xv34n4h3mq6kwq2qcqzzsxqzfvsp5z0jwpehkuz9f2kv96h62p8x30nku76aj8yddpcust7g8ad0tr52q9qyyssqfy622q25helv8cj8hyxqltws4rdwz0xx2hw0uh575mn7a76cp3q4jcptmtjkjs4a34dqqxn8uy70d0qlxqleezv4zp84uk30pp5q3nqq4c9gkz";
+
+/// The pubkey of the node making a swap
+#[cfg(test)]
+pub(crate) fn our_pubkey() -> PublicKey {
+    PublicKey::from_str("02cae09cf2c8842ace44068a5bf3117a494ebbf69a99e79712483c36f97cdb7b54")
+        .unwrap()
+}
+
+/// A new swap for [`INVOICE`] from [`our_pubkey`], tests fill in
+/// the fields they care about with struct update syntax.
+#[cfg(test)]
+pub(crate) fn swap_fixture(swap_type: SwapType) -> Swap {
+    let invoice = Invoice::from_str(INVOICE).unwrap();
+    Swap {
+        id: "swap".to_string(),
+        swap_type,
+        status: SwapStatus::Created,
+        node: our_pubkey(),
+        payment_hash: *invoice.payment_hash(),
+        invoice,
+        amount_sats: 92_372,
+        address: Address::p2wsh(&Script::new(), Network::Bitcoin),
+        redeem_script: Script::new(),
+        timeout_block_height: 800_144,
+        onchain_amount_sats: 92_372,
+        lockup_outpoint: None,
+        spend_txid: None,
+        fees_paid: None,
+        destination: None,
+        last_updated: utils::now().as_secs(),
+        key_index: 1,
+        preimage: None,
+        spend_height: None,
+        spend_fee_sats: None,
+    }
+}
@@ -14,6 +14,46 @@ pub(crate) fn min_lightning_amount(network: Network) -> u64 {
     }
 }
 
+/// The RFC 4648 base32 alphabet, lowercase as used by onion addresses
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Lowercase RFC 4648 base32 without padding, as used by onion addresses
+pub(crate) fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8 + 4) / 5);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes lowercase RFC 4648 base32 without padding, the reverse of
+/// [`base32_encode`]. Returns None if it has a character outside the alphabet.
+pub(crate) fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a == c)?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
 pub async fn sleep(millis: i32) {
     #[cfg(target_arch = "wasm32")]
     {
@@ -110,13 +150,17 @@ impl<'a, S: Writeable> Writeable for MutexGuard<'a, S> {
     }
 }
 
+/// Runs the future in the background.
+///
+/// Our futures are not `Send`, so on native this must be called from within a
+/// tokio `LocalSet`, the task runs for as long as that `LocalSet` is driven.
 pub fn spawn<F>(future: F)
 where
     F: core::future::Future<Output = ()> + 'static,
 {
     #[cfg(not(target_arch = "wasm32"))]
     {
-        tokio::task::LocalSet::new().spawn_local(future);
+        tokio::task::spawn_local(future);
     }
     #[cfg(target_arch = "wasm32")]
     {
@@ -21,6 +21,9 @@ pub enum MutinyJsError {
     /// A network connection has been closed.
     #[error("Network connection closed.")]
     ConnectionFailed,
+    /// The proxy is in strict mode and the destination is not an onion service.
+    #[error("Refused to make a clearnet connection in strict proxy mode.")]
+    ClearnetConnectionRefused,
     /// The invoice or address is on a different network
     #[error("The invoice or address is on a different network.")]
     IncorrectNetwork(Network),
@@ -58,6 +61,27 @@ pub enum MutinyJsError {
     /// LSP indicated it was not connected to the client node.
     #[error("Failed to have a connection to the LSP node.")]
     LspConnectionError,
+    /// The LSP's fee is higher than the max fee configured for it.
+    #[error("Failed to request channel from LSP due to fee being above the configured max.")]
+    LspFeeTooHighError,
+    /// The LSP's fee went up too much since the invoice was quoted.
+    #[error("Failed to create invoice, the LSP's fee changed since the quote.")]
+    LspQuoteChangedError,
+    /// No swap provider is configured.
+    #[error("Swap provider not configured.")]
+    SwapProviderNotConfigured,
+    /// The swap provider could not be reached or gave an invalid response.
+    #[error("Failed to make a request to the swap provider.")]
+    SwapProviderError,
+    /// The HTLC or invoice the swap provider gave does not match the swap.
+    #[error("The swap provider's HTLC does not match the swap.")]
+    SwapHtlcMismatch,
+    /// The swap provider's fee is higher than the max fee given.
+    #[error("The swap provider's fee is above the max fee.")]
+    SwapFeeTooHighError,
+    /// The fee of paying on-chain from lightning went up since the quote.
+    #[error("Failed to pay on-chain, the fee went up since the quote.")]
+    PayOnchainQuoteChangedError,
     /// Subscription Client Not Configured
     #[error("Subscription Client Not Configured")]
     SubscriptionClientNotConfigured,
@@ -128,6 +152,15 @@ pub enum MutinyJsError {
     /// Invalid Arguments were given
     #[error("Invalid Arguments were given")]
     InvalidArgumentsError,
+    /// The channel policy is not one we can use
+    #[error("Invalid channel policy: {0}")]
+    InvalidChannelPolicy(String),
+    /// Not enough on-chain funds would be left to fee bump our anchor channels
+    #[error("Not enough on-chain funds would be left to fee bump our anchor channels.")]
+    AnchorReserveRequired,
+    /// The channel backup is too big to fit in a peer storage message
+    #[error("The channel backup is too large for peer storage.")]
+    PeerStorageTooLarge,
     /// Incorrect password entered.
     #[error("Incorrect password entered.")]
     IncorrectPassword,
@@ -144,6 +177,7 @@ impl From<MutinyError> for MutinyJsError {
             MutinyError::NotFound => MutinyJsError::NotFound,
             MutinyError::FundingTxCreationFailed => MutinyJsError::FundingTxCreationFailed,
             MutinyError::ConnectionFailed => MutinyJsError::ConnectionFailed,
+            MutinyError::ClearnetConnectionRefused => MutinyJsError::ClearnetConnectionRefused,
             MutinyError::IncorrectNetwork(net) => MutinyJsError::IncorrectNetwork(net),
             MutinyError::NonUniquePaymentHash => MutinyJsError::NonUniquePaymentHash,
             MutinyError::PaymentTimeout => MutinyJsError::PaymentTimeout,
@@ -155,6 +189,13 @@ impl From<MutinyError> for MutinyJsError {
             MutinyError::LspGenericError => MutinyJsError::LspGenericError,
             MutinyError::LspFundingError => MutinyJsError::LspFundingError,
             MutinyError::LspConnectionError => MutinyJsError::LspConnectionError,
+            MutinyError::LspFeeTooHighError => MutinyJsError::LspFeeTooHighError,
+            MutinyError::LspQuoteChangedError => MutinyJsError::LspQuoteChangedError,
+            MutinyError::SwapProviderNotConfigured => MutinyJsError::SwapProviderNotConfigured,
+            MutinyError::SwapProviderError => MutinyJsError::SwapProviderError,
+            MutinyError::SwapHtlcMismatch => MutinyJsError::SwapHtlcMismatch,
+            MutinyError::SwapFeeTooHighError => MutinyJsError::SwapFeeTooHighError,
+            MutinyError::PayOnchainQuoteChangedError => MutinyJsError::PayOnchainQuoteChangedError,
             MutinyError::RoutingFailed => MutinyJsError::RoutingFailed,
             MutinyError::PeerInfoParseFailed => MutinyJsError::PeerInfoParseFailed,
             MutinyError::ChannelCreationFailed => MutinyJsError::ChannelCreationFailed,
@@ -180,6 +221,9 @@ impl From<MutinyError> for MutinyJsError {
                 MutinyJsError::SubscriptionClientNotConfigured
             }
             MutinyError::InvalidArgumentsError => MutinyJsError::InvalidArgumentsError,
+            MutinyError::InvalidChannelPolicy(e) => MutinyJsError::InvalidChannelPolicy(e),
+            MutinyError::AnchorReserveRequired => MutinyJsError::AnchorReserveRequired,
+            MutinyError::PeerStorageTooLarge => MutinyJsError::PeerStorageTooLarge,
             MutinyError::LspAmountTooHighError => MutinyJsError::LspAmountTooHighError,
         }
     }
@@ -24,16 +24,24 @@ use gloo_utils::format::JsValueSerdeExt;
 use lightning::routing::gossip::NodeId;
 use lightning_invoice::Invoice;
 use lnurl::lnurl::LnUrl;
+use mutiny_core::activity::ActivityFilter;
 use mutiny_core::auth::MutinyAuthClient;
 use mutiny_core::lnurlauth::AuthManager;
+use mutiny_core::logging::{LogLevel, LogQuery};
+use mutiny_core::pay_onchain::{PayOnchainManager, PayOnchainQuote};
 use mutiny_core::redshift::RedshiftManager;
 use mutiny_core::redshift::RedshiftRecipient;
+use mutiny_core::scb::backup::{LocalScbDestination, NostrScbDestination, VssScbDestination};
 use mutiny_core::scb::EncryptedSCB;
 use mutiny_core::storage::MutinyStorage;
+use mutiny_core::swaps::SwapManager;
 use mutiny_core::vss::MutinyVssClient;
 use mutiny_core::{encrypt::encryption_key_from_pass, generate_seed, nostr::nwc::NwcProfile};
 use mutiny_core::{labels::LabelStorage, nodemanager::NodeManager};
 use mutiny_core::{logging::MutinyLogger, nostr::ProfileType};
+use mutiny_core::{
+    CallbackChannelApprover, ChannelPolicy, InboundChannelPolicy, LspConfig, LspSelectionPolicy,
+};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::{
@@ -74,6 +82,13 @@ impl MutinyWallet {
         subscription_url: Option<String>,
         storage_url: Option<String>,
         do_not_connect_peers: Option<bool>,
+        log_level: Option<String>,
+        lsps: Option<String>, /* JSON Vec<LspConfig> */
+        lsp_selection_policy: Option<String>,
+        channel_policy: Option<String>, /* JSON ChannelPolicy */
+        inbound_channel_policy: Option<String>, /* JSON InboundChannelPolicy */
+        inbound_channel_approver: Option<js_sys::Function>,
+        swap_provider_url: Option<String>,
     ) -> Result<MutinyWallet, MutinyJsError> {
         utils::set_panic_hook();
         let logger = Arc::new(MutinyLogger::default());
@@ -142,7 +157,8 @@ impl MutinyWallet {
             (None, None)
         };
 
-        let storage = IndexedDbStorage::new(password, cipher, vss_client, logger.clone()).await?;
+        let storage =
+            IndexedDbStorage::new(password, cipher, vss_client.clone(), logger.clone()).await?;
 
         let mut config = mutiny_core::MutinyWalletConfig::new(
             xprivkey,
@@ -155,10 +171,75 @@ impl MutinyWallet {
             subscription_url,
         );
 
+        // back up our channels to vss whenever they change
+        if let Some(vss) = vss_client {
+            config = config.with_scb_backup_destination(Arc::new(VssScbDestination::new(vss)));
+        }
+
         if let Some(true) = do_not_connect_peers {
             config = config.with_do_not_connect_peers();
         }
 
+        if let Some(log_level) = log_level {
+            let log_level =
+                LogLevel::from_str(&log_level).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+            config = config.with_log_level(log_level);
+        }
+
+        if let Some(lsps) = lsps {
+            let lsps: Vec<LspConfig> =
+                serde_json::from_str(&lsps).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+            config = config.with_lsps(lsps);
+        }
+
+        if let Some(policy) = lsp_selection_policy {
+            let policy = LspSelectionPolicy::from_str(&policy)
+                .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+            config = config.with_lsp_selection_policy(policy);
+        }
+
+        if let Some(policy) = channel_policy {
+            let policy: ChannelPolicy =
+                serde_json::from_str(&policy).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+            config = config.with_channel_policy(policy);
+        }
+
+        if let Some(policy) = inbound_channel_policy {
+            let policy: InboundChannelPolicy =
+                serde_json::from_str(&policy).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+            config = config.with_inbound_channel_policy(policy);
+        }
+
+        // called with the JSON InboundChannelRequest,
+        // should return a boolean or a promise of one
+        if let Some(approve) = inbound_channel_approver {
+            let approver = CallbackChannelApprover::new(move |request| {
+                let approve = approve.clone();
+                Box::pin(async move {
+                    let request = match serde_json::to_string(&request) {
+                        Ok(request) => request,
+                        Err(_) => return false,
+                    };
+                    let result = match approve.call1(&JsValue::NULL, &JsValue::from_str(&request)) {
+                        Ok(result) => result,
+                        Err(_) => return false,
+                    };
+                    let result = match result.dyn_into::<js_sys::Promise>() {
+                        Ok(promise) => wasm_bindgen_futures::JsFuture::from(promise)
+                            .await
+                            .unwrap_or(JsValue::FALSE),
+                        Err(result) => result,
+                    };
+                    result.as_bool().unwrap_or(false)
+                })
+            });
+            config = config.with_inbound_channel_approver(Arc::new(approver));
+        }
+
+        if let Some(url) = swap_provider_url {
+            config = config.with_swap_provider_url(url);
+        }
+
         let inner = mutiny_core::MutinyWallet::new(storage, config).await?;
         Ok(MutinyWallet { mnemonic, inner })
     }
@@ -315,7 +396,8 @@ impl MutinyWallet {
             .to_string())
     }
 
-    /// Sweeps all the funds from the wallet to the given address.
+    /// Sweeps all the funds from the wallet to the given address, except for
+    /// the reserve kept to fee bump our anchor channels.
     /// The fee rate is in sat/vbyte.
     ///
     /// If a fee rate is not provided, one will be used from the fee estimator.
@@ -439,6 +521,13 @@ impl MutinyWallet {
         Ok(self.inner.node_manager.get_balance().await?.into())
     }
 
+    /// The on-chain funds kept to fee bump our anchor channels, in sats.
+    /// Sending on-chain will fail if it would leave less than this.
+    #[wasm_bindgen]
+    pub async fn get_anchor_reserve(&self) -> u64 {
+        self.inner.node_manager.anchor_reserve_sats().await
+    }
+
     /// Lists all the UTXOs in the wallet.
     #[wasm_bindgen]
     pub fn list_utxos(&self) -> Result<JsValue, MutinyJsError> {
@@ -473,6 +562,27 @@ impl MutinyWallet {
         )?)
     }
 
+    /// Sets the channel policy for a node, overriding the default one.
+    /// Passing `undefined` removes the override.
+    /// The policy takes effect the next time the node is started.
+    #[wasm_bindgen]
+    pub async fn set_node_channel_policy(
+        &self,
+        self_node_pubkey: String,
+        channel_policy: Option<String>, /* JSON ChannelPolicy */
+    ) -> Result<(), MutinyJsError> {
+        let self_node_pubkey = PublicKey::from_str(&self_node_pubkey)?;
+        let channel_policy: Option<ChannelPolicy> = channel_policy
+            .map(|p| serde_json::from_str(&p))
+            .transpose()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .set_node_channel_policy(&self_node_pubkey, channel_policy)
+            .await?)
+    }
+
     /// Attempts to connect to a peer from the selected node.
     #[wasm_bindgen]
     pub async fn connect_to_peer(
@@ -554,6 +664,81 @@ impl MutinyWallet {
             .into())
     }
 
+    /// Gets a quote for receiving the given amount, in satoshis.
+    /// The quote shows the LSP's fee, if a new channel is needed, and what we will receive.
+    #[wasm_bindgen]
+    pub async fn quote_invoice(
+        &self,
+        amount: u64,
+    ) -> Result<JsValue /* InvoiceQuote */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.quote_invoice(amount).await?,
+        )?)
+    }
+
+    /// Creates an invoice for a quote from `quote_invoice`.
+    /// Fails if the quote expired or if the LSP's fee changed since the quote.
+    #[wasm_bindgen]
+    pub async fn create_invoice_from_quote(
+        &self,
+        quote_id: String,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .create_invoice_from_quote(&quote_id, labels)
+            .await?
+            .into())
+    }
+
+    /// Creates a hold invoice for the given payment hash. The amount should be in satoshis.
+    /// Payments to it are held until `settle_hold_invoice` or `cancel_hold_invoice` is called.
+    #[wasm_bindgen]
+    pub async fn create_hold_invoice(
+        &self,
+        payment_hash: String,
+        amount: Option<u64>,
+        labels: JsValue, /* Vec<String> */
+    ) -> Result<MutinyInvoice, MutinyJsError> {
+        let payment_hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        let labels: Vec<String> = labels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .create_hold_invoice(payment_hash, amount, labels)
+            .await?
+            .into())
+    }
+
+    /// Settles a held payment for a hold invoice with the given hex encoded preimage.
+    #[wasm_bindgen]
+    pub async fn settle_hold_invoice(&self, preimage: String) -> Result<(), MutinyJsError> {
+        let preimage: [u8; 32] =
+            FromHex::from_hex(&preimage).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .settle_hold_invoice(preimage)
+            .await?)
+    }
+
+    /// Cancels a hold invoice, failing back any held payment for it.
+    #[wasm_bindgen]
+    pub async fn cancel_hold_invoice(&self, payment_hash: String) -> Result<(), MutinyJsError> {
+        let payment_hash: sha256::Hash = sha256::Hash::from_str(&payment_hash)?;
+        Ok(self
+            .inner
+            .node_manager
+            .cancel_hold_invoice(&payment_hash)
+            .await?)
+    }
+
     /// Pays a lightning invoice from the selected node.
     /// An amount should only be provided if the invoice does not have an amount.
     /// The amount should be in satoshis.
@@ -728,15 +913,22 @@ impl MutinyWallet {
     /// Opens a channel from our selected node to the given pubkey.
     /// The amount is in satoshis.
     ///
+    /// The push amount, in millisatoshis, is given to the peer when the channel opens.
+    /// The label and note are kept with the channel and its eventual closure.
+    ///
     /// The node must be online and have a connection to the peer.
     /// The wallet much have enough funds to open the channel.
     #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
     pub async fn open_channel(
         &self,
         from_node: String,
         to_pubkey: Option<String>,
         amount: u64,
         fee_rate: Option<f32>,
+        push_msat: Option<u64>,
+        label: Option<String>,
+        note: Option<String>,
     ) -> Result<MutinyChannel, MutinyJsError> {
         let from_node = PublicKey::from_str(&from_node)?;
 
@@ -750,11 +942,42 @@ impl MutinyWallet {
         Ok(self
             .inner
             .node_manager
-            .open_channel(&from_node, to_pubkey, amount, fee_rate, None)
+            .open_channel(
+                &from_node, to_pubkey, amount, push_msat, fee_rate, None, label, note,
+            )
             .await?
             .into())
     }
 
+    /// Opens channels from our selected node to all the given pubkeys,
+    /// funded by a single on-chain transaction. The amounts are in satoshis.
+    ///
+    /// The node must be online and have a connection to every peer.
+    /// If any peer rejects its channel, none of the channels are opened.
+    #[wasm_bindgen]
+    pub async fn open_channels(
+        &self,
+        from_node: String,
+        channels: JsValue, /* Vec<(String, u64)> */
+        fee_rate: Option<f32>,
+    ) -> Result<JsValue /* Vec<MutinyChannel> */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let channels: Vec<(PublicKey, u64)> = channels
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+
+        let channels: Vec<MutinyChannel> = self
+            .inner
+            .node_manager
+            .open_channels(&from_node, channels, fee_rate)
+            .await?
+            .into_iter()
+            .map(|c| c.into())
+            .collect();
+
+        Ok(JsValue::from_serde(&channels)?)
+    }
+
     /// Opens a channel from our selected node to the given pubkey.
     /// It will spend the all the on-chain utxo in full to fund the channel.
     ///
@@ -806,6 +1029,201 @@ impl MutinyWallet {
             .await?)
     }
 
+    /// Cooperatively closes a channel with the given outpoint.
+    ///
+    /// If a destination address is given, our side of the channel is paid to it
+    /// instead of the on-chain wallet. It must be a segwit address.
+    ///
+    /// The fee rate is in sats per vbyte and is the target for the closing transaction.
+    /// The max fee is how many sats above that target we are willing to pay to close.
+    #[wasm_bindgen]
+    pub async fn close_channel_to_address(
+        &self,
+        outpoint: String,
+        destination: Option<String>,
+        fee_rate: Option<f32>,
+        max_fee_sats: Option<u64>,
+    ) -> Result<(), MutinyJsError> {
+        let outpoint: OutPoint =
+            OutPoint::from_str(&outpoint).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let destination = destination
+            .map(|d| Address::from_str(&d))
+            .transpose()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(self
+            .inner
+            .node_manager
+            .close_channel_to_address(&outpoint, destination, fee_rate, max_fee_sats)
+            .await?)
+    }
+
+    /// Moves lightning liquidity from one of our nodes to another.
+    /// The amounts are in satoshis.
+    ///
+    /// Fails without paying if routing would cost more than the max fee.
+    #[wasm_bindgen]
+    pub async fn rebalance(
+        &self,
+        from_node: String,
+        to_node: String,
+        amount_sats: u64,
+        max_fee_sats: u64,
+    ) -> Result<JsValue /* Rebalance */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        let to_node = PublicKey::from_str(&to_node)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .rebalance(&from_node, &to_node, amount_sats, max_fee_sats)
+                .await?,
+        )?)
+    }
+
+    /// Moves lightning liquidity between two channels of the same node,
+    /// out through the first channel and back in through the second.
+    /// The amounts are in satoshis.
+    ///
+    /// Fails without paying if routing would cost more than the max fee.
+    #[wasm_bindgen]
+    pub async fn rebalance_channels(
+        &self,
+        node: String,
+        from_channel: String,
+        to_channel: String,
+        amount_sats: u64,
+        max_fee_sats: u64,
+    ) -> Result<JsValue /* Rebalance */, MutinyJsError> {
+        let node = PublicKey::from_str(&node)?;
+        let from_channel: OutPoint =
+            OutPoint::from_str(&from_channel).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let to_channel: OutPoint =
+            OutPoint::from_str(&to_channel).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .rebalance_channels(&node, &from_channel, &to_channel, amount_sats, max_fee_sats)
+                .await?,
+        )?)
+    }
+
+    /// Lists all the rebalances between our nodes and channels, newest first.
+    #[wasm_bindgen]
+    pub async fn list_rebalances(&self) -> Result<JsValue /* Vec<Rebalance> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_rebalances().await?,
+        )?)
+    }
+
+    /// Swaps on-chain funds for lightning funds on the given node through the swap provider.
+    /// The provider pays an invoice for the amount once we fund its HTLC with that plus its fee.
+    /// The amounts are in satoshis.
+    ///
+    /// Fails before funding if the provider's fee is more than the max fee.
+    #[wasm_bindgen]
+    pub async fn loop_in(
+        &self,
+        node: String,
+        amount_sats: u64,
+        fee_rate: Option<f32>,
+        max_fee_sats: Option<u64>,
+    ) -> Result<JsValue /* Swap */, MutinyJsError> {
+        let node = PublicKey::from_str(&node)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .loop_in(&node, amount_sats, fee_rate, max_fee_sats)
+                .await?,
+        )?)
+    }
+
+    /// Swaps lightning funds from the given node for on-chain funds through the swap provider.
+    /// We pay the provider's invoice and claim the amount on-chain into our wallet.
+    /// The amounts are in satoshis.
+    ///
+    /// Fails before paying if the provider's fee is more than the max fee.
+    #[wasm_bindgen]
+    pub async fn loop_out(
+        &self,
+        node: String,
+        amount_sats: u64,
+        max_fee_sats: Option<u64>,
+    ) -> Result<JsValue /* Swap */, MutinyJsError> {
+        let node = PublicKey::from_str(&node)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .loop_out(&node, amount_sats, max_fee_sats)
+                .await?,
+        )?)
+    }
+
+    /// Gets a swap by the id the swap provider gave it.
+    #[wasm_bindgen]
+    pub fn get_swap(&self, id: String) -> Result<JsValue /* Option<Swap> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_swap(&id)?,
+        )?)
+    }
+
+    /// Lists all our swaps, newest first.
+    #[wasm_bindgen]
+    pub fn list_swaps(&self) -> Result<JsValue /* Vec<Swap> */, MutinyJsError> {
+        Ok(JsValue::from_serde(&self.inner.node_manager.list_swaps()?)?)
+    }
+
+    /// Works out the cheapest way to pay an on-chain address from our lightning balance,
+    /// either a swap out or closing a channel and reopening what is left, and what it costs.
+    #[wasm_bindgen]
+    pub async fn quote_pay_onchain_from_lightning(
+        &self,
+        address: String,
+        amount_sats: u64,
+    ) -> Result<JsValue /* PayOnchainQuote */, MutinyJsError> {
+        let address = Address::from_str(&address)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .quote_pay_onchain_from_lightning(&address, amount_sats)
+                .await?,
+        )?)
+    }
+
+    /// Pays an on-chain address from our lightning balance with a quote the user accepted
+    /// from `quote_pay_onchain_from_lightning`. Fails if it now costs more than that quote.
+    /// This only starts the payment, it is finished in the background.
+    #[wasm_bindgen]
+    pub async fn pay_onchain_from_lightning(
+        &self,
+        address: String,
+        amount_sats: u64,
+        quote: JsValue, /* PayOnchainQuote */
+    ) -> Result<JsValue /* PayOnchainJob */, MutinyJsError> {
+        let address = Address::from_str(&address)?;
+        let quote: PayOnchainQuote = quote
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .pay_onchain_from_lightning(address, amount_sats, quote)
+                .await?,
+        )?)
+    }
+
+    /// Lists all our payments to on-chain addresses from our lightning balance, newest first.
+    #[wasm_bindgen]
+    pub fn list_pay_onchain_jobs(&self) -> Result<JsValue /* Vec<PayOnchainJob> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.list_pay_onchain_jobs()?,
+        )?)
+    }
+
     /// Lists all the channels for all the nodes in the node manager.
     #[wasm_bindgen]
     pub async fn list_channels(&self) -> Result<JsValue /* Vec<MutinyChannel> */, MutinyJsError> {
@@ -814,6 +1232,59 @@ impl MutinyWallet {
         )?)
     }
 
+    /// Gets the limits of the inbound liquidity the node's LSP will sell.
+    /// The LSP must support LSPS1.
+    #[wasm_bindgen]
+    pub async fn get_liquidity_options(
+        &self,
+        from_node: String,
+    ) -> Result<JsValue /* LiquidityOptions */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .get_liquidity_options(&from_node)
+                .await?,
+        )?)
+    }
+
+    /// Orders a channel with the given amount of inbound liquidity, in satoshis,
+    /// from the node's LSP. The order's invoice must be paid before the LSP opens the channel.
+    #[wasm_bindgen]
+    pub async fn create_liquidity_order(
+        &self,
+        from_node: String,
+        lsp_balance_sat: u64,
+        channel_expiry_blocks: Option<u32>,
+    ) -> Result<JsValue /* LiquidityOrder */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .create_liquidity_order(&from_node, lsp_balance_sat, channel_expiry_blocks)
+                .await?,
+        )?)
+    }
+
+    /// Gets the current state of a liquidity order from the node's LSP.
+    #[wasm_bindgen]
+    pub async fn get_liquidity_order(
+        &self,
+        from_node: String,
+        order_id: String,
+    ) -> Result<JsValue /* LiquidityOrder */, MutinyJsError> {
+        let from_node = PublicKey::from_str(&from_node)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .get_liquidity_order(&from_node, order_id)
+                .await?,
+        )?)
+    }
+
     /// Takes an encrypted static channel backup and recovers the channels from it.
     /// If the backup is encrypted with a different key than the current key, it will fail.
     #[wasm_bindgen]
@@ -829,6 +1300,23 @@ impl MutinyWallet {
         Ok(())
     }
 
+    /// Lists the nodes and channels in an encrypted static channel backup without recovering them.
+    /// Also returns any of our current channels that are missing from the backup.
+    #[wasm_bindgen]
+    pub async fn inspect_static_channel_backup(
+        &self,
+        scb: String,
+    ) -> Result<JsValue /* ScbInspection */, MutinyJsError> {
+        let scb = EncryptedSCB::from_str(&scb).map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        Ok(JsValue::from_serde(
+            &self
+                .inner
+                .node_manager
+                .inspect_static_channel_backup(scb)
+                .await?,
+        )?)
+    }
+
     /// Creates a static channel backup for all the nodes in the node manager.
     /// The backup is encrypted with the SCB key.
     #[wasm_bindgen]
@@ -841,6 +1329,73 @@ impl MutinyWallet {
         Ok(scb.to_string())
     }
 
+    /// Makes a new static channel backup now and pushes it to all backup destinations.
+    /// Backups are also made automatically whenever a channel opens or closes.
+    #[wasm_bindgen]
+    pub async fn backup_static_channels(
+        &self,
+    ) -> Result<JsValue /* ScbBackupRecord */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.backup_static_channels().await?,
+        )?)
+    }
+
+    /// Gets when the latest static channel backup was made
+    /// and when each backup destination last got a backup.
+    #[wasm_bindgen]
+    pub fn get_scb_backup_status(&self) -> Result<JsValue /* ScbBackupStatus */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_scb_backup_status()?,
+        )?)
+    }
+
+    /// Gets the static channel backups we have made, newest first.
+    #[wasm_bindgen]
+    pub fn get_scb_backup_history(
+        &self,
+    ) -> Result<JsValue /* Vec<ScbBackupRecord> */, MutinyJsError> {
+        Ok(JsValue::from_serde(
+            &self.inner.node_manager.get_scb_backup_history()?,
+        )?)
+    }
+
+    /// Backs up our channels to the given nostr relays as an encrypted replaceable event.
+    #[wasm_bindgen]
+    pub async fn enable_nostr_scb_backups(
+        &self,
+        relays: JsValue, /* Vec<String> */
+    ) -> Result<(), MutinyJsError> {
+        let relays: Vec<String> = relays
+            .into_serde()
+            .map_err(|_| MutinyJsError::InvalidArgumentsError)?;
+        let destination = NostrScbDestination::new(self.inner.nostr.primary_key.clone(), relays);
+        self.inner
+            .node_manager
+            .add_scb_backup_destination(Arc::new(destination))
+            .await;
+        Ok(())
+    }
+
+    /// Calls the given function with each new static channel backup,
+    /// so it can be saved locally.
+    #[wasm_bindgen]
+    pub async fn enable_local_scb_backups(&self, export: js_sys::Function) {
+        let destination = LocalScbDestination::new(move |scb| {
+            export
+                .call1(&JsValue::NULL, &JsValue::from_str(scb))
+                .map_err(|_| {
+                    mutiny_core::error::MutinyError::Other(anyhow::anyhow!(
+                        "Failed to export channel backup"
+                    ))
+                })?;
+            Ok(())
+        });
+        self.inner
+            .node_manager
+            .add_scb_backup_destination(Arc::new(destination))
+            .await;
+    }
+
     /// Lists all the peers for all the nodes in the node manager.
     #[wasm_bindgen]
     pub async fn list_peers(&self) -> Result<JsValue /* Vec<MutinyPeer> */, MutinyJsError> {
@@ -858,20 +1413,46 @@ impl MutinyWallet {
 
         // add contacts to the activity
         let contacts = self.inner.node_manager.get_contacts()?;
-        for a in activity.iter_mut() {
-            // find labels that have a contact and add them to the item
-            for label in a.labels.iter() {
-                if let Some(contact) = contacts.get(label) {
-                    a.contacts.push(Contact::from(contact.clone()));
-                }
-            }
-            // remove labels that have a contact to prevent duplicates
-            a.labels.retain(|l| !contacts.contains_key(l));
-        }
+        add_contacts_to_activity(&mut activity, &contacts);
 
         Ok(JsValue::from_serde(&activity)?)
     }
 
+    /// Returns a page of on-chain and lightning activity that matches the filter, newest first.
+    /// If no filter is given, all activity is included.
+    #[wasm_bindgen]
+    pub async fn query_activity(
+        &self,
+        filter: JsValue, /* Option<ActivityFilter> */
+        offset: usize,
+        limit: usize,
+    ) -> Result<JsValue /* ActivityPage */, MutinyJsError> {
+        let filter: ActivityFilter = if filter.is_null() || filter.is_undefined() {
+            ActivityFilter::default()
+        } else {
+            filter
+                .into_serde()
+                .map_err(|_| MutinyJsError::InvalidArgumentsError)?
+        };
+
+        let page = self
+            .inner
+            .node_manager
+            .query_activity(filter, offset, limit)
+            .await?;
+        let mut items: Vec<ActivityItem> = page.items.into_iter().map(|a| a.into()).collect();
+
+        // add contacts to the activity
+        let contacts = self.inner.node_manager.get_contacts()?;
+        add_contacts_to_activity(&mut items, &contacts);
+
+        let page = ActivityPage {
+            items,
+            total: page.total,
+        };
+        Ok(JsValue::from_serde(&page)?)
+    }
+
     /// Initiates a redshift
     #[wasm_bindgen]
     pub async fn init_redshift(
@@ -1049,6 +1630,30 @@ impl MutinyWallet {
         Ok(res)
     }
 
+    /// Gets the structured log records that match the query.
+    /// A null query returns all of the saved logs.
+    #[wasm_bindgen]
+    pub async fn query_logs(
+        query: JsValue, /* Option<LogQuery> */
+    ) -> Result<JsValue /* Option<Vec<LogRecord>> */, MutinyJsError> {
+        let query: LogQuery = if query.is_null() || query.is_undefined() {
+            LogQuery::default()
+        } else {
+            query
+                .into_serde()
+                .map_err(|_| MutinyJsError::InvalidArgumentsError)?
+        };
+
+        let logger = Arc::new(MutinyLogger::default());
+        // Password should not be required for logs
+        let storage = IndexedDbStorage::new(None, None, None, logger.clone()).await?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let logger = Arc::new(MutinyLogger::with_writer(stop.clone(), storage.clone()));
+        let res = JsValue::from_serde(&NodeManager::query_logs(storage, logger, &query)?)?;
+        stop.swap(true, Ordering::Relaxed);
+        Ok(res)
+    }
+
     /// Get nostr wallet connect profiles
     #[wasm_bindgen]
     pub fn get_nwc_profiles(&self) -> Result<JsValue /* Vec<NwcProfile> */, MutinyJsError> {
@@ -1183,6 +1788,33 @@ impl MutinyWallet {
         Ok(self.inner.reset_onchain_tracker().await?)
     }
 
+    /// Exports the wallet's activity as a CSV file for accounting.
+    #[wasm_bindgen]
+    pub async fn export_activity_csv(&self) -> Result<String, MutinyJsError> {
+        Ok(self.inner.node_manager.export_activity_csv().await?)
+    }
+
+    /// Exports the wallet's labels in the BIP-329 JSONL format.
+    #[wasm_bindgen]
+    pub fn export_bip329_labels(&self) -> Result<String, MutinyJsError> {
+        Ok(self.inner.node_manager.export_bip329_labels()?)
+    }
+
+    /// Imports labels in the BIP-329 JSONL format.
+    /// Returns the number of labels that were imported.
+    #[wasm_bindgen]
+    pub fn import_bip329_labels(&self, jsonl: String) -> Result<usize, MutinyJsError> {
+        Ok(self.inner.node_manager.import_bip329_labels(&jsonl)?)
+    }
+
+    /// Creates a diagnostics bundle as a json string that can be attached to a
+    /// support ticket. Secrets like preimages and invoices are not included.
+    #[wasm_bindgen]
+    pub async fn export_diagnostics(&self) -> Result<String, MutinyJsError> {
+        let diagnostics = self.inner.node_manager.diagnostics().await?;
+        Ok(serde_json::to_string(&diagnostics)?)
+    }
+
     /// Exports the current state of the node manager to a json object.
     #[wasm_bindgen]
     pub async fn export_json(password: Option<String>) -> Result<String, MutinyJsError> {
@@ -1297,6 +1929,13 @@ mod tests {
             None,
             None,
             None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -1328,6 +1967,13 @@ mod tests {
             None,
             None,
             None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
         )
         .await
         .unwrap();
@@ -1362,6 +2008,13 @@ mod tests {
             None,
             None,
             None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
         )
         .await
         .expect("mutiny wallet should initialize");
@@ -9,6 +9,7 @@ use mutiny_core::labels::Contact as MutinyContact;
 use mutiny_core::redshift::{RedshiftRecipient, RedshiftStatus};
 use mutiny_core::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
 use wasm_bindgen::prelude::*;
 
@@ -21,6 +22,8 @@ pub enum ActivityType {
     Lightning,
     ChannelOpen,
     ChannelClose,
+    Rebalance,
+    Swap,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -33,6 +36,9 @@ pub struct ActivityItem {
     pub(crate) labels: Vec<String>,
     pub(crate) contacts: Vec<Contact>,
     pub last_updated: Option<u64>,
+    destination: Option<String>,
+    /// The fees paid for outgoing lightning payments, rebalances and swaps
+    pub fees_paid: Option<u64>,
 }
 
 #[wasm_bindgen]
@@ -56,6 +62,12 @@ impl ActivityItem {
     pub fn contacts(&self) -> JsValue /* Vec<Contact> */ {
         JsValue::from_serde(&self.contacts).unwrap()
     }
+
+    /// The address a channel close paid to, if one was chosen.
+    #[wasm_bindgen(getter)]
+    pub fn destination(&self) -> Option<String> {
+        self.destination.clone()
+    }
 }
 
 impl From<nodemanager::ActivityItem> for ActivityItem {
@@ -70,6 +82,8 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             }
             nodemanager::ActivityItem::Lightning(_) => ActivityType::Lightning,
             nodemanager::ActivityItem::ChannelClosed(_) => ActivityType::ChannelClose,
+            nodemanager::ActivityItem::Rebalance(_) => ActivityType::Rebalance,
+            nodemanager::ActivityItem::Swap(_) => ActivityType::Swap,
         };
 
         let id = match a {
@@ -78,6 +92,8 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             nodemanager::ActivityItem::ChannelClosed(ref c) => {
                 c.user_channel_id.map(|c| c.to_hex()).unwrap_or_default()
             }
+            nodemanager::ActivityItem::Rebalance(ref r) => r.payment_hash.to_hex(),
+            nodemanager::ActivityItem::Swap(ref s) => s.id.clone(),
         };
 
         let (inbound, amount_sats) = match a {
@@ -92,6 +108,22 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             }
             nodemanager::ActivityItem::Lightning(ref ln) => (ln.inbound, ln.amount_sats),
             nodemanager::ActivityItem::ChannelClosed(_) => (false, None),
+            nodemanager::ActivityItem::Rebalance(ref r) => (false, Some(r.amount_sats)),
+            nodemanager::ActivityItem::Swap(ref s) => (false, Some(s.amount_received_sats())),
+        };
+
+        let fees_paid = match a {
+            nodemanager::ActivityItem::Lightning(ref ln) if !ln.inbound => ln.fees_paid,
+            nodemanager::ActivityItem::Rebalance(ref r) => r.fees_paid,
+            nodemanager::ActivityItem::Swap(ref s) => s.fees_paid,
+            _ => None,
+        };
+
+        let destination = match a {
+            nodemanager::ActivityItem::ChannelClosed(ref c) => {
+                c.destination.as_ref().map(|d| d.to_string())
+            }
+            _ => None,
         };
 
         ActivityItem {
@@ -102,7 +134,33 @@ impl From<nodemanager::ActivityItem> for ActivityItem {
             labels: a.labels(),
             contacts: vec![],
             last_updated: a.last_updated(),
+            destination,
+            fees_paid,
+        }
+    }
+}
+
+/// A page of activity returned by `query_activity`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ActivityPage {
+    pub items: Vec<ActivityItem>,
+    pub total: usize,
+}
+
+/// Moves the labels of the activity items that belong to a contact into the item's contacts
+pub(crate) fn add_contacts_to_activity(
+    activity: &mut [ActivityItem],
+    contacts: &HashMap<String, MutinyContact>,
+) {
+    for a in activity.iter_mut() {
+        // find labels that have a contact and add them to the item
+        for label in a.labels.iter() {
+            if let Some(contact) = contacts.get(label) {
+                a.contacts.push(Contact::from(contact.clone()));
+            }
         }
+        // remove labels that have a contact to prevent duplicates
+        a.labels.retain(|l| !contacts.contains_key(l));
     }
 }
 
@@ -114,9 +172,11 @@ pub struct MutinyInvoice {
     payment_hash: String,
     preimage: Option<String>,
     payee_pubkey: Option<String>,
+    lsp_pubkey: Option<String>,
     pub amount_sats: Option<u64>,
     pub expire: u64,
     pub paid: bool,
+    pub held: bool,
     pub fees_paid: Option<u64>,
     pub inbound: bool,
     pub last_updated: u64,
@@ -155,6 +215,12 @@ impl MutinyInvoice {
         self.payee_pubkey.clone()
     }
 
+    /// The LSP used to create the invoice, if any
+    #[wasm_bindgen(getter)]
+    pub fn lsp_pubkey(&self) -> Option<String> {
+        self.lsp_pubkey.clone()
+    }
+
     #[wasm_bindgen(getter)]
     pub fn labels(&self) -> JsValue /* Vec<String> */ {
         JsValue::from_serde(&self.labels).unwrap()
@@ -169,9 +235,11 @@ impl From<nodemanager::MutinyInvoice> for MutinyInvoice {
             payment_hash: m.payment_hash.to_hex(),
             preimage: m.preimage,
             payee_pubkey: m.payee_pubkey.map(|p| p.to_hex()),
+            lsp_pubkey: m.lsp_pubkey.map(|p| p.to_hex()),
             amount_sats: m.amount_sats,
             expire: m.expire,
             paid: m.paid,
+            held: m.held,
             fees_paid: m.fees_paid,
             inbound: m.inbound,
             last_updated: m.last_updated,
@@ -189,6 +257,9 @@ pub struct MutinyPeer {
     color: Option<String>,
     label: Option<String>,
     pub is_connected: bool,
+    pub last_connected: Option<u64>,
+    pub connection_failures: u32,
+    pub latency_ms: Option<u64>,
 }
 
 #[wasm_bindgen]
@@ -233,6 +304,9 @@ impl From<nodemanager::MutinyPeer> for MutinyPeer {
             color: m.color,
             label: m.label,
             is_connected: m.is_connected,
+            last_connected: m.last_connected,
+            connection_failures: m.connection_failures,
+            latency_ms: m.latency_ms,
         }
     }
 }
@@ -247,6 +321,8 @@ pub struct MutinyChannel {
     peer: String,
     pub confirmations_required: Option<u32>,
     pub confirmations: u32,
+    label: Option<String>,
+    note: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -273,6 +349,16 @@ impl MutinyChannel {
             None => false,
         }
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn label(&self) -> Option<String> {
+        self.label.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn note(&self) -> Option<String> {
+        self.note.clone()
+    }
 }
 
 impl From<nodemanager::MutinyChannel> for MutinyChannel {
@@ -285,6 +371,8 @@ impl From<nodemanager::MutinyChannel> for MutinyChannel {
             peer: m.peer.to_hex(),
             confirmations_required: m.confirmations_required,
             confirmations: m.confirmations,
+            label: m.label,
+            note: m.note,
         }
     }
 }
@@ -297,6 +385,9 @@ pub struct ChannelClosure {
     node_id: Option<PublicKey>,
     reason: String,
     pub timestamp: u64,
+    destination: Option<String>,
+    label: Option<String>,
+    note: Option<String>,
 }
 
 #[wasm_bindgen]
@@ -320,6 +411,21 @@ impl ChannelClosure {
     pub fn reason(&self) -> String {
         self.reason.clone()
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn destination(&self) -> Option<String> {
+        self.destination.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn label(&self) -> Option<String> {
+        self.label.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn note(&self) -> Option<String> {
+        self.note.clone()
+    }
 }
 
 impl PartialOrd for ChannelClosure {
@@ -341,6 +447,9 @@ impl From<nodemanager::ChannelClosure> for ChannelClosure {
             node_id: c.node_id,
             reason: c.reason,
             timestamp: c.timestamp,
+            destination: c.destination.map(|d| d.to_string()),
+            label: c.label,
+            note: c.note,
         }
     }
 }